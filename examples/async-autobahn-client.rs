@@ -8,7 +8,6 @@ use futures::sink::Sink;
 use futures::stream::Stream;
 use futures::Future;
 use futures::future::{self, Loop};
-use tokio::reactor::Handle;
 
 type BoxFuture<I, E> = Box<Future<Item = I, Error = E> + Send>;
 
@@ -28,7 +27,7 @@ fn main() {
 
 		let test_case = ClientBuilder::new(&url)
 			.unwrap()
-			.async_connect_insecure(&Handle::default())
+			.async_connect_insecure()
 			.and_then(move |(duplex, _)| {
 				println!("Executing test case: {}/{}", case_id, case_count);
 				future::loop_fn(duplex, |stream| {
@@ -88,7 +87,7 @@ fn get_case_count(addr: String) -> usize {
 
 	let counter = ClientBuilder::new(&url)
 		.unwrap()
-		.async_connect_insecure(&Handle::default())
+		.async_connect_insecure()
 		.and_then(|(s, _)| s.into_future().map_err(|e| e.0))
 		.and_then(move |(msg, _)| match msg {
 			Some(OwnedMessage::Text(txt)) => Ok(txt.parse().unwrap()),
@@ -104,7 +103,7 @@ fn update_reports(addr: String, agent: &str) {
 
 	let updater = ClientBuilder::new(&url)
 		.unwrap()
-		.async_connect_insecure(&Handle::default())
+		.async_connect_insecure()
 		.and_then(|(sink, _)| sink.send(OwnedMessage::Close(None)));
 	tokio::run(updater.map(|_| ()).map_err(|_| ()));
 
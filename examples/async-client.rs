@@ -8,7 +8,6 @@ use futures::future::Future;
 use futures::sink::Sink;
 use futures::stream::Stream;
 use futures::sync::mpsc;
-use tokio::reactor::Handle;
 use websocket::result::WebSocketError;
 use websocket::{ClientBuilder, OwnedMessage};
 
@@ -45,7 +44,7 @@ fn main() {
 	let runner = ClientBuilder::new(CONNECTION)
 		.unwrap()
 		.add_protocols(vec!["rust-websocket"])
-		.async_connect_insecure(&Handle::default())
+		.async_connect_insecure()
 		.and_then(|(duplex, _)| {
 			let (sink, stream) = duplex.split();
 			stream.filter_map(|message| {
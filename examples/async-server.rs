@@ -21,8 +21,9 @@ fn main() {
 	// time to build the server's future
 	// this will be a struct containing everything the server is going to do
 
-	// a stream of incoming connections
-	let f = server.incoming()
+	// a stream of incoming connections, skipping the ones that failed their
+	// handshake so a single bad client does not stop the server
+	let f = server.incoming_filtered()
 		// we don't wanna save the stream if it drops
 		.map_err(|InvalidConnection { error, .. }| error)
 		.for_each(|(upgrade, addr)| {
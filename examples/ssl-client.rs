@@ -8,7 +8,6 @@ use futures::future::Future;
 use futures::sink::Sink;
 use futures::stream::Stream;
 use futures::sync::mpsc;
-use tokio::reactor::Handle;
 use websocket::result::WebSocketError;
 use websocket::{ClientBuilder, OwnedMessage};
 
@@ -44,7 +43,7 @@ fn main() {
 
 	let runner = ClientBuilder::new(CONNECTION)
 		.unwrap()
-		.async_connect_secure(None, &Handle::default())
+		.async_connect_secure(None)
 		.and_then(|(duplex, _)| {
 			let (sink, stream) = duplex.split();
 			stream.filter_map(|message| {
@@ -0,0 +1,256 @@
+//! The framing layer of the protocol on its own, see section 5 of RFC 6455.
+//!
+//! Nothing in here needs `std` or an allocator: headers are encoded into and
+//! decoded from byte slices and payloads are masked in place, so a device
+//! without `std` can speak the framing layer with buffers of its own. The
+//! readers, writers and codecs of the `websocket` crate build on it, and it is
+//! re-exported there as `websocket::frames`.
+//!
+//! ```rust
+//! use websocket_frames::{decode_header, encode_header, mask_in_place, DataFrameHeader, FIN};
+//!
+//! let header = DataFrameHeader {
+//!     flags: FIN,
+//!     opcode: 1,
+//!     mask: Some([1, 2, 3, 4]),
+//!     len: 5,
+//! };
+//! let mut buf = [0u8; 16];
+//! let used = encode_header(&header, &mut buf).unwrap();
+//! buf[used..used + 5].copy_from_slice(b"hello");
+//! mask_in_place([1, 2, 3, 4], 0, &mut buf[used..used + 5]);
+//!
+//! assert_eq!(decode_header(&buf).unwrap(), Some((header, used)));
+//! ```
+#![no_std]
+
+#[macro_use]
+extern crate bitflags;
+
+use core::fmt;
+
+bitflags! {
+	/// Flags relevant to a WebSocket data frame.
+	pub struct DataFrameFlags: u8 {
+		/// Marks this dataframe as the last dataframe
+		const FIN = 0x80;
+		/// First reserved bit
+		const RSV1 = 0x40;
+		/// Second reserved bit
+		const RSV2 = 0x20;
+		/// Third reserved bit
+		const RSV3 = 0x10;
+	}
+}
+
+/// Represents a data frame header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DataFrameHeader {
+	/// The bit flags for the first byte of the header.
+	pub flags: DataFrameFlags,
+	/// The opcode of the header - must be <= 16.
+	pub opcode: u8,
+	/// The masking key, if any.
+	pub mask: Option<[u8; 4]>,
+	/// The length of the payload.
+	pub len: u64,
+}
+
+/// The largest a data frame header can get.
+pub const MAX_HEADER_SIZE: usize = 14;
+
+/// Why a header could not be encoded or decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+	/// The opcode does not fit into four bits.
+	InvalidOpcode,
+	/// The payload length was not given with the fewest bytes possible, or
+	/// has its most significant bit set.
+	InvalidLength,
+	/// A control frame has more than 125 bytes of payload.
+	ControlFrameTooLong,
+	/// A control frame is not the last frame of its message.
+	FragmentedControlFrame,
+	/// The buffer to encode the header into is too small.
+	BufferTooSmall,
+}
+
+impl FrameError {
+	/// A short description of the error.
+	pub fn description(&self) -> &'static str {
+		match *self {
+			FrameError::InvalidOpcode => "Invalid data frame opcode",
+			FrameError::InvalidLength => "Invalid data frame length",
+			FrameError::ControlFrameTooLong => "Control frame length too long",
+			FrameError::FragmentedControlFrame => "Illegal fragmented control frame",
+			FrameError::BufferTooSmall => "Buffer too small for the data frame header",
+		}
+	}
+}
+
+impl fmt::Display for FrameError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.description())
+	}
+}
+
+/// The size of a header, told by its second byte.
+pub fn header_size(second_byte: u8) -> usize {
+	let len_size = match second_byte & 0x7F {
+		126 => 2,
+		127 => 8,
+		_ => 0,
+	};
+	let mask_size = if second_byte & 0x80 == 0x80 { 4 } else { 0 };
+	2 + len_size + mask_size
+}
+
+/// Encode `header` into the start of `buf`, giving back how many bytes it
+/// took up. A buffer of `MAX_HEADER_SIZE` bytes fits any header.
+pub fn encode_header(header: &DataFrameHeader, buf: &mut [u8]) -> Result<usize, FrameError> {
+	if header.opcode > 0xF {
+		return Err(FrameError::InvalidOpcode);
+	}
+	if header.opcode >= 8 && header.len >= 126 {
+		return Err(FrameError::ControlFrameTooLong);
+	}
+
+	let len_byte = match header.len {
+		0...125 => header.len as u8,
+		126...65535 => 126,
+		_ => 127,
+	};
+	let mask_bit = if header.mask.is_some() { 0x80 } else { 0x00 };
+	let size = header_size(mask_bit | len_byte);
+	if buf.len() < size {
+		return Err(FrameError::BufferTooSmall);
+	}
+
+	// 'FIN', 'RSV1', 'RSV2', 'RSV3' and 'opcode', then 'MASK' and 'Payload len'
+	buf[0] = header.flags.bits() | header.opcode;
+	buf[1] = mask_bit | len_byte;
+
+	// 'Extended payload length'
+	let len_size = match len_byte {
+		126 => 2,
+		127 => 8,
+		_ => 0,
+	};
+	for i in 0..len_size {
+		buf[2 + i] = (header.len >> (8 * (len_size - 1 - i))) as u8;
+	}
+
+	// 'Masking-key'
+	if let Some(mask) = header.mask {
+		buf[2 + len_size..size].copy_from_slice(&mask);
+	}
+
+	Ok(size)
+}
+
+/// Decode the header at the start of `buf`, giving back the header and how
+/// many bytes it took up, or `None` if `buf` does not hold all of it yet.
+pub fn decode_header(buf: &[u8]) -> Result<Option<(DataFrameHeader, usize)>, FrameError> {
+	if buf.len() < 2 {
+		return Ok(None);
+	}
+	let size = header_size(buf[1]);
+	if buf.len() < size {
+		return Ok(None);
+	}
+
+	let flags = DataFrameFlags::from_bits_truncate(buf[0]);
+	let opcode = buf[0] & 0x0F;
+
+	let (len, len_size) = match buf[1] & 0x7F {
+		126 => (read_be(&buf[2..4]), 2),
+		127 => (read_be(&buf[2..10]), 8),
+		len => (len as u64, 0),
+	};
+	if (len_size == 2 && len <= 125) || (len_size == 8 && len <= 65535) {
+		return Err(FrameError::InvalidLength);
+	}
+	if len >> 63 == 1 {
+		return Err(FrameError::InvalidLength);
+	}
+
+	if opcode >= 8 {
+		if len >= 126 {
+			return Err(FrameError::ControlFrameTooLong);
+		}
+		if !flags.contains(FIN) {
+			return Err(FrameError::FragmentedControlFrame);
+		}
+	}
+
+	let mask = if buf[1] & 0x80 == 0x80 {
+		let mut mask = [0u8; 4];
+		mask.copy_from_slice(&buf[2 + len_size..size]);
+		Some(mask)
+	} else {
+		None
+	};
+
+	let header = DataFrameHeader {
+		flags: flags,
+		opcode: opcode,
+		mask: mask,
+		len: len,
+	};
+	Ok(Some((header, size)))
+}
+
+/// Mask or unmask `data` with `key`, `offset` bytes into the payload, so a
+/// payload can be masked in pieces.
+pub fn mask_in_place(key: [u8; 4], offset: usize, data: &mut [u8]) {
+	for (i, byte) in data.iter_mut().enumerate() {
+		*byte ^= key[(offset + i) % 4];
+	}
+}
+
+fn read_be(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0, |n, &byte| (n << 8) | byte as u64)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn headers_round_trip() {
+		let headers = [
+			DataFrameHeader { flags: FIN, opcode: 1, mask: None, len: 43 },
+			DataFrameHeader { flags: RSV1, opcode: 2, mask: Some([2, 4, 8, 16]), len: 512 },
+			DataFrameHeader { flags: FIN, opcode: 2, mask: Some([1, 2, 3, 4]), len: 1 << 40 },
+			DataFrameHeader { flags: FIN, opcode: 9, mask: None, len: 125 },
+		];
+		for header in &headers {
+			let mut buf = [0u8; MAX_HEADER_SIZE];
+			let used = encode_header(header, &mut buf).unwrap();
+			assert_eq!(decode_header(&buf[..used]).unwrap(), Some((*header, used)));
+			assert_eq!(decode_header(&buf[..used - 1]).unwrap(), None);
+		}
+
+		let mut short = [0u8; 2];
+		assert_eq!(encode_header(&headers[1], &mut short), Err(FrameError::BufferTooSmall));
+		assert_eq!(decode_header(&[0x82, 0x7E, 0x00, 0x7D]), Err(FrameError::InvalidLength));
+		assert_eq!(decode_header(&[0x09, 0x00]), Err(FrameError::FragmentedControlFrame));
+		assert_eq!(
+			decode_header(&[0x82, 0x7F, 0x80, 0, 0, 0, 0, 0, 0, 0]),
+			Err(FrameError::InvalidLength)
+		);
+	}
+
+	#[test]
+	fn masks_in_pieces() {
+		let key = [1, 2, 3, 4];
+		let mut whole = *b"hello world";
+		mask_in_place(key, 0, &mut whole);
+
+		let mut pieces = *b"hello world";
+		let (first, second) = pieces.split_at_mut(5);
+		mask_in_place(key, 0, first);
+		mask_in_place(key, 5, second);
+		assert_eq!(whole, pieces);
+	}
+}
@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate websocket;
+
+fuzz_target!(|data: &[u8]| {
+	websocket::fuzzing::fuzz_parse_handshake(data);
+});
@@ -17,7 +17,6 @@
 //! # extern crate futures;
 //! # extern crate tokio;
 //! # extern crate websocket;
-//! use tokio::reactor::Handle;
 //! use websocket::ClientBuilder;
 //! use websocket::async::client::{Client, ClientNew};
 //! use websocket::async::TcpStream;
@@ -28,7 +27,7 @@
 //! // create a Future of a client
 //! let client_future: ClientNew<TcpStream> =
 //!     ClientBuilder::new("ws://echo.websocket.org").unwrap()
-//!         .async_connect_insecure(&Handle::default());
+//!         .async_connect_insecure();
 //!
 //! // send a message
 //! let send_future = client_future
@@ -46,14 +45,28 @@ pub use tokio::reactor::Handle;
 pub use tokio_io::codec::Framed;
 pub use tokio::net::TcpStream;
 pub use futures::Future;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use futures::{Async, AsyncSink, Poll, Sink, Stream};
+use futures::task::{self, Task};
+use futures::sink::SendAll;
+use futures::stream::{self, IterOk};
+use futures::sync::mpsc;
 use http::header::HeaderMap;
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use result::WebSocketError;
 use codec::ws::MessageCodec;
 use message::OwnedMessage;
+use close::{CloseCode, CloseFrame};
 
 #[cfg(feature = "async-ssl")]
 pub use tokio_tls::TlsStream;
+#[cfg(feature = "serde")]
+pub use json::TypedClient;
+pub use client::negotiated::Negotiated;
+pub use close::AutoClose;
 
 /// An asynchronous websocket client.
 ///
@@ -67,10 +80,438 @@ pub type Client<S: Send> = Framed<S, MessageCodec<OwnedMessage>>;
 /// The `Client` can send and receive websocket messages, and the Headers are
 /// the headers that came back from the server handshake.
 /// If the user used a protocol or attached some other headers check these response
-/// headers to see if the server accepted the protocol or other custom header.
-/// This crate will not automatically close the connection if the server refused
-/// to use the user protocols given to it, you must check that the server accepted.
+/// headers to see if the server accepted the protocol or other custom header,
+/// `Negotiated::from_headers` reads the protocol and extensions from them.
+/// Connecting fails if the server selected a protocol or extension that was not
+/// offered, but not if it refused to use the user protocols given to it, you must
+/// check that the server accepted.
 pub type ClientNew<S: Send> = Box<
 	Future<Item = (Client<S>, HeaderMap), Error = WebSocketError>
 		+ Send,
 >;
+
+/// A future sending a batch of messages, see `send_messages`.
+pub type SendMessages<S, I> = SendAll<Client<S>, IterOk<I, WebSocketError>>;
+
+/// Sends several messages at once, evaluating to the client once they were
+/// all sent.
+///
+/// The messages are encoded one after the other into the client's write
+/// buffer, which is only flushed once it is full or all messages are in,
+/// rather than after each message like `Sink::send` does.
+pub fn send_messages<S, I>(client: Client<S>, messages: I) -> SendMessages<S, I::IntoIter>
+where
+	S: AsyncRead + AsyncWrite,
+	I: IntoIterator<Item = OwnedMessage>,
+{
+	client.send_all(stream::iter_ok(messages))
+}
+
+/// Talks over `client` through a pair of channels, for code that would rather
+/// not deal with `Sink` and `Stream` directly.
+///
+/// Messages sent to the returned sender are sent to the peer, messages from
+/// the peer come out of the returned receiver. A task spawned onto the
+/// default executor moves the messages between the two, so this has to be
+/// called on a tokio runtime. Pings are answered and pongs are dropped by
+/// the task. A close from the peer is echoed and handed on, the receiver
+/// ends after it. Dropping the sender closes the connection, as does
+/// dropping the receiver. Each channel holds up to `buffer` messages.
+///
+/// ```rust,no_run
+/// # extern crate tokio;
+/// # extern crate websocket;
+/// # fn main() {
+/// use websocket::ClientBuilder;
+/// use websocket::OwnedMessage;
+/// use websocket::async::client::into_channels;
+/// use websocket::futures::{Future, Sink, Stream};
+///
+/// let chat = ClientBuilder::new("ws://127.0.0.1:1234")
+///     .unwrap()
+///     .async_connect_insecure()
+///     .map_err(|e| println!("{}", e))
+///     .and_then(|(client, _)| {
+///         let (tx, rx) = into_channels(client, 16);
+///         tx.send(OwnedMessage::Text("hello".into()))
+///             .map_err(|_| ())
+///             .and_then(|_| rx.for_each(|message| Ok(println!("{:?}", message))))
+///     });
+/// tokio::run(chat);
+/// # }
+/// ```
+pub fn into_channels<S>(
+	client: Client<S>,
+	buffer: usize,
+) -> (mpsc::Sender<OwnedMessage>, mpsc::Receiver<OwnedMessage>)
+where
+	S: AsyncRead + AsyncWrite + Send + 'static,
+{
+	let (outgoing, outgoing_rx) = mpsc::channel(buffer);
+	let (incoming_tx, incoming) = mpsc::channel(buffer);
+	let pump = Pump::new(client, outgoing_rx, incoming_tx);
+	::tokio::spawn(pump.map_err(|e| debug!("connection behind channels failed: {}", e)));
+	(outgoing, incoming)
+}
+
+/// Moves messages between a client and the channels of `into_channels`.
+struct Pump<C> {
+	client: C,
+	/// `None` once the application hung up
+	outgoing: Option<mpsc::Receiver<OwnedMessage>>,
+	/// `None` once the application stopped listening
+	incoming: Option<mpsc::Sender<OwnedMessage>>,
+	/// a pong or close to send before anything else
+	reply: Option<OwnedMessage>,
+	/// a message of the application waiting for room in the client
+	sending: Option<OwnedMessage>,
+	/// a message of the peer waiting for room in the channel
+	received: Option<OwnedMessage>,
+	close_sent: bool,
+	close_received: bool,
+}
+
+impl<C> Pump<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	fn new(
+		client: C,
+		outgoing: mpsc::Receiver<OwnedMessage>,
+		incoming: mpsc::Sender<OwnedMessage>,
+	) -> Self {
+		Pump {
+			client: client,
+			outgoing: Some(outgoing),
+			incoming: Some(incoming),
+			reply: None,
+			sending: None,
+			received: None,
+			close_sent: false,
+			close_received: false,
+		}
+	}
+
+	/// Send a close, unless one was sent already.
+	fn close(&mut self, code: CloseCode) {
+		if !self.close_sent {
+			self.close_sent = true;
+			self.reply = Some(CloseFrame::new(code, "").into());
+		}
+	}
+
+	/// Move a message of the application into the client, gives whether
+	/// anything happened.
+	fn send(&mut self) -> Result<bool, WebSocketError> {
+		if let Some(reply) = self.reply.take() {
+			if let AsyncSink::NotReady(reply) = self.client.start_send(reply)? {
+				self.reply = Some(reply);
+				return Ok(false);
+			}
+			return Ok(true);
+		}
+		if self.close_sent {
+			return Ok(false);
+		}
+		if self.sending.is_none() {
+			let polled = match self.outgoing {
+				Some(ref mut outgoing) => outgoing.poll(),
+				None => return Ok(false),
+			};
+			match polled {
+				Ok(Async::Ready(Some(message))) => self.sending = Some(message),
+				Ok(Async::NotReady) => return Ok(false),
+				Ok(Async::Ready(None)) | Err(()) => {
+					self.outgoing = None;
+					self.close(CloseCode::Normal);
+					return Ok(true);
+				}
+			}
+		}
+		let message = self.sending.take().expect("a message to send");
+		let close = message.is_close();
+		if let AsyncSink::NotReady(message) = self.client.start_send(message)? {
+			self.sending = Some(message);
+			return Ok(false);
+		}
+		self.close_sent = close;
+		Ok(true)
+	}
+
+	/// Move a message of the peer into the channel, gives whether anything
+	/// happened.
+	fn deliver(&mut self) -> Result<bool, WebSocketError> {
+		if let Some(message) = self.received.take() {
+			let sent = match self.incoming {
+				Some(ref mut incoming) => {
+					incoming.start_send(message).and_then(|sent| incoming.poll_complete().map(|_| sent))
+				}
+				None => return Ok(true),
+			};
+			match sent {
+				Ok(AsyncSink::Ready) => return Ok(true),
+				Ok(AsyncSink::NotReady(message)) => {
+					self.received = Some(message);
+					return Ok(false);
+				}
+				Err(_) => {
+					self.incoming = None;
+					self.close(CloseCode::Normal);
+					return Ok(true);
+				}
+			}
+		}
+		if self.close_received || self.reply.is_some() {
+			return Ok(false);
+		}
+		match self.client.poll()? {
+			Async::Ready(Some(OwnedMessage::Ping(data))) => self.reply = Some(OwnedMessage::Pong(data)),
+			Async::Ready(Some(OwnedMessage::Pong(_))) => {}
+			Async::Ready(Some(message)) => {
+				if let Some(close) = CloseFrame::from_message(&message) {
+					let echo = close.echo();
+					if !self.close_sent {
+						self.close_sent = true;
+						self.reply = Some(echo);
+					}
+					self.close_received = true;
+				}
+				self.received = Some(message);
+			}
+			Async::Ready(None) => {
+				// the connection is gone, there is nobody left to close it with
+				self.close_received = true;
+				self.close_sent = true;
+				self.reply = None;
+			}
+			Async::NotReady => return Ok(false),
+		}
+		Ok(true)
+	}
+}
+
+impl<C> Future for Pump<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = ();
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<(), WebSocketError> {
+		loop {
+			let sent = self.send()?;
+			let delivered = self.deliver()?;
+			if !sent && !delivered {
+				break;
+			}
+		}
+		let flushed = self.client.poll_complete()?.is_ready();
+		// the closing handshake is done once both closes went through
+		let done = self.close_received && self.close_sent && self.reply.is_none() &&
+			self.received.is_none();
+		if done && flushed {
+			return Ok(Async::Ready(()));
+		}
+		Ok(Async::NotReady)
+	}
+}
+
+/// Abandons futures from the outside, e.g. a connect the user stopped
+/// waiting for, see `cancellable`.
+///
+/// Cloning is cheap, all clones cancel the same futures.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+	inner: Arc<CancelInner>,
+}
+
+#[derive(Default)]
+struct CancelInner {
+	cancelled: AtomicBool,
+	/// the tasks of the futures waiting on the token
+	tasks: Mutex<Vec<Task>>,
+}
+
+impl CancelToken {
+	/// A token that was not cancelled yet.
+	pub fn new() -> Self {
+		CancelToken::default()
+	}
+
+	/// Cancel the futures of this token, they fail with
+	/// `WebSocketError::Cancelled` the next time they are polled, which
+	/// happens right away.
+	pub fn cancel(&self) {
+		self.inner.cancelled.store(true, Ordering::SeqCst);
+		for task in self.inner.tasks.lock().unwrap().drain(..) {
+			task.notify();
+		}
+	}
+
+	/// Whether the token was cancelled.
+	pub fn is_cancelled(&self) -> bool {
+		self.inner.cancelled.load(Ordering::SeqCst)
+	}
+
+	fn register(&self) {
+		let mut tasks = self.inner.tasks.lock().unwrap();
+		if !tasks.iter().any(Task::will_notify_current) {
+			tasks.push(task::current());
+		}
+	}
+}
+
+impl fmt::Debug for CancelToken {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("CancelToken").field("cancelled", &self.is_cancelled()).finish()
+	}
+}
+
+/// Let `token` cancel `future`.
+///
+/// A cancelled future is dropped right away, for a connect this closes the
+/// connection it was making. Dropping a connect future works just as well
+/// if it is at hand, tokens are for cancelling from elsewhere.
+///
+/// ```rust,no_run
+/// # extern crate tokio;
+/// # extern crate websocket;
+/// # fn main() {
+/// use websocket::ClientBuilder;
+/// use websocket::async::client::{cancellable, CancelToken};
+/// use websocket::futures::Future;
+///
+/// let token = CancelToken::new();
+/// let connect = cancellable(
+///     ClientBuilder::new("ws://127.0.0.1:1234").unwrap().async_connect_insecure(),
+///     &token,
+/// );
+///
+/// // when the user navigates away
+/// token.cancel();
+/// # tokio::run(connect.map(|_| ()).map_err(|_| ()));
+/// # }
+/// ```
+pub fn cancellable<F>(future: F, token: &CancelToken) -> Cancellable<F>
+where
+	F: Future<Error = WebSocketError>,
+{
+	Cancellable {
+		future: Some(future),
+		token: token.clone(),
+	}
+}
+
+/// A future that can be cancelled, see `cancellable`.
+pub struct Cancellable<F> {
+	future: Option<F>,
+	token: CancelToken,
+}
+
+impl<F> Future for Cancellable<F>
+where
+	F: Future<Error = WebSocketError>,
+{
+	type Item = F::Item;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<F::Item, WebSocketError> {
+		if !self.token.is_cancelled() {
+			self.token.register();
+			// the token might have been cancelled before the task was registered
+			if !self.token.is_cancelled() {
+				if let Some(ref mut future) = self.future {
+					return future.poll();
+				}
+			}
+		}
+		self.future = None;
+		Err(WebSocketError::Cancelled)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{future, StartSend};
+
+	/// A client whose peer sends `incoming` and then waits.
+	struct Peer {
+		sent: Vec<OwnedMessage>,
+		incoming: Vec<OwnedMessage>,
+	}
+
+	impl Stream for Peer {
+		type Item = OwnedMessage;
+		type Error = WebSocketError;
+
+		fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+			if self.incoming.is_empty() {
+				return Ok(Async::NotReady);
+			}
+			Ok(Async::Ready(Some(self.incoming.remove(0))))
+		}
+	}
+
+	impl Sink for Peer {
+		type SinkItem = OwnedMessage;
+		type SinkError = WebSocketError;
+
+		fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+			self.sent.push(item);
+			Ok(AsyncSink::Ready)
+		}
+
+		fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+			Ok(Async::Ready(()))
+		}
+	}
+
+	#[test]
+	fn pump_answers_pings_and_closes() {
+		future::lazy(|| {
+			let (outgoing, outgoing_rx) = mpsc::channel(4);
+			let (incoming_tx, incoming) = mpsc::channel(4);
+			let peer = Peer {
+				sent: Vec::new(),
+				incoming: vec![
+					OwnedMessage::Ping(vec![1]),
+					OwnedMessage::Text("hi".into()),
+					OwnedMessage::Close(None),
+				],
+			};
+			let mut pump = Pump::new(peer, outgoing_rx, incoming_tx);
+			let outgoing = outgoing.send(OwnedMessage::Binary(vec![2])).wait().unwrap();
+			assert!(pump.poll().unwrap().is_ready());
+			assert_eq!(
+				pump.client.sent,
+				vec![
+					OwnedMessage::Binary(vec![2]),
+					OwnedMessage::Pong(vec![1]),
+					OwnedMessage::Close(None),
+				]
+			);
+			drop((pump, outgoing));
+			let received: Vec<_> = incoming.collect().wait().unwrap();
+			assert_eq!(received, vec![OwnedMessage::Text("hi".into()), OwnedMessage::Close(None)]);
+			Ok::<(), ()>(())
+		}).wait()
+		  .unwrap();
+	}
+
+	#[test]
+	fn cancelled_futures_fail() {
+		let token = CancelToken::new();
+		let done = cancellable(future::ok::<u8, WebSocketError>(1), &token);
+		assert_eq!(done.wait().unwrap(), 1);
+
+		let pending = cancellable(future::empty::<u8, WebSocketError>(), &token);
+		token.cancel();
+		match pending.wait() {
+			Err(WebSocketError::Cancelled) => {}
+			other => panic!("expected a cancellation, got {:?}", other),
+		}
+	}
+}
@@ -0,0 +1,473 @@
+//! Resolving host names and connecting to one of their addresses.
+//!
+//! Hosts often have both IPv6 and IPv4 addresses and some of them may not
+//! be reachable, so the addresses are tried in turns of both families as
+//! RFC 8305 ("Happy Eyeballs") describes. Async connections race their
+//! attempts, starting the next one whenever the previous is slow to succeed.
+use std::net::SocketAddr;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::io;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::net::IpAddr;
+
+#[cfg(feature = "sync")]
+use std::net::TcpStream;
+
+#[cfg(any(feature = "sync", feature = "async"))]
+use net2::TcpBuilder;
+
+use stream::TcpOptions;
+use super::resolve::SharedResolver;
+#[cfg(any(feature = "sync", feature = "async"))]
+use super::resolve::Resolver;
+
+#[cfg(feature = "async")]
+pub use self::async::*;
+
+/// How the sockets of client connections are set up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SocketOptions {
+	/// the address to bind to before connecting
+	pub local_addr: Option<SocketAddr>,
+	/// the network interface to bind to, Linux only
+	pub device: Option<String>,
+	/// the options to set on the socket once it is connected
+	pub tcp: TcpOptions,
+	/// what looks up the addresses of host names
+	pub resolver: SharedResolver,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl SocketOptions {
+	/// Whether a connection to `addr` can be made with these options.
+	fn allows(&self, addr: &SocketAddr) -> bool {
+		self.local_addr.map_or(true, |local| local.is_ipv4() == addr.is_ipv4())
+	}
+
+	/// Create a socket to connect to `addr` with.
+	fn socket(&self, addr: &SocketAddr) -> io::Result<TcpBuilder> {
+		let socket = if addr.is_ipv4() {
+			TcpBuilder::new_v4()?
+		} else {
+			TcpBuilder::new_v6()?
+		};
+		if let Some(ref device) = self.device {
+			bind_device(&socket, device)?;
+		}
+		if let Some(local_addr) = self.local_addr {
+			socket.bind(local_addr)?;
+		}
+		Ok(socket)
+	}
+}
+
+#[cfg(all(target_os = "linux", any(feature = "sync", feature = "async")))]
+fn bind_device(socket: &TcpBuilder, device: &str) -> io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+	use libc;
+
+	let result = unsafe {
+		libc::setsockopt(
+			socket.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_BINDTODEVICE,
+			device.as_ptr() as *const libc::c_void,
+			device.len() as libc::socklen_t,
+		)
+	};
+	if result == -1 {
+		Err(io::Error::last_os_error())
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(all(not(target_os = "linux"), any(feature = "sync", feature = "async")))]
+fn bind_device(_socket: &TcpBuilder, _device: &str) -> io::Result<()> {
+	Err(io::Error::new(
+		io::ErrorKind::Other,
+		"binding to a device is only supported on Linux",
+	))
+}
+
+/// Look up the addresses of `host`, IP addresses are used as they are.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn lookup(host: &str, port: u16, resolver: &Resolver) -> io::Result<Vec<SocketAddr>> {
+	match literal(host, port) {
+		Some(addr) => Ok(vec![addr]),
+		None => resolver.resolve(host, port),
+	}
+}
+
+/// The address of a host that is an IP address.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn literal(host: &str, port: u16) -> Option<SocketAddr> {
+	// IPv6 hosts of urls come in brackets
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	host.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, port))
+}
+
+/// Order addresses to alternate between IPv6 and IPv4, starting with the
+/// family of the first one, leaving out those the options don't allow.
+#[cfg(any(feature = "sync", feature = "async"))]
+fn interleave(mut addrs: Vec<SocketAddr>, options: &SocketOptions) -> Vec<SocketAddr> {
+	addrs.retain(|addr| options.allows(addr));
+	let len = addrs.len();
+	let first_v6 = addrs.first().map_or(false, SocketAddr::is_ipv6);
+	let (preferred, other): (Vec<_>, Vec<_>) =
+		addrs.into_iter().partition(|addr| addr.is_ipv6() == first_v6);
+
+	let mut preferred = preferred.into_iter();
+	let mut other = other.into_iter();
+	let mut interleaved = Vec::with_capacity(len);
+	loop {
+		match (preferred.next(), other.next()) {
+			(None, None) => return interleaved,
+			(a, b) => {
+				interleaved.extend(a);
+				interleaved.extend(b);
+			}
+		}
+	}
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+fn no_addresses() -> io::Error {
+	io::Error::new(io::ErrorKind::NotFound, "host has no addresses")
+}
+
+/// Connect to `host`, trying its addresses one after the other until one of
+/// them accepts the connection.
+#[cfg(feature = "sync")]
+pub fn connect(host: &str, port: u16, options: &SocketOptions) -> io::Result<TcpStream> {
+	let mut last_error = None;
+	for addr in interleave(lookup(host, port, &options.resolver)?, options) {
+		debug!("connecting to {}", addr);
+		let connected = options.socket(&addr).and_then(|socket| socket.connect(addr));
+		match connected {
+			Ok(stream) => {
+				options.tcp.apply(&stream)?;
+				return Ok(stream);
+			}
+			Err(e) => {
+				debug!("connecting to {} failed: {}", addr, e);
+				last_error = Some(e);
+			}
+		}
+	}
+	Err(last_error.unwrap_or_else(no_addresses))
+}
+
+#[cfg(feature = "async")]
+mod async {
+	use std::io;
+	use std::net::SocketAddr;
+	use std::thread;
+	use std::time::{Duration, Instant};
+	use std::vec;
+
+	use futures::{Async, Future, Poll};
+	use futures::sync::oneshot;
+	use tokio::net::{ConnectFuture, TcpStream};
+	use tokio::reactor::Handle;
+	use tokio::timer::Delay;
+
+	use super::{interleave, literal, lookup, no_addresses, SocketOptions};
+	use super::super::resolve::SharedResolver;
+
+	/// How long an attempt may take before the next one is started
+	/// alongside it, as recommended by RFC 8305.
+	const ATTEMPT_DELAY_MS: u64 = 250;
+
+	type Addrs = io::Result<Vec<SocketAddr>>;
+
+	/// Look up the addresses of `host`.
+	///
+	/// The lookup happens on a thread of its own so the event loop is not
+	/// blocked waiting for DNS.
+	pub fn resolve(host: &str, port: u16, resolver: &SharedResolver) -> Resolve {
+		if let Some(addr) = literal(host, port) {
+			return Resolve::Done(Some(Ok(vec![addr])));
+		}
+
+		let (tx, rx) = oneshot::channel();
+		let host = host.to_string();
+		let resolver = resolver.clone();
+		let spawned = thread::Builder::new()
+			.name("websocket-resolver".to_string())
+			.spawn(move || {
+				// nobody might be waiting anymore
+				let _ = tx.send(lookup(&host, port, &resolver));
+			});
+		match spawned {
+			Ok(_) => Resolve::Pending(rx),
+			Err(e) => Resolve::Done(Some(Err(e))),
+		}
+	}
+
+	/// The addresses of a host, see `resolve`.
+	pub enum Resolve {
+		Done(Option<Addrs>),
+		Pending(oneshot::Receiver<Addrs>),
+	}
+
+	impl Future for Resolve {
+		type Item = Vec<SocketAddr>;
+		type Error = io::Error;
+
+		fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+			match *self {
+				Resolve::Done(ref mut addrs) => {
+					addrs.take().expect("cannot poll Resolve twice").map(Async::Ready)
+				}
+				Resolve::Pending(ref mut rx) => {
+					match rx.poll() {
+						Ok(Async::Ready(addrs)) => addrs.map(Async::Ready),
+						Ok(Async::NotReady) => Ok(Async::NotReady),
+						Err(_) => {
+							Err(io::Error::new(io::ErrorKind::Other, "the resolver thread died"))
+						}
+					}
+				}
+			}
+		}
+	}
+
+	/// Connect to `host`, racing attempts to its addresses.
+	///
+	/// Whenever an attempt fails or has not succeeded within 250ms, one to the
+	/// next address is started, the first connection made wins.
+	pub fn async_connect(host: &str, port: u16, options: &SocketOptions, handle: &Handle) -> Connect {
+		Connect {
+			options: options.clone(),
+			handle: handle.clone(),
+			resolving: Some(resolve(host, port, &options.resolver)),
+			addrs: Vec::new().into_iter(),
+			attempts: Vec::new(),
+			next_attempt: None,
+			last_error: None,
+		}
+	}
+
+	/// A connection to the first address of a host that works, see
+	/// `async_connect`.
+	pub struct Connect {
+		options: SocketOptions,
+		handle: Handle,
+		resolving: Option<Resolve>,
+		addrs: vec::IntoIter<SocketAddr>,
+		attempts: Vec<ConnectFuture>,
+		next_attempt: Option<Delay>,
+		last_error: Option<io::Error>,
+	}
+
+	impl Connect {
+		fn attempt(&self, addr: &SocketAddr) -> io::Result<ConnectFuture> {
+			let socket = self.options.socket(addr)?.to_tcp_stream()?;
+			// the socket is not connected yet, applying the options now
+			// saves having to wait for it
+			self.options.tcp.apply(&socket)?;
+			Ok(TcpStream::connect_std(socket, addr, &self.handle))
+		}
+	}
+
+	impl Future for Connect {
+		type Item = TcpStream;
+		type Error = io::Error;
+
+		fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+			if let Some(mut resolving) = self.resolving.take() {
+				match resolving.poll()? {
+					Async::Ready(addrs) => {
+						self.addrs = interleave(addrs, &self.options).into_iter()
+					}
+					Async::NotReady => {
+						self.resolving = Some(resolving);
+						return Ok(Async::NotReady);
+					}
+				}
+			}
+
+			loop {
+				let mut failed = false;
+				let mut i = 0;
+				while i < self.attempts.len() {
+					match self.attempts[i].poll() {
+						Ok(Async::Ready(stream)) => return Ok(Async::Ready(stream)),
+						Ok(Async::NotReady) => i += 1,
+						Err(e) => {
+							debug!("connecting failed: {}", e);
+							self.last_error = Some(e);
+							self.attempts.swap_remove(i);
+							failed = true;
+						}
+					}
+				}
+
+				// start the next attempt once one failed or the last one is
+				// taking too long
+				let due = failed || self.attempts.is_empty() || match self.next_attempt {
+					Some(ref mut delay) => {
+						delay.poll()
+						     .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+						     .is_ready()
+					}
+					None => false,
+				};
+				if !due {
+					return Ok(Async::NotReady);
+				}
+
+				match self.addrs.next() {
+					Some(addr) => {
+						debug!("connecting to {}", addr);
+						match self.attempt(&addr) {
+							Ok(attempt) => self.attempts.push(attempt),
+							Err(e) => {
+								debug!("connecting to {} failed: {}", addr, e);
+								self.last_error = Some(e);
+							}
+						}
+						let delay = Duration::from_millis(ATTEMPT_DELAY_MS);
+						self.next_attempt = Some(Delay::new(Instant::now() + delay));
+					}
+					None if self.attempts.is_empty() => {
+						return Err(self.last_error.take().unwrap_or_else(no_addresses));
+					}
+					None => {
+						self.next_attempt = None;
+						return Ok(Async::NotReady);
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn interleaves_address_families() {
+		let addrs = vec![
+			"[::1]:80".parse().unwrap(),
+			"[::2]:80".parse().unwrap(),
+			"[::3]:80".parse().unwrap(),
+			"127.0.0.1:80".parse().unwrap(),
+		];
+		let expected: Vec<SocketAddr> = vec![
+			"[::1]:80".parse().unwrap(),
+			"127.0.0.1:80".parse().unwrap(),
+			"[::2]:80".parse().unwrap(),
+			"[::3]:80".parse().unwrap(),
+		];
+		assert_eq!(interleave(addrs, &SocketOptions::default()), expected);
+
+		let options = SocketOptions {
+			local_addr: Some("0.0.0.0:0".parse().unwrap()),
+			..SocketOptions::default()
+		};
+		let addrs = vec!["[::1]:80".parse().unwrap(), "127.0.0.1:80".parse().unwrap()];
+		assert_eq!(interleave(addrs, &options), vec!["127.0.0.1:80".parse().unwrap()]);
+	}
+
+	#[test]
+	fn uses_ip_addresses_as_they_are() {
+		let resolver = SharedResolver::default();
+		assert_eq!(lookup("[::1]", 80, &resolver).unwrap(), vec!["[::1]:80".parse().unwrap()]);
+		assert_eq!(lookup("10.0.0.1", 80, &resolver).unwrap(), vec!["10.0.0.1:80".parse().unwrap()]);
+	}
+
+	#[test]
+	#[cfg(feature = "sync")]
+	fn connections_get_the_socket_options() {
+		use std::net::TcpListener;
+		use std::time::Duration;
+		use net2::TcpStreamExt;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let options = SocketOptions {
+			local_addr: Some("127.0.0.1:0".parse().unwrap()),
+			tcp: TcpOptions {
+				nodelay: Some(true),
+				keepalive: Some(Duration::from_secs(60)),
+				..TcpOptions::default()
+			},
+			..SocketOptions::default()
+		};
+
+		let stream = connect("127.0.0.1", port, &options).unwrap();
+		let (accepted, _) = listener.accept().unwrap();
+		assert_eq!(accepted.peer_addr().unwrap(), stream.local_addr().unwrap());
+		assert!(stream.nodelay().unwrap());
+		assert_eq!(TcpStreamExt::keepalive(&stream).unwrap(), Some(Duration::from_secs(60)));
+
+		// a local address of the other family leaves nothing to connect to
+		let options = SocketOptions {
+			local_addr: Some("[::1]:0".parse().unwrap()),
+			..SocketOptions::default()
+		};
+		assert!(connect("127.0.0.1", port, &options).is_err());
+	}
+
+	#[test]
+	#[cfg(all(feature = "sync", target_os = "linux"))]
+	fn connections_are_bound_to_the_device() {
+		use std::net::TcpListener;
+		use std::os::unix::io::AsRawFd;
+		use libc;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let options = SocketOptions {
+			device: Some("lo".to_string()),
+			..SocketOptions::default()
+		};
+		let stream = connect("127.0.0.1", port, &options).unwrap();
+
+		let mut device = [0u8; 16];
+		let mut len = device.len() as libc::socklen_t;
+		let result = unsafe {
+			libc::getsockopt(
+				stream.as_raw_fd(),
+				libc::SOL_SOCKET,
+				libc::SO_BINDTODEVICE,
+				device.as_mut_ptr() as *mut libc::c_void,
+				&mut len,
+			)
+		};
+		assert_eq!(result, 0);
+		assert_eq!(device[..len as usize].split(|b| *b == 0).next(), Some(&b"lo"[..]));
+
+		let options = SocketOptions {
+			device: Some("no-such-device".to_string()),
+			..SocketOptions::default()
+		};
+		assert!(connect("127.0.0.1", port, &options).is_err());
+	}
+
+	#[test]
+	#[cfg(feature = "async")]
+	fn async_connections_get_the_socket_options() {
+		use std::net::TcpListener;
+		use tokio::reactor::Handle;
+		use tokio::runtime::Runtime;
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let options = SocketOptions {
+			tcp: TcpOptions {
+				nodelay: Some(true),
+				..TcpOptions::default()
+			},
+			..SocketOptions::default()
+		};
+
+		let mut runtime = Runtime::new().unwrap();
+		let connect = async_connect("127.0.0.1", port, &options, &Handle::default());
+		let stream = runtime.block_on(connect).unwrap();
+		assert!(stream.nodelay().unwrap());
+	}
+}
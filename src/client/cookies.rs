@@ -0,0 +1,287 @@
+//! Remembering the cookies servers set, like a browser does.
+//!
+//! A `CookieJar` given to a `ClientBuilder` takes the `Set-Cookie` headers of
+//! the handshake responses and sends the cookies back in the `Cookie` header
+//! of later handshakes with matching hosts and paths. This is what session
+//! authenticated endpoints expect.
+//!
+//! Cookies without a `Max-Age` live as long as the jar does, the `Expires`
+//! attribute is not looked at.
+//!
+//! A server may set cookies for the domains it is in, but not for a top
+//! level domain like `com` and only for itself if it is an IP address. The
+//! jar has no list of public suffixes, so a server could still set cookies
+//! for e.g. `co.uk`.
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cookie {
+	name: String,
+	value: String,
+	domain: String,
+	host_only: bool,
+	path: String,
+	secure: bool,
+	expires: Option<Instant>,
+}
+
+impl Cookie {
+	/// Parse a `Set-Cookie` header received from `url`.
+	fn parse(url: &Url, header: &str, now: Instant) -> Option<Self> {
+		let host = url.host_str()?.to_lowercase();
+		let mut parts = header.split(';');
+		let (name, value) = split_pair(parts.next()?)?;
+		if name.is_empty() {
+			return None;
+		}
+		let mut cookie = Cookie {
+			name: name.to_string(),
+			value: value.to_string(),
+			domain: host.clone(),
+			host_only: true,
+			path: default_path(url),
+			secure: false,
+			expires: None,
+		};
+		for attribute in parts {
+			let (key, value) = split_pair(attribute).unwrap_or((attribute.trim(), ""));
+			match &*key.to_lowercase() {
+				"domain" if !value.is_empty() => {
+					let domain = value.trim_start_matches('.').to_lowercase();
+					// a server may only set cookies for itself and its parents
+					if !domain_matches(&host, &domain) {
+						return None;
+					}
+					// but not for a whole top level domain
+					if !domain.contains('.') && domain != host {
+						return None;
+					}
+					if domain != host {
+						cookie.domain = domain;
+						cookie.host_only = false;
+					}
+				}
+				"path" if value.starts_with('/') => cookie.path = value.to_string(),
+				"secure" => cookie.secure = true,
+				"max-age" => {
+					if let Ok(secs) = value.parse::<i64>() {
+						cookie.expires = Some(if secs <= 0 {
+							now
+						} else {
+							now + Duration::from_secs(secs as u64)
+						});
+					}
+				}
+				_ => {}
+			}
+		}
+		Some(cookie)
+	}
+
+	fn is_expired(&self, now: Instant) -> bool {
+		self.expires.map_or(false, |expires| expires <= now)
+	}
+
+	/// Whether this cookie goes along with a request to `url`.
+	fn matches(&self, url: &Url) -> bool {
+		let host = match url.host_str() {
+			Some(host) => host.to_lowercase(),
+			None => return false,
+		};
+		let host_ok = if self.host_only {
+			host == self.domain
+		} else {
+			domain_matches(&host, &self.domain)
+		};
+		host_ok && path_matches(url.path(), &self.path) && (!self.secure || url.scheme() == "wss")
+	}
+}
+
+fn split_pair(pair: &str) -> Option<(&str, &str)> {
+	let eq = pair.find('=')?;
+	Some((pair[..eq].trim(), pair[eq + 1..].trim()))
+}
+
+/// Whether `host` is `domain` or in it. IP addresses are only in themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+	if host == domain {
+		return true;
+	}
+	let is_ip = host.starts_with('[') || host.parse::<IpAddr>().is_ok();
+	!is_ip && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.')
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+	path == cookie_path ||
+		(path.starts_with(cookie_path) &&
+			 (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The directory of the path of `url`, which cookies without a `Path` are
+/// limited to.
+fn default_path(url: &Url) -> String {
+	let path = url.path();
+	match path.rfind('/') {
+		Some(0) | None => "/".to_string(),
+		Some(i) => path[..i].to_string(),
+	}
+}
+
+/// The cookies servers set during handshakes.
+///
+/// Cloning is cheap, all clones share the same cookies, so a jar can be kept
+/// around to look at the cookies after it was given to a `ClientBuilder`.
+///
+/// ```rust
+/// use websocket::client::CookieJar;
+/// use websocket::url::Url;
+///
+/// let jar = CookieJar::new();
+/// let url = Url::parse("wss://chat.example.com/rooms/1").unwrap();
+/// jar.set_cookie(&url, "session=abc123; Path=/; Secure");
+///
+/// assert_eq!(jar.cookie_header(&url), Some("session=abc123".to_string()));
+/// ```
+#[derive(Clone, Default)]
+pub struct CookieJar {
+	cookies: Arc<Mutex<Vec<Cookie>>>,
+}
+
+impl CookieJar {
+	/// An empty jar.
+	pub fn new() -> Self {
+		CookieJar::default()
+	}
+
+	/// Store the cookie of a `Set-Cookie` header received from `url`,
+	/// replacing the cookie of the same name, domain and path. Malformed
+	/// headers and cookies for other domains are ignored.
+	pub fn set_cookie(&self, url: &Url, header: &str) {
+		let now = Instant::now();
+		let cookie = match Cookie::parse(url, header, now) {
+			Some(cookie) => cookie,
+			None => {
+				// the value is a secret, only say which cookie it was
+				let name = header.split(|c| c == ';' || c == '=').next().unwrap_or("").trim();
+				debug!("ignoring cookie {} from {}", name, url.host_str().unwrap_or(""));
+				return;
+			}
+		};
+		let mut cookies = self.lock();
+		cookies.retain(|old| {
+			!(old.name == cookie.name && old.domain == cookie.domain && old.path == cookie.path) &&
+				!old.is_expired(now)
+		});
+		if !cookie.is_expired(now) {
+			cookies.push(cookie);
+		}
+	}
+
+	/// The value of the `Cookie` header to send to `url`, if any cookies
+	/// go along with it. Cookies with longer paths come first.
+	pub fn cookie_header(&self, url: &Url) -> Option<String> {
+		let now = Instant::now();
+		let mut cookies = self.lock();
+		cookies.retain(|cookie| !cookie.is_expired(now));
+		let mut matching: Vec<&Cookie> = cookies.iter().filter(|c| c.matches(url)).collect();
+		if matching.is_empty() {
+			return None;
+		}
+		matching.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+		let pairs: Vec<String> = matching
+			.iter()
+			.map(|c| format!("{}={}", c.name, c.value))
+			.collect();
+		Some(pairs.join("; "))
+	}
+
+	/// The number of cookies in the jar.
+	pub fn len(&self) -> usize {
+		self.lock().len()
+	}
+
+	/// Whether the jar has no cookies.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Throw away all cookies.
+	pub fn clear(&self) {
+		self.lock().clear();
+	}
+
+	fn lock(&self) -> MutexGuard<Vec<Cookie>> {
+		// the list of cookies is left consistent even if a holder panicked
+		match self.cookies.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+}
+
+impl fmt::Debug for CookieJar {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "CookieJar({})", self.len())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn url(s: &str) -> Url {
+		Url::parse(s).unwrap()
+	}
+
+	#[test]
+	fn scopes_cookies_by_domain_and_path() {
+		let jar = CookieJar::new();
+		let origin = url("ws://api.example.com/chat/room");
+		jar.set_cookie(&origin, "a=1");
+		jar.set_cookie(&origin, "b=2; Domain=.example.com; Path=/");
+		jar.set_cookie(&origin, "c=3; Domain=other.com");
+		jar.set_cookie(&origin, "d=4; Secure; Path=/");
+		assert_eq!(jar.len(), 3);
+
+		assert_eq!(jar.cookie_header(&origin), Some("a=1; b=2".to_string()));
+		assert_eq!(jar.cookie_header(&url("ws://www.example.com/")), Some("b=2".to_string()));
+		assert_eq!(
+			jar.cookie_header(&url("wss://api.example.com/chat")),
+			Some("a=1; b=2; d=4".to_string())
+		);
+		assert_eq!(jar.cookie_header(&url("ws://api.example.com/chatter")), Some("b=2".to_string()));
+		assert_eq!(jar.cookie_header(&url("ws://example.org/")), None);
+	}
+
+	#[test]
+	fn replaces_and_expires_cookies() {
+		let jar = CookieJar::new();
+		let origin = url("ws://example.com/");
+		jar.set_cookie(&origin, "session=old");
+		jar.set_cookie(&origin, "session=new");
+		assert_eq!(jar.cookie_header(&origin), Some("session=new".to_string()));
+		jar.set_cookie(&origin, "session=; Max-Age=0");
+		assert!(jar.is_empty());
+		assert_eq!(jar.cookie_header(&origin), None);
+	}
+
+	#[test]
+	fn refuses_cookies_for_other_sites() {
+		let jar = CookieJar::new();
+		jar.set_cookie(&url("ws://api.example.com/"), "a=1; Domain=com");
+		jar.set_cookie(&url("ws://127.0.0.1/"), "b=2; Domain=0.0.1");
+		jar.set_cookie(&url("ws://[::1]/"), "c=3; Domain=1]");
+		assert!(jar.is_empty());
+
+		jar.set_cookie(&url("ws://127.0.0.1/"), "d=4; Domain=127.0.0.1");
+		jar.set_cookie(&url("ws://localhost/"), "e=5; Domain=localhost");
+		assert_eq!(jar.cookie_header(&url("ws://127.0.0.1/")), Some("d=4".to_string()));
+		assert_eq!(jar.cookie_header(&url("ws://localhost/")), Some("e=5".to_string()));
+		assert_eq!(jar.cookie_header(&url("ws://a.localhost/")), None);
+	}
+}
@@ -16,8 +16,21 @@
 pub mod builder;
 pub use self::builder::{ClientBuilder, Url, ParseError};
 
+pub mod cookies;
+pub use self::cookies::CookieJar;
+
+pub mod negotiated;
+pub use self::negotiated::Negotiated;
+
+pub mod resolve;
+pub use self::resolve::Resolver;
+
+pub mod state;
+pub use self::state::{ConnectionState, StateWatch};
+
 #[cfg(feature = "async")]
 pub mod async;
+mod connect;
 
 #[cfg(feature = "sync")]
 pub mod sync;
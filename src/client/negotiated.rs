@@ -0,0 +1,120 @@
+//! What the client and server agreed on during the handshake.
+use http::header::HeaderMap;
+use http::header::SEC_WEBSOCKET_PROTOCOL;
+
+use header::HeaderMapExt;
+use header::sec_websocket_extensions::{Extension, WebSocketExtensions};
+use result::{WebSocketError, WebSocketResult};
+
+/// The protocol and extensions a server selected in its handshake response.
+///
+/// Synchronous clients keep this around, see `Client::negotiated_protocol`.
+/// Asynchronous clients can get it from the headers they connected with:
+///
+/// ```rust,no_run
+/// # extern crate websocket;
+/// # fn main() {
+/// use websocket::ClientBuilder;
+/// use websocket::client::Negotiated;
+/// use websocket::futures::Future;
+///
+/// let connect = ClientBuilder::new("ws://127.0.0.1:1234")
+///     .unwrap()
+///     .add_protocols(vec!["chat.v2", "chat.v1"])
+///     .async_connect_insecure()
+///     .map(|(client, headers)| {
+///         let negotiated = Negotiated::from_headers(&headers);
+///         println!("speaking {:?}", negotiated.protocol());
+///     });
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Negotiated {
+	protocol: Option<String>,
+	extensions: Vec<Extension>,
+}
+
+impl Negotiated {
+	/// Read the protocol and extensions from the headers of a handshake
+	/// response.
+	pub fn from_headers(headers: &HeaderMap) -> Self {
+		let protocol = headers
+			.get(SEC_WEBSOCKET_PROTOCOL)
+			.and_then(|value| value.to_str().ok())
+			.map(|value| value.trim().to_string())
+			.filter(|value| !value.is_empty());
+		Negotiated {
+			protocol: protocol,
+			extensions: extensions(headers),
+		}
+	}
+
+	/// The subprotocol the server selected, if any.
+	pub fn protocol(&self) -> Option<&str> {
+		self.protocol.as_ref().map(|p| &p[..])
+	}
+
+	/// The extensions the server selected, in the order they apply.
+	pub fn extensions(&self) -> &[Extension] {
+		&self.extensions
+	}
+
+	/// Check the server only selected what the client offered in `request`.
+	#[doc(hidden)]
+	pub fn check_offered(&self, request: &HeaderMap) -> WebSocketResult<()> {
+		if let Some(ref protocol) = self.protocol {
+			let offered = request
+				.get_all(SEC_WEBSOCKET_PROTOCOL)
+				.iter()
+				.filter_map(|value| value.to_str().ok())
+				.flat_map(|value| value.split(','))
+				.any(|offer| offer.trim() == protocol);
+			if !offered {
+				return Err(WebSocketError::ResponseError(
+					"Server selected a protocol that was not offered",
+				));
+			}
+		}
+		let offered = extensions(request);
+		for extension in &self.extensions {
+			if !offered.iter().any(|offer| offer.name == extension.name) {
+				return Err(WebSocketError::ResponseError(
+					"Server selected an extension that was not offered",
+				));
+			}
+		}
+		Ok(())
+	}
+}
+
+fn extensions(headers: &HeaderMap) -> Vec<Extension> {
+	headers.typed_get::<WebSocketExtensions>().map(|e| e.0).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::header::{HeaderValue, SEC_WEBSOCKET_EXTENSIONS};
+
+	fn headers(protocol: &'static str, extensions: &'static str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocol));
+		headers.insert(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static(extensions));
+		headers
+	}
+
+	#[test]
+	fn checks_selection_against_offer() {
+		let request = headers("chat.v2, chat.v1", "permessage-deflate; client_max_window_bits");
+		let response = Negotiated::from_headers(&headers("chat.v1", "permessage-deflate"));
+		assert_eq!(response.protocol(), Some("chat.v1"));
+		assert_eq!(response.extensions()[0].name, "permessage-deflate");
+		assert!(response.check_offered(&request).is_ok());
+
+		let response = Negotiated::from_headers(&headers("chat.v3", ""));
+		assert!(response.check_offered(&request).is_err());
+		let response = Negotiated::from_headers(&headers("chat.v2", "x-webkit-deflate-frame"));
+		assert!(response.check_offered(&request).is_err());
+		assert!(Negotiated::from_headers(&HeaderMap::new()).check_offered(&request).is_ok());
+	}
+}
@@ -0,0 +1,295 @@
+//! Looking up the addresses of the hosts clients connect to.
+//!
+//! By default host names are resolved by the system, like
+//! `std::net::ToSocketAddrs` does. `ClientBuilder::resolver` takes any other
+//! `Resolver`, e.g. one asking a DNS server of its own, or `StaticResolver`
+//! to send a host name to a specific backend.
+//!
+//! Clients that reconnect often can keep the addresses they looked up around
+//! with a `CachingResolver`.
+//!
+//! ```rust
+//! use websocket::ClientBuilder;
+//! use websocket::client::resolve::StaticResolver;
+//!
+//! let resolver = StaticResolver::new().host("chat.example.com", vec!["10.0.0.7".parse().unwrap()]);
+//! let builder = ClientBuilder::new("ws://chat.example.com").unwrap().resolver(resolver);
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Looks up the addresses of a host.
+///
+/// Resolving may block, asynchronous connects call it on a thread of its
+/// own. IP addresses are never given to a resolver, they are used as they
+/// are.
+pub trait Resolver: Send + Sync {
+	/// The addresses of `host` with `port`, in the order they should be
+	/// tried in.
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// Resolves host names with the system's resolver.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		Ok((host, port).to_socket_addrs()?.collect())
+	}
+}
+
+/// Gives fixed addresses for some hosts and asks another resolver about the
+/// rest, like entries in `/etc/hosts` that only apply to one client.
+#[derive(Clone)]
+pub struct StaticResolver {
+	hosts: HashMap<String, Vec<IpAddr>>,
+	fallback: SharedResolver,
+}
+
+impl StaticResolver {
+	/// A resolver without fixed hosts, resolving everything with the system.
+	pub fn new() -> Self {
+		StaticResolver::with_fallback(SystemResolver)
+	}
+
+	/// A resolver without fixed hosts, resolving everything with
+	/// `fallback`.
+	pub fn with_fallback<R>(fallback: R) -> Self
+	where
+		R: Resolver + 'static,
+	{
+		StaticResolver {
+			hosts: HashMap::new(),
+			fallback: SharedResolver::new(fallback),
+		}
+	}
+
+	/// Resolve `host` to `addrs`. Host names are matched ignoring case.
+	pub fn host<H>(mut self, host: H, addrs: Vec<IpAddr>) -> Self
+	where
+		H: Into<String>,
+	{
+		self.hosts.insert(host.into().to_lowercase(), addrs);
+		self
+	}
+}
+
+impl Default for StaticResolver {
+	fn default() -> Self {
+		StaticResolver::new()
+	}
+}
+
+impl Resolver for StaticResolver {
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		match self.hosts.get(&host.to_lowercase()) {
+			Some(ips) => Ok(ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect()),
+			None => self.fallback.resolve(host, port),
+		}
+	}
+}
+
+impl fmt::Debug for StaticResolver {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("StaticResolver").field("hosts", &self.hosts).finish()
+	}
+}
+
+/// How long a `CachingResolver` keeps addresses, unless told otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Remembers the addresses another resolver gave for a while, so clients
+/// reconnecting over and over don't look the host up every time.
+///
+/// The system's resolver does not tell how long its answers are valid, so
+/// addresses are kept for a fixed time. Failed lookups are not kept. Clones
+/// share their cache, a clone kept around can drop addresses that stopped
+/// working:
+///
+/// ```rust
+/// use std::time::Duration;
+/// use websocket::ClientBuilder;
+/// use websocket::client::resolve::CachingResolver;
+///
+/// let cache = CachingResolver::new().with_ttl(Duration::from_secs(300));
+/// let builder = ClientBuilder::new("ws://feed.example.com").unwrap().resolver(cache.clone());
+///
+/// // after failing over to another data center
+/// cache.invalidate("feed.example.com");
+/// ```
+#[derive(Clone)]
+pub struct CachingResolver {
+	inner: SharedResolver,
+	ttl: Duration,
+	cache: Arc<Mutex<Cache>>,
+}
+
+/// addresses and when they expire, by host and port
+type Cache = HashMap<(String, u16), (Vec<SocketAddr>, Instant)>;
+
+impl CachingResolver {
+	/// Cache the answers of the system's resolver for `DEFAULT_TTL`.
+	pub fn new() -> Self {
+		CachingResolver::wrap(SystemResolver)
+	}
+
+	/// Cache the answers of `inner` for `DEFAULT_TTL`.
+	pub fn wrap<R>(inner: R) -> Self
+	where
+		R: Resolver + 'static,
+	{
+		CachingResolver {
+			inner: SharedResolver::new(inner),
+			ttl: DEFAULT_TTL,
+			cache: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// Keep addresses for `ttl` instead.
+	pub fn with_ttl(mut self, ttl: Duration) -> Self {
+		self.ttl = ttl;
+		self
+	}
+
+	/// Forget the addresses of `host`, the next connect looks it up again.
+	pub fn invalidate(&self, host: &str) {
+		let host = host.to_lowercase();
+		self.lock().retain(|&(ref cached, _), _| *cached != host);
+	}
+
+	/// Forget all addresses.
+	pub fn clear(&self) {
+		self.lock().clear();
+	}
+
+	fn lock(&self) -> MutexGuard<Cache> {
+		// the cache stays consistent even if a holder of the lock panicked
+		match self.cache.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+}
+
+impl Default for CachingResolver {
+	fn default() -> Self {
+		CachingResolver::new()
+	}
+}
+
+impl Resolver for CachingResolver {
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		let key = (host.to_lowercase(), port);
+		if let Some(&(ref addrs, expires)) = self.lock().get(&key) {
+			if Instant::now() < expires {
+				return Ok(addrs.clone());
+			}
+		}
+		// the lock is not held while resolving, which may take a while
+		let addrs = self.inner.resolve(host, port)?;
+		self.lock().insert(key, (addrs.clone(), Instant::now() + self.ttl));
+		Ok(addrs)
+	}
+}
+
+impl fmt::Debug for CachingResolver {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("CachingResolver").field("ttl", &self.ttl).finish()
+	}
+}
+
+/// A resolver that can be cloned into the threads resolving for
+/// asynchronous connects.
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct SharedResolver(Arc<Resolver>);
+
+impl SharedResolver {
+	/// Share `resolver`.
+	pub fn new<R>(resolver: R) -> Self
+	where
+		R: Resolver + 'static,
+	{
+		SharedResolver(Arc::new(resolver))
+	}
+}
+
+impl Default for SharedResolver {
+	fn default() -> Self {
+		SharedResolver::new(SystemResolver)
+	}
+}
+
+impl Resolver for SharedResolver {
+	fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+		self.0.resolve(host, port)
+	}
+}
+
+impl PartialEq for SharedResolver {
+	fn eq(&self, other: &SharedResolver) -> bool {
+		Arc::ptr_eq(&self.0, &other.0)
+	}
+}
+
+impl fmt::Debug for SharedResolver {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("Resolver")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn static_hosts_come_first() {
+		let ip: IpAddr = "10.0.0.7".parse().unwrap();
+		let resolver = StaticResolver::new().host("Chat.Example.com", vec![ip]);
+		assert_eq!(
+			resolver.resolve("chat.example.COM", 443).unwrap(),
+			vec![SocketAddr::new(ip, 443)]
+		);
+
+		let nested = StaticResolver::with_fallback(resolver).host("other", vec![]);
+		assert_eq!(nested.resolve("chat.example.com", 80).unwrap(), vec![SocketAddr::new(ip, 80)]);
+		assert!(nested.resolve("other", 80).unwrap().is_empty());
+	}
+
+	/// Counts how often it was asked.
+	struct Counting(Arc<Mutex<usize>>);
+
+	impl Resolver for Counting {
+		fn resolve(&self, _: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+			*self.0.lock().unwrap() += 1;
+			Ok(vec![SocketAddr::new("10.0.0.1".parse().unwrap(), port)])
+		}
+	}
+
+	#[test]
+	fn caches_until_invalidated() {
+		let asked = Arc::new(Mutex::new(0));
+		let cache = CachingResolver::wrap(Counting(asked.clone()));
+		cache.resolve("feed", 80).unwrap();
+		cache.resolve("FEED", 80).unwrap();
+		assert_eq!(*asked.lock().unwrap(), 1);
+
+		cache.resolve("feed", 443).unwrap();
+		assert_eq!(*asked.lock().unwrap(), 2);
+
+		cache.clone().invalidate("feed");
+		cache.resolve("feed", 80).unwrap();
+		assert_eq!(*asked.lock().unwrap(), 3);
+
+		let expired = cache.with_ttl(Duration::from_secs(0));
+		expired.clear();
+		expired.resolve("feed", 80).unwrap();
+		expired.resolve("feed", 80).unwrap();
+		assert_eq!(*asked.lock().unwrap(), 5);
+	}
+}
@@ -0,0 +1,229 @@
+//! Following the state of a client's connection.
+//!
+//! A `StateWatch` given to `ClientBuilder::state_watch` is told when the
+//! client starts connecting, shakes hands, is open and closes, so a user
+//! interface or a supervisor can show the connection's status without
+//! guessing it from errors. Its state can be looked at any time, functions
+//! added with `on_change` are called on every change, and asynchronous
+//! code can get the changes as a stream with `subscribe`.
+//!
+//! ```rust,no_run
+//! use websocket::ClientBuilder;
+//! use websocket::client::state::{ConnectionState, StateWatch};
+//!
+//! let watch = StateWatch::new();
+//! watch.on_change(|state| println!("connection is {:?}", state));
+//!
+//! let _client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .state_watch(watch.clone())
+//!     .connect_insecure()
+//!     .unwrap();
+//! assert_eq!(watch.get(), ConnectionState::Open);
+//! ```
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(feature = "async")]
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use middleware::{Direction, Middleware};
+
+/// Where a connection is at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+	/// Looking up the host and opening a TCP connection to it.
+	Connecting,
+	/// Setting up TLS on the TCP connection.
+	TlsHandshake,
+	/// Sent the handshake request, waiting for the server to accept it.
+	Upgrading,
+	/// Messages can be sent and received.
+	Open,
+	/// One side sent a close message, the other one has not answered yet.
+	Closing,
+	/// The connection failed, finished its closing handshake or the client
+	/// was dropped.
+	Closed,
+}
+
+/// Shares the state of a connection with whoever wants to know, see the
+/// module docs.
+///
+/// Cloning is cheap, all clones watch the same connection. A watch can be
+/// used for the connections of a client reconnecting over and over, it is
+/// `Closed` before the first one.
+#[derive(Clone)]
+pub struct StateWatch {
+	inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+	state: ConnectionState,
+	callbacks: Vec<Arc<Fn(ConnectionState) + Send + Sync>>,
+	#[cfg(feature = "async")]
+	subscribers: Vec<UnboundedSender<ConnectionState>>,
+}
+
+impl Inner {
+	#[cfg(feature = "async")]
+	fn publish(&mut self, state: ConnectionState) {
+		self.subscribers.retain(|s| s.unbounded_send(state).is_ok());
+	}
+
+	#[cfg(not(feature = "async"))]
+	fn publish(&mut self, _: ConnectionState) {}
+}
+
+impl StateWatch {
+	/// A watch of a connection that isn't there yet.
+	pub fn new() -> Self {
+		StateWatch {
+			inner: Arc::new(Mutex::new(Inner {
+				state: ConnectionState::Closed,
+				callbacks: Vec::new(),
+				#[cfg(feature = "async")]
+				subscribers: Vec::new(),
+			})),
+		}
+	}
+
+	/// The current state of the connection.
+	pub fn get(&self) -> ConnectionState {
+		self.lock().state
+	}
+
+	/// Call `callback` with the new state whenever it changes. It is called
+	/// right in the middle of connecting, reading and writing, so it should
+	/// return quickly.
+	pub fn on_change<F>(&self, callback: F)
+	where
+		F: Fn(ConnectionState) + Send + Sync + 'static,
+	{
+		self.lock().callbacks.push(Arc::new(callback));
+	}
+
+	/// A stream of the states of the connection, starting with the current
+	/// one. Dropping it is fine, it just stops getting them.
+	#[cfg(feature = "async")]
+	pub fn subscribe(&self) -> UnboundedReceiver<ConnectionState> {
+		let (sender, receiver) = mpsc::unbounded();
+		let mut inner = self.lock();
+		let _ = sender.unbounded_send(inner.state);
+		inner.subscribers.push(sender);
+		receiver
+	}
+
+	/// Move to `state`, telling everyone if it changed.
+	#[doc(hidden)]
+	pub fn set(&self, state: ConnectionState) {
+		let callbacks = {
+			let mut inner = self.lock();
+			if inner.state == state {
+				return;
+			}
+			inner.state = state;
+			inner.publish(state);
+			inner.callbacks.clone()
+		};
+		// the lock is not held while calling out, callbacks may look at the state
+		for callback in callbacks {
+			callback(state);
+		}
+	}
+
+	/// The middleware telling this watch about the closing handshake of a
+	/// connection that just opened, and that it closed once it is dropped.
+	#[doc(hidden)]
+	pub fn opened(&self) -> StateLayer {
+		self.set(ConnectionState::Open);
+		StateLayer {
+			watch: self.clone(),
+			closes: Mutex::new((false, false)),
+		}
+	}
+
+	fn lock(&self) -> MutexGuard<Inner> {
+		// the state stays consistent even if a callback panicked
+		match self.inner.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+}
+
+impl Default for StateWatch {
+	fn default() -> Self {
+		StateWatch::new()
+	}
+}
+
+impl fmt::Debug for StateWatch {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("StateWatch").field("state", &self.get()).finish()
+	}
+}
+
+/// Follows the closing handshake of an open connection for a `StateWatch`.
+#[doc(hidden)]
+pub struct StateLayer {
+	watch: StateWatch,
+	/// whether a close was sent and received
+	closes: Mutex<(bool, bool)>,
+}
+
+impl Middleware for StateLayer {
+	fn on_close(&self, direction: Direction, _: Option<u16>, _: &str) {
+		let both = {
+			let mut closes = match self.closes.lock() {
+				Ok(guard) => guard,
+				Err(poisoned) => poisoned.into_inner(),
+			};
+			match direction {
+				Direction::Outbound => closes.0 = true,
+				Direction::Inbound => closes.1 = true,
+			}
+			closes.0 && closes.1
+		};
+		self.watch.set(if both { ConnectionState::Closed } else { ConnectionState::Closing });
+	}
+}
+
+impl Drop for StateLayer {
+	fn drop(&mut self) {
+		self.watch.set(ConnectionState::Closed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[test]
+	fn follows_the_closing_handshake() {
+		let watch = StateWatch::new();
+		let changes = Arc::new(AtomicUsize::new(0));
+		let counter = changes.clone();
+		watch.on_change(move |_| {
+			counter.fetch_add(1, Ordering::SeqCst);
+		});
+
+		watch.set(ConnectionState::Connecting);
+		watch.set(ConnectionState::Connecting);
+		let layer = watch.opened();
+		assert_eq!(watch.get(), ConnectionState::Open);
+
+		layer.on_close(Direction::Inbound, Some(1000), "");
+		assert_eq!(watch.get(), ConnectionState::Closing);
+		layer.on_close(Direction::Outbound, Some(1000), "");
+		assert_eq!(watch.get(), ConnectionState::Closed);
+		drop(layer);
+		assert_eq!(changes.load(Ordering::SeqCst), 4);
+
+		// a client that is dropped while open is closed as well
+		let layer = watch.opened();
+		drop(layer);
+		assert_eq!(watch.get(), ConnectionState::Closed);
+	}
+}
@@ -1,10 +1,11 @@
 //! Contains the WebSocket client.
-use std::net::TcpStream;
 use std::net::SocketAddr;
 use std::io::Result as IoResult;
-use std::io::{Read, Write};
+use std::io::{self, BufRead, Read, Write};
 use std::str::{self, FromStr};
+use std::time::{Duration, Instant};
 
+use bytes::BytesMut;
 use http::header::HeaderMap;
 use http::header::{SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL};
 use std::io::BufReader;
@@ -13,17 +14,39 @@ use ws;
 use ws::sender::Sender as SenderTrait;
 use ws::receiver::{DataFrameIterator, MessageIterator};
 use ws::receiver::Receiver as ReceiverTrait;
-use message::OwnedMessage;
+use message::{CloseData, OwnedMessage};
+use close::{CloseGuard, Incoming};
+use events::MessageEvent;
+use pool::BufferPool;
+use middleware::Middlewares;
+use pause::ReadPause;
+use ping::{PendingPong, Pings};
+use progress::ProgressCallback;
+use stats::ConnectionStats;
+use ws::util::mask::KeySource;
+use server::ratelimit::{RateLimit, POLICY_VIOLATION};
+use server::limit::ConnectionPermit;
 use result::WebSocketResult;
+#[cfg(feature = "serde")]
+use result::WebSocketError;
+#[cfg(feature = "serde")]
+use serializer::{Json, MessageSerializer};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+use server::watchdog::{self, Watchdog, WatchdogState, Verdict};
 use stream::sync::{AsTcpStream, Stream, Splittable, Shutdown};
-use dataframe::DataFrame;
+use dataframe::{DataFrame, Opcode, ReservedClaims};
+use ws::util::header::DataFrameFlags;
 use ws::dataframe::DataFrame as DataFrameable;
-use sender::Sender;
-use receiver::Receiver;
+use sender::{send_before, PartialSend, Sender};
+use receiver::{Receiver, Received};
 pub use sender::Writer;
 pub use receiver::Reader;
 
 use header::sec_websocket_extensions::Extension;
+use client::negotiated::Negotiated;
 
 /// Represents a WebSocket client, which can send and receive messages/data frames.
 ///
@@ -35,7 +58,8 @@ use header::sec_websocket_extensions::Extension;
 /// to different threads, often using a send loop and receiver loop concurrently,
 /// as shown in the client example in `examples/client.rs`.
 /// This is only possible for streams that implement the `Splittable` trait, which
-/// currently is only TCP streams. (it is unsafe to duplicate an SSL stream)
+/// are TCP streams, SSL streams and the boxed streams `ClientBuilder::connect`
+/// returns. The halves of an SSL stream take turns on it, see `SharedStream`.
 ///
 ///# Connecting to a Server
 ///
@@ -62,9 +86,18 @@ where
 	headers: HeaderMap,
 	sender: Sender,
 	receiver: Receiver,
+	watchdog: Option<WatchdogState>,
+	negotiated: Negotiated,
+	auto_close: Option<CloseGuard>,
+	middleware: Middlewares,
+	stats: Option<ConnectionStats>,
+	pings: Option<Pings>,
 }
 
-impl Client<TcpStream> {
+impl<S> Client<S>
+where
+	S: AsTcpStream + Stream,
+{
 	/// Shuts down the sending half of the client connection, will cause all pending
 	/// and future IO to return immediately with an appropriate value.
 	pub fn shutdown_sender(&self) -> IoResult<()> {
@@ -76,14 +109,15 @@ impl Client<TcpStream> {
 	pub fn shutdown_receiver(&self) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().shutdown(Shutdown::Read)
 	}
-}
 
-impl<S> Client<S>
-where
-	S: AsTcpStream + Stream,
-{
 	/// Shuts down the client connection, will cause all pending and future IO to
 	/// return immediately with an appropriate value.
+	///
+	/// The halves of a split client can do the same with `Writer::shutdown_all`
+	/// and `Reader::shutdown_all`, e.g. the writer after a fatal protocol
+	/// error while the reader is blocked on another thread. Either works for
+	/// every stream `split` accepts, TLS streams are shut down underneath
+	/// without a close notify.
 	pub fn shutdown(&self) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().shutdown(Shutdown::Both)
 	}
@@ -110,6 +144,90 @@ where
 	pub fn set_nonblocking(&self, nonblocking: bool) -> IoResult<()> {
 		self.stream.get_ref().as_tcp().set_nonblocking(nonblocking)
 	}
+
+	/// Sends a single message like `send_message`, failing with
+	/// `WebSocketError::Timeout` if writing it takes longer than `timeout`, so
+	/// a peer that stopped reading can't block the sending thread forever.
+	/// Returns the number of bytes written.
+	///
+	/// A send that failed tells how much of the message got written anyway.
+	/// If any of it did, the peer got part of a frame, so the connection
+	/// should be dropped. This uses the write timeout of the underlying TCP
+	/// stream, which is put back afterwards.
+	///
+	/// ```rust,no_run
+	/// # use std::time::Duration;
+	/// # use websocket::{ClientBuilder, Message};
+	/// # let mut client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	/// #     .connect_insecure().unwrap();
+	/// match client.send_message_timeout(&Message::text("tick"), Duration::from_secs(1)) {
+	///     Ok(written) => println!("sent {} bytes", written),
+	///     Err(ref partial) if partial.written == 0 => println!("nothing sent, try again later"),
+	///     Err(_) => client.shutdown().unwrap(),
+	/// }
+	/// ```
+	pub fn send_message_timeout<M>(&mut self, message: &M, timeout: Duration) -> Result<usize, PartialSend>
+	where
+		M: ws::Message,
+	{
+		let sender = &mut self.sender;
+		send_before(self.stream.get_mut(), Instant::now() + timeout, |writer| {
+			sender.send_message(writer, message)
+		})
+	}
+
+	/// Reads a single message like `recv_message`, while the client's watchdog
+	/// (see `set_watchdog`) pings the peer when the connection goes quiet.
+	/// Fails with a `TimedOut` error and shuts the connection down once the
+	/// watchdog decides the peer is gone.
+	///
+	/// This uses the read timeout of the underlying TCP stream, any read timeout
+	/// set on it beforehand is cleared.
+	pub fn recv_message_watched(&mut self) -> WebSocketResult<OwnedMessage> {
+		loop {
+			let next_check = match self.watchdog {
+				Some(ref state) => state.next_check(),
+				None => return self.recv_message(),
+			};
+
+			let now = Instant::now();
+			if next_check > now && self.wait_readable(next_check - now)? {
+				let message = self.recv_message()?;
+				if let Some(ref mut state) = self.watchdog {
+					state.saw(&message);
+				}
+				return Ok(message);
+			}
+
+			let verdict = match self.watchdog {
+				Some(ref mut state) => state.check(Instant::now()),
+				None => Verdict::Wait,
+			};
+			match verdict {
+				Verdict::Wait => {}
+				Verdict::Ping => self.send_message(&OwnedMessage::Ping(Vec::new()))?,
+				Verdict::Close => {
+					let _ = self.shutdown();
+					return Err(watchdog::timed_out());
+				}
+			}
+		}
+	}
+
+	/// Wait at most `timeout` for something to read, without consuming it.
+	fn wait_readable(&mut self, timeout: Duration) -> IoResult<bool> {
+		// a zero timeout is refused by the OS
+		let timeout = ::std::cmp::max(timeout, Duration::from_millis(1));
+		self.stream.get_ref().as_tcp().set_read_timeout(Some(timeout))?;
+		let readable = match self.stream.fill_buf() {
+			Ok(_) => Ok(true),
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+			              e.kind() == io::ErrorKind::TimedOut => Ok(false),
+			Err(e) => Err(e),
+		};
+		self.stream.get_ref().as_tcp().set_read_timeout(None)?;
+		readable
+	}
 }
 
 impl<S> Client<S>
@@ -128,11 +246,173 @@ where
 		in_mask: bool,
 	) -> Self {
 		Client {
+			negotiated: Negotiated::from_headers(&headers),
 			headers: headers,
 			stream: stream,
 			sender: Sender::new(out_mask), // true
 			receiver: Receiver::new(in_mask), // false
+			watchdog: None,
+			auto_close: None,
+			middleware: Middlewares::default(),
+			stats: None,
+			pings: None,
+		}
+	}
+
+	/// Take the buffers for incoming frames from `pool`, give the messages
+	/// back to the pool with `BufferPool::recycle` once they were handled.
+	pub fn set_buffer_pool(&mut self, pool: Option<BufferPool>) {
+		self.receiver.set_pool(pool);
+	}
+
+	/// Draw the masking keys of outgoing frames from `keys` instead of the
+	/// thread's random number generator, e.g. to make them reproducible in
+	/// tests.
+	pub fn set_key_source(&mut self, keys: Option<KeySource>) {
+		self.sender.set_key_source(keys);
+	}
+
+	/// Send text and binary messages larger than `size` bytes in fragments
+	/// of that size, see `Sender::set_fragment_size`.
+	pub fn set_fragment_size(&mut self, size: Option<usize>) {
+		self.sender.set_fragment_size(size);
+	}
+
+	/// Report the progress of the text and binary messages sent and
+	/// received, see `ProgressCallback`.
+	pub fn set_progress(&mut self, progress: Option<ProgressCallback>) {
+		self.sender.set_progress(progress.clone());
+		self.receiver.set_progress(progress);
+	}
+
+	/// Mask outgoing frames or not, see `Sender::dangerous_set_masking`.
+	pub fn dangerous_set_masking(&mut self, mask: bool) {
+		self.sender.dangerous_set_masking(mask);
+	}
+
+	/// Run every frame sent and received through this middleware.
+	///
+	/// Clients accepted by a server configured with middleware have it set already.
+	pub fn set_middleware(&mut self, middleware: Middlewares) {
+		self.middleware = middleware.clone();
+		let mut middleware = match self.auto_close {
+			Some(ref guard) => {
+				let mut layers = Middlewares::new();
+				layers.push(guard.clone());
+				layers.append(&self.middleware);
+				layers
+			}
+			None => middleware,
+		};
+		if let Some(ref stats) = self.stats {
+			middleware.push(stats.clone());
+		}
+		if let Some(ref pings) = self.pings {
+			middleware.push(pings.clone());
+		}
+		self.sender.set_middleware(middleware.clone());
+		self.receiver.set_middleware(middleware);
+	}
+
+	/// Count the messages, bytes and pings of this connection, see
+	/// `ConnectionStats`. The counting runs after the middleware.
+	pub fn set_stats(&mut self, stats: ConnectionStats) {
+		let middleware = self.middleware.clone();
+		self.stats = Some(stats);
+		self.set_middleware(middleware);
+	}
+
+	/// The counters of this connection, if it has any.
+	pub fn stats(&self) -> Option<&ConnectionStats> {
+		self.stats.as_ref()
+	}
+
+	/// Track the pings of this connection with `pings`, which `ping_with`
+	/// uses as well.
+	pub fn set_pings(&mut self, pings: Pings) {
+		let middleware = self.middleware.clone();
+		self.pings = Some(pings);
+		self.set_middleware(middleware);
+	}
+
+	/// Send a ping with `payload` and get the pong answering it, which
+	/// tells how long the peer took to answer, see the `ping` module.
+	///
+	/// The pong is only seen while reading, wait for it on another thread
+	/// than the one reading from the connection.
+	pub fn ping_with(&mut self, payload: Vec<u8>) -> WebSocketResult<PendingPong> {
+		let pings = match self.pings {
+			Some(ref pings) => pings.clone(),
+			None => {
+				let pings = Pings::new();
+				self.set_pings(pings.clone());
+				pings
+			}
+		};
+		let (ping, pong) = pings.ping(payload);
+		self.send_message(&ping)?;
+		Ok(pong)
+	}
+
+	/// Let the reserved bits and opcodes an extension claimed through the
+	/// message readers, see `ReservedClaims`.
+	pub fn set_reserved_claims(&mut self, claims: ReservedClaims) {
+		self.receiver.set_reserved_claims(claims);
+	}
+
+	/// Limit how fast messages may be received, see `RateLimit`. A peer
+	/// going over a limit with `Exceeded::Close` is sent a close message
+	/// with `POLICY_VIOLATION`.
+	///
+	/// Clients accepted by a server configured with a rate limit have it set already.
+	pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+		self.receiver.set_rate_limit(limit);
+	}
+
+	/// Count this connection against a `ConnectionLimiter` for as long as the
+	/// client (or the `Reader` half after `split`) is alive.
+	///
+	/// Clients accepted by a server configured with a limiter have it set already.
+	pub fn set_permit(&mut self, permit: Option<ConnectionPermit>) {
+		self.receiver.set_permit(permit);
+	}
+
+	/// Tell the peer it went over its rate limit if a read failed because
+	/// of that.
+	fn enforce_rate_limit<T>(&mut self, result: WebSocketResult<T>) -> WebSocketResult<T> {
+		if result.is_err() && self.receiver.take_rate_close() {
+			let close = CloseData::new(POLICY_VIOLATION, String::new());
+			// the peer is being kicked out, it's fine if it doesn't get the message
+			let _ = self.send_message(&OwnedMessage::Close(Some(close)));
 		}
+		result
+	}
+
+	/// Watch this connection with a watchdog, which takes effect when
+	/// receiving messages with `recv_message_watched`.
+	///
+	/// Clients accepted by a server configured with a watchdog have it set already.
+	pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+		self.watchdog = watchdog.map(Watchdog::start);
+	}
+
+	/// Stop reading from the connection until `resume_reading` is called,
+	/// so a peer sending faster than the messages are handled is slowed
+	/// down by TCP. The receive methods wait in the meantime, see the
+	/// `pause` module.
+	pub fn pause_reading(&self) {
+		self.receiver.read_pause().pause();
+	}
+
+	/// Go on reading from the connection.
+	pub fn resume_reading(&self) {
+		self.receiver.read_pause().resume();
+	}
+
+	/// A handle to pause and resume the reading from another thread, it
+	/// stays with the reader when the client is split.
+	pub fn read_pause(&self) -> ReadPause {
+		self.receiver.read_pause()
 	}
 
 	/// Sends a single data frame to the remote endpoint.
@@ -151,9 +431,39 @@ where
 		self.sender.send_message(self.stream.get_mut(), message)
 	}
 
+	/// Sends several messages to the remote endpoint at once.
+	///
+	/// ```rust,no_run
+	/// # use websocket::{ClientBuilder, Message};
+	/// # let mut client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	/// #     .connect_insecure().unwrap();
+	/// let ticks: Vec<_> = (0..1000).map(|i| Message::text(i.to_string())).collect();
+	/// // a single write instead of a thousand
+	/// client.send_messages(&ticks).unwrap();
+	/// ```
+	pub fn send_messages<'m, M, I>(&mut self, messages: I) -> WebSocketResult<()>
+	where
+		M: ws::Message + 'm,
+		I: IntoIterator<Item = &'m M>,
+	{
+		self.sender.send_messages(self.stream.get_mut(), messages)
+	}
+
+	/// Sends a frame exactly as given, bypassing message assembly and the
+	/// middleware. It is still masked. See `Sender::send_raw_dataframe`.
+	pub fn send_raw_dataframe(
+		&mut self,
+		flags: DataFrameFlags,
+		opcode: Opcode,
+		payload: &[u8],
+	) -> WebSocketResult<()> {
+		self.sender.send_raw_dataframe(self.stream.get_mut(), flags, opcode, payload)
+	}
+
 	/// Reads a single data frame from the remote endpoint.
 	pub fn recv_dataframe(&mut self) -> WebSocketResult<DataFrame> {
-		self.receiver.recv_dataframe(&mut self.stream)
+		let result = self.receiver.recv_dataframe(&mut self.stream);
+		self.enforce_rate_limit(result)
 	}
 
 	/// Returns an iterator over incoming data frames.
@@ -175,7 +485,165 @@ where
 	/// let response = client.recv_message().unwrap();
 	/// ```
 	pub fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
-		self.receiver.recv_message(&mut self.stream)
+		let result = self.receiver.recv_message(&mut self.stream);
+		self.enforce_rate_limit(result)
+	}
+
+	/// Echo close messages from the peer when they are read with
+	/// `recv_incoming` or `incoming`, see the `close` module. Off by default.
+	///
+	/// With auto-close on, sending anything but the echo after a close was
+	/// received, or anything at all after a close was sent, fails with
+	/// `WebSocketError::ConnectionClosed`, also on the halves of a split
+	/// client.
+	pub fn set_auto_close(&mut self, auto_close: bool) {
+		if auto_close == self.auto_close.is_some() {
+			return;
+		}
+		let middleware = self.middleware.clone();
+		self.auto_close = if auto_close { Some(CloseGuard::new()) } else { None };
+		self.set_middleware(middleware);
+	}
+
+	/// Reads a single message, handing out a close message as a
+	/// `CloseFrame`. With auto-close on, the close is echoed before it is
+	/// returned, unless it answers a close sent before.
+	pub fn recv_incoming(&mut self) -> WebSocketResult<Incoming> {
+		let incoming = Incoming::from(self.recv_message()?);
+		if let Incoming::Close(ref close) = incoming {
+			let echo = match self.auto_close {
+				Some(ref guard) => !guard.sent(),
+				None => false,
+			};
+			if echo {
+				self.send_message(&close.echo())?;
+			}
+		}
+		Ok(incoming)
+	}
+
+	/// Returns an iterator over `recv_incoming`, which ends after the close
+	/// message or the first error.
+	pub fn incoming(&mut self) -> Incomings<S> {
+		Incomings {
+			client: self,
+			done: false,
+		}
+	}
+
+	/// Reads a single message, appending its payload to `buf` instead of
+	/// allocating a new message. Handy for receive loops that reuse one buffer.
+	///
+	/// Control messages are returned as `Received::Control`, leaving `buf`
+	/// alone. See `Receiver::recv_message_into_buf` for the details.
+	///
+	/// ```rust,no_run
+	/// # extern crate bytes;
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use bytes::BytesMut;
+	/// use websocket::{ClientBuilder, OwnedMessage};
+	/// use websocket::receiver::Received;
+	///
+	/// let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+	///     .unwrap()
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let mut buf = BytesMut::with_capacity(4096);
+	/// loop {
+	///     match client.recv_message_into_buf(&mut buf).unwrap() {
+	///         Received::Control(OwnedMessage::Close(_)) => break,
+	///         Received::Control(_) => continue,
+	///         Received::Text | Received::Binary => {
+	///             // handle the message ...
+	///             buf.clear();
+	///         }
+	///     }
+	/// }
+	/// # }
+	/// ```
+	pub fn recv_message_into_buf(&mut self, buf: &mut BytesMut) -> WebSocketResult<Received> {
+		let result = self.receiver.recv_message_into_buf(&mut self.stream, buf);
+		self.enforce_rate_limit(result)
+	}
+
+	/// Reads a single text message, appending it to `text`, like
+	/// `recv_message_into_buf` does. Receiving a binary message is an error.
+	pub fn recv_text_into(&mut self, text: &mut String) -> WebSocketResult<Received> {
+		let result = self.receiver.recv_text_into(&mut self.stream, text);
+		self.enforce_rate_limit(result)
+	}
+
+	/// Reads the next piece of the messages received, handing out the
+	/// payload of every fragment as soon as it arrives, see the `events`
+	/// module and `Receiver::recv_event`.
+	pub fn recv_event(&mut self) -> WebSocketResult<MessageEvent> {
+		let result = self.receiver.recv_event(&mut self.stream);
+		self.enforce_rate_limit(result)
+	}
+
+	/// Returns an iterator over `recv_event`, which ends after a close
+	/// message or the first error.
+	pub fn incoming_events(&mut self) -> Events<S> {
+		Events {
+			client: self,
+			done: false,
+		}
+	}
+
+	/// Sends `value` serialized to JSON as a text message.
+	#[cfg(feature = "serde")]
+	pub fn send_json<T>(&mut self, value: &T) -> WebSocketResult<()>
+	where
+		T: Serialize + ?Sized,
+	{
+		self.send_as::<Json, T>(value)
+	}
+
+	/// Reads the next text or binary message and deserializes it from JSON,
+	/// see `recv_as`.
+	#[cfg(feature = "serde")]
+	pub fn recv_json<T>(&mut self) -> WebSocketResult<T>
+	where
+		T: DeserializeOwned,
+	{
+		self.recv_as::<Json, T>()
+	}
+
+	/// Sends `value` serialized with the format `F`.
+	#[cfg(feature = "serde")]
+	pub fn send_as<F, T>(&mut self, value: &T) -> WebSocketResult<()>
+	where
+		F: MessageSerializer,
+		T: Serialize + ?Sized,
+	{
+		let message = F::serialize(value)?;
+		self.send_message(&message)
+	}
+
+	/// Reads the next text or binary message and deserializes it with the
+	/// format `F`.
+	///
+	/// Pings are answered and pongs are skipped while waiting for it. A
+	/// close message from the peer gives `WebSocketError::NoDataAvailable`.
+	#[cfg(feature = "serde")]
+	pub fn recv_as<F, T>(&mut self) -> WebSocketResult<T>
+	where
+		F: MessageSerializer,
+		T: DeserializeOwned,
+	{
+		loop {
+			let message = self.recv_message()?;
+			if let Some(value) = F::deserialize(&message) {
+				return value;
+			}
+			match message {
+				OwnedMessage::Ping(data) => self.send_message(&OwnedMessage::Pong(data))?,
+				OwnedMessage::Close(_) => return Err(WebSocketError::NoDataAvailable),
+				_ => {}
+			}
+		}
 	}
 
 	/// Access the headers that were sent in the server's handshake response.
@@ -238,6 +706,28 @@ where
 		    .unwrap_or(vec![])
 	}
 
+	/// The subprotocol the server selected in the handshake, which is always
+	/// one of those the client offered.
+	///
+	/// ```rust,no_run
+	/// # use websocket::ClientBuilder;
+	/// let client = ClientBuilder::new("wss://test.fysh.in").unwrap()
+	///     .add_protocols(vec!["chat.v2", "chat.v1"])
+	///     .connect_insecure()
+	///     .unwrap();
+	///
+	/// let legacy = client.negotiated_protocol() == Some("chat.v1");
+	/// ```
+	pub fn negotiated_protocol(&self) -> Option<&str> {
+		self.negotiated.protocol()
+	}
+
+	/// The extensions the server selected in the handshake, which are always
+	/// among those the client offered.
+	pub fn negotiated_extensions(&self) -> &[Extension] {
+		self.negotiated.extensions()
+	}
+
 	/// Get a reference to the stream.
 	/// Useful to be able to set options on the stream.
 	///
@@ -406,4 +896,152 @@ where
 			},
 		))
 	}
+
+	/// Put the halves `split` gave back together into a client, e.g. to
+	/// close the connection from one place or hand the stream off.
+	///
+	/// The halves are handed back if they don't belong to the same
+	/// connection, or if the reader still has data buffered that was not
+	/// read as a message yet, in which case read the pending messages first.
+	/// The handshake headers are not kept by the halves, the reunited
+	/// client has none. Its middleware and counters keep running, but
+	/// `stats` forgets about them until they are set again.
+	///
+	///```no_run
+	///# extern crate websocket;
+	///# fn main() {
+	///use std::net::TcpStream;
+	///use websocket::ClientBuilder;
+	///use websocket::sync::Client;
+	///
+	///let client = ClientBuilder::new("ws://127.0.0.1:1234").unwrap()
+	///                     .connect_insecure().unwrap();
+	///let (receiver, sender) = client.split().unwrap();
+	///// ... read and write on different threads ...
+	///let client: Client<TcpStream> = Client::reunite(receiver, sender).ok().unwrap();
+	///client.shutdown().unwrap();
+	///# }
+	///```
+	pub fn reunite(
+		reader: Reader<<S as Splittable>::Reader>,
+		writer: Writer<<S as Splittable>::Writer>,
+	) -> Result<Self, (Reader<<S as Splittable>::Reader>, Writer<<S as Splittable>::Writer>)> {
+		if !reader.stream.buffer().is_empty() {
+			return Err((reader, writer));
+		}
+		let Reader { stream, receiver } = reader;
+		let Writer { stream: write, sender } = writer;
+		match S::reunite(stream.into_inner(), write) {
+			Ok(stream) => Ok(Client {
+				stream: BufReader::new(stream),
+				headers: HeaderMap::new(),
+				sender: sender,
+				receiver: receiver,
+				watchdog: None,
+				negotiated: Negotiated::default(),
+				auto_close: None,
+				middleware: Middlewares::default(),
+				stats: None,
+				pings: None,
+			}),
+			Err((read, write)) => Err((
+				Reader {
+					stream: BufReader::new(read),
+					receiver: receiver,
+				},
+				Writer {
+					stream: write,
+					sender: sender,
+				},
+			)),
+		}
+	}
+}
+
+/// An iterator over the messages of a client up to its close, see
+/// `Client::incoming`.
+pub struct Incomings<'a, S>
+where
+	S: Stream + 'a,
+{
+	client: &'a mut Client<S>,
+	done: bool,
+}
+
+impl<'a, S> Iterator for Incomings<'a, S>
+where
+	S: Stream,
+{
+	type Item = WebSocketResult<Incoming>;
+
+	fn next(&mut self) -> Option<WebSocketResult<Incoming>> {
+		if self.done {
+			return None;
+		}
+		let incoming = self.client.recv_incoming();
+		self.done = match incoming {
+			Ok(Incoming::Close(_)) | Err(_) => true,
+			Ok(Incoming::Message(_)) => false,
+		};
+		Some(incoming)
+	}
+}
+
+/// An iterator over the pieces of the messages of a client up to its close,
+/// see `Client::incoming_events`.
+pub struct Events<'a, S>
+where
+	S: Stream + 'a,
+{
+	client: &'a mut Client<S>,
+	done: bool,
+}
+
+impl<'a, S> Iterator for Events<'a, S>
+where
+	S: Stream,
+{
+	type Item = WebSocketResult<MessageEvent>;
+
+	fn next(&mut self) -> Option<WebSocketResult<MessageEvent>> {
+		if self.done {
+			return None;
+		}
+		let event = self.client.recv_event();
+		self.done = match event {
+			Ok(MessageEvent::Control(ref message)) => message.is_close(),
+			Ok(_) => false,
+			Err(_) => true,
+		};
+		Some(event)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::{TcpListener, TcpStream};
+	use std::thread;
+	use result::WebSocketError;
+	use stream::sync::NetworkStream;
+
+	#[test]
+	fn writer_shutdown_wakes_the_reader() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		// the peer never sends anything
+		let (_peer, _) = listener.accept().unwrap();
+
+		let stream: Box<NetworkStream + Send> = Box::new(stream);
+		let client = Client::unchecked(BufReader::new(stream), HeaderMap::new(), true, false);
+		let (mut reader, writer) = client.split().unwrap();
+		let read = thread::spawn(move || reader.recv_message());
+
+		thread::sleep(Duration::from_millis(50));
+		writer.shutdown_all().unwrap();
+		match read.join().unwrap() {
+			Err(WebSocketError::NoDataAvailable) | Err(WebSocketError::IoError(_)) => {}
+			other => panic!("expected the read to fail, got {:?}", other),
+		}
+	}
 }
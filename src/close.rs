@@ -0,0 +1,444 @@
+//! Telling close messages apart from the rest of the conversation.
+//!
+//! A close message ends the conversation, but `OwnedMessage` hands it out
+//! like any other message with its status code buried in an optional
+//! `CloseData`. The readers here give back an `Incoming`, which is either a
+//! message or the `CloseFrame` that ended the conversation, with the status
+//! code as a `CloseCode`.
+//!
+//! With auto-close on, the close is echoed to the peer before it is handed
+//! out, completing the closing handshake of RFC 6455. The echo carries the
+//! status code the peer sent, and is left out if a close was sent already.
+//! Once the close was received only the echo can be sent, once a close was
+//! sent nothing can, anything else fails with
+//! `WebSocketError::ConnectionClosed` rather than breaking the protocol.
+//!
+//! ```rust,no_run
+//! use websocket::ClientBuilder;
+//! use websocket::close::Incoming;
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//! client.set_auto_close(true);
+//!
+//! for incoming in client.incoming() {
+//!     match incoming.unwrap() {
+//!         Incoming::Message(message) => println!("{:?}", message),
+//!         Incoming::Close(close) => println!("closed: {:?} {}", close.code, close.reason),
+//!     }
+//! }
+//! ```
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use dataframe::{DataFrame, Opcode};
+use message::{CloseData, OwnedMessage};
+use middleware::{Direction, Middleware};
+use result::{WebSocketError, WebSocketResult};
+
+#[cfg(feature = "async")]
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+/// The status code of a close message, see section 7.4 of RFC 6455.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CloseCode {
+	/// 1000, the connection did what it was made for.
+	Normal,
+	/// 1001, the endpoint is going away, e.g. a server shutting down.
+	GoingAway,
+	/// 1002, the peer broke the protocol.
+	ProtocolError,
+	/// 1003, the endpoint can't take this type of data.
+	Unsupported,
+	/// 1005, the close message had no status code. Never sent.
+	NoStatus,
+	/// 1006, the connection was lost without a close message. Never sent.
+	Abnormal,
+	/// 1007, a message's data did not match its type, e.g. text that is not
+	/// UTF-8.
+	InvalidPayload,
+	/// 1008, a message broke the endpoint's policy.
+	PolicyViolation,
+	/// 1009, a message was too big to handle.
+	TooBig,
+	/// 1010, the server did not agree to an extension the client needs.
+	MandatoryExtension,
+	/// 1011, the server ran into an error.
+	InternalError,
+	/// 1012, the server is restarting.
+	ServiceRestart,
+	/// 1013, the server is overloaded, try again later.
+	TryAgainLater,
+	/// 1015, the TLS handshake failed. Never sent.
+	TlsHandshake,
+	/// Any other code, e.g. one from the 4000-4999 range kept for
+	/// applications.
+	Other(u16),
+}
+
+impl CloseCode {
+	/// Whether the code may be sent in a close message, the codes that
+	/// stand for the lack of one may not.
+	pub fn is_sendable(&self) -> bool {
+		match *self {
+			CloseCode::NoStatus | CloseCode::Abnormal | CloseCode::TlsHandshake => false,
+			_ => true,
+		}
+	}
+}
+
+impl From<u16> for CloseCode {
+	fn from(code: u16) -> CloseCode {
+		match code {
+			1000 => CloseCode::Normal,
+			1001 => CloseCode::GoingAway,
+			1002 => CloseCode::ProtocolError,
+			1003 => CloseCode::Unsupported,
+			1005 => CloseCode::NoStatus,
+			1006 => CloseCode::Abnormal,
+			1007 => CloseCode::InvalidPayload,
+			1008 => CloseCode::PolicyViolation,
+			1009 => CloseCode::TooBig,
+			1010 => CloseCode::MandatoryExtension,
+			1011 => CloseCode::InternalError,
+			1012 => CloseCode::ServiceRestart,
+			1013 => CloseCode::TryAgainLater,
+			1015 => CloseCode::TlsHandshake,
+			code => CloseCode::Other(code),
+		}
+	}
+}
+
+impl From<CloseCode> for u16 {
+	fn from(code: CloseCode) -> u16 {
+		match code {
+			CloseCode::Normal => 1000,
+			CloseCode::GoingAway => 1001,
+			CloseCode::ProtocolError => 1002,
+			CloseCode::Unsupported => 1003,
+			CloseCode::NoStatus => 1005,
+			CloseCode::Abnormal => 1006,
+			CloseCode::InvalidPayload => 1007,
+			CloseCode::PolicyViolation => 1008,
+			CloseCode::TooBig => 1009,
+			CloseCode::MandatoryExtension => 1010,
+			CloseCode::InternalError => 1011,
+			CloseCode::ServiceRestart => 1012,
+			CloseCode::TryAgainLater => 1013,
+			CloseCode::TlsHandshake => 1015,
+			CloseCode::Other(code) => code,
+		}
+	}
+}
+
+/// A close message with its status code and reason.
+///
+/// A close message without a status code has `CloseCode::NoStatus` and an
+/// empty reason.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame<'a> {
+	/// Why the connection is closed.
+	pub code: CloseCode,
+	/// The reason given for closing, meant for people rather than programs.
+	pub reason: Cow<'a, str>,
+}
+
+impl<'a> CloseFrame<'a> {
+	/// A close with `code` and `reason`.
+	pub fn new<R>(code: CloseCode, reason: R) -> Self
+	where
+		R: Into<Cow<'a, str>>,
+	{
+		CloseFrame {
+			code: code,
+			reason: reason.into(),
+		}
+	}
+
+	/// Read the close out of `message`, gives `None` for other messages.
+	pub fn from_message(message: &'a OwnedMessage) -> Option<Self> {
+		match *message {
+			OwnedMessage::Close(Some(ref data)) => {
+				Some(CloseFrame::new(data.status_code.into(), data.reason.as_str()))
+			}
+			OwnedMessage::Close(None) => Some(CloseFrame::new(CloseCode::NoStatus, "")),
+			_ => None,
+		}
+	}
+
+	/// Copy the reason if it was borrowed.
+	pub fn into_owned(self) -> CloseFrame<'static> {
+		CloseFrame {
+			code: self.code,
+			reason: Cow::Owned(self.reason.into_owned()),
+		}
+	}
+
+	/// The close message echoing this one back, with the same status code
+	/// and no reason.
+	pub fn echo(&self) -> OwnedMessage {
+		CloseFrame::new(self.code, "").into()
+	}
+}
+
+impl<'a> From<CloseFrame<'a>> for OwnedMessage {
+	/// A close with a code that can't be sent becomes a close message
+	/// without a status code.
+	fn from(close: CloseFrame<'a>) -> OwnedMessage {
+		if !close.code.is_sendable() {
+			return OwnedMessage::Close(None);
+		}
+		OwnedMessage::Close(Some(CloseData::new(close.code.into(), close.reason.into_owned())))
+	}
+}
+
+/// A message, or the close that ended the conversation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Incoming {
+	/// A text, binary, ping or pong message.
+	Message(OwnedMessage),
+	/// The peer closed the connection, nothing comes after this.
+	Close(CloseFrame<'static>),
+}
+
+impl From<OwnedMessage> for Incoming {
+	fn from(message: OwnedMessage) -> Incoming {
+		match message {
+			OwnedMessage::Close(Some(data)) => {
+				Incoming::Close(CloseFrame::new(data.status_code.into(), data.reason))
+			}
+			OwnedMessage::Close(None) => Incoming::Close(CloseFrame::new(CloseCode::NoStatus, "")),
+			message => Incoming::Message(message),
+		}
+	}
+}
+
+/// Keeps track of the closes sent and received for auto-close, refusing to
+/// send what may not be sent anymore.
+///
+/// Cloning is cheap, all clones follow the same connection.
+#[doc(hidden)]
+#[derive(Clone, Default, Debug)]
+pub struct CloseGuard {
+	inner: Arc<Closes>,
+}
+
+#[derive(Default, Debug)]
+struct Closes {
+	sent: AtomicBool,
+	received: AtomicBool,
+}
+
+impl CloseGuard {
+	/// A connection that did not start closing yet.
+	pub fn new() -> Self {
+		CloseGuard::default()
+	}
+
+	/// Whether a close was sent.
+	pub fn sent(&self) -> bool {
+		self.inner.sent.load(Ordering::SeqCst)
+	}
+
+	/// Whether a close was received.
+	pub fn received(&self) -> bool {
+		self.inner.received.load(Ordering::SeqCst)
+	}
+
+	/// Fail unless a message can still be sent, `close` telling whether it
+	/// is a close message.
+	fn check(&self, close: bool) -> WebSocketResult<()> {
+		if self.sent() || (self.received() && !close) {
+			return Err(WebSocketError::ConnectionClosed);
+		}
+		Ok(())
+	}
+
+	fn note(&self, direction: Direction) {
+		match direction {
+			Direction::Outbound => self.inner.sent.store(true, Ordering::SeqCst),
+			Direction::Inbound => self.inner.received.store(true, Ordering::SeqCst),
+		}
+	}
+}
+
+impl Middleware for CloseGuard {
+	fn on_frame(&self, direction: Direction, frame: &mut DataFrame) -> WebSocketResult<()> {
+		let close = frame.opcode == Opcode::Close;
+		if direction == Direction::Outbound {
+			self.check(close)?;
+		}
+		if close {
+			self.note(direction);
+		}
+		Ok(())
+	}
+}
+
+/// An asynchronous client whose stream hands out `Incoming`s and ends after
+/// the close, see the module docs.
+///
+/// With auto-close on, the stream only hands out the close once its echo
+/// was sent, and the sink refuses what may not be sent after a close.
+#[cfg(feature = "async")]
+pub struct AutoClose<C> {
+	inner: C,
+	auto_close: bool,
+	guard: CloseGuard,
+	/// the close received, waiting for its echo to go out
+	closing: Option<CloseFrame<'static>>,
+	closed: bool,
+}
+
+#[cfg(feature = "async")]
+impl<C> AutoClose<C> {
+	/// Hand out the closes of `inner`, echoing them if `auto_close` is on.
+	pub fn new(inner: C, auto_close: bool) -> Self {
+		AutoClose {
+			inner: inner,
+			auto_close: auto_close,
+			guard: CloseGuard::new(),
+			closing: None,
+			closed: false,
+		}
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Give the wrapped client back.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Stream for AutoClose<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = Incoming;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<Incoming>, WebSocketError> {
+		if self.closed {
+			return Ok(Async::Ready(None));
+		}
+		if self.closing.is_none() {
+			let message = match self.inner.poll()? {
+				Async::Ready(Some(message)) => message,
+				Async::Ready(None) => return Ok(Async::Ready(None)),
+				Async::NotReady => return Ok(Async::NotReady),
+			};
+			match Incoming::from(message) {
+				Incoming::Close(close) => {
+					self.guard.note(Direction::Inbound);
+					self.closing = Some(close);
+				}
+				message => return Ok(Async::Ready(Some(message))),
+			}
+		}
+		if self.auto_close {
+			// a close we sent already is the answer to this one
+			if !self.guard.sent() {
+				let echo = self.closing.as_ref().map_or(OwnedMessage::Close(None), CloseFrame::echo);
+				if let AsyncSink::NotReady(_) = self.inner.start_send(echo)? {
+					return Ok(Async::NotReady);
+				}
+				self.guard.note(Direction::Outbound);
+			}
+			if let Async::NotReady = self.inner.poll_complete()? {
+				return Ok(Async::NotReady);
+			}
+		}
+		self.closed = true;
+		Ok(Async::Ready(self.closing.take().map(Incoming::Close)))
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Sink for AutoClose<C>
+where
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		if !self.auto_close {
+			return self.inner.start_send(item);
+		}
+		let close = item.is_close();
+		self.guard.check(close)?;
+		let sent = self.inner.start_send(item)?;
+		if close && sent.is_ready() {
+			self.guard.note(Direction::Outbound);
+		}
+		Ok(sent)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn closes_become_close_frames() {
+		let message = OwnedMessage::Close(Some(CloseData::new(4001, "bye".into())));
+		let close = CloseFrame::from_message(&message).unwrap();
+		assert_eq!(close.code, CloseCode::Other(4001));
+		assert_eq!(close.reason, "bye");
+		assert_eq!(close.echo(), OwnedMessage::Close(Some(CloseData::new(4001, String::new()))));
+
+		match Incoming::from(OwnedMessage::Close(None)) {
+			Incoming::Close(close) => {
+				assert_eq!(close.code, CloseCode::NoStatus);
+				assert_eq!(close.echo(), OwnedMessage::Close(None));
+			}
+			other => panic!("expected a close, got {:?}", other),
+		}
+		let ping = OwnedMessage::Ping(vec![1]);
+		assert_eq!(Incoming::from(ping.clone()), Incoming::Message(ping));
+
+		for code in 1000..5000 {
+			assert_eq!(u16::from(CloseCode::from(code)), code);
+		}
+	}
+
+	#[test]
+	fn nothing_goes_out_after_the_closes() {
+		let guard = CloseGuard::new();
+		let mut text = DataFrame::new(true, Opcode::Text, b"hi".to_vec());
+		let mut close = DataFrame::new(true, Opcode::Close, vec![0x03, 0xe8]);
+		guard.on_frame(Direction::Outbound, &mut text).unwrap();
+
+		guard.on_frame(Direction::Inbound, &mut close).unwrap();
+		match guard.on_frame(Direction::Outbound, &mut text) {
+			Err(WebSocketError::ConnectionClosed) => {}
+			other => panic!("expected the connection to be closed, got {:?}", other),
+		}
+		// the echo still goes out, but nothing after it
+		guard.on_frame(Direction::Outbound, &mut close).unwrap();
+		assert!(guard.sent());
+		assert!(guard.on_frame(Direction::Outbound, &mut close).is_err());
+	}
+}
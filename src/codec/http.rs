@@ -1,47 +1,139 @@
 //! Send HTTP requests and responses asynchronously.
 //!
 //! This module has both an `HttpClientCodec` for an async HTTP client and an
-//! `HttpServerCodec` for an async HTTP server.
-use std::borrow::Cow;
-use std::io::{self, BufReader, Write};
+//! `HttpServerCodec` for an async HTTP server. They read and write the head
+//! of a message (the request or status line and the headers) as a
+//! `MessageHead`, a body is left to whoever uses them. That is all a
+//! websocket handshake needs, so they can also be used to do a handshake
+//! over a transport of your own, or to answer some requests on a connection
+//! over plain HTTP before upgrading others.
+//!
+//! Parsing is done with `httparse`. When parsing heads yourself,
+//! `record_header_indices` and `HeadersAsBytesIter` turn the headers it found
+//! into a `HeaderMap` sharing the bytes of the head instead of copying them:
+//!
+//! ```rust
+//! # extern crate bytes;
+//! # extern crate http;
+//! # extern crate httparse;
+//! # extern crate websocket;
+//! use bytes::Bytes;
+//! use http::header::HeaderMap;
+//! use websocket::codec::http::{record_header_indices, HeaderIndices, HeadersAsBytesIter};
+//!
+//! # fn main() {
+//! let head = Bytes::from(&b"GET /chat HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]);
+//! let mut indices = [HeaderIndices::default(); 16];
+//! let found = {
+//!     let mut headers = [httparse::EMPTY_HEADER; 16];
+//!     let mut request = httparse::Request::new(&mut headers);
+//!     request.parse(&head).unwrap();
+//!     record_header_indices(&head, &request.headers, &mut indices);
+//!     request.headers.len()
+//! };
+//!
+//! let mut headers = HeaderMap::new();
+//! headers.extend(HeadersAsBytesIter::new(indices[..found].iter(), head));
+//! assert_eq!(headers["host"], "example.com");
+//! # }
+//! ```
+//!
+//! This module is only there with the `async` feature.
+use std::cmp;
+use std::io::{self, BufReader};
 use std::error::Error;
 use std::fmt::{self, Formatter, Display};
+use std::time::Duration;
 
-use bytes::{BufMut, BytesMut, Bytes};
+use bytes::{BytesMut, Bytes};
 use http::{self, Method, StatusCode, Uri};
-use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::header::{HeaderMap, HeaderName, HeaderValue, HOST};
 use httparse::{self, Request};
 use hyper;
 use tokio_io::codec::{Decoder, Encoder};
 
+use header::wire;
+
 #[cfg(any(feature = "sync", feature = "async"))]
 use http::Version;
 
+/// The default limit on the number of headers in a message head.
 pub const MAX_HEADERS: usize = 100;
+
+/// The room for headers a message head is first parsed with, it grows up to
+/// the limit for heads with more.
+const INITIAL_HEADERS: usize = 16;
+
+/// Limits enforced while reading the head of an HTTP request, these protect
+/// servers from clients sending huge handshakes or sending them very slowly.
+///
+/// A client exceeding these limits is answered with `414 URI Too Long`,
+/// `431 Request Header Fields Too Large` or `408 Request Timeout` (when the
+/// connection is still usable) and then disconnected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HandshakeLimits {
+	/// The maximum length of the request line (e.g. `GET /chat HTTP/1.1`) in bytes.
+	pub max_request_line: usize,
+	/// The maximum number of headers in a request.
+	pub max_headers: usize,
+	/// The maximum size of the request line and headers together, in bytes.
+	pub max_head_bytes: usize,
+	/// How long a client has to send its entire handshake.
+	pub timeout: Option<Duration>,
+}
+
+impl Default for HandshakeLimits {
+	fn default() -> Self {
+		HandshakeLimits {
+			max_request_line: 8 * 1024,
+			max_headers: MAX_HEADERS,
+			max_head_bytes: 64 * 1024,
+			timeout: Some(Duration::from_secs(30)),
+		}
+	}
+}
+
+// kept so code naming it keeps building, nothing here returns it anymore
+#[doc(hidden)]
 pub type ParseRespose<T> = hyper::Result<Option<(MessageHead<T>, usize)>>;
 
 /// An incoming request message.
 pub type RequestHead = MessageHead<RequestLine>;
 
+/// The method and the target of a request, e.g. `GET` and `/chat`. Targets
+/// sent as an absolute url are reduced to their path and query, see
+/// `HttpServerCodec`.
 #[derive(Debug, Default, PartialEq)]
 pub struct RequestLine(pub Method, pub Uri);
 
 /// An incoming response message.
 pub type ResponseHead = MessageHead<StatusCode>;
 
+/// The head of an HTTP message, everything before the body.
 #[derive(Debug)]
 pub struct MessageHead<T> {
+	/// The HTTP version of the message.
 	pub version: Version,
+	/// What the first line of the message says, a `RequestLine` for requests
+	/// and the status for responses.
 	pub subject: T,
+	/// The headers of the message.
 	pub headers: HeaderMap,
 }
 
-#[derive(Clone, Copy)]
+/// Where the name and value of a header are in the bytes of a head, as
+/// `start..end` ranges.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct HeaderIndices {
+	/// The range of the name of the header.
 	pub name: (usize, usize),
+	/// The range of the value of the header.
 	pub value: (usize, usize),
 }
 
+/// Record where the `headers` httparse found in `bytes` are, so they can be
+/// taken out of the bytes later with `HeadersAsBytesIter`. `indices` should
+/// have room for all of them, headers without room are left out.
 pub fn record_header_indices(
 	bytes: &[u8],
 	headers: &[httparse::Header],
@@ -58,27 +150,64 @@ pub fn record_header_indices(
 	}
 }
 
+/// Run `parse` on room for a few headers, giving it more each time it runs
+/// out until there is room for `max_headers`. Most heads are small, only the
+/// large ones pay for more.
+fn parse_growing<'b, T, F>(max_headers: usize, mut parse: F) -> httparse::Result<T>
+where
+	F: FnMut(&mut [httparse::Header<'b>]) -> httparse::Result<T>,
+{
+	let mut room = cmp::min(INITIAL_HEADERS, max_headers);
+	loop {
+		let mut headers = vec![httparse::EMPTY_HEADER; room];
+		match parse(&mut headers[..]) {
+			Err(httparse::Error::TooManyHeaders) if room < max_headers => {
+				room = cmp::min(room * 2, max_headers);
+			}
+			result => return result,
+		}
+	}
+}
+
+/// The indices of `headers` in `bytes`.
+fn header_indices(bytes: &[u8], headers: &[httparse::Header]) -> Vec<HeaderIndices> {
+	let mut indices = vec![HeaderIndices::default(); headers.len()];
+	record_header_indices(bytes, headers, &mut indices);
+	indices
+}
+
+/// The headers recorded with `record_header_indices`, taken out of the bytes
+/// they were parsed from. The values share the bytes instead of being copied.
+///
+/// # Panics
+///
+/// When iterated over, if the indices don't point at valid header names and
+/// values in `slice`, e.g. because they were recorded from other bytes.
 pub struct HeadersAsBytesIter<'a> {
+	/// The indices of the headers left.
 	pub headers: ::std::slice::Iter<'a, HeaderIndices>,
+	/// The bytes the headers were parsed from.
 	pub slice: Bytes,
 }
 
+impl<'a> HeadersAsBytesIter<'a> {
+	/// Take the headers at `headers` out of `slice`.
+	pub fn new(headers: ::std::slice::Iter<'a, HeaderIndices>, slice: Bytes) -> Self {
+		HeadersAsBytesIter {
+			headers: headers,
+			slice: slice,
+		}
+	}
+}
+
 impl<'a> Iterator for HeadersAsBytesIter<'a> {
 	type Item = (HeaderName, HeaderValue);
 	fn next(&mut self) -> Option<Self::Item> {
 		self.headers.next().map(|header| {
-			let name = unsafe {
-				let bytes = ::std::slice::from_raw_parts(
-					self.slice.as_ref().as_ptr().offset(header.name.0 as isize),
-					header.name.1 - header.name.0,
-				);
-				::std::str::from_utf8_unchecked(bytes)
-			};
-			let name =
-				HeaderName::from_bytes(name.as_bytes()).expect("header name already validated");
-			let value = unsafe {
-				HeaderValue::from_shared_unchecked(self.slice.slice(header.value.0, header.value.1))
-			};
+			let name = HeaderName::from_bytes(&self.slice[header.name.0..header.name.1])
+				.expect("header name already validated");
+			let value = HeaderValue::from_shared(self.slice.slice(header.value.0, header.value.1))
+				.expect("header value already validated");
 			(name, value)
 		})
 	}
@@ -109,7 +238,7 @@ impl<'a> Iterator for HeadersAsBytesIter<'a> {
 ///
 ///let f = TcpStream::connect(&addr)
 ///    .and_then(|s| {
-///        Ok(s.framed(HttpClientCodec))
+///        Ok(s.framed(HttpClientCodec::default()))
 ///    })
 ///    .and_then(|s| {
 ///        s.send(MessageHead {
@@ -125,7 +254,28 @@ impl<'a> Iterator for HeadersAsBytesIter<'a> {
 ///tokio::run(f.map(|_| ()).map_err(|_| ()));
 ///# }
 ///```
-pub struct HttpClientCodec;
+pub struct HttpClientCodec {
+	max_headers: usize,
+}
+
+impl HttpClientCodec {
+	/// Create a codec which fails on responses with more than `max_headers`
+	/// headers.
+	pub fn with_max_headers(max_headers: usize) -> Self {
+		HttpClientCodec { max_headers: max_headers }
+	}
+
+	/// The most headers a response may have.
+	pub fn max_headers(&self) -> usize {
+		self.max_headers
+	}
+}
+
+impl Default for HttpClientCodec {
+	fn default() -> Self {
+		HttpClientCodec::with_max_headers(MAX_HEADERS)
+	}
+}
 
 fn split_off_http(src: &mut BytesMut) -> Option<BytesMut> {
 	match src.windows(4).position(|i| i == b"\r\n\r\n") {
@@ -134,13 +284,11 @@ fn split_off_http(src: &mut BytesMut) -> Option<BytesMut> {
 	}
 }
 
-fn write_headers(headers: &HeaderMap, dst: &mut BytesMut) {
-	for (name, value) in headers {
-		dst.extend(name.as_str().as_bytes());
-		dst.extend(b": ");
-		dst.extend(value.as_bytes());
-		dst.extend(b"\r\n");
-	}
+fn write_head(line: String, headers: &HeaderMap, dst: &mut BytesMut) {
+	let mut head = line.into_bytes();
+	wire::write_headers(headers, &mut head);
+	head.extend_from_slice(b"\r\n");
+	dst.extend_from_slice(&head);
 }
 
 impl Encoder for HttpClientCodec {
@@ -149,24 +297,9 @@ impl Encoder for HttpClientCodec {
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
 
-		// TODO: optomize this!
-		let mut request = format!(
-			"{} {} {:?}\r\n",
-			item.subject.0, item.subject.1, item.version
-		).to_owned();
-
-		for (key, value) in item.headers.iter() {
-			request += &format!("{}: {}\r\n", key.as_str(), value.to_str().unwrap());
-		}
-
-		request += "\r\n";
-
-		let byte_len = request.as_bytes().len();
-		if byte_len > dst.remaining_mut() {
-			dst.reserve(byte_len);
-		}
-
-		dst.writer().write(request.as_bytes()).map(|_| ())
+		let line = format!("{} {} {:?}\r\n", item.subject.0, item.subject.1, item.version);
+		write_head(line, &item.headers, dst);
+		Ok(())
 
 	}
 }
@@ -181,53 +314,41 @@ impl Decoder for HttpClientCodec {
 			return Ok(None);
 		}
 
-		let mut headers_indices = [HeaderIndices {
-			name: (0, 0),
-			value: (0, 0),
-		}; MAX_HEADERS];
-
-		let (len, code, reason, version, headers_len) = {
-			let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-			//trace!("Response.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-			let mut res = httparse::Response::new(&mut headers);
+		let parsed = {
 			let bytes = buf.as_ref();
-			match res.parse(bytes).unwrap_or(httparse::Status::Partial) {
-				httparse::Status::Complete(len) => {
-					//trace!("Response.parse Complete({})", len);
-					let code = res.code.unwrap();
-					let status = try!(StatusCode::from_u16(code).map_err(
-						|_| httparse::Error::Status,
-					));
-					let reason = match status.canonical_reason() {
-						Some(reason) if reason == res.reason.unwrap() => Cow::Borrowed(reason),
-						_ => Cow::Owned(res.reason.unwrap().to_owned()),
-					};
-					let version = if res.version.unwrap() == 1 {
-						Version::HTTP_11
-					} else {
-						Version::HTTP_10
-					};
-					record_header_indices(bytes, &res.headers, &mut headers_indices);
-					let headers_len = res.headers.len();
-					(len, code, reason, version, headers_len)
-				}
-				httparse::Status::Partial => return Ok(None),
-			}
+			try!(parse_growing(self.max_headers, |headers| {
+				let mut res = httparse::Response::new(headers);
+				let len = match try!(res.parse(bytes)) {
+					httparse::Status::Complete(len) => len,
+					httparse::Status::Partial => return Ok(httparse::Status::Partial),
+				};
+				let code = try!(res.code.ok_or(httparse::Error::Status));
+				let status = try!(StatusCode::from_u16(code).map_err(
+					|_| httparse::Error::Status,
+				));
+				let version = if res.version == Some(1) {
+					Version::HTTP_11
+				} else {
+					Version::HTTP_10
+				};
+				let indices = header_indices(bytes, &res.headers);
+				Ok(httparse::Status::Complete((len, status, version, indices)))
+			}))
+		};
+		let (len, status, version, indices) = match parsed {
+			httparse::Status::Complete(parsed) => parsed,
+			httparse::Status::Partial => return Ok(None),
 		};
 
-		let mut headers = HeaderMap::with_capacity(headers_len);
+		let mut headers = HeaderMap::with_capacity(indices.len());
 
 		let slice = buf.split_to(len).freeze();
 
-		let new_headers = HeadersAsBytesIter {
-			headers: headers_indices[..headers_len].iter(),
-			slice: slice,
-		};
-		headers.extend(new_headers);
+		headers.extend(HeadersAsBytesIter::new(indices.iter(), slice));
 
 		Ok(Some(MessageHead {
 			version: version,
-			subject: StatusCode::from_u16(code).unwrap(),
+			subject: status,
 			headers: headers,
 		}))
 	}
@@ -282,7 +403,10 @@ impl Decoder for HttpClientCodec {
 ///tokio::run(f.map(|_| ()).map_err(|_| ()));
 ///# }
 ///```
-#[derive(Copy, Clone, Debug)]
+///
+///Requests are held to the default `HandshakeLimits`, use a
+///`LimitedHttpServerCodec` to choose others.
+#[derive(Copy, Clone, Debug, Default)]
 pub struct HttpServerCodec;
 
 impl Encoder for HttpServerCodec {
@@ -290,12 +414,7 @@ impl Encoder for HttpServerCodec {
 	type Error = io::Error;
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		dst.extend(
-			format!("{:?} {}\r\n", item.version, item.subject).as_bytes(),
-		);
-		write_headers(&item.headers, dst);
-		dst.extend(b"\r\n");
-		Ok(())
+		LimitedHttpServerCodec::default().encode(item, dst)
 	}
 }
 
@@ -304,6 +423,60 @@ impl Decoder for HttpServerCodec {
 	type Error = HttpCodecError;
 
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		LimitedHttpServerCodec::default().decode(src)
+	}
+}
+
+/// An `HttpServerCodec` which rejects requests exceeding the given
+/// `HandshakeLimits` rather than the default ones.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LimitedHttpServerCodec {
+	limits: HandshakeLimits,
+}
+
+impl LimitedHttpServerCodec {
+	/// Create a codec which rejects requests exceeding the given limits.
+	/// The `timeout` of the limits is not enforced by the codec itself.
+	pub fn with_limits(limits: HandshakeLimits) -> Self {
+		LimitedHttpServerCodec { limits: limits }
+	}
+
+	/// The limits enforced by this codec.
+	pub fn limits(&self) -> &HandshakeLimits {
+		&self.limits
+	}
+
+	fn check_limits(&self, src: &BytesMut) -> Result<(), HttpCodecError> {
+		let line_len = src.windows(2).position(|w| w == b"\r\n").unwrap_or(src.len());
+		if line_len > self.limits.max_request_line {
+			return Err(HttpCodecError::RequestLineTooLong);
+		}
+		let head_len = src.windows(4).position(|w| w == b"\r\n\r\n").map(|p| p + 4);
+		if head_len.unwrap_or(src.len()) > self.limits.max_head_bytes {
+			return Err(HttpCodecError::TooLarge);
+		}
+		Ok(())
+	}
+}
+
+impl Encoder for LimitedHttpServerCodec {
+	type Item = ResponseHead;
+	type Error = io::Error;
+
+	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		let line = format!("{:?} {}\r\n", item.version, item.subject);
+		write_head(line, &item.headers, dst);
+		Ok(())
+	}
+}
+
+impl Decoder for LimitedHttpServerCodec {
+	type Item = RequestHead;
+	type Error = HttpCodecError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		self.check_limits(src)?;
+
 		// check if we get a request from hyper
 		// TODO: this is ineffecient, but hyper does not give us a better way to parse
 		match split_off_http(src) {
@@ -313,50 +486,47 @@ impl Decoder for HttpServerCodec {
 					return Ok(None);
 				}
 
-				let mut headers_indices = [HeaderIndices {
-					name: (0, 0),
-					value: (0, 0),
-				}; MAX_HEADERS];
-
-				let (len, method, path, version, headers_len) = {
-					let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
-					//println!("Request.parse([Header; {}], [u8; {}])", headers.len(), buf.len());
-					let mut req = httparse::Request::new(&mut headers);
-					match try!(req.parse(&buf)) {
-						httparse::Status::Complete(len) => {
-							//println!("Request.parse Complete({})", len);
-							let method = Method::from_bytes(req.method.unwrap().as_bytes())?;
-							let path = req.path.unwrap();
-							let bytes_ptr = buf.as_ref().as_ptr() as usize;
-							let path_start = path.as_ptr() as usize - bytes_ptr;
-							let path_end = path_start + path.len();
-							let path = (path_start, path_end);
-							let version = if req.version.unwrap() == 1 {
-								Version::HTTP_11
-							} else {
-								Version::HTTP_10
-							};
-
-							record_header_indices(buf.as_ref(), &req.headers, &mut headers_indices);
-							let headers_len = req.headers.len();
-							(len, method, path, version, headers_len)
-						}
-						httparse::Status::Partial => return Ok(None),
-					}
+				let parsed = {
+					let bytes = buf.as_ref();
+					try!(parse_growing(self.limits.max_headers, |headers| {
+						let mut req = httparse::Request::new(headers);
+						let len = match try!(req.parse(bytes)) {
+							httparse::Status::Complete(len) => len,
+							httparse::Status::Partial => return Ok(httparse::Status::Partial),
+						};
+						trace!("parsed request head of {} bytes", len);
+						let method = Method::from_bytes(req.method.unwrap_or("").as_bytes());
+						let path = req.path.unwrap_or("");
+						let path_start = path.as_ptr() as usize - bytes.as_ptr() as usize;
+						let path = (path_start, path_start + path.len());
+						let version = if req.version == Some(1) {
+							Version::HTTP_11
+						} else {
+							Version::HTTP_10
+						};
+						let indices = header_indices(bytes, &req.headers);
+						Ok(httparse::Status::Complete((len, method, path, version, indices)))
+					}))
+				};
+				let (len, method, path, version, indices) = match parsed {
+					httparse::Status::Complete(parsed) => parsed,
+					httparse::Status::Partial => return Ok(None),
 				};
+				let method = method?;
 
-				let mut headers = HeaderMap::with_capacity(headers_len);
+				let mut headers = HeaderMap::with_capacity(indices.len());
 				let slice = buf.split_to(len).freeze();
 				let path = slice.slice(path.0, path.1);
 
 				// path was found to be utf8 by httparse
-				let path = Uri::from_shared(path)?;
+				let (path, host) = origin_form(Uri::from_shared(path)?)?;
 				let subject = RequestLine(method, path);
 
-				headers.extend(HeadersAsBytesIter {
-					headers: headers_indices[..headers_len].iter(),
-					slice: slice,
-				});
+				headers.extend(HeadersAsBytesIter::new(indices.iter(), slice));
+				// the host of the request line wins over the Host header
+				if let Some(host) = host {
+					headers.insert(HOST, host);
+				}
 
 
 				Ok(Some(RequestHead {
@@ -371,6 +541,32 @@ impl Decoder for HttpServerCodec {
 	}
 }
 
+/// Reduce a request target in absolute form (`GET ws://host/chat HTTP/1.1`)
+/// or authority form (`GET host:80 HTTP/1.1`), as some proxies and older
+/// clients send them, to the path and query servers route by. The host it
+/// named is returned along with it.
+fn origin_form(uri: Uri) -> Result<(Uri, Option<HeaderValue>), HttpCodecError> {
+	let host = match uri.host() {
+		Some(host) => match uri.port() {
+			Some(port) => format!("{}:{}", host, port),
+			None => host.to_string(),
+		},
+		None => return Ok((uri, None)),
+	};
+	let host = HeaderValue::from_str(&host).map_err(|_| HttpCodecError::Uri)?;
+	let path = if uri.path().starts_with('/') {
+		uri.path()
+	} else {
+		"/"
+	};
+	let path = match uri.query() {
+		Some(query) => format!("{}?{}", path, query),
+		None => path.to_string(),
+	};
+	let path = path.parse().map_err(|_| HttpCodecError::Uri)?;
+	Ok((path, Some(host)))
+}
+
 /// Any error that can happen during the writing or parsing of HTTP requests
 /// and responses. This consists of HTTP parsing errors (the `Http` variant) and
 /// errors that can occur when writing to IO (the `Io` variant).
@@ -386,6 +582,8 @@ pub enum HttpCodecError {
 	Header,
 	/// A message head is too large to be reasonable.
 	TooLarge,
+	/// The request line is too long to be reasonable.
+	RequestLineTooLong,
 	/// An invalid `Status`, such as `1337 ELITE`.
 	Status,
 	/// An error that occurs during the writing or reading of HTTP data
@@ -407,6 +605,7 @@ impl Error for HttpCodecError {
 			HttpCodecError::Uri => "invalid URI",
 			HttpCodecError::Header => "invalid Header provided",
 			HttpCodecError::TooLarge => "message head is too large",
+			HttpCodecError::RequestLineTooLong => "request line is too long",
 			HttpCodecError::Status => "invalid Status provided",
 			HttpCodecError::Io(ref e) => e.description(),
 		}
@@ -470,7 +669,7 @@ mod tests {
 		let output = Cursor::new(Vec::new());
 
 		let f = ReadWritePair(input, output)
-			.framed(HttpClientCodec)
+			.framed(HttpClientCodec::default())
 			.send(MessageHead {
 				version: Version::HTTP_11,
 				subject: (Method::GET, "/".to_string().parse().unwrap()),
@@ -514,4 +713,103 @@ mod tests {
 			});
 		tokio::run(f.map(|_| ()).map_err(|_| ()));
 	}
+
+	#[test]
+	fn test_server_http_codec_limits() {
+		let limits = HandshakeLimits {
+			max_request_line: 16,
+			max_headers: 1,
+			max_head_bytes: 64,
+			timeout: None,
+		};
+
+		let mut codec = LimitedHttpServerCodec::with_limits(limits);
+		let mut src = BytesMut::from(&b"GET /a/very/long/path HTTP/1.1\r\n"[..]);
+		match codec.decode(&mut src) {
+			Err(HttpCodecError::RequestLineTooLong) => {}
+			_ => panic!("expected the request line to be rejected"),
+		}
+
+		let mut codec = LimitedHttpServerCodec::with_limits(limits);
+		let mut src = BytesMut::from(&b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n"[..]);
+		match codec.decode(&mut src) {
+			Err(HttpCodecError::TooLarge) => {}
+			_ => panic!("expected too many headers to be rejected"),
+		}
+
+		let mut codec = LimitedHttpServerCodec::with_limits(limits);
+		let mut src = BytesMut::from(&b"GET / HTTP/1.1\r\nA: "[..]);
+		src.extend_from_slice(&[b'a'; 64]);
+		match codec.decode(&mut src) {
+			Err(HttpCodecError::TooLarge) => {}
+			_ => panic!("expected an unterminated head to be rejected"),
+		}
+	}
+
+	#[test]
+	fn test_server_http_codec_absolute_targets() {
+		let targets: &[(&[u8], &str, Option<&str>)] = &[
+			(b"/chat?room=1", "/chat?room=1", Some("example.org")),
+			(b"ws://other.org:8080/chat?room=1", "/chat?room=1", Some("other.org:8080")),
+			(b"http://user@other.org", "/", Some("other.org")),
+			(b"other.org:80", "/", Some("other.org:80")),
+		];
+		for &(target, path, host) in targets {
+			let mut src = BytesMut::from(&b"GET "[..]);
+			src.extend_from_slice(target);
+			src.extend_from_slice(b" HTTP/1.1\r\nHost: example.org\r\n\r\n");
+			let head = HttpServerCodec.decode(&mut src).unwrap().unwrap();
+			assert_eq!(head.subject.1, path);
+			assert_eq!(head.headers.get(HOST).map(|h| h.to_str().unwrap()), host);
+		}
+	}
+
+	#[test]
+	fn test_http_codec_many_headers() {
+		let mut head = b"GET / HTTP/1.1\r\n".to_vec();
+		for i in 0..150 {
+			head.extend_from_slice(format!("X-Header-{}: {}\r\n", i, i).as_bytes());
+		}
+		head.extend_from_slice(b"\r\n");
+
+		let mut src = BytesMut::from(&head[..]);
+		match HttpServerCodec.decode(&mut src) {
+			Err(HttpCodecError::TooLarge) => {}
+			_ => panic!("expected too many headers to be rejected"),
+		}
+
+		let limits = HandshakeLimits {
+			max_headers: 200,
+			..HandshakeLimits::default()
+		};
+		let mut src = BytesMut::from(&head[..]);
+		let request = LimitedHttpServerCodec::with_limits(limits).decode(&mut src).unwrap().unwrap();
+		assert_eq!(request.headers.len(), 150);
+		assert_eq!(request.headers["x-header-149"], "149");
+
+		let response = [&b"HTTP/1.1 101 Switching Protocols\r\n"[..], &head[16..]].concat();
+		let mut src = BytesMut::from(&response[..]);
+		assert!(HttpClientCodec::default().decode(&mut src).is_err());
+		let mut src = BytesMut::from(&response[..]);
+		let response = HttpClientCodec::with_max_headers(150).decode(&mut src).unwrap().unwrap();
+		assert_eq!(response.headers.len(), 150);
+	}
+
+	#[test]
+	fn test_client_http_codec_garbage() {
+		let responses: &[&[u8]] = &[
+			b"HTTP/1.1 999 Nope\r\n\r\n",
+			b"HTTP/1.1 101\r\n\r\n",
+			b"HTTP/1.1 101 \xff\xfe\r\n\r\n",
+			b"HTTP/9.9 101 Switching Protocols\r\n\r\n",
+			b"\x00\x01\x02\x03\r\n\r\n",
+		];
+		// whatever the server sends, decoding it must not panic
+		for response in responses {
+			let mut src = BytesMut::from(*response);
+			let _ = HttpClientCodec::default().decode(&mut src);
+		}
+		let mut src = BytesMut::from(&b"\x00\x01\x02\x03\r\n\r\n"[..]);
+		assert!(HttpClientCodec::default().decode(&mut src).is_err());
+	}
 }
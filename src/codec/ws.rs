@@ -17,11 +17,20 @@ use tokio_io::codec::Encoder;
 use bytes::BytesMut;
 use bytes::BufMut;
 
-use dataframe::DataFrame;
+use dataframe::{DataFrame, Opcode, ReservedClaims};
 use message::OwnedMessage;
+use pool::BufferPool;
+use middleware::{Direction, Middlewares};
+use progress::{Progress, ProgressCallback};
+use stats::ConnectionStats;
+use ping::Pings;
+use server::limit::ConnectionPermit;
+#[cfg(feature = "metrics")]
+use metrics::{self, ConnectionGuard};
 use ws::dataframe::DataFrame as DataFrameTrait;
 use ws::message::Message as MessageTrait;
 use ws::util::header::read_header;
+use ws::util::mask::{self, KeySource};
 use result::WebSocketError;
 
 /// Even though a websocket connection may look perfectly symmetrical
@@ -41,6 +50,28 @@ pub enum Context {
 	Client,
 }
 
+/// What `MessageCodec` does with text messages that are not valid UTF-8.
+///
+/// The protocol requires text to be UTF-8 and `Strict` is the default, the
+/// others are for peers that send almost-UTF-8 or for when the payload was
+/// validated elsewhere already. Close reasons are always checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Policy {
+	/// Fail with `WebSocketError::Utf8Error` on invalid text.
+	Strict,
+	/// Replace the invalid sequences with U+FFFD REPLACEMENT CHARACTER.
+	Lossy,
+	/// Don't look at text at all, deliver text messages as
+	/// `OwnedMessage::Binary` with their payload untouched.
+	Unchecked,
+}
+
+impl Default for Utf8Policy {
+	fn default() -> Self {
+		Utf8Policy::Strict
+	}
+}
+
 /**************
  * Dataframes *
  **************/
@@ -59,6 +90,10 @@ pub enum Context {
 pub struct DataFrameCodec<D> {
 	is_server: bool,
 	frame_type: PhantomData<D>,
+	pool: Option<BufferPool>,
+	keys: Option<KeySource>,
+	/// whether to mask outgoing frames regardless of the context
+	masking: Option<bool>,
 }
 
 impl DataFrameCodec<DataFrame> {
@@ -83,8 +118,42 @@ impl<D> DataFrameCodec<D> {
 		DataFrameCodec {
 			is_server: context == Context::Server,
 			frame_type: PhantomData,
+			pool: None,
+			keys: None,
+			masking: None,
 		}
 	}
+
+	/// Take the buffers for incoming frames from `pool` rather than
+	/// allocating a new one for each frame.
+	pub fn set_pool(&mut self, pool: Option<BufferPool>) {
+		self.pool = pool;
+	}
+
+	/// Draw the masking keys of outgoing frames from `keys` instead of the
+	/// thread's random number generator.
+	pub fn set_key_source(&mut self, keys: Option<KeySource>) {
+		self.keys = keys;
+	}
+
+	/// Mask outgoing frames or not, no matter the context.
+	///
+	/// This breaks the protocol: servers must reject unmasked frames from
+	/// clients and clients must reject masked frames from servers. It is
+	/// only meant for trusted peers that accept it, test harnesses and
+	/// measuring what masking costs. Incoming frames are still expected to
+	/// be masked as the context says.
+	pub fn dangerous_set_masking(&mut self, mask: bool) {
+		self.masking = Some(mask);
+	}
+
+	fn masks(&self) -> bool {
+		self.masking.unwrap_or(!self.is_server)
+	}
+
+	fn next_mask(&self) -> Option<[u8; 4]> {
+		mask::next_mask(self.masks(), self.keys.as_ref())
+	}
 }
 
 impl<D> Decoder for DataFrameCodec<D> {
@@ -114,7 +183,14 @@ impl<D> Decoder for DataFrameCodec<D> {
 
 		// TODO: using usize is not the right thing here (can be larger)
 		let _ = src.split_to(bytes_read as usize);
-		let body = src.split_to(header.len as usize).to_vec();
+		let body = match self.pool {
+			Some(ref pool) => {
+				let mut body = pool.get(header.len as usize);
+				body.extend_from_slice(&src.split_to(header.len as usize));
+				body
+			}
+			None => src.split_to(header.len as usize).to_vec(),
+		};
 
 		// construct a dataframe
 		Ok(Some(DataFrame::read_dataframe_body(
@@ -133,12 +209,12 @@ where
 	type Error = WebSocketError;
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		let masked = !self.is_server;
+		let masked = self.masks();
 		let frame_size = item.borrow().frame_size(masked);
 		if frame_size > dst.remaining_mut() {
 			dst.reserve(frame_size);
 		}
-		item.borrow().write_to(&mut dst.writer(), masked)
+		item.borrow().write_with_key(&mut dst.writer(), self.next_mask())
 	}
 }
 
@@ -152,6 +228,12 @@ where
 /// the user will receive messages as `OwnedMessage`s. However it can encode
 /// any type of message that implements the `ws::Message` trait (that type is
 /// decided by the `M` type parameter) like `OwnedMessage` and `Message`.
+/// A `MessageCodec<DataFrame>` sends `DataFrame`s exactly as they are, which is
+/// the asynchronous way to send raw frames (masked as the context requires).
+/// A `MessageCodec<Arc<OwnedMessage>>` or `MessageCodec<Cow<OwnedMessage>>`
+/// sends shared or borrowed messages without cloning them, which is what a
+/// server sending the same message to many clients wants, see
+/// `into_message_type`.
 ///
 /// Warning: if you don't know what your doing or want a simple websocket connection
 /// please use the `ClientBuilder` or the `Server` structs. You should only use this
@@ -199,6 +281,21 @@ where
 	buffer: Vec<DataFrame>,
 	dataframe_codec: DataFrameCodec<DataFrame>,
 	message_type: PhantomData<fn(M)>,
+	middleware: Middlewares,
+	/// the middleware that was set, `middleware` adds the counters to it
+	layers: Middlewares,
+	stats: Option<ConnectionStats>,
+	pings: Option<Pings>,
+	claims: ReservedClaims,
+	/// whether the rest of a message with a claimed opcode is being skipped
+	skipping: bool,
+	fragment_size: Option<usize>,
+	progress: Option<ProgressCallback>,
+	utf8: Utf8Policy,
+	/// the slot the connection takes up in a server's `ConnectionLimiter`
+	permit: Option<ConnectionPermit>,
+	#[cfg(feature = "metrics")]
+	_connection: ConnectionGuard,
 }
 
 impl MessageCodec<OwnedMessage> {
@@ -230,7 +327,136 @@ where
 			buffer: Vec::new(),
 			dataframe_codec: DataFrameCodec::new(context),
 			message_type: PhantomData,
+			middleware: Middlewares::default(),
+			layers: Middlewares::default(),
+			stats: None,
+			pings: None,
+			claims: ReservedClaims::new(),
+			skipping: false,
+			fragment_size: None,
+			progress: None,
+			utf8: Utf8Policy::Strict,
+			permit: None,
+			#[cfg(feature = "metrics")]
+			_connection: ConnectionGuard::new(),
+		}
+	}
+}
+
+impl<M> MessageCodec<M>
+where
+	M: MessageTrait + Send,
+{
+	/// Keep the settings and state of this codec but encode another type
+	/// of messages, e.g. `DataFrame`s to send messages in fragments.
+	pub fn into_message_type<N>(self) -> MessageCodec<N>
+	where
+		N: MessageTrait + Send,
+	{
+		MessageCodec {
+			buffer: self.buffer,
+			dataframe_codec: self.dataframe_codec,
+			message_type: PhantomData,
+			middleware: self.middleware,
+			layers: self.layers,
+			stats: self.stats,
+			pings: self.pings,
+			claims: self.claims,
+			skipping: self.skipping,
+			fragment_size: self.fragment_size,
+			progress: self.progress,
+			utf8: self.utf8,
+			permit: self.permit,
+			#[cfg(feature = "metrics")]
+			_connection: self._connection,
+		}
+	}
+
+	/// Take the buffers for incoming frames from `pool` rather than
+	/// allocating a new one for each frame, give the messages back to the
+	/// pool with `BufferPool::recycle` once they were handled.
+	pub fn set_pool(&mut self, pool: Option<BufferPool>) {
+		self.dataframe_codec.set_pool(pool);
+	}
+
+	/// Run every frame sent and received through this middleware.
+	pub fn set_middleware(&mut self, middleware: Middlewares) {
+		self.layers = middleware.clone();
+		self.middleware = middleware;
+		if let Some(ref stats) = self.stats {
+			self.middleware.push(stats.clone());
 		}
+		if let Some(ref pings) = self.pings {
+			self.middleware.push(pings.clone());
+		}
+	}
+
+	/// Count the messages, bytes and pings going through this codec, see
+	/// `ConnectionStats`. The counting runs after the middleware.
+	pub fn set_stats(&mut self, stats: ConnectionStats) {
+		let layers = self.layers.clone();
+		self.stats = Some(stats);
+		self.set_middleware(layers);
+	}
+
+	/// The counters of this codec, if it has any.
+	pub fn stats(&self) -> Option<&ConnectionStats> {
+		self.stats.as_ref()
+	}
+
+	/// Answer the pings sent with `pings` with the pongs received by this
+	/// codec, see the `ping` module.
+	pub fn set_pings(&mut self, pings: Pings) {
+		let layers = self.layers.clone();
+		self.pings = Some(pings);
+		self.set_middleware(layers);
+	}
+
+	/// Send text and binary messages larger than `size` bytes in fragments
+	/// of that size, see `Sender::set_fragment_size`.
+	///
+	/// # Panics
+	///
+	/// If `size` is `Some(0)`.
+	pub fn set_fragment_size(&mut self, size: Option<usize>) {
+		assert!(size != Some(0), "fragments must not be empty");
+		self.fragment_size = size;
+	}
+
+	/// Report the progress of the text and binary messages sent and
+	/// received, see `ProgressCallback`.
+	pub fn set_progress(&mut self, progress: Option<ProgressCallback>) {
+		self.progress = progress;
+	}
+
+	/// Decide what to do with received text that is not valid UTF-8, see
+	/// `Utf8Policy`.
+	pub fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+		self.utf8 = policy;
+	}
+
+	/// Count the connection against a `ConnectionLimiter` for as long as
+	/// this codec (and so the client framed with it) is alive.
+	pub fn set_permit(&mut self, permit: Option<ConnectionPermit>) {
+		self.permit = permit;
+	}
+
+	/// Mask outgoing messages or not, no matter the context, see
+	/// `DataFrameCodec::dangerous_set_masking`.
+	pub fn dangerous_set_masking(&mut self, mask: bool) {
+		self.dataframe_codec.dangerous_set_masking(mask);
+	}
+
+	/// Let the reserved bits and opcodes an extension claimed through to
+	/// the messages, see `ReservedClaims`.
+	pub fn set_reserved_claims(&mut self, claims: ReservedClaims) {
+		self.claims = claims;
+	}
+
+	/// Draw the masking keys of outgoing messages from `keys` instead of
+	/// the thread's random number generator.
+	pub fn set_key_source(&mut self, keys: Option<KeySource>) {
+		self.dataframe_codec.set_key_source(keys);
 	}
 }
 
@@ -242,7 +468,11 @@ where
 	type Error = WebSocketError;
 
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		while let Some(frame) = self.dataframe_codec.decode(src)? {
+		while let Some(mut frame) = self.dataframe_codec.decode(src)? {
+			self.middleware.frame(Direction::Inbound, &mut frame)?;
+			if !self.claims.admit(&mut frame, &mut self.skipping) {
+				continue;
+			}
 			let is_first = self.buffer.is_empty();
 			let finished = frame.finished;
 
@@ -255,7 +485,7 @@ where
 				}
 				// control frame
 				8...15 => {
-					return Ok(Some(OwnedMessage::from_dataframes(vec![frame])?));
+					return self.received(vec![frame]).map(Some);
 				}
 				// data frame
 				1...7 if !is_first => {
@@ -266,12 +496,20 @@ where
 				// its good
 				_ => {
 					self.buffer.push(frame);
+					if let Some(ref progress) = self.progress {
+						progress.report(Progress {
+							direction: Direction::Inbound,
+							bytes: self.buffer.iter().map(|frame| frame.data.len()).sum(),
+							total: None,
+							done: finished,
+						});
+					}
 				}
 			};
 
 			if finished {
 				let buffer = mem::replace(&mut self.buffer, Vec::new());
-				return Ok(Some(OwnedMessage::from_dataframes(buffer)?));
+				return self.received(buffer).map(Some);
 			}
 		}
 
@@ -279,6 +517,45 @@ where
 	}
 }
 
+impl<M> MessageCodec<M>
+where
+	M: MessageTrait + Send,
+{
+	fn received(&self, mut frames: Vec<DataFrame>) -> Result<OwnedMessage, WebSocketError> {
+		if frames[0].opcode == Opcode::Text {
+			match self.utf8 {
+				Utf8Policy::Strict => {}
+				Utf8Policy::Lossy => replace_invalid_utf8(&mut frames),
+				Utf8Policy::Unchecked => frames[0].opcode = Opcode::Binary,
+			}
+		}
+		let message = OwnedMessage::from_dataframes(frames)?;
+		#[cfg(feature = "metrics")]
+		metrics::received(&message, self.dataframe_codec.is_server);
+		Ok(message)
+	}
+}
+
+/// Join the payloads of a text message into its first frame, replacing what
+/// is not UTF-8.
+fn replace_invalid_utf8(frames: &mut Vec<DataFrame>) {
+	if frames.iter().any(|frame| frame.reserved != [false; 3]) {
+		// not ours to touch, let the message be refused as usual
+		return;
+	}
+	let rest: Vec<_> = frames.drain(1..).collect();
+	let first = &mut frames[0];
+	for frame in rest {
+		first.data.extend_from_slice(&frame.data);
+	}
+	let text = match String::from_utf8(mem::replace(&mut first.data, Vec::new())) {
+		Ok(text) => text,
+		Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+	};
+	first.data = text.into_bytes();
+	first.finished = true;
+}
+
 impl<M> Encoder for MessageCodec<M>
 where
 	M: MessageTrait + Send,
@@ -287,12 +564,50 @@ where
 	type Error = WebSocketError;
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		let masked = !self.dataframe_codec.is_server;
-		let frame_size = item.message_size(masked);
-		if frame_size > dst.remaining_mut() {
-			dst.reserve(frame_size);
+		let masked = self.dataframe_codec.masks();
+		let fragment_size = self.fragment_size.filter(|&size| item.message_size(false) > size);
+		if fragment_size.is_none() && self.progress.is_none() && self.middleware.is_empty() {
+			let frame_size = item.message_size(masked);
+			if frame_size > dst.remaining_mut() {
+				dst.reserve(frame_size);
+			}
+			item.serialize_with_key(&mut dst.writer(), self.dataframe_codec.next_mask())?;
+		} else {
+			let frame = DataFrame::from_message(&item)?;
+			let total = frame.data.len();
+			let is_data = (frame.opcode as u8) < 8;
+			let frames = match fragment_size {
+				Some(size) => frame.fragment(size),
+				None => vec![frame],
+			};
+			let mut sent = 0;
+			for mut frame in frames {
+				sent += frame.data.len();
+				let done = frame.finished;
+				self.middleware.frame(Direction::Outbound, &mut frame)?;
+				let frame_size = frame.frame_size(masked);
+				if frame_size > dst.remaining_mut() {
+					dst.reserve(frame_size);
+				}
+				frame.write_with_key(&mut dst.writer(), self.dataframe_codec.next_mask())?;
+				match self.progress {
+					Some(ref progress) if is_data => {
+						// the frame is only encoded, the bytes go out once the
+						// codec's buffer is flushed
+						progress.report(Progress {
+							direction: Direction::Outbound,
+							bytes: sent,
+							total: Some(total),
+							done: done,
+						})
+					}
+					_ => {}
+				}
+			}
 		}
-		item.serialize(&mut dst.writer(), masked)
+		#[cfg(feature = "metrics")]
+		metrics::sent(&item, masked);
+		Ok(())
 	}
 }
 
@@ -394,6 +709,132 @@ mod tests {
 		tokio::run(f.map_err(|_| ()));
 	}
 
+	#[test]
+	fn claimed_reserved_bits_and_opcodes_pass() {
+		use dataframe::Opcode;
+
+		let mut compressed = DataFrame::new(true, Opcode::Text, b"hi".to_vec());
+		compressed.set_rsv1(true);
+		let frames = vec![
+			compressed,
+			DataFrame::new(false, Opcode::NonControl1, vec![1]),
+			DataFrame::new(true, Opcode::Continuation, vec![2]),
+			DataFrame::new(true, Opcode::Binary, vec![3]),
+		];
+		let mut input = Vec::new();
+		for frame in &frames {
+			frame.write_to(&mut input, false).unwrap();
+		}
+
+		let mut codec = MessageCodec::default(Context::Client);
+		assert!(codec.decode(&mut BytesMut::from(&input[..])).is_err());
+
+		let mut codec = MessageCodec::default(Context::Client);
+		codec.set_reserved_claims(ReservedClaims::new().claim_rsv(1).claim_opcode(Opcode::NonControl1));
+		let mut src = BytesMut::from(&input[..]);
+		assert_eq!(codec.decode(&mut src).unwrap(), Some(OwnedMessage::Text("hi".to_string())));
+		assert_eq!(codec.decode(&mut src).unwrap(), Some(OwnedMessage::Binary(vec![3])));
+	}
+
+	#[test]
+	fn masking_can_be_turned_off() {
+		let mut client = MessageCodec::default(Context::Client);
+		client.dangerous_set_masking(false);
+		let mut dst = BytesMut::new();
+		client.encode(OwnedMessage::Text("hi".to_string()), &mut dst).unwrap();
+		assert_eq!(&dst[..], b"\x81\x02hi");
+
+		// a client reads unmasked frames
+		let mut peer = MessageCodec::default(Context::Client);
+		assert_eq!(peer.decode(&mut dst).unwrap(), Some(OwnedMessage::Text("hi".to_string())));
+	}
+
+	#[test]
+	fn invalid_text_follows_the_utf8_policy() {
+		let frames = vec![
+			DataFrame::new(false, Opcode::Text, b"caf\xc3".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"\xa9 \xff".to_vec()),
+		];
+		let mut input = Vec::new();
+		for frame in &frames {
+			frame.write_to(&mut input, false).unwrap();
+		}
+
+		let mut codec = MessageCodec::default(Context::Client);
+		assert!(codec.decode(&mut BytesMut::from(&input[..])).is_err());
+
+		codec = MessageCodec::default(Context::Client);
+		codec.set_utf8_policy(Utf8Policy::Lossy);
+		assert_eq!(
+			codec.decode(&mut BytesMut::from(&input[..])).unwrap(),
+			Some(OwnedMessage::Text("café \u{fffd}".to_string()))
+		);
+
+		codec.set_utf8_policy(Utf8Policy::Unchecked);
+		assert_eq!(
+			codec.decode(&mut BytesMut::from(&input[..])).unwrap(),
+			Some(OwnedMessage::Binary(b"caf\xc3\xa9 \xff".to_vec()))
+		);
+	}
+
+	#[test]
+	fn large_messages_are_fragmented() {
+		let mut server = MessageCodec::default(Context::Server);
+		server.set_fragment_size(Some(4));
+		let mut dst = BytesMut::new();
+		server.encode(OwnedMessage::Binary(vec![1; 10]), &mut dst).unwrap();
+		server.encode(OwnedMessage::Ping(vec![2; 10]), &mut dst).unwrap();
+
+		let mut frames = DataFrameCodec::default(Context::Client);
+		let mut opcodes = Vec::new();
+		while let Some(frame) = frames.decode(&mut dst).unwrap() {
+			opcodes.push((frame.opcode as u8, frame.finished, frame.data.len()));
+		}
+		assert_eq!(opcodes, vec![(2, false, 4), (0, false, 4), (0, true, 2), (9, true, 10)]);
+	}
+
+	#[test]
+	fn shared_messages_encode_like_owned_ones() {
+		use std::borrow::Cow;
+		use std::sync::Arc;
+
+		let message = OwnedMessage::Binary(vec![7; 300]);
+		let mut owned = BytesMut::new();
+		MessageCodec::default(Context::Server).encode(message.clone(), &mut owned).unwrap();
+
+		let shared = Arc::new(message.clone());
+		for _ in 0..2 {
+			let mut codec = MessageCodec::default(Context::Server).into_message_type();
+			let mut dst = BytesMut::new();
+			codec.encode(shared.clone(), &mut dst).unwrap();
+			assert_eq!(dst, owned);
+		}
+
+		let mut codec = MessageCodec::default(Context::Server).into_message_type();
+		let mut dst = BytesMut::new();
+		codec.encode(Cow::Borrowed(&message), &mut dst).unwrap();
+		assert_eq!(dst, owned);
+		assert_eq!(Arc::strong_count(&shared), 1);
+	}
+
+	#[test]
+	fn borrowed_messages_encode_like_owned_ones() {
+		let text = "unborrowed".to_string();
+		let mut owned = BytesMut::new();
+		MessageCodec::default(Context::Server)
+			.encode(OwnedMessage::Text(text.clone()), &mut owned)
+			.unwrap();
+
+		let mut codec = MessageCodec::new(Context::Server);
+		let mut borrowed = BytesMut::new();
+		codec.encode(Message::text(&text[..]), &mut borrowed).unwrap();
+		assert_eq!(borrowed, owned);
+
+		let mut formatted = BytesMut::new();
+		codec.encode(Message::text_fmt(format_args!("un{}", &text[2..])), &mut formatted).unwrap();
+		assert_eq!(formatted, owned);
+	}
+
 	//test]
 	/*fn message_codec_server_send_receive() {
 		let mut input = Vec::new();
@@ -1,10 +1,17 @@
 //! Module containing the default implementation of data frames.
 use std::io::{self, Read, Write};
 use result::{WebSocketResult, WebSocketError};
+use ws;
 use ws::dataframe::DataFrame as DataFrameable;
-use ws::util::header::DataFrameHeader;
+use ws::util::header::{DataFrameFlags, DataFrameHeader};
 use ws::util::header as dfh;
 use ws::util::mask;
+use pool::BufferPool;
+
+/// The most payload space set aside before any of it arrived, larger
+/// payloads grow their buffer as they are read. Otherwise a peer could make
+/// us allocate whatever length it puts in a header.
+const MAX_PREALLOCATED: u64 = 64 * 1024;
 
 /// Represents a WebSocket data frame.
 ///
@@ -37,6 +44,83 @@ impl DataFrame {
 		}
 	}
 
+	/// Encode `message` as a single frame.
+	pub fn from_message<M>(message: &M) -> WebSocketResult<DataFrame>
+	where
+		M: ws::Message,
+	{
+		let mut buf = Vec::with_capacity(message.message_size(false));
+		message.serialize(&mut buf, false)?;
+		DataFrame::read_dataframe(&mut &buf[..], false)
+	}
+
+	/// Split this frame into frames of at most `size` bytes of payload, the
+	/// first one keeping the opcode and reserved bits, the others being
+	/// continuation frames. Control frames and frames that are small enough
+	/// are left whole.
+	///
+	/// ```rust
+	/// use websocket::dataframe::{DataFrame, Opcode};
+	///
+	/// let frames = DataFrame::new(true, Opcode::Text, b"hello".to_vec()).fragment(2);
+	/// assert_eq!(frames.len(), 3);
+	/// assert_eq!(frames[1].opcode, Opcode::Continuation);
+	/// assert!(!frames[1].finished && frames[2].finished);
+	/// ```
+	///
+	/// # Panics
+	///
+	/// If `size` is 0.
+	pub fn fragment(self, size: usize) -> Vec<DataFrame> {
+		assert!(size > 0, "fragments must not be empty");
+		if self.data.len() <= size || self.opcode as u8 >= 8 {
+			return vec![self];
+		}
+		let count = (self.data.len() + size - 1) / size;
+		self.data
+			.chunks(size)
+			.enumerate()
+			.map(|(i, chunk)| {
+				DataFrame {
+					finished: self.finished && i + 1 == count,
+					reserved: if i == 0 { self.reserved } else { [false; 3] },
+					opcode: if i == 0 { self.opcode } else { Opcode::Continuation },
+					data: chunk.to_vec(),
+				}
+			})
+			.collect()
+	}
+
+	/// Whether the first reserved bit is set.
+	pub fn rsv1(&self) -> bool {
+		self.reserved[0]
+	}
+
+	/// Whether the second reserved bit is set.
+	pub fn rsv2(&self) -> bool {
+		self.reserved[1]
+	}
+
+	/// Whether the third reserved bit is set.
+	pub fn rsv3(&self) -> bool {
+		self.reserved[2]
+	}
+
+	/// Set or clear the first reserved bit.
+	pub fn set_rsv1(&mut self, set: bool) {
+		self.reserved[0] = set;
+	}
+
+	/// Set or clear the second reserved bit.
+	pub fn set_rsv2(&mut self, set: bool) {
+		self.reserved[1] = set;
+	}
+
+	/// Set or clear the third reserved bit.
+	pub fn set_rsv3(&mut self, set: bool) {
+		self.reserved[2] = set;
+	}
+
 	/// Take the body and header of a dataframe and combine it into a single
 	/// Dataframe struct. A websocket message can be made up of many individual
 	/// dataframes, use the methods from the Message or OwnedMessage structs to
@@ -54,7 +138,10 @@ impl DataFrame {
 			header.flags.contains(dfh::RSV3),
 		];
 
-		let opcode = Opcode::new(header.opcode).expect("Invalid header opcode!");
+		let opcode = match Opcode::new(header.opcode) {
+			Some(opcode) => opcode,
+			None => return Err(WebSocketError::DataFrameError("Invalid data frame opcode")),
+		};
 
 		let data = match header.mask {
 			Some(mask) => {
@@ -63,7 +150,9 @@ impl DataFrame {
 						"Expected unmasked data frame",
 					));
 				}
-				mask::mask_data(mask, &body)
+				let mut body = body;
+				mask::mask_data_in_place(mask, &mut body);
+				body
 			}
 			None => {
 				if should_be_masked {
@@ -73,6 +162,11 @@ impl DataFrame {
 			}
 		};
 
+		trace!("read {:?} frame of {} bytes (fin: {})", opcode, data.len(), finished);
+		if opcode == Opcode::Close {
+			debug!("received close frame");
+		}
+
 		Ok(DataFrame {
 			finished: finished,
 			reserved: reserved,
@@ -87,8 +181,34 @@ impl DataFrame {
 		R: Read,
 	{
 		let header = dfh::read_header(reader)?;
+		let data = Vec::with_capacity(header.len.min(MAX_PREALLOCATED) as usize);
+		DataFrame::read_dataframe_payload(reader, header, data, should_be_masked)
+	}
+
+	/// Reads a DataFrame from a Reader like `read_dataframe`, taking the
+	/// buffer for its payload from `pool`.
+	pub fn read_dataframe_pooled<R>(
+		reader: &mut R,
+		should_be_masked: bool,
+		pool: &BufferPool,
+	) -> WebSocketResult<Self>
+	where
+		R: Read,
+	{
+		let header = dfh::read_header(reader)?;
+		let data = pool.get(header.len.min(MAX_PREALLOCATED) as usize);
+		DataFrame::read_dataframe_payload(reader, header, data, should_be_masked)
+	}
 
-		let mut data: Vec<u8> = Vec::with_capacity(header.len as usize);
+	fn read_dataframe_payload<R>(
+		reader: &mut R,
+		header: DataFrameHeader,
+		mut data: Vec<u8>,
+		should_be_masked: bool,
+	) -> WebSocketResult<Self>
+	where
+		R: Read,
+	{
 		let read = reader.take(header.len).read_to_end(&mut data)?;
 		if (read as u64) < header.len {
 			return Err(
@@ -133,6 +253,180 @@ impl DataFrameable for DataFrame {
 	}
 }
 
+/// Sending a `DataFrame` as a message writes it as it is, e.g. to send raw
+/// frames on an asynchronous client framed with a `MessageCodec<DataFrame>`.
+impl ws::Message for DataFrame {
+	/// Writes the frame to the writer
+	fn serialize(&self, writer: &mut Write, masked: bool) -> WebSocketResult<()> {
+		self.write_to(writer, masked)
+	}
+
+	/// Writes the frame, masked with `masking_key` if there is one
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		self.write_with_key(writer, masking_key)
+	}
+
+	/// Returns how many bytes the frame will take up
+	fn message_size(&self, masked: bool) -> usize {
+		self.frame_size(masked)
+	}
+
+	/// Joins the payloads of the frames into a single, final frame
+	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
+	where
+		D: DataFrameable,
+	{
+		let (opcode, reserved) = match frames.first() {
+			Some(first) => (first.opcode(), *first.reserved()),
+			None => return Err(WebSocketError::ProtocolError("No dataframes provided")),
+		};
+		let opcode = Opcode::new(opcode)
+			.ok_or(WebSocketError::ProtocolError("Invalid data frame opcode"))?;
+		let mut data = Vec::new();
+		for frame in frames {
+			data.extend(frame.take_payload());
+		}
+		Ok(DataFrame {
+			finished: true,
+			reserved: reserved,
+			opcode: opcode,
+			data: data,
+		})
+	}
+}
+
+/// Writes a frame with exactly the given flags, opcode and payload, masked
+/// with `masking_key` if there is one.
+///
+/// Only the length of control frames is checked, the frame may well break
+/// the protocol otherwise. This is meant for testing tools and bridges that
+/// forward frames byte for byte.
+pub fn write_raw_dataframe(
+	writer: &mut Write,
+	flags: DataFrameFlags,
+	opcode: Opcode,
+	payload: &[u8],
+	masking_key: Option<[u8; 4]>,
+) -> WebSocketResult<()> {
+	let header = DataFrameHeader {
+		flags: flags,
+		opcode: opcode as u8,
+		mask: masking_key,
+		len: payload.len() as u64,
+	};
+	let mut buf = [0u8; dfh::MAX_HEADER_SIZE];
+	let len = dfh::encode_header(header, &mut buf)?;
+	let mut frame = Vec::with_capacity(len + payload.len());
+	frame.extend_from_slice(&buf[..len]);
+	match masking_key {
+		Some(key) => frame.extend(mask::mask_data(key, payload)),
+		None => frame.extend_from_slice(payload),
+	}
+	writer.write_all(&frame)?;
+	writer.flush()?;
+	Ok(())
+}
+
+/// The reserved bits and non-control opcodes an extension took over.
+///
+/// RFC 6455 requires failing the connection when a frame uses a reserved bit
+/// or opcode no extension defined, which is what the message readers do. An
+/// extension built as a `Middleware` sees every frame as it came in and can
+/// read and change the bits with `DataFrame::rsv1` and friends. Registering
+/// what it claimed with `Receiver::set_reserved_claims` or
+/// `MessageCodec::set_reserved_claims` keeps the message readers from
+/// rejecting what it left in place:
+///
+/// * claimed bits still set once the middleware ran are left out of the
+///   message the frame becomes part of,
+/// * messages with a claimed opcode are skipped by the message readers, as
+///   there is no message type to hold them. Read them with `recv_dataframe`
+///   or in the middleware.
+///
+/// ```rust
+/// use websocket::dataframe::{DataFrame, Opcode, ReservedClaims};
+///
+/// let claims = ReservedClaims::new().claim_rsv(1).claim_opcode(Opcode::NonControl1);
+///
+/// let mut frame = DataFrame::new(true, Opcode::Text, b"hi".to_vec());
+/// frame.set_rsv1(true);
+/// assert!(frame.rsv1());
+/// assert!(claims.claims_rsv(1));
+/// assert!(!claims.claims_rsv(2));
+/// assert!(claims.claims_opcode(Opcode::NonControl1));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReservedClaims {
+	rsv: [bool; 3],
+	/// bit `n` is set when opcode `n` is claimed
+	opcodes: u8,
+}
+
+impl ReservedClaims {
+	/// Nothing claimed, every reserved bit and opcode is rejected.
+	pub fn new() -> Self {
+		ReservedClaims::default()
+	}
+
+	/// Claim reserved bit `bit`, numbered 1 to 3 like RSV1 to RSV3.
+	///
+	/// # Panics
+	///
+	/// If `bit` is not 1, 2 or 3.
+	pub fn claim_rsv(mut self, bit: usize) -> Self {
+		assert!(bit >= 1 && bit <= 3, "reserved bits are numbered 1 to 3");
+		self.rsv[bit - 1] = true;
+		self
+	}
+
+	/// Claim a reserved non-control opcode.
+	///
+	/// # Panics
+	///
+	/// If `opcode` is not one of `Opcode::NonControl1` to
+	/// `Opcode::NonControl5`.
+	pub fn claim_opcode(mut self, opcode: Opcode) -> Self {
+		let op = opcode as u8;
+		assert!(op >= 3 && op <= 7, "only reserved non-control opcodes can be claimed");
+		self.opcodes |= 1 << op;
+		self
+	}
+
+	/// Whether reserved bit `bit` (1 to 3) was claimed.
+	pub fn claims_rsv(&self, bit: usize) -> bool {
+		bit >= 1 && bit <= 3 && self.rsv[bit - 1]
+	}
+
+	/// Whether `opcode` was claimed.
+	pub fn claims_opcode(&self, opcode: Opcode) -> bool {
+		self.opcodes & (1 << opcode as u8) != 0
+	}
+
+	/// Prepare a received frame for the message readers, `skipping` tracks
+	/// whether a fragmented message with a claimed opcode is being skipped.
+	/// Gives `false` for frames to skip.
+	#[doc(hidden)]
+	pub fn admit(&self, frame: &mut DataFrame, skipping: &mut bool) -> bool {
+		match frame.opcode {
+			Opcode::Continuation if *skipping => {
+				*skipping = !frame.finished;
+				return false;
+			}
+			opcode if self.claims_opcode(opcode) => {
+				*skipping = !frame.finished;
+				return false;
+			}
+			_ => {}
+		}
+		for (set, claimed) in frame.reserved.iter_mut().zip(self.rsv.iter()) {
+			if *claimed {
+				*set = false;
+			}
+		}
+		true
+	}
+}
+
 /// Represents a WebSocket data frame opcode
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub enum Opcode {
@@ -238,6 +532,15 @@ mod tests {
 		assert_eq!(more_payload.unwrap().data, payload);
 	}
 
+	#[test]
+	fn read_lying_lengths() {
+		// claims almost 2^63 bytes of payload, sends four
+		let data = [0x82u8, 0x7F, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2, 3, 4];
+		assert!(DataFrame::read_dataframe(&mut &data[..], false).is_err());
+		let pool = BufferPool::default();
+		assert!(DataFrame::read_dataframe_pooled(&mut &data[..], false, &pool).is_err());
+	}
+
 	#[bench]
 	fn bench_read_dataframe(b: &mut Bencher) {
 		let data = b"The quick brown fox jumps over the lazy dog";
@@ -0,0 +1,201 @@
+//! Handling messages piece by piece while their fragments arrive.
+//!
+//! A large message sent in fragments is usually only handed out once its
+//! last fragment arrived. Reading it as `MessageEvent`s instead gives the
+//! payload of every fragment right away, framed by a `MessageStart` and a
+//! `MessageEnd`, so e.g. a JSON or NDJSON parser can get going on the first
+//! bytes. Control messages are handed out as they arrive, which may be in
+//! the middle of a message.
+//!
+//! Synchronous clients and readers have `recv_event` and `incoming_events`.
+//! Asynchronously, wrap a stream of dataframes (a connection framed with a
+//! `DataFrameCodec`) in an `EventStream`.
+//!
+//! ```rust,no_run
+//! use websocket::ClientBuilder;
+//! use websocket::events::MessageEvent;
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//!
+//! let mut received = 0;
+//! for event in client.incoming_events() {
+//!     match event.unwrap() {
+//!         MessageEvent::MessageStart(opcode) => println!("a {:?} message", opcode),
+//!         MessageEvent::MessagePart(bytes) => received += bytes.len(),
+//!         MessageEvent::MessageEnd => println!("done after {} bytes", received),
+//!         MessageEvent::Control(message) => println!("{:?}", message),
+//!     }
+//! }
+//! ```
+use std::collections::VecDeque;
+
+#[cfg(feature = "async")]
+use futures::{Async, Poll, Stream};
+
+use dataframe::{DataFrame, Opcode};
+use message::OwnedMessage;
+use result::{WebSocketError, WebSocketResult};
+use ws::Message;
+
+/// A piece of a message, see the module docs.
+#[derive(Debug, PartialEq, Clone)]
+pub enum MessageEvent {
+	/// A text or binary message starts, its payload follows in parts.
+	MessageStart(Opcode),
+	/// The next bytes of the payload of the current message. The parts of
+	/// text are not checked to be UTF-8, and may split a character.
+	MessagePart(Vec<u8>),
+	/// The current message is complete.
+	MessageEnd,
+	/// A ping, pong or close message.
+	Control(OwnedMessage),
+}
+
+/// Turns the frames of a connection into `MessageEvent`s, checking that
+/// the fragments of messages come in order.
+#[derive(Debug, Default)]
+pub struct EventReader {
+	/// the opcode of the message in progress
+	partial: Option<Opcode>,
+	events: VecDeque<MessageEvent>,
+}
+
+impl EventReader {
+	/// A reader that is not in the middle of a message.
+	pub fn new() -> Self {
+		EventReader::default()
+	}
+
+	/// Read the events of the next `frame` received.
+	pub fn push(&mut self, frame: DataFrame) -> WebSocketResult<()> {
+		if frame.opcode as u8 >= 8 {
+			let message = OwnedMessage::from_dataframes(vec![frame])?;
+			self.events.push_back(MessageEvent::Control(message));
+			return Ok(());
+		}
+		if frame.reserved != [false; 3] {
+			return Err(WebSocketError::ProtocolError(
+				"Unsupported reserved bits received",
+			));
+		}
+		match (frame.opcode, self.partial) {
+			(Opcode::Continuation, Some(_)) => {}
+			(Opcode::Continuation, None) => {
+				return Err(WebSocketError::ProtocolError(
+					"Unexpected continuation data frame opcode",
+				));
+			}
+			(Opcode::Text, None) | (Opcode::Binary, None) => {
+				self.events.push_back(MessageEvent::MessageStart(frame.opcode));
+			}
+			(_, None) => return Err(WebSocketError::ProtocolError("Unsupported opcode received")),
+			(_, Some(_)) => {
+				return Err(WebSocketError::ProtocolError(
+					"Unexpected data frame opcode",
+				));
+			}
+		}
+		if frame.opcode != Opcode::Continuation {
+			self.partial = Some(frame.opcode);
+		}
+		if !frame.data.is_empty() {
+			self.events.push_back(MessageEvent::MessagePart(frame.data));
+		}
+		if frame.finished {
+			self.partial = None;
+			self.events.push_back(MessageEvent::MessageEnd);
+		}
+		Ok(())
+	}
+
+	/// The next event read, `None` once another frame is needed.
+	pub fn pop(&mut self) -> Option<MessageEvent> {
+		self.events.pop_front()
+	}
+}
+
+/// A stream of the `MessageEvent`s of a stream of dataframes, see the
+/// module docs.
+#[cfg(feature = "async")]
+pub struct EventStream<S> {
+	frames: S,
+	reader: EventReader,
+}
+
+#[cfg(feature = "async")]
+impl<S> EventStream<S> {
+	/// Read the events of `frames`.
+	pub fn new(frames: S) -> Self {
+		EventStream {
+			frames: frames,
+			reader: EventReader::new(),
+		}
+	}
+
+	/// Get a reference to the wrapped stream.
+	pub fn get_ref(&self) -> &S {
+		&self.frames
+	}
+
+	/// Get a mutable reference to the wrapped stream.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.frames
+	}
+
+	/// Give the wrapped stream back.
+	pub fn into_inner(self) -> S {
+		self.frames
+	}
+}
+
+#[cfg(feature = "async")]
+impl<S> Stream for EventStream<S>
+where
+	S: Stream<Item = DataFrame, Error = WebSocketError>,
+{
+	type Item = MessageEvent;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<MessageEvent>, WebSocketError> {
+		loop {
+			if let Some(event) = self.reader.pop() {
+				return Ok(Async::Ready(Some(event)));
+			}
+			match self.frames.poll()? {
+				Async::Ready(Some(frame)) => self.reader.push(frame)?,
+				Async::Ready(None) => return Ok(Async::Ready(None)),
+				Async::NotReady => return Ok(Async::NotReady),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fragments_become_parts() {
+		let mut reader = EventReader::new();
+		reader.push(DataFrame::new(false, Opcode::Text, b"[1,".to_vec())).unwrap();
+		assert_eq!(reader.pop(), Some(MessageEvent::MessageStart(Opcode::Text)));
+		assert_eq!(reader.pop(), Some(MessageEvent::MessagePart(b"[1,".to_vec())));
+		assert_eq!(reader.pop(), None);
+
+		reader.push(DataFrame::new(true, Opcode::Ping, vec![7])).unwrap();
+		assert_eq!(reader.pop(), Some(MessageEvent::Control(OwnedMessage::Ping(vec![7]))));
+		assert!(reader.push(DataFrame::new(true, Opcode::Binary, vec![])).is_err());
+
+		reader.push(DataFrame::new(true, Opcode::Continuation, b"2]".to_vec())).unwrap();
+		assert_eq!(reader.pop(), Some(MessageEvent::MessagePart(b"2]".to_vec())));
+		assert_eq!(reader.pop(), Some(MessageEvent::MessageEnd));
+
+		reader.push(DataFrame::new(true, Opcode::Binary, vec![])).unwrap();
+		assert_eq!(reader.pop(), Some(MessageEvent::MessageStart(Opcode::Binary)));
+		assert_eq!(reader.pop(), Some(MessageEvent::MessageEnd));
+		assert!(reader.push(DataFrame::new(true, Opcode::Continuation, vec![1])).is_err());
+	}
+}
@@ -0,0 +1,71 @@
+//! Entry points for fuzzing the parsers that read what peers send.
+//!
+//! They are only built with `--cfg fuzzing`, which `cargo fuzz` sets, and are
+//! driven by the targets in the `fuzz` directory:
+//!
+//! ```text
+//! cargo +nightly fuzz run handshake
+//! cargo +nightly fuzz run frame
+//! ```
+//!
+//! Every entry point is deterministic and takes any bytes, the only failure
+//! is a panic (or a hang), errors are expected and ignored.
+use std::str::{self, FromStr};
+
+use bytes::BytesMut;
+use tokio_io::codec::Decoder;
+
+use codec::http::{HttpClientCodec, HttpServerCodec};
+use codec::ws::{Context, DataFrameCodec, MessageCodec};
+use frames;
+use header::{Origin, WebSocketExtensions, WebSocketProtocol};
+use header::connection::Connection;
+use header::upgrade::Upgrade;
+use message::Message;
+use server::upgrade::validate;
+
+/// Parse `data` as the head of a handshake request, and as the head of a
+/// handshake response, checking the headers a websocket handshake looks at.
+pub fn fuzz_parse_handshake(data: &[u8]) {
+	let mut request = BytesMut::from(data);
+	if let Ok(Some(head)) = HttpServerCodec.decode(&mut request) {
+		let _ = validate(&head.subject.0, &head.version, &head.headers);
+		for value in head.headers.values() {
+			fuzz_parse_headers(value.as_bytes());
+		}
+	}
+
+	let mut response = BytesMut::from(data);
+	if let Ok(Some(head)) = HttpClientCodec::default().decode(&mut response) {
+		for value in head.headers.values() {
+			fuzz_parse_headers(value.as_bytes());
+		}
+	}
+}
+
+/// Parse `data` as the value of each of the websocket headers.
+pub fn fuzz_parse_headers(data: &[u8]) {
+	if let Ok(value) = str::from_utf8(data) {
+		let _ = WebSocketExtensions::from_str(value);
+		let _ = WebSocketProtocol::from_str(value);
+		let _ = Connection::from_str(value);
+		let _ = Upgrade::from_str(value);
+		let _ = Origin::from_str(value);
+	}
+}
+
+/// Parse `data` as frames and messages sent by a client and by a server.
+pub fn fuzz_parse_frame(data: &[u8]) {
+	let _ = frames::decode_header(data);
+	let _ = Message::parse(data);
+
+	for context in vec![Context::Server, Context::Client] {
+		let mut frames = BytesMut::from(data);
+		let mut codec = DataFrameCodec::default(context.clone());
+		while let Ok(Some(_)) = codec.decode(&mut frames) {}
+
+		let mut messages = BytesMut::from(data);
+		let mut codec = MessageCodec::default(context);
+		while let Ok(Some(_)) = codec.decode(&mut messages) {}
+	}
+}
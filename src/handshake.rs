@@ -0,0 +1,53 @@
+//! The parts of the opening handshake that don't depend on an HTTP
+//! implementation, for frameworks that parse requests themselves and only
+//! want to answer an upgrade.
+//!
+//! ```rust
+//! use websocket::handshake::{derive_accept_key, verify_accept_key};
+//! use websocket::header::WebSocketKey;
+//!
+//! // the example of RFC 6455
+//! let key: WebSocketKey = "dGhlIHNhbXBsZSBub25jZQ==".parse().unwrap();
+//! let accept = derive_accept_key(&key);
+//! assert_eq!(accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+//! assert!(verify_accept_key(&key, &accept));
+//! ```
+use header::{WebSocketAccept, WebSocketKey};
+
+/// The GUID every key is concatenated with to get its accept value.
+pub const MAGIC_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The value of the `Sec-WebSocket-Accept` header answering a request with
+/// `key`: the base64 of the SHA-1 of the key and `MAGIC_GUID`.
+pub fn derive_accept_key(key: &WebSocketKey) -> String {
+	WebSocketAccept::new(*key).to_string()
+}
+
+/// Whether `accept`, the `Sec-WebSocket-Accept` header of a response,
+/// answers a request with `key`.
+pub fn verify_accept_key(key: &WebSocketKey, accept: &str) -> bool {
+	match accept.trim().parse::<WebSocketAccept>() {
+		Ok(accept) => accept == WebSocketAccept::new(*key),
+		Err(_) => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::{SeedableRng, StdRng};
+
+	#[test]
+	fn seeded_keys_repeat() {
+		let seed: &[_] = &[1, 2, 3, 4];
+		let first = WebSocketKey::random_with(&mut StdRng::from_seed(seed));
+		let second = WebSocketKey::random_with(&mut StdRng::from_seed(seed));
+		assert_eq!(first, second);
+		assert!(first != WebSocketKey::new());
+
+		let accept = derive_accept_key(&first);
+		assert!(verify_accept_key(&first, &accept));
+		assert!(!verify_accept_key(&WebSocketKey::new(), &accept));
+		assert!(!verify_accept_key(&first, "not base64!"));
+	}
+}
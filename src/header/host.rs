@@ -2,6 +2,8 @@ use std::borrow::Cow;
 use std::fmt;
 use std::str::FromStr;
 
+use http::header::HeaderValue;
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct Host {
 	hostname: Cow<'static, str>,
@@ -38,10 +40,22 @@ impl fmt::Display for Host {
 	}
 }
 
+impl From<Host> for HeaderValue {
+	fn from(host: Host) -> Self {
+		HeaderValue::from_str(&host.to_string()).unwrap()
+	}
+}
+
 impl FromStr for Host {
 	type Err = ();
 	fn from_str(s: &str) -> Result<Host, Self::Err> {
-		let idx = s.rfind(':');
+		// the colons of an IPv6 address are not followed by a port, the
+		// address is in brackets when one is
+		let idx = match s.rfind(':') {
+			Some(idx) if !s.starts_with('[') && s[..idx].contains(':') => None,
+			Some(idx) if s.starts_with('[') && !s[..idx].ends_with(']') => None,
+			idx => idx,
+		};
 		let port = idx.and_then(|idx| s[idx + 1..].parse().ok());
 		let hostname = match port {
 			None => s,
@@ -54,3 +68,21 @@ impl FromStr for Host {
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_ipv6_hosts() {
+		let host: Host = "[::1]:9001".parse().unwrap();
+		assert_eq!((host.hostname(), host.port()), ("[::1]", Some(9001)));
+		let host: Host = "[::1]".parse().unwrap();
+		assert_eq!((host.hostname(), host.port()), ("[::1]", None));
+		let host: Host = "::1".parse().unwrap();
+		assert_eq!((host.hostname(), host.port()), ("::1", None));
+		let host: Host = "example.com:8080".parse().unwrap();
+		assert_eq!((host.hostname(), host.port()), ("example.com", Some(8080)));
+		assert_eq!(Host::new("[::1]", 9001).to_string(), "[::1]:9001");
+	}
+}
@@ -1,7 +1,8 @@
 //! Structs representing headers relevant in a WebSocket context.
 //!
 //! These headers are commonly used in WebSocket requests and responses.
-//! The `Header` trait from the `hyper` crate is used.
+//! `HeaderMapExt` gets and sets them on the `HeaderMap` of a request or
+//! response.
 
 pub use self::host::Host;
 pub use self::origin::Origin;
@@ -11,6 +12,7 @@ pub use self::sec_websocket_protocol::WebSocketProtocol;
 pub use self::sec_websocket_version::WebSocketVersion;
 pub use self::sec_websocket_extensions::WebSocketExtensions;
 pub use self::upgrade::Upgrade;
+pub use self::typed::{HeaderMapExt, TypedHeader};
 
 pub mod connection;
 mod host;
@@ -19,5 +21,7 @@ mod sec_websocket_accept;
 mod sec_websocket_key;
 mod sec_websocket_protocol;
 mod sec_websocket_version;
+mod typed;
 pub mod sec_websocket_extensions;
 pub mod upgrade;
+pub mod wire;
@@ -2,6 +2,8 @@ use std::borrow::Cow;
 use std::fmt;
 use std::str::FromStr;
 
+use http::header::HeaderValue;
+
 use header::Host;
 
 /// Represents an Origin header
@@ -82,6 +84,12 @@ impl FromStr for Origin {
 	}
 }
 
+impl From<Origin> for HeaderValue {
+	fn from(origin: Origin) -> Self {
+		HeaderValue::from_str(&origin.to_string()).unwrap()
+	}
+}
+
 impl fmt::Display for Origin {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self.0 {
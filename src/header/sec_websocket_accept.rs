@@ -3,12 +3,11 @@ use base64;
 use http::header::HeaderValue;
 use std::fmt::{self, Debug};
 use std::str::FromStr;
+use handshake::MAGIC_GUID;
 use header::WebSocketKey;
 use result::{WebSocketResult, WebSocketError};
 use sha1::Sha1;
 
-static MAGIC_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
-
 /// Represents a Sec-WebSocket-Accept header
 #[derive(PartialEq, Clone, Copy)]
 pub struct WebSocketAccept([u8; 20]);
@@ -1,6 +1,5 @@
 //! Provides the Sec-WebSocket-Extensions header.
 
-//use hyper::header::parsing::{from_comma_delimited,};
 use std::fmt;
 use std::str::FromStr;
 use std::ops::Deref;
@@ -10,8 +9,7 @@ use http::header::HeaderValue;
 use result::{WebSocketResult, WebSocketError};
 
 const INVALID_EXTENSION: &'static str = "Invalid Sec-WebSocket-Extensions extension name";
-
-// TODO: check if extension name is valid according to spec
+const INVALID_PARAMETER: &'static str = "Invalid Sec-WebSocket-Extensions extension parameter";
 
 /// Represents a Sec-WebSocket-Extensions header
 #[derive(PartialEq, Clone, Debug)]
@@ -28,27 +26,40 @@ impl Deref for WebSocketExtensions {
 impl FromStr for WebSocketExtensions {
 	type Err = ();
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		Ok(WebSocketExtensions(
-			s.split(',')
-			 .map(|s| s.trim().parse::<Extension>().unwrap())
-			 .collect(),
-		))
+		split_unquoted(s, ',')
+			.map_err(|_| ())?
+			.into_iter()
+			.filter(|s| !s.trim().is_empty())
+			.map(|s| s.parse::<Extension>())
+			.collect::<WebSocketResult<Vec<Extension>>>()
+			.map(WebSocketExtensions)
+			.map_err(|_| ())
 	}
 }
 
 impl From<WebSocketExtensions> for HeaderValue {
 	fn from(extensions: WebSocketExtensions) -> Self {
-		HeaderValue::from_str(&extensions.0
-		           .iter()
-		           .map(|e| format!("{}", e))
-		           .collect::<Vec<String>>()
-		           .join(", "))
-		.unwrap()
+		HeaderValue::from_str(&extensions.to_string()).unwrap()
 	}
 }
 
+/// A WebSocket extension, e.g. `permessage-deflate; client_max_window_bits=10`.
+///
+/// ```rust
+/// use websocket::header::sec_websocket_extensions::Extension;
+///
+/// let deflate = Extension::new("permessage-deflate")
+///     .with_param("server_no_context_takeover")
+///     .with_param_value("client_max_window_bits", 10);
+/// assert_eq!(
+///     deflate.to_string(),
+///     "permessage-deflate; server_no_context_takeover; client_max_window_bits=10"
+/// );
+///
+/// let parsed: Extension = "permessage-deflate; client_max_window_bits=\"10\"".parse().unwrap();
+/// assert_eq!(parsed.param("client_max_window_bits").unwrap().value, Some("10".to_string()));
+/// ```
 #[derive(PartialEq, Clone, Debug)]
-/// A WebSocket extension
 pub struct Extension {
 	/// The name of this extension
 	pub name: String,
@@ -64,27 +75,39 @@ impl Extension {
 			params: Vec::new(),
 		}
 	}
+
+	/// Add a parameter without a value.
+	pub fn with_param(mut self, name: &str) -> Extension {
+		self.params.push(Parameter::new(name.to_owned(), None));
+		self
+	}
+
+	/// Add a parameter with a value, it is quoted when written if it needs
+	/// to be.
+	pub fn with_param_value<V: ToString>(mut self, name: &str, value: V) -> Extension {
+		self.params.push(Parameter::new(name.to_owned(), Some(value.to_string())));
+		self
+	}
+
+	/// The first parameter called `name`, ignoring case.
+	pub fn param(&self, name: &str) -> Option<&Parameter> {
+		self.params.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+	}
 }
 
 impl FromStr for Extension {
 	type Err = WebSocketError;
 
 	fn from_str(s: &str) -> WebSocketResult<Extension> {
-		let mut ext = s.split(';').map(|x| x.trim());
+		let mut ext = split_unquoted(s, ';')?.into_iter().map(|x| x.trim());
+		let name = match ext.next() {
+			Some(x) if is_token(x) => x.to_string(),
+			_ => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
+		};
+		let params = ext.map(|x| x.parse()).collect::<WebSocketResult<Vec<Parameter>>>()?;
 		Ok(Extension {
-			name: match ext.next() {
-				Some(x) => x.to_string(),
-				None => return Err(WebSocketError::ProtocolError(INVALID_EXTENSION)),
-			},
-			params: ext.map(|x| {
-				let mut pair = x.splitn(1, '=').map(|x| x.trim().to_string());
-
-				Parameter {
-					name: pair.next().unwrap(),
-					value: pair.next(),
-				}
-			})
-			           .collect(),
+			name: name,
+			params: params,
 		})
 	}
 }
@@ -104,7 +127,7 @@ impl fmt::Display for Extension {
 pub struct Parameter {
 	/// The name of this parameter
 	pub name: String,
-	/// The value of this parameter, if any
+	/// The value of this parameter, if any, without quotes
 	pub value: Option<String>,
 }
 
@@ -118,16 +141,101 @@ impl Parameter {
 	}
 }
 
+impl FromStr for Parameter {
+	type Err = WebSocketError;
+
+	fn from_str(s: &str) -> WebSocketResult<Parameter> {
+		let mut pair = s.splitn(2, '=').map(|x| x.trim());
+		let name = match pair.next() {
+			Some(name) if is_token(name) => name.to_string(),
+			_ => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+		};
+		let value = match pair.next() {
+			None => None,
+			Some(value) if value.starts_with('"') => Some(unquote(value)?),
+			Some(value) if is_token(value) => Some(value.to_string()),
+			Some(_) => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+		};
+		Ok(Parameter::new(name, value))
+	}
+}
+
 impl fmt::Display for Parameter {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{}", self.name)?;
-		if let Some(ref x) = self.value {
-			write!(f, "={}", x)?;
+		match self.value {
+			Some(ref x) if is_token(x) => write!(f, "={}", x)?,
+			Some(ref x) => {
+				f.write_str("=\"")?;
+				for c in x.chars() {
+					if c == '"' || c == '\\' {
+						f.write_str("\\")?;
+					}
+					write!(f, "{}", c)?;
+				}
+				f.write_str("\"")?;
+			}
+			None => (),
 		}
 		Ok(())
 	}
 }
 
+/// Whether `s` is a token of RFC 7230, which may go without quotes.
+fn is_token(s: &str) -> bool {
+	!s.is_empty() && s.bytes().all(|b| match b {
+		b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' => true,
+		b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' |
+		b'`' | b'|' | b'~' => true,
+		_ => false,
+	})
+}
+
+/// Split `s` at every `separator` that isn't in a quoted-string.
+fn split_unquoted(s: &str, separator: char) -> WebSocketResult<Vec<&str>> {
+	let mut parts = Vec::new();
+	let mut start = 0;
+	let mut quoted = false;
+	let mut escaped = false;
+	for (i, c) in s.char_indices() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if quoted => escaped = true,
+			'"' => quoted = !quoted,
+			c if c == separator && !quoted => {
+				parts.push(&s[start..i]);
+				start = i + 1;
+			}
+			_ => (),
+		}
+	}
+	if quoted {
+		return Err(WebSocketError::ProtocolError(INVALID_PARAMETER));
+	}
+	parts.push(&s[start..]);
+	Ok(parts)
+}
+
+/// The contents of the quoted-string `s`.
+fn unquote(s: &str) -> WebSocketResult<String> {
+	if s.len() < 2 || !s.ends_with('"') {
+		return Err(WebSocketError::ProtocolError(INVALID_PARAMETER));
+	}
+	let mut value = String::with_capacity(s.len() - 2);
+	let mut chars = s[1..s.len() - 1].chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some(c) => value.push(c),
+				None => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+			},
+			'"' => return Err(WebSocketError::ProtocolError(INVALID_PARAMETER)),
+			c => value.push(c),
+		}
+	}
+	Ok(value)
+}
+
 // impl Header for WebSocketExtensions {
 // 	fn header_name() -> &'static str {
 // 		"Sec-WebSocket-Extensions"
@@ -144,7 +252,13 @@ impl fmt::Display for Parameter {
 
 impl fmt::Display for WebSocketExtensions {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-		fmt::Display::fmt(self, fmt)
+		for (i, extension) in self.0.iter().enumerate() {
+			if i > 0 {
+				fmt.write_str(", ")?;
+			}
+			write!(fmt, "{}", extension)?;
+		}
+		Ok(())
 	}
 }
 
@@ -184,3 +298,31 @@ mod tests {
 		});
 	}
 }
+
+#[cfg(test)]
+mod parse_tests {
+	use super::*;
+
+	#[test]
+	fn params_with_values() {
+		let header: WebSocketExtensions =
+			"permessage-deflate; client_max_window_bits=10; server_no_context_takeover, \
+			 x-webkit; note=\"a, b; \\\"c\\\"\"".parse().unwrap();
+		assert_eq!(header.len(), 2);
+		assert_eq!(
+			header[0],
+			Extension::new("permessage-deflate")
+				.with_param_value("client_max_window_bits", 10)
+				.with_param("server_no_context_takeover")
+		);
+		assert_eq!(header[1].param("NOTE").unwrap().value, Some("a, b; \"c\"".to_string()));
+		assert_eq!(header.to_string().parse::<WebSocketExtensions>(), Ok(header));
+	}
+
+	#[test]
+	fn malformed_params() {
+		for value in &["deflate; =1", "deflate; a=", "deflate; a=\"b", "deflate; a=b c", "a b"] {
+			assert!(value.parse::<WebSocketExtensions>().is_err(), "{}", value);
+		}
+	}
+}
@@ -1,4 +1,3 @@
-use std::mem;
 use std::str::FromStr;
 #[cfg(feature = "nightly")]
 use std::convert::TryFrom;
@@ -7,7 +6,7 @@ use base64;
 use http::header::HeaderValue;
 //use hyper::header::parsing::from_one_raw_str;
 use std::fmt::{self, Debug};
-use rand;
+use rand::{self, Rng};
 use result::{WebSocketResult, WebSocketError};
 
 /// Represents a Sec-WebSocket-Key header.
@@ -50,10 +49,14 @@ impl WebSocketKey {
 	pub const HEADER_NAME: &'static str = "Sec-WebSocket-Key";
 	/// Generate a new, random WebSocketKey
 	pub fn new() -> WebSocketKey {
-		let key: [u8; 16] = unsafe {
-			// Much faster than calling random()q several times
-			mem::transmute(rand::random::<(u64, u64)>())
-		};
+		WebSocketKey::random_with(&mut rand::thread_rng())
+	}
+
+	/// Generate a new WebSocketKey with the random numbers of `rng`, e.g. a
+	/// seeded one to make handshakes reproducible in tests.
+	pub fn random_with<R: Rng>(rng: &mut R) -> WebSocketKey {
+		let mut key = [0u8; 16];
+		rng.fill_bytes(&mut key);
 		WebSocketKey(key)
 	}
 }
@@ -68,7 +71,7 @@ impl From<WebSocketKey> for HeaderValue {
 impl TryFrom<HeaderValue> for WebSocketKey {
 	type Error = ();
 	fn try_from(value: HeaderValue) -> Result<WebSocketKey, ()> {
-		Ok(WebSocketKey([0u8; 16]))
+		value.to_str().map_err(|_| ())?.parse().map_err(|_| ())
 	}
 }
 
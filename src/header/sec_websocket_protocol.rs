@@ -35,7 +35,7 @@ impl From<WebSocketProtocol> for HeaderValue {
 
 impl fmt::Display for WebSocketProtocol {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		Ok(())
+		f.write_str(&self.0.join(", "))
 	}
 }
 
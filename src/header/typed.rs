@@ -0,0 +1,147 @@
+//! Getting and setting the headers of this module on a `HeaderMap`.
+//!
+//! ```rust
+//! # extern crate http;
+//! # extern crate websocket;
+//! # fn main() {
+//! use http::header::HeaderMap;
+//! use websocket::header::{HeaderMapExt, WebSocketProtocol};
+//!
+//! let mut headers = HeaderMap::new();
+//! headers.typed_insert(WebSocketProtocol(vec!["chat".to_string(), "superchat".to_string()]));
+//!
+//! let protocols: WebSocketProtocol = headers.typed_get().unwrap();
+//! assert_eq!(protocols.0, vec!["chat", "superchat"]);
+//! # }
+//! ```
+use std::str::FromStr;
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::header::{CONNECTION, HOST, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS,
+                   SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE};
+
+use header::{Host, Origin, Upgrade, WebSocketAccept, WebSocketExtensions, WebSocketKey,
+             WebSocketProtocol, WebSocketVersion};
+use header::connection::Connection;
+
+/// A header which knows its name and how to read and write its value.
+pub trait TypedHeader: Sized {
+	/// The name of the header.
+	fn header_name() -> HeaderName;
+
+	/// Parse the header from its value, the values of a header sent on
+	/// several lines are joined with commas first.
+	fn parse_value(value: &str) -> Option<Self>;
+
+	/// The value of the header.
+	fn to_value(self) -> HeaderValue;
+}
+
+macro_rules! typed_header {
+	($header:ty, $name:expr) => {
+		impl TypedHeader for $header {
+			fn header_name() -> HeaderName {
+				$name
+			}
+
+			fn parse_value(value: &str) -> Option<Self> {
+				<$header as FromStr>::from_str(value).ok()
+			}
+
+			fn to_value(self) -> HeaderValue {
+				self.into()
+			}
+		}
+	};
+}
+
+typed_header!(WebSocketAccept, SEC_WEBSOCKET_ACCEPT);
+typed_header!(WebSocketExtensions, SEC_WEBSOCKET_EXTENSIONS);
+typed_header!(WebSocketKey, SEC_WEBSOCKET_KEY);
+typed_header!(WebSocketProtocol, SEC_WEBSOCKET_PROTOCOL);
+typed_header!(WebSocketVersion, SEC_WEBSOCKET_VERSION);
+typed_header!(Connection, CONNECTION);
+typed_header!(Upgrade, UPGRADE);
+typed_header!(Origin, ORIGIN);
+typed_header!(Host, HOST);
+
+/// Typed access to the headers of a `HeaderMap`, instead of parsing the
+/// values by hand.
+pub trait HeaderMapExt {
+	/// The header `H`, `None` if it is missing or can't be parsed.
+	fn typed_get<H: TypedHeader>(&self) -> Option<H>;
+
+	/// Set the header `H`, replacing all values it had.
+	fn typed_insert<H: TypedHeader>(&mut self, header: H);
+
+	/// Remove the header `H`, giving back what it was.
+	fn typed_remove<H: TypedHeader>(&mut self) -> Option<H>;
+}
+
+impl HeaderMapExt for HeaderMap {
+	fn typed_get<H: TypedHeader>(&self) -> Option<H> {
+		let mut joined = String::new();
+		for value in self.get_all(H::header_name()) {
+			if !joined.is_empty() {
+				joined.push_str(", ");
+			}
+			match value.to_str() {
+				Ok(value) => joined.push_str(value),
+				Err(_) => return None,
+			}
+		}
+		if joined.is_empty() && !self.contains_key(H::header_name()) {
+			return None;
+		}
+		H::parse_value(&joined)
+	}
+
+	fn typed_insert<H: TypedHeader>(&mut self, header: H) {
+		self.insert(H::header_name(), header.to_value());
+	}
+
+	fn typed_remove<H: TypedHeader>(&mut self) -> Option<H> {
+		let header = self.typed_get();
+		self.remove(H::header_name());
+		header
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn values_on_several_lines() {
+		let mut headers = HeaderMap::new();
+		headers.append(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("chat"));
+		headers.append(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("v2.chat, v3.chat"));
+		let protocols: WebSocketProtocol = headers.typed_get().unwrap();
+		assert_eq!(protocols.0, vec!["chat", "v2.chat", "v3.chat"]);
+
+		// a header that may only be sent once is not valid twice
+		headers.append(SEC_WEBSOCKET_KEY, HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="));
+		assert!(headers.typed_get::<WebSocketKey>().is_some());
+		headers.append(SEC_WEBSOCKET_KEY, HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="));
+		assert!(headers.typed_get::<WebSocketKey>().is_none());
+	}
+
+	#[test]
+	fn insert_then_get() {
+		let mut headers = HeaderMap::new();
+		assert!(headers.typed_get::<Origin>().is_none());
+
+		headers.typed_insert(WebSocketVersion::WebSocket13);
+		headers.typed_insert(Origin::new("https", "example.com", None));
+		headers.typed_insert(Host::new("example.com", 8080));
+		assert_eq!(headers.get(SEC_WEBSOCKET_VERSION).unwrap(), "13");
+		assert_eq!(headers.get(HOST).unwrap(), "example.com:8080");
+		assert_eq!(headers.typed_get(), Some(WebSocketVersion::WebSocket13));
+		assert_eq!(headers.typed_get(), Some(Origin::new("https", "example.com", None)));
+
+		let key = WebSocketKey::new();
+		headers.typed_insert(key);
+		assert_eq!(headers.typed_remove(), Some(key));
+		assert!(!headers.contains_key(SEC_WEBSOCKET_KEY));
+	}
+}
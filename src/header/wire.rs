@@ -0,0 +1,119 @@
+//! Writing headers the way they go over the wire.
+//!
+//! A `HeaderMap` keeps its names in lowercase and can hold several values
+//! for a name. When written, every value gets a line of its own, in the order
+//! it was added, and its bytes are copied as they are. Values are never
+//! folded onto several lines (RFC 7230 deprecates that), and never joined
+//! with commas, which would break headers like `Set-Cookie`.
+//!
+//! Names are written in their usual casing, since a few servers out there
+//! still care about it even though they shouldn't:
+//!
+//! ```rust
+//! # extern crate http;
+//! # extern crate websocket;
+//! # fn main() {
+//! use http::header::{HeaderMap, HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+//! use websocket::header::wire::write_headers;
+//!
+//! let mut headers = HeaderMap::new();
+//! headers.append(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("chat"));
+//! headers.append(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("superchat"));
+//!
+//! let mut written = Vec::new();
+//! write_headers(&headers, &mut written);
+//! assert_eq!(
+//!     &written[..],
+//!     &b"Sec-WebSocket-Protocol: chat\r\nSec-WebSocket-Protocol: superchat\r\n"[..]
+//! );
+//! # }
+//! ```
+use http::header::{HeaderMap, HeaderName};
+
+/// Words of header names which aren't just capitalized.
+const SPECIAL_WORDS: [(&'static str, &'static str); 6] = [
+	("websocket", "WebSocket"),
+	("www", "WWW"),
+	("te", "TE"),
+	("dnt", "DNT"),
+	("etag", "ETag"),
+	("md5", "MD5"),
+];
+
+/// The usual casing of a header name, e.g. `Sec-WebSocket-Key` for
+/// `sec-websocket-key`.
+pub fn canonical_name(name: &HeaderName) -> String {
+	let mut canonical = String::with_capacity(name.as_str().len());
+	for (i, word) in name.as_str().split('-').enumerate() {
+		if i > 0 {
+			canonical.push('-');
+		}
+		match SPECIAL_WORDS.iter().find(|&&(lower, _)| lower == word) {
+			Some(&(_, special)) => canonical.push_str(special),
+			None => {
+				let mut chars = word.chars();
+				if let Some(first) = chars.next() {
+					canonical.push(first.to_ascii_uppercase());
+					canonical.extend(chars);
+				}
+			}
+		}
+	}
+	canonical
+}
+
+/// Append a `Name: value` line for every value in `headers` to `dst`.
+pub fn write_headers(headers: &HeaderMap, dst: &mut Vec<u8>) {
+	for (name, value) in headers {
+		dst.extend_from_slice(canonical_name(name).as_bytes());
+		dst.extend_from_slice(b": ");
+		dst.extend_from_slice(value.as_bytes());
+		dst.extend_from_slice(b"\r\n");
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::header::{HeaderValue, SET_COOKIE, WWW_AUTHENTICATE};
+	use httparse;
+
+	#[test]
+	fn names_are_cased() {
+		let cased = |name: &str| canonical_name(&name.parse().unwrap());
+		assert_eq!(cased("sec-websocket-extensions"), "Sec-WebSocket-Extensions");
+		assert_eq!(cased("www-authenticate"), "WWW-Authenticate");
+		assert_eq!(cased("x-forwarded-for"), "X-Forwarded-For");
+		assert_eq!(cased("x--odd-"), "X--Odd-");
+	}
+
+	#[test]
+	fn values_round_trip() {
+		let mut headers = HeaderMap::new();
+		headers.append(SET_COOKIE, HeaderValue::from_static("a=1, b"));
+		headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+		headers.append(SET_COOKIE, HeaderValue::from_static("c=2"));
+		headers.append("x-raw", HeaderValue::from_bytes(b"caf\xe9\t ok").unwrap());
+
+		let mut written = Vec::new();
+		write_headers(&headers, &mut written);
+		written.extend_from_slice(b"\r\n");
+
+		let mut parsed = [httparse::EMPTY_HEADER; 8];
+		match httparse::parse_headers(&written, &mut parsed).unwrap() {
+			httparse::Status::Complete((_, parsed)) => {
+				let parsed: Vec<_> = parsed.iter().map(|h| (h.name, h.value)).collect();
+				let expected: Vec<_> = headers
+					.iter()
+					.map(|(name, value)| (name.as_str(), value.as_bytes()))
+					.collect();
+				assert_eq!(parsed.len(), expected.len());
+				for (&(name, value), &(lower, original)) in parsed.iter().zip(expected.iter()) {
+					assert!(name.eq_ignore_ascii_case(lower));
+					assert_eq!(value, original);
+				}
+			}
+			_ => panic!("headers were cut short"),
+		}
+	}
+}
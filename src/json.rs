@@ -0,0 +1,234 @@
+//! Sending and receiving messages as JSON.
+//!
+//! With the `serde` feature on, synchronous clients get `send_json` and
+//! `recv_json`, and asynchronous clients can be wrapped in a `TypedClient`
+//! whose items are deserialized structs instead of `OwnedMessage`s. Values
+//! are sent as text messages, both text and binary messages are read.
+//!
+//! ```rust,no_run
+//! # #[macro_use] extern crate serde_derive;
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::ClientBuilder;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Tick {
+//!     price: u64,
+//! }
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//!
+//! client.send_json(&Tick { price: 100 }).unwrap();
+//! let tick: Tick = client.recv_json().unwrap();
+//! # }
+//! ```
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use message::OwnedMessage;
+use result::WebSocketResult;
+
+#[cfg(feature = "async")]
+use std::marker::PhantomData;
+#[cfg(feature = "async")]
+use serializer::{Json, MessageSerializer};
+#[cfg(feature = "async")]
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+#[cfg(feature = "async")]
+use result::WebSocketError;
+
+/// Serialize `value` into a text message.
+pub fn encode<T>(value: &T) -> WebSocketResult<OwnedMessage>
+where
+	T: Serialize + ?Sized,
+{
+	Ok(OwnedMessage::Text(serde_json::to_string(value)?))
+}
+
+/// Deserialize the payload of a text or binary message, gives `None` for
+/// control messages.
+pub fn decode<T>(message: &OwnedMessage) -> Option<WebSocketResult<T>>
+where
+	T: DeserializeOwned,
+{
+	let value = match *message {
+		OwnedMessage::Text(ref text) => serde_json::from_str(text),
+		OwnedMessage::Binary(ref data) => serde_json::from_slice(data),
+		_ => return None,
+	};
+	Some(value.map_err(Into::into))
+}
+
+/// An asynchronous client whose messages are JSON, receiving `T`s and
+/// sending `U`s. Messages can be in another format by picking another
+/// `MessageSerializer` for `F`.
+///
+/// Pings are answered and pongs are skipped, the stream ends once the peer
+/// sends a close message. A message that does not deserialize into a `T`
+/// fails the stream with the error of the format.
+///
+/// ```rust,no_run
+/// # extern crate futures;
+/// # extern crate tokio;
+/// # #[macro_use] extern crate serde_derive;
+/// # extern crate websocket;
+/// # fn main() {
+/// use futures::{Future, Sink, Stream};
+/// use websocket::ClientBuilder;
+/// use websocket::json::TypedClient;
+///
+/// #[derive(Serialize)]
+/// struct Subscribe {
+///     topic: String,
+/// }
+///
+/// #[derive(Deserialize, Debug)]
+/// struct Tick {
+///     price: u64,
+/// }
+///
+/// let ticks = ClientBuilder::new("ws://127.0.0.1:1234")
+///     .unwrap()
+///     .async_connect_insecure()
+///     .and_then(|(client, _)| {
+///         let client: TypedClient<Tick, Subscribe, _> = TypedClient::new(client);
+///         client.send(Subscribe { topic: "prices".into() })
+///     })
+///     .and_then(|client| client.for_each(|tick| Ok(println!("{:?}", tick))));
+///
+/// tokio::run(ticks.map_err(|e| println!("{}", e)));
+/// # }
+/// ```
+#[cfg(feature = "async")]
+pub struct TypedClient<T, U, C, F = Json> {
+	inner: C,
+	pong: Option<OwnedMessage>,
+	_items: PhantomData<fn(U, F) -> T>,
+}
+
+#[cfg(feature = "async")]
+impl<T, U, C, F> TypedClient<T, U, C, F>
+where
+	F: MessageSerializer,
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	/// Start sending and receiving JSON over a client.
+	pub fn new(inner: C) -> Self {
+		TypedClient {
+			inner: inner,
+			pong: None,
+			_items: PhantomData,
+		}
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Give the wrapped client back.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+
+	fn send_pong(&mut self) -> Result<(), WebSocketError> {
+		if let Some(pong) = self.pong.take() {
+			if let AsyncSink::NotReady(pong) = self.inner.start_send(pong)? {
+				self.pong = Some(pong);
+				return Ok(());
+			}
+			self.inner.poll_complete()?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "async")]
+impl<T, U, C, F> Stream for TypedClient<T, U, C, F>
+where
+	T: DeserializeOwned,
+	F: MessageSerializer,
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = T;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<T>, WebSocketError> {
+		loop {
+			self.send_pong()?;
+			if self.pong.is_some() {
+				return Ok(Async::NotReady);
+			}
+			let message = match self.inner.poll()? {
+				Async::Ready(Some(message)) => message,
+				Async::Ready(None) => return Ok(Async::Ready(None)),
+				Async::NotReady => return Ok(Async::NotReady),
+			};
+			if let Some(value) = F::deserialize(&message) {
+				return value.map(|value| Async::Ready(Some(value)));
+			}
+			match message {
+				OwnedMessage::Ping(data) => self.pong = Some(OwnedMessage::Pong(data)),
+				OwnedMessage::Close(_) => return Ok(Async::Ready(None)),
+				_ => {}
+			}
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl<T, U, C, F> Sink for TypedClient<T, U, C, F>
+where
+	U: Serialize,
+	F: MessageSerializer,
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = U;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: U) -> StartSend<U, WebSocketError> {
+		match self.inner.start_send(F::serialize(&item)?)? {
+			AsyncSink::Ready => Ok(AsyncSink::Ready),
+			AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(item)),
+		}
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use result::WebSocketError;
+
+	#[test]
+	fn decodes_text_and_binary() {
+		let text: Vec<u32> = decode(&OwnedMessage::Text("[1,2]".into())).unwrap().unwrap();
+		assert_eq!(text, vec![1, 2]);
+		let binary: Vec<u32> = decode(&OwnedMessage::Binary(b"[3]".to_vec())).unwrap().unwrap();
+		assert_eq!(binary, vec![3]);
+		assert!(decode::<Vec<u32>>(&OwnedMessage::Ping(Vec::new())).is_none());
+		match decode::<Vec<u32>>(&OwnedMessage::Text("{".into())) {
+			Some(Err(WebSocketError::JsonError(ref e))) if e.is::<serde_json::Error>() => {}
+			other => panic!("unexpected {:?}", other.map(|r| r.is_ok())),
+		}
+		assert_eq!(encode(&vec![1, 2]).unwrap(), OwnedMessage::Text("[1,2]".into()));
+	}
+}
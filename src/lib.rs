@@ -38,33 +38,70 @@ extern crate rand;
 extern crate byteorder;
 extern crate sha1;
 extern crate base64;
+extern crate net2;
+#[cfg(target_os = "linux")]
+extern crate libc;
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 extern crate native_tls;
 #[cfg(feature = "async")]
 extern crate tokio;
 #[cfg(feature = "async")]
 extern crate tokio_io;
+#[cfg(all(unix, feature = "async"))]
+extern crate tokio_uds;
 #[cfg(feature = "async")]
 extern crate bytes;
 #[cfg(feature = "async")]
 pub extern crate futures;
 #[cfg(feature = "async-ssl")]
 extern crate tokio_tls;
-
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "cbor")]
+extern crate ciborium;
+
+pub extern crate websocket_frames as frames;
+#[cfg(feature = "log")]
 #[macro_use]
-extern crate bitflags;
+extern crate log;
 
 #[cfg(all(feature = "nightly", test))]
-extern crate test;
+extern crate test as bench;
+
+#[macro_use]
+mod logging;
 
 pub mod ws;
 pub mod dataframe;
 pub mod message;
+pub mod close;
+pub mod events;
 pub mod result;
 pub mod header;
+pub mod handshake;
+pub mod pool;
+pub mod middleware;
+pub mod progress;
+pub mod stats;
+pub mod pause;
+pub mod ping;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "serde")]
+pub mod serializer;
 
 #[cfg(feature = "async")]
 pub mod codec;
+#[cfg(feature = "async")]
+pub mod priority;
+#[cfg(all(test, feature = "async"))]
+mod test_util;
+#[cfg(all(fuzzing, feature = "async"))]
+pub mod fuzzing;
 
 #[cfg(feature = "sync")]
 pub mod receiver;
@@ -75,6 +112,12 @@ pub mod client;
 pub mod server;
 pub mod stream;
 
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "sync")]
+pub mod test;
+
 /// A collection of handy synchronous-only parts of the crate.
 #[cfg(feature = "sync")]
 pub mod sync {
@@ -112,6 +155,7 @@ pub mod async {
 	pub use codec::ws::Context as MsgCodecCtx;
 	pub use codec::http::HttpClientCodec;
 	pub use codec::http::HttpServerCodec;
+	pub use codec::http::LimitedHttpServerCodec;
 
 	pub use stream::async::Stream;
 	pub use stream::async as stream;
@@ -137,6 +181,8 @@ pub mod async {
 	pub use futures;
 	pub use tokio::net::TcpStream;
 	pub use tokio::net::TcpListener;
+	#[cfg(unix)]
+	pub use tokio_uds::{UnixListener, UnixStream};
 	pub use tokio::reactor::Reactor;
 	pub use tokio::reactor::Handle;
 }
@@ -144,7 +190,10 @@ pub mod async {
 pub use self::message::Message;
 pub use self::message::CloseData;
 pub use self::message::OwnedMessage;
+pub use self::message::PreparedMessage;
 pub use self::client::builder::ClientBuilder;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub use self::server::ServerBuilder;
 
 pub use self::result::WebSocketError;
 pub use self::result::WebSocketResult;
@@ -0,0 +1,23 @@
+//! Stand-ins for the macros of the `log` crate, used when the `log` feature
+//! is disabled.
+//!
+//! They still type check their arguments, so a log statement can't break the
+//! build of one configuration and not the other, but they never run.
+#![cfg(not(feature = "log"))]
+#![allow(unused_macros)]
+
+macro_rules! error {
+	($($arg:tt)+) => { if false { format_args!($($arg)+); } };
+}
+
+macro_rules! warn {
+	($($arg:tt)+) => { if false { format_args!($($arg)+); } };
+}
+
+macro_rules! debug {
+	($($arg:tt)+) => { if false { format_args!($($arg)+); } };
+}
+
+macro_rules! trace {
+	($($arg:tt)+) => { if false { format_args!($($arg)+); } };
+}
@@ -1,13 +1,19 @@
 //! Module containing the default implementation for messages.
+use std::fmt;
 use std::str::from_utf8;
 use std::io;
 use std::io::Write;
 use std::borrow::Cow;
+use std::sync::Arc;
 use result::{WebSocketResult, WebSocketError};
-use dataframe::Opcode;
+use dataframe::{DataFrame, Opcode};
 use ws::dataframe::DataFrame as DataFrameTrait;
 use byteorder::{WriteBytesExt, ReadBytesExt, BigEndian};
 use ws::util::bytes_to_string;
+use ws::util::header as dfh;
+use frames;
+#[cfg(feature = "async")]
+use bytes::{BufMut, BytesMut};
 use ws;
 
 const FALSE_RESERVED_BITS: &'static [bool; 3] = &[false; 3];
@@ -70,6 +76,25 @@ impl<'a> Message<'a> {
 		)
 	}
 
+	/// Create a new WebSocket message with text formatted from `args`,
+	/// which is written straight into the payload instead of a `String`
+	/// first.
+	///
+	/// ```rust
+	/// use websocket::Message;
+	///
+	/// let user = "ferris";
+	/// let message = Message::text_fmt(format_args!("{} joined #{}", user, 42));
+	/// assert_eq!(message, Message::text("ferris joined #42"));
+	/// ```
+	pub fn text_fmt(args: fmt::Arguments) -> Message<'static> {
+		let mut payload = Vec::new();
+		// formatting only fails if a `Display` impl does, and writing to a
+		// vector never does
+		payload.write_fmt(args).expect("a Display implementation returned an error");
+		Message::new(Type::Text, None, Cow::Owned(payload))
+	}
+
 	/// Create a new WebSocket message with binary data
 	pub fn binary<B>(data: B) -> Self
 	where
@@ -119,6 +144,12 @@ impl<'a> Message<'a> {
 		Message::new(Type::Pong, None, data.into())
 	}
 
+	/// Copy the payload if it is borrowed, so the message can outlive what it
+	/// borrowed from.
+	pub fn into_owned(self) -> Message<'static> {
+		Message::new(self.opcode, self.cd_status_code, Cow::Owned(self.payload.into_owned()))
+	}
+
 	/// Convert a ping message to a pong, keeping the data.
 	/// This will fail if the original message is not a ping.
 	pub fn into_pong(&mut self) -> Result<(), ()> {
@@ -129,6 +160,61 @@ impl<'a> Message<'a> {
 			Err(())
 		}
 	}
+
+	/// Append this message as it goes over the wire to `dst`, masked with a
+	/// random key if `masked` (as clients send them). This needs no
+	/// connection, the bytes can be carried by any other transport and read
+	/// back with `Message::parse`.
+	#[cfg(feature = "async")]
+	pub fn serialize_into(&self, dst: &mut BytesMut, masked: bool) -> WebSocketResult<()> {
+		dst.reserve(self.frame_size(masked));
+		self.write_to(&mut dst.writer(), masked)
+	}
+
+	/// Read the message at the start of `data`, which is in the wire format
+	/// `serialize_into` writes, giving back the message and how many bytes
+	/// of `data` it took up. A message may be made of several frames, which
+	/// can be masked or not but all the same.
+	///
+	/// ```rust
+	/// # extern crate bytes;
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use bytes::BytesMut;
+	/// use websocket::Message;
+	///
+	/// let mut buf = BytesMut::new();
+	/// Message::text("hello").serialize_into(&mut buf, true).unwrap();
+	/// Message::binary(vec![1, 2, 3]).serialize_into(&mut buf, true).unwrap();
+	///
+	/// let (first, used) = Message::parse(&buf).unwrap();
+	/// assert_eq!(first, Message::text("hello"));
+	/// let (second, _) = Message::parse(&buf[used..]).unwrap();
+	/// assert_eq!(second, Message::binary(vec![1, 2, 3]));
+	/// # }
+	/// ```
+	pub fn parse(data: &[u8]) -> WebSocketResult<(Message<'static>, usize)> {
+		let masked = data.len() > 1 && data[1] & 0x80 == 0x80;
+		let mut reader = io::Cursor::new(data);
+		let mut frames = Vec::new();
+		loop {
+			let header = dfh::read_header(&mut reader)?;
+			let start = reader.position() as usize;
+			if header.len > (data.len() - start) as u64 {
+				return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete payload").into());
+			}
+			let end = start + header.len as usize;
+			reader.set_position(end as u64);
+			let frame = DataFrame::read_dataframe_body(header, data[start..end].to_vec(), masked)?;
+			let finished = frame.finished;
+			frames.push(frame);
+			if finished {
+				break;
+			}
+		}
+		let message = <Message as ws::Message>::from_dataframes(frames)?;
+		Ok((message, reader.position() as usize))
+	}
 }
 
 impl<'a> ws::dataframe::DataFrame for Message<'a> {
@@ -177,6 +263,11 @@ impl<'a> ws::Message for Message<'a> {
 		self.write_to(writer, masked)
 	}
 
+	/// Writes this message, masked with `masking_key` if there is one
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		self.write_with_key(writer, masking_key)
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize {
 		self.frame_size(masked)
@@ -193,9 +284,9 @@ impl<'a> ws::Message for Message<'a> {
 
 		let opcode = Opcode::new(opcode);
 
-		let payload_size = frames.iter().map(|d| d.size()).sum();
+		let payload_size: usize = frames.iter().map(|d| d.size()).sum();
 
-		let mut data = Vec::with_capacity(payload_size);
+		let mut data = Vec::new();
 
 		for (i, dataframe) in frames.into_iter().enumerate() {
 			if i > 0 && dataframe.opcode() != Opcode::Continuation as u8 {
@@ -208,7 +299,13 @@ impl<'a> ws::Message for Message<'a> {
 					"Unsupported reserved bits received",
 				));
 			}
-			data.append(&mut dataframe.take_payload());
+			if i == 0 {
+				// reuse the first payload's buffer, a single frame needs no copying
+				data = dataframe.take_payload();
+				data.reserve(payload_size.saturating_sub(data.len()));
+			} else {
+				data.append(&mut dataframe.take_payload());
+			}
 		}
 
 		if opcode == Some(Opcode::Text) {
@@ -250,7 +347,8 @@ impl<'a> ws::Message for Message<'a> {
 /// If you would like to create a message out of borrowed data to use for sending
 /// please use the `Message` struct (which contains a `Cow`).
 ///
-/// Note that `OwnedMessage` and `Message` can be converted into each other.
+/// Note that `OwnedMessage` and `Message` can be converted into each other, the
+/// conversions take over owned payloads instead of copying them.
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub enum OwnedMessage {
 	/// A message containing UTF-8 text data
@@ -270,6 +368,19 @@ pub enum OwnedMessage {
 }
 
 impl OwnedMessage {
+	/// Append this message as it goes over the wire to `dst`, see
+	/// `Message::serialize_into`.
+	#[cfg(feature = "async")]
+	pub fn serialize_into(&self, dst: &mut BytesMut, masked: bool) -> WebSocketResult<()> {
+		dst.reserve(self.frame_size(masked));
+		self.write_to(&mut dst.writer(), masked)
+	}
+
+	/// Read the message at the start of `data`, see `Message::parse`.
+	pub fn parse(data: &[u8]) -> WebSocketResult<(OwnedMessage, usize)> {
+		Message::parse(data).map(|(message, used)| (message.into(), used))
+	}
+
 	/// Checks if this message is a close message.
 	///
 	///```rust
@@ -349,6 +460,11 @@ impl ws::Message for OwnedMessage {
 		self.write_to(writer, masked)
 	}
 
+	/// Writes this message, masked with `masking_key` if there is one
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		self.write_with_key(writer, masking_key)
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize {
 		self.frame_size(masked)
@@ -442,19 +558,28 @@ impl ws::dataframe::DataFrame for OwnedMessage {
 	}
 }
 
+/// Move an owned payload into a `String`, copying it only if it is borrowed.
+/// Nothing guarantees a text payload is UTF-8 (see `Utf8Policy`), invalid
+/// sequences are replaced with U+FFFD REPLACEMENT CHARACTER.
+fn payload_into_string(payload: Cow<[u8]>) -> String {
+	match String::from_utf8(payload.into_owned()) {
+		Ok(text) => text,
+		Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+	}
+}
+
 impl<'m> From<Message<'m>> for OwnedMessage {
+	/// Takes over the payload of `message` if it owns it, instead of
+	/// copying it.
 	fn from(message: Message<'m>) -> Self {
 		match message.opcode {
-			Type::Text => {
-				let convert = String::from_utf8_lossy(&message.payload).into_owned();
-				OwnedMessage::Text(convert)
-			}
+			Type::Text => OwnedMessage::Text(payload_into_string(message.payload)),
 			Type::Close => {
 				match message.cd_status_code {
 					Some(code) => {
 						OwnedMessage::Close(Some(CloseData {
 							status_code: code,
-							reason: String::from_utf8_lossy(&message.payload).into_owned(),
+							reason: payload_into_string(message.payload),
 						}))
 					}
 					None => OwnedMessage::Close(None),
@@ -468,6 +593,7 @@ impl<'m> From<Message<'m>> for OwnedMessage {
 }
 
 impl<'m> From<OwnedMessage> for Message<'m> {
+	/// Takes over the payload of `message` without copying it.
 	fn from(message: OwnedMessage) -> Self {
 		match message {
 			OwnedMessage::Text(txt) => Message::text(txt),
@@ -484,6 +610,135 @@ impl<'m> From<OwnedMessage> for Message<'m> {
 	}
 }
 
+/// A message encoded once, to be sent to many connections.
+///
+/// Broadcasting a message encodes it again for each connection it goes to,
+/// a prepared message is only encoded when it is made and copied as it is
+/// to connections that don't mask their frames (servers don't). Connections
+/// that do mask a copy of its payload with a key of their own, since every
+/// frame needs a fresh one. Cloning is cheap, the clones share the encoded
+/// message.
+///
+/// ```rust
+/// use websocket::{OwnedMessage, PreparedMessage};
+/// use websocket::ws::Message;
+///
+/// let prepared = PreparedMessage::new(&OwnedMessage::Text("to everyone".to_string())).unwrap();
+/// let mut wire = Vec::new();
+/// prepared.serialize(&mut wire, false).unwrap();
+/// assert_eq!(&wire[..], &b"\x81\x0bto everyone"[..]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreparedMessage {
+	/// the unmasked frame, as it goes over the wire
+	frame: Arc<Vec<u8>>,
+	header_len: usize,
+	opcode: u8,
+	reserved: [bool; 3],
+}
+
+impl PreparedMessage {
+	/// Encode `message`, which has to fit in a single frame like every
+	/// message of this crate does.
+	pub fn new<M>(message: &M) -> WebSocketResult<Self>
+	where
+		M: ws::Message,
+	{
+		let mut frame = Vec::with_capacity(message.message_size(false));
+		message.serialize(&mut frame, false)?;
+		let (header, header_len) = match frames::decode_header(&frame)? {
+			Some(decoded) => decoded,
+			None => return Err(WebSocketError::DataFrameError("Incomplete data frame header")),
+		};
+		if !header.flags.contains(frames::FIN) || (frame.len() - header_len) as u64 != header.len {
+			return Err(WebSocketError::ProtocolError("A prepared message must be a single frame"));
+		}
+		Ok(PreparedMessage {
+			header_len: header_len,
+			opcode: header.opcode,
+			reserved: [
+				header.flags.contains(frames::RSV1),
+				header.flags.contains(frames::RSV2),
+				header.flags.contains(frames::RSV3),
+			],
+			frame: Arc::new(frame),
+		})
+	}
+
+	/// The message as it is sent unmasked.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.frame
+	}
+
+	/// The payload of the message.
+	pub fn payload(&self) -> &[u8] {
+		&self.frame[self.header_len..]
+	}
+}
+
+impl ws::dataframe::DataFrame for PreparedMessage {
+	#[inline(always)]
+	fn is_last(&self) -> bool {
+		true
+	}
+
+	#[inline(always)]
+	fn opcode(&self) -> u8 {
+		self.opcode
+	}
+
+	#[inline(always)]
+	fn reserved(&self) -> &[bool; 3] {
+		&self.reserved
+	}
+
+	fn size(&self) -> usize {
+		self.frame.len() - self.header_len
+	}
+
+	fn write_payload(&self, socket: &mut Write) -> WebSocketResult<()> {
+		socket.write_all(self.payload())?;
+		Ok(())
+	}
+
+	fn take_payload(self) -> Vec<u8> {
+		self.payload().to_vec()
+	}
+}
+
+impl ws::Message for PreparedMessage {
+	/// Copies the encoded message, or masks a copy of it if `masked`
+	fn serialize(&self, writer: &mut Write, masked: bool) -> WebSocketResult<()> {
+		if masked {
+			return self.write_to(writer, true);
+		}
+		writer.write_all(&self.frame)?;
+		Ok(())
+	}
+
+	/// Copies the encoded message, or masks a copy of it with `masking_key`
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		if masking_key.is_some() {
+			return self.write_with_key(writer, masking_key);
+		}
+		writer.write_all(&self.frame)?;
+		Ok(())
+	}
+
+	/// Returns how many bytes this message will take up
+	fn message_size(&self, masked: bool) -> usize {
+		self.frame.len() + if masked { 4 } else { 0 }
+	}
+
+	/// Attempt to form a message from a series of data frames
+	fn from_dataframes<D>(frames: Vec<D>) -> WebSocketResult<Self>
+	where
+		D: DataFrameTrait,
+	{
+		PreparedMessage::new(&OwnedMessage::from_dataframes(frames)?)
+	}
+}
+
 /// Represents data contained in a Close message
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct CloseData {
@@ -531,8 +786,92 @@ impl<'a> IntoCowBytes<'a> for &'a [u8] {
 	}
 }
 
+impl<'a> IntoCowBytes<'a> for &'a Vec<u8> {
+	fn into(self) -> Cow<'a, [u8]> {
+		Cow::Borrowed(&self[..])
+	}
+}
+
+impl<'a> IntoCowBytes<'a> for Box<[u8]> {
+	fn into(self) -> Cow<'a, [u8]> {
+		Cow::Owned(self.into_vec())
+	}
+}
+
 impl<'a> IntoCowBytes<'a> for Cow<'a, [u8]> {
 	fn into(self) -> Cow<'a, [u8]> {
 		self
 	}
 }
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_what_it_serializes() {
+		let messages = vec![
+			OwnedMessage::Text("nilbog".to_string()),
+			OwnedMessage::Binary(vec![42; 65555]),
+			OwnedMessage::Ping(vec![1, 2]),
+			OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string()))),
+			OwnedMessage::Close(None),
+		];
+		for &masked in &[false, true] {
+			let mut buf = BytesMut::new();
+			for message in &messages {
+				message.serialize_into(&mut buf, masked).unwrap();
+			}
+			let mut rest = &buf[..];
+			for message in &messages {
+				let (parsed, used) = OwnedMessage::parse(rest).unwrap();
+				assert_eq!(&parsed, message);
+				rest = &rest[used..];
+			}
+			assert!(rest.is_empty());
+		}
+
+		let mut buf = BytesMut::new();
+		Message::text("cut short").serialize_into(&mut buf, false).unwrap();
+		assert!(Message::parse(&buf[..buf.len() - 1]).is_err());
+	}
+
+	#[test]
+	fn conversions_move_payloads() {
+		let text = "moved, not copied".to_string();
+		let at = text.as_ptr();
+		let message: Message = OwnedMessage::Text(text).into();
+		assert_eq!(message.payload.as_ptr(), at);
+		match OwnedMessage::from(message) {
+			OwnedMessage::Text(text) => assert_eq!(text.as_ptr(), at),
+			other => panic!("not a text message: {:?}", other),
+		}
+
+		let close = OwnedMessage::from(Message::close_because(1000, "bye".to_string()));
+		assert_eq!(close, OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string()))));
+		let lossy = OwnedMessage::from(Message::new(Type::Text, None, Cow::Borrowed(b"caf\xe9")));
+		assert_eq!(lossy, OwnedMessage::Text("caf\u{fffd}".to_string()));
+	}
+
+	#[test]
+	fn prepared_messages_are_sent_like_the_original() {
+		use ws::Message as MessageTrait;
+
+		let messages = vec![
+			OwnedMessage::Text("nilbog".to_string()),
+			OwnedMessage::Binary(vec![42; 65555]),
+			OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string()))),
+		];
+		for message in messages {
+			let prepared = PreparedMessage::new(&message).unwrap();
+			let mut original = Vec::new();
+			message.serialize(&mut original, false).unwrap();
+			assert_eq!(prepared.as_bytes(), &original[..]);
+
+			let mut masked = Vec::new();
+			prepared.serialize(&mut masked, true).unwrap();
+			assert_eq!(masked.len(), prepared.message_size(true));
+			assert_eq!(OwnedMessage::parse(&masked).unwrap().0, message);
+		}
+	}
+}
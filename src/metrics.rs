@@ -0,0 +1,317 @@
+//! Counting what connections are up to, for monitoring.
+//!
+//! With the `metrics` feature enabled the crate reports connections opening
+//! and closing, handshakes, messages and close codes to a single, process-wide
+//! `MetricsSink`. Implement the trait to forward these events to whatever
+//! collects your metrics (prometheus, statsd, ...) and install it once with
+//! `set_sink` before opening any connections.
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use websocket::metrics::{self, Counters};
+//!
+//! let counters = Arc::new(Counters::default());
+//! metrics::set_sink(Box::new(counters.clone())).unwrap();
+//!
+//! // ... later on, e.g. when scraped
+//! println!("{} connections open", counters.active_connections());
+//! ```
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use message::OwnedMessage;
+use ws::Message as MessageTrait;
+
+/// Receives the events the crate reports, every method does nothing by default.
+///
+/// The methods are called right in the middle of reading and writing, so they
+/// should return quickly.
+pub trait MetricsSink: Send + Sync {
+	/// A websocket connection was opened.
+	fn connection_opened(&self) {}
+
+	/// A websocket connection was closed, i.e. its client was dropped.
+	fn connection_closed(&self) {}
+
+	/// A handshake went through.
+	fn handshake_succeeded(&self) {}
+
+	/// A handshake failed or was refused by the server's policies, `reason` is
+	/// a short description of what went wrong.
+	fn handshake_failed(&self, _reason: &str) {}
+
+	/// A message of `bytes` bytes (including framing) was received.
+	fn message_received(&self, _bytes: usize) {}
+
+	/// A message of `bytes` bytes (including framing) was sent.
+	fn message_sent(&self, _bytes: usize) {}
+
+	/// The peer closed the connection with this status code.
+	fn close_received(&self, _code: Option<u16>) {}
+}
+
+impl<T> MetricsSink for Arc<T>
+where
+	T: MetricsSink + ?Sized,
+{
+	fn connection_opened(&self) {
+		(**self).connection_opened()
+	}
+
+	fn connection_closed(&self) {
+		(**self).connection_closed()
+	}
+
+	fn handshake_succeeded(&self) {
+		(**self).handshake_succeeded()
+	}
+
+	fn handshake_failed(&self, reason: &str) {
+		(**self).handshake_failed(reason)
+	}
+
+	fn message_received(&self, bytes: usize) {
+		(**self).message_received(bytes)
+	}
+
+	fn message_sent(&self, bytes: usize) {
+		(**self).message_sent(bytes)
+	}
+
+	fn close_received(&self, code: Option<u16>) {
+		(**self).close_received(code)
+	}
+}
+
+struct NopSink;
+
+impl MetricsSink for NopSink {}
+
+static mut SINK: &'static MetricsSink = &NopSink;
+static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
+
+const UNINITIALIZED: usize = 0;
+const INITIALIZING: usize = 1;
+const INITIALIZED: usize = 2;
+
+/// Install the sink the crate reports to, this can only be done once.
+pub fn set_sink(sink: Box<MetricsSink>) -> Result<(), SetSinkError> {
+	let claimed = STATE.compare_exchange(UNINITIALIZED, INITIALIZING, Ordering::SeqCst, Ordering::SeqCst);
+	if claimed.is_err() {
+		return Err(SetSinkError(()));
+	}
+	unsafe {
+		SINK = &*Box::into_raw(sink);
+	}
+	STATE.store(INITIALIZED, Ordering::SeqCst);
+	Ok(())
+}
+
+/// The sink the crate reports to, one that does nothing if none was installed.
+pub fn sink() -> &'static MetricsSink {
+	if STATE.load(Ordering::SeqCst) != INITIALIZED {
+		static NOP: NopSink = NopSink;
+		return &NOP;
+	}
+	unsafe { SINK }
+}
+
+/// The error returned by `set_sink` when a sink was already installed.
+#[derive(Debug)]
+pub struct SetSinkError(());
+
+impl fmt::Display for SetSinkError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.description())
+	}
+}
+
+impl Error for SetSinkError {
+	fn description(&self) -> &str {
+		"a metrics sink was already installed"
+	}
+}
+
+/// Reports a connection as open for as long as it is alive.
+///
+/// Every connection owns one of these, inside its receiving half.
+#[derive(Debug)]
+pub struct ConnectionGuard(());
+
+impl ConnectionGuard {
+	/// Report a connection as opened.
+	pub fn new() -> Self {
+		sink().connection_opened();
+		ConnectionGuard(())
+	}
+}
+
+impl Default for ConnectionGuard {
+	fn default() -> Self {
+		ConnectionGuard::new()
+	}
+}
+
+impl Drop for ConnectionGuard {
+	fn drop(&mut self) {
+		sink().connection_closed();
+	}
+}
+
+/// Report how a handshake went.
+#[doc(hidden)]
+pub fn handshake<T, E>(result: &Result<T, E>)
+where
+	E: Error,
+{
+	match *result {
+		Ok(_) => sink().handshake_succeeded(),
+		Err(ref e) => sink().handshake_failed(e.description()),
+	}
+}
+
+/// Report a message that was just received.
+#[doc(hidden)]
+pub fn received(message: &OwnedMessage, masked: bool) {
+	let sink = sink();
+	sink.message_received(message.message_size(masked));
+	if let OwnedMessage::Close(ref data) = *message {
+		sink.close_received(data.as_ref().map(|data| data.status_code));
+	}
+}
+
+/// Report a message that was just sent.
+#[doc(hidden)]
+pub fn sent<M>(message: &M, masked: bool)
+where
+	M: MessageTrait,
+{
+	sink().message_sent(message.message_size(masked));
+}
+
+/// A sink that simply counts everything, handy for exposing the numbers
+/// from a `/metrics` endpoint or for testing.
+///
+/// Share it with an `Arc` to keep reading the counters after installing it.
+#[derive(Debug, Default)]
+pub struct Counters {
+	opened: AtomicUsize,
+	closed: AtomicUsize,
+	handshakes: AtomicUsize,
+	failed_handshakes: AtomicUsize,
+	messages_in: AtomicUsize,
+	messages_out: AtomicUsize,
+	bytes_in: AtomicUsize,
+	bytes_out: AtomicUsize,
+	closes: AtomicUsize,
+}
+
+impl Counters {
+	/// The number of connections open right now.
+	pub fn active_connections(&self) -> usize {
+		let closed = self.closed.load(Ordering::SeqCst);
+		self.opened.load(Ordering::SeqCst).saturating_sub(closed)
+	}
+
+	/// The number of connections opened so far.
+	pub fn connections(&self) -> usize {
+		self.opened.load(Ordering::SeqCst)
+	}
+
+	/// The number of handshakes that went through.
+	pub fn handshakes(&self) -> usize {
+		self.handshakes.load(Ordering::SeqCst)
+	}
+
+	/// The number of handshakes that failed or were refused.
+	pub fn failed_handshakes(&self) -> usize {
+		self.failed_handshakes.load(Ordering::SeqCst)
+	}
+
+	/// The number of messages received.
+	pub fn messages_in(&self) -> usize {
+		self.messages_in.load(Ordering::SeqCst)
+	}
+
+	/// The number of messages sent.
+	pub fn messages_out(&self) -> usize {
+		self.messages_out.load(Ordering::SeqCst)
+	}
+
+	/// The number of bytes received as messages.
+	pub fn bytes_in(&self) -> usize {
+		self.bytes_in.load(Ordering::SeqCst)
+	}
+
+	/// The number of bytes sent as messages.
+	pub fn bytes_out(&self) -> usize {
+		self.bytes_out.load(Ordering::SeqCst)
+	}
+
+	/// The number of close messages received.
+	pub fn closes(&self) -> usize {
+		self.closes.load(Ordering::SeqCst)
+	}
+}
+
+impl MetricsSink for Counters {
+	fn connection_opened(&self) {
+		self.opened.fetch_add(1, Ordering::SeqCst);
+	}
+
+	fn connection_closed(&self) {
+		self.closed.fetch_add(1, Ordering::SeqCst);
+	}
+
+	fn handshake_succeeded(&self) {
+		self.handshakes.fetch_add(1, Ordering::SeqCst);
+	}
+
+	fn handshake_failed(&self, _reason: &str) {
+		self.failed_handshakes.fetch_add(1, Ordering::SeqCst);
+	}
+
+	fn message_received(&self, bytes: usize) {
+		self.messages_in.fetch_add(1, Ordering::SeqCst);
+		self.bytes_in.fetch_add(bytes, Ordering::SeqCst);
+	}
+
+	fn message_sent(&self, bytes: usize) {
+		self.messages_out.fetch_add(1, Ordering::SeqCst);
+		self.bytes_out.fetch_add(bytes, Ordering::SeqCst);
+	}
+
+	fn close_received(&self, _code: Option<u16>) {
+		self.closes.fetch_add(1, Ordering::SeqCst);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counters_keep_count() {
+		let counters = Arc::new(Counters::default());
+		let sink: &MetricsSink = &counters;
+
+		sink.connection_opened();
+		sink.connection_opened();
+		sink.connection_closed();
+		sink.handshake_succeeded();
+		sink.handshake_failed("bad request");
+		sink.message_received(10);
+		sink.message_received(5);
+		sink.message_sent(7);
+
+		assert_eq!(counters.active_connections(), 1);
+		assert_eq!(counters.connections(), 2);
+		assert_eq!(counters.handshakes(), 1);
+		assert_eq!(counters.failed_handshakes(), 1);
+		assert_eq!(counters.messages_in(), 2);
+		assert_eq!(counters.bytes_in(), 15);
+		assert_eq!(counters.bytes_out(), 7);
+	}
+}
@@ -0,0 +1,229 @@
+//! Hooks to observe and change what goes over a connection.
+//!
+//! A `Middleware` registered with a server sees the handshake request of
+//! every connection it accepts and every frame going in or out of them, which
+//! is enough for logging, auditing or enforcing policies without touching the
+//! application's receive and send loops. Clients can use them as well through
+//! `Client::set_middleware` and `MessageCodec::set_middleware`.
+//!
+//! ```rust
+//! use websocket::dataframe::DataFrame;
+//! use websocket::middleware::{Direction, Middleware};
+//! use websocket::server::ServerBuilder;
+//! use websocket::WebSocketResult;
+//!
+//! struct Audit;
+//!
+//! impl Middleware for Audit {
+//!     fn on_frame(&self, direction: Direction, frame: &mut DataFrame) -> WebSocketResult<()> {
+//!         println!("{:?} {:?} frame of {} bytes", direction, frame.opcode, frame.data.len());
+//!         Ok(())
+//!     }
+//!
+//!     fn on_close(&self, direction: Direction, code: Option<u16>, reason: &str) {
+//!         println!("{:?} close {:?}: {}", direction, code, reason);
+//!     }
+//! }
+//!
+//! let builder = ServerBuilder::new().middleware(Audit);
+//! ```
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use http::header::HeaderMap;
+
+use codec::http::RequestHead;
+use dataframe::{DataFrame, Opcode};
+use result::{WebSocketError, WebSocketResult};
+use server::filter::Decision;
+use ws::dataframe::DataFrame as DataFrameable;
+
+/// Which way a frame is going.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+	/// The frame was received from the peer.
+	Inbound,
+	/// The frame is about to be sent to the peer.
+	Outbound,
+}
+
+/// Observes and changes the handshakes and frames of connections, every
+/// method does nothing by default.
+///
+/// The methods are called right in the middle of reading and writing, so they
+/// should return quickly.
+pub trait Middleware: Send + Sync {
+	/// A server read a handshake request, `response` holds the headers it
+	/// is going to answer with. Anything but `Decision::Accept` refuses the
+	/// connection.
+	fn on_handshake(&self, _request: &RequestHead, _response: &mut HeaderMap) -> Decision {
+		Decision::Accept
+	}
+
+	/// A frame was received or is about to be sent. Returning an error fails
+	/// the read or the write with that error.
+	fn on_frame(&self, _direction: Direction, _frame: &mut DataFrame) -> WebSocketResult<()> {
+		Ok(())
+	}
+
+	/// A close frame was received or is about to be sent.
+	fn on_close(&self, _direction: Direction, _code: Option<u16>, _reason: &str) {}
+}
+
+/// The middleware of a connection, run in the order they were added.
+///
+/// Cloning is cheap, all clones share the same middleware.
+#[derive(Clone, Default)]
+pub struct Middlewares {
+	layers: Vec<Arc<Middleware>>,
+}
+
+impl Middlewares {
+	/// No middleware at all.
+	pub fn new() -> Self {
+		Middlewares::default()
+	}
+
+	/// Run `middleware` after the ones added so far.
+	pub fn push<M>(&mut self, middleware: M)
+	where
+		M: Middleware + 'static,
+	{
+		self.layers.push(Arc::new(middleware));
+	}
+
+	/// Run the middleware of `other` after the ones added so far.
+	pub fn append(&mut self, other: &Middlewares) {
+		self.layers.extend(other.layers.iter().cloned());
+	}
+
+	/// Whether there is no middleware to run.
+	pub fn is_empty(&self) -> bool {
+		self.layers.is_empty()
+	}
+
+	/// Run the handshake hooks, the first one refusing the connection has the
+	/// final say.
+	pub fn handshake(&self, request: &RequestHead, response: &mut HeaderMap) -> Decision {
+		for layer in &self.layers {
+			match layer.on_handshake(request, response) {
+				Decision::Accept => continue,
+				refusal => return refusal,
+			}
+		}
+		Decision::Accept
+	}
+
+	/// Run the frame hooks, and the close hooks if `frame` closes the
+	/// connection.
+	pub fn frame(&self, direction: Direction, frame: &mut DataFrame) -> WebSocketResult<()> {
+		if self.layers.is_empty() {
+			return Ok(());
+		}
+		for layer in &self.layers {
+			layer.on_frame(direction, frame)?;
+		}
+		if frame.opcode == Opcode::Close {
+			let (code, reason) = match frame.data.len() {
+				0 | 1 => (None, Cow::Borrowed("")),
+				_ => {
+					let code = (frame.data[0] as u16) << 8 | frame.data[1] as u16;
+					(Some(code), String::from_utf8_lossy(&frame.data[2..]))
+				}
+			};
+			for layer in &self.layers {
+				layer.on_close(direction, code, &reason);
+			}
+		}
+		Ok(())
+	}
+
+	/// Turn an outgoing message into a frame and run the hooks on it, the
+	/// messages of this crate are sent as a single frame.
+	#[doc(hidden)]
+	pub fn outbound_message<M>(&self, message: &M) -> WebSocketResult<DataFrame>
+	where
+		M: DataFrameable,
+	{
+		self.outbound_dataframe(message)
+	}
+
+	/// Copy an outgoing frame and run the hooks on the copy.
+	#[doc(hidden)]
+	pub fn outbound_dataframe<D>(&self, dataframe: &D) -> WebSocketResult<DataFrame>
+	where
+		D: DataFrameable + ?Sized,
+	{
+		let opcode = Opcode::new(dataframe.opcode())
+			.ok_or(WebSocketError::ProtocolError("Invalid data frame opcode"))?;
+		let mut data = Vec::with_capacity(dataframe.size());
+		dataframe.write_payload(&mut data)?;
+		let mut frame = DataFrame::new(dataframe.is_last(), opcode, data);
+		frame.reserved = *dataframe.reserved();
+		self.frame(Direction::Outbound, &mut frame)?;
+		Ok(frame)
+	}
+}
+
+impl fmt::Debug for Middlewares {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Middlewares({})", self.layers.len())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+	use message::Message as WsMessage;
+
+	struct Record(Arc<Mutex<Vec<String>>>);
+
+	impl Middleware for Record {
+		fn on_frame(&self, direction: Direction, frame: &mut DataFrame) -> WebSocketResult<()> {
+			if frame.opcode == Opcode::Text {
+				frame.data.extend_from_slice(b"!");
+			}
+			self.0.lock().unwrap().push(format!("{:?} {:?}", direction, frame.opcode));
+			Ok(())
+		}
+
+		fn on_close(&self, direction: Direction, code: Option<u16>, reason: &str) {
+			self.0.lock().unwrap().push(format!("{:?} close {:?} {}", direction, code, reason));
+		}
+	}
+
+	#[test]
+	fn runs_frame_and_close_hooks() {
+		let events = Arc::new(Mutex::new(Vec::new()));
+		let mut middlewares = Middlewares::new();
+		middlewares.push(Record(events.clone()));
+
+		let frame = middlewares.outbound_message(&WsMessage::text("hi")).unwrap();
+		assert_eq!(frame.data, b"hi!".to_vec());
+
+		let mut close = DataFrame::new(true, Opcode::Close, vec![3, 232, b'b', b'y', b'e']);
+		middlewares.frame(Direction::Inbound, &mut close).unwrap();
+
+		assert_eq!(
+			*events.lock().unwrap(),
+			vec![
+				"Outbound Text".to_string(),
+				"Inbound Close".to_string(),
+				"Inbound close Some(1000) bye".to_string(),
+			]
+		);
+	}
+
+	#[test]
+	fn outbound_messages_become_a_single_frame() {
+		use message::{CloseData, OwnedMessage};
+
+		let close = OwnedMessage::Close(Some(CloseData::new(1000, "bye".to_string())));
+		let frame = Middlewares::new().outbound_message(&close).unwrap();
+		assert_eq!(frame.opcode, Opcode::Close);
+		assert!(frame.finished);
+		assert_eq!(frame.data, vec![3, 232, b'b', b'y', b'e']);
+	}
+}
@@ -0,0 +1,257 @@
+//! Pausing the reading of a connection.
+//!
+//! An application that can't keep up with what a peer sends has to buffer
+//! it somewhere. Pausing the reading instead leaves the data in the socket,
+//! so once its buffers fill up TCP tells the peer to slow down.
+//!
+//! Synchronous clients (and their readers after a split) wait in their
+//! receive methods while paused, see `Client::pause_reading`. Asynchronous
+//! clients are wrapped in a `Pausable` stream, which stops polling the
+//! client while paused. Either way a `ReadPause` handle can pause and resume
+//! the reading from anywhere.
+//!
+//! ```rust,no_run
+//! use websocket::ClientBuilder;
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//! let pause = client.read_pause();
+//!
+//! // the thread handling the messages fell behind
+//! pause.pause();
+//! // ... and caught up again, `recv_message` goes on reading
+//! pause.resume();
+//! # let _ = client.recv_message();
+//! ```
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "async")]
+use futures::{Async, Poll, Sink, StartSend, Stream};
+#[cfg(feature = "async")]
+use futures::task::{self, Task};
+
+/// Pauses and resumes the reading of a connection, see the module docs.
+///
+/// Cloning is cheap, all clones pause the same connection.
+#[derive(Clone, Default)]
+pub struct ReadPause {
+	inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+	state: Mutex<State>,
+	resumed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+	paused: bool,
+	/// the task of the `Pausable` waiting to be resumed
+	#[cfg(feature = "async")]
+	task: Option<Task>,
+}
+
+impl ReadPause {
+	/// A handle on a connection that reads as usual.
+	pub fn new() -> Self {
+		ReadPause::default()
+	}
+
+	/// Stop reading from the connection until `resume` is called. A read
+	/// that already started is finished first.
+	pub fn pause(&self) {
+		self.lock().paused = true;
+	}
+
+	/// Go on reading from the connection.
+	pub fn resume(&self) {
+		let mut state = self.lock();
+		state.paused = false;
+		#[cfg(feature = "async")]
+		{
+			if let Some(task) = state.task.take() {
+				task.notify();
+			}
+		}
+		self.inner.resumed.notify_all();
+	}
+
+	/// Whether the reading is paused.
+	pub fn is_paused(&self) -> bool {
+		self.lock().paused
+	}
+
+	/// Block until the reading is not paused.
+	#[doc(hidden)]
+	pub fn wait(&self) {
+		let mut state = self.lock();
+		while state.paused {
+			state = match self.inner.resumed.wait(state) {
+				Ok(guard) => guard,
+				Err(poisoned) => poisoned.into_inner(),
+			};
+		}
+	}
+
+	/// Whether the reading is paused, making sure the current task is woken
+	/// up once it is resumed.
+	#[cfg(feature = "async")]
+	fn poll_paused(&self) -> bool {
+		let mut state = self.lock();
+		if state.paused {
+			state.task = Some(task::current());
+		}
+		state.paused
+	}
+
+	fn lock(&self) -> MutexGuard<State> {
+		// a flag can't be left inconsistent by a panic
+		match self.inner.state.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+}
+
+impl fmt::Debug for ReadPause {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ReadPause").field("paused", &self.is_paused()).finish()
+	}
+}
+
+/// An asynchronous client whose reading can be paused, see the module docs.
+///
+/// While paused the wrapped client is not polled for messages, sending
+/// goes on as usual.
+#[cfg(feature = "async")]
+pub struct Pausable<C> {
+	inner: C,
+	pause: ReadPause,
+}
+
+#[cfg(feature = "async")]
+impl<C> Pausable<C> {
+	/// Read from `inner` until paused.
+	pub fn new(inner: C) -> Self {
+		Pausable::with_pause(inner, ReadPause::new())
+	}
+
+	/// Read from `inner` while `pause` is not paused.
+	pub fn with_pause(inner: C, pause: ReadPause) -> Self {
+		Pausable {
+			inner: inner,
+			pause: pause,
+		}
+	}
+
+	/// Stop reading until `resume_reading` is called.
+	pub fn pause_reading(&self) {
+		self.pause.pause();
+	}
+
+	/// Go on reading.
+	pub fn resume_reading(&self) {
+		self.pause.resume();
+	}
+
+	/// A handle to pause and resume the reading, e.g. from another task.
+	pub fn read_pause(&self) -> ReadPause {
+		self.pause.clone()
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Give the wrapped client back.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Stream for Pausable<C>
+where
+	C: Stream,
+{
+	type Item = C::Item;
+	type Error = C::Error;
+
+	fn poll(&mut self) -> Poll<Option<C::Item>, C::Error> {
+		if self.pause.poll_paused() {
+			return Ok(Async::NotReady);
+		}
+		self.inner.poll()
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Sink for Pausable<C>
+where
+	C: Sink,
+{
+	type SinkItem = C::SinkItem;
+	type SinkError = C::SinkError;
+
+	fn start_send(&mut self, item: C::SinkItem) -> StartSend<C::SinkItem, C::SinkError> {
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), C::SinkError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), C::SinkError> {
+		self.inner.close()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn waits_until_resumed() {
+		let pause = ReadPause::new();
+		pause.wait();
+		pause.pause();
+		assert!(pause.is_paused());
+
+		let resumer = pause.clone();
+		let handle = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(20));
+			resumer.resume();
+		});
+		pause.wait();
+		assert!(!pause.is_paused());
+		handle.join().unwrap();
+	}
+
+	#[test]
+	#[cfg(feature = "async")]
+	fn pausable_stops_polling() {
+		use futures::{future, stream, Future};
+
+		let mut messages = Pausable::new(stream::iter_ok::<_, ()>(vec![1, 2]));
+		messages.pause_reading();
+		future::lazy(|| {
+			assert_eq!(messages.poll(), Ok(Async::NotReady));
+			messages.resume_reading();
+			assert_eq!(messages.poll(), Ok(Async::Ready(Some(1))));
+			Ok::<(), ()>(())
+		}).wait()
+			.unwrap();
+	}
+}
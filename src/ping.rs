@@ -0,0 +1,231 @@
+//! Sending a ping and waiting for its pong.
+//!
+//! `Pings` keeps track of the pings sent on a connection and sees the pongs
+//! coming back, as middleware of the connection. A pong answers the oldest
+//! ping waiting for one with the same payload, and the `PendingPong` of that
+//! ping then tells how long the answer took. That is all an application
+//! needs to check the health of a connection on its own terms.
+//!
+//! A pong is only seen while the connection is read from, so a synchronous
+//! client has to be read from on another thread (e.g. after a split) while
+//! waiting for a pong. The `PendingPong` of an asynchronous client is a
+//! future, put a deadline on it with `tokio::timer::Deadline`.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use websocket::ClientBuilder;
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//! let pong = client.ping_with(b"health".to_vec()).unwrap();
+//!
+//! let (mut reader, _writer) = client.split().unwrap();
+//! ::std::thread::spawn(move || for message in reader.incoming_messages() {
+//!     // ... handle the messages, the pong is seen on the way
+//! #   let _ = message;
+//! });
+//!
+//! let latency = pong.wait(Duration::from_secs(5)).unwrap();
+//! println!("the peer answered in {:?}", latency);
+//! ```
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async")]
+use futures::{Async, Future, Poll};
+#[cfg(feature = "async")]
+use futures::task::{self, Task};
+
+use dataframe::{DataFrame, Opcode};
+use message::OwnedMessage;
+use middleware::{Direction, Middleware};
+use result::{WebSocketError, WebSocketResult};
+
+/// The pings of a connection waiting for their pongs, see the module docs.
+///
+/// Cloning is cheap, all clones track the same pings.
+#[derive(Clone, Default)]
+pub struct Pings {
+	pending: Arc<Mutex<Vec<Arc<Slot>>>>,
+}
+
+/// Where the answer to a ping ends up.
+struct Slot {
+	payload: Vec<u8>,
+	sent: Instant,
+	latency: Mutex<Answer>,
+	answered: Condvar,
+}
+
+#[derive(Default)]
+struct Answer {
+	latency: Option<Duration>,
+	#[cfg(feature = "async")]
+	task: Option<Task>,
+}
+
+impl Pings {
+	/// No pings sent yet.
+	pub fn new() -> Self {
+		Pings::default()
+	}
+
+	/// A ping with `payload` to send, and the pong to wait for. The time is
+	/// measured from now on, so the ping should be sent right away.
+	pub fn ping(&self, payload: Vec<u8>) -> (OwnedMessage, PendingPong) {
+		let slot = Arc::new(Slot {
+			payload: payload.clone(),
+			sent: Instant::now(),
+			latency: Mutex::new(Answer::default()),
+			answered: Condvar::new(),
+		});
+		lock(&self.pending).push(slot.clone());
+		(OwnedMessage::Ping(payload), PendingPong { slot: slot })
+	}
+
+	/// The number of pings still waiting for their pong.
+	pub fn pending(&self) -> usize {
+		let mut pending = lock(&self.pending);
+		// nobody waits for the ones whose `PendingPong` was dropped
+		pending.retain(|slot| Arc::strong_count(slot) > 1);
+		pending.len()
+	}
+
+	/// Answer the oldest ping with `payload`.
+	fn pong(&self, payload: &[u8]) {
+		let slot = {
+			let mut pending = lock(&self.pending);
+			pending.retain(|slot| Arc::strong_count(slot) > 1);
+			match pending.iter().position(|slot| slot.payload == payload) {
+				Some(i) => pending.remove(i),
+				None => return,
+			}
+		};
+		let mut answer = lock(&slot.latency);
+		answer.latency = Some(slot.sent.elapsed());
+		#[cfg(feature = "async")]
+		{
+			if let Some(task) = answer.task.take() {
+				task.notify();
+			}
+		}
+		slot.answered.notify_all();
+	}
+}
+
+impl Middleware for Pings {
+	fn on_frame(&self, direction: Direction, frame: &mut DataFrame) -> WebSocketResult<()> {
+		if direction == Direction::Inbound && frame.opcode == Opcode::Pong {
+			self.pong(&frame.data);
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for Pings {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Pings").field("pending", &self.pending()).finish()
+	}
+}
+
+/// The pong to a ping sent with `Pings::ping`, see the module docs.
+pub struct PendingPong {
+	slot: Arc<Slot>,
+}
+
+impl PendingPong {
+	/// How long the pong took to arrive, `None` if it didn't yet.
+	pub fn latency(&self) -> Option<Duration> {
+		lock(&self.slot.latency).latency
+	}
+
+	/// Block until the pong arrives, failing with `WebSocketError::Timeout`
+	/// if that takes longer than `timeout`.
+	pub fn wait(self, timeout: Duration) -> WebSocketResult<Duration> {
+		let deadline = Instant::now() + timeout;
+		let mut answer = lock(&self.slot.latency);
+		loop {
+			if let Some(latency) = answer.latency {
+				return Ok(latency);
+			}
+			let now = Instant::now();
+			if now >= deadline {
+				return Err(WebSocketError::Timeout);
+			}
+			answer = match self.slot.answered.wait_timeout(answer, deadline - now) {
+				Ok((guard, _)) => guard,
+				Err(poisoned) => poisoned.into_inner().0,
+			};
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl Future for PendingPong {
+	type Item = Duration;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Duration, WebSocketError> {
+		let mut answer = lock(&self.slot.latency);
+		match answer.latency {
+			Some(latency) => Ok(Async::Ready(latency)),
+			None => {
+				answer.task = Some(task::current());
+				Ok(Async::NotReady)
+			}
+		}
+	}
+}
+
+impl fmt::Debug for PendingPong {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("PendingPong")
+			.field("payload", &self.slot.payload)
+			.field("latency", &self.latency())
+			.finish()
+	}
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+	// what is guarded is left consistent even if a holder panicked
+	match mutex.lock() {
+		Ok(guard) => guard,
+		Err(poisoned) => poisoned.into_inner(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pongs_answer_the_oldest_matching_ping() {
+		let pings = Pings::new();
+		let (ping, first) = pings.ping(b"a".to_vec());
+		assert_eq!(ping, OwnedMessage::Ping(b"a".to_vec()));
+		let (_, second) = pings.ping(b"a".to_vec());
+		let (_, other) = pings.ping(b"b".to_vec());
+		let (_, dropped) = pings.ping(b"c".to_vec());
+		drop(dropped);
+		assert_eq!(pings.pending(), 3);
+
+		let mut pong = DataFrame::new(true, Opcode::Pong, b"a".to_vec());
+		pings.on_frame(Direction::Inbound, &mut pong).unwrap();
+		assert!(first.latency().is_some());
+		assert!(second.latency().is_none());
+
+		// our own pongs answer nothing
+		pings.on_frame(Direction::Outbound, &mut pong).unwrap();
+		assert!(second.latency().is_none());
+		assert!(first.wait(Duration::from_secs(1)).is_ok());
+
+		match other.wait(Duration::from_millis(10)) {
+			Err(WebSocketError::Timeout) => {}
+			_ => panic!("expected a timeout"),
+		}
+		assert_eq!(pings.pending(), 1);
+	}
+}
@@ -0,0 +1,139 @@
+//! Reusing buffers between messages instead of allocating new ones.
+//!
+//! Every frame that is read needs a buffer for its payload, on a busy server
+//! that is a lot of allocations. A `BufferPool` keeps the buffers of messages
+//! one is done with around and hands them out again for the next frames.
+//!
+//! ```rust
+//! use websocket::OwnedMessage;
+//! use websocket::pool::BufferPool;
+//!
+//! // keep up to 64 buffers of at most 64 KiB around
+//! let pool = BufferPool::new(64, 64 * 1024);
+//!
+//! # let message = OwnedMessage::Binary(vec![0; 100]);
+//! // once a message was handled, give its buffer back
+//! pool.recycle(message);
+//! assert_eq!(pool.len(), 1);
+//!
+//! let buffer = pool.get(10);
+//! assert!(buffer.capacity() >= 100);
+//! ```
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use message::OwnedMessage;
+
+/// A pool of byte buffers, see the module documentation.
+///
+/// Clones of a pool share their buffers, so a single pool can serve every
+/// connection of a server.
+#[derive(Clone)]
+pub struct BufferPool {
+	max_buffers: usize,
+	max_capacity: usize,
+	buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl BufferPool {
+	/// Create a pool keeping at most `max_buffers` buffers around, buffers
+	/// larger than `max_capacity` bytes are not kept so a few huge messages
+	/// don't hold on to a lot of memory.
+	pub fn new(max_buffers: usize, max_capacity: usize) -> Self {
+		BufferPool {
+			max_buffers: max_buffers,
+			max_capacity: max_capacity,
+			buffers: Arc::new(Mutex::new(Vec::new())),
+		}
+	}
+
+	/// Take an empty buffer able to hold at least `capacity` bytes, a new
+	/// one is allocated if the pool has none to spare.
+	pub fn get(&self, capacity: usize) -> Vec<u8> {
+		let mut buffer = self.lock().pop().unwrap_or_else(Vec::new);
+		buffer.reserve(capacity);
+		buffer
+	}
+
+	/// Give a buffer back to the pool.
+	pub fn put(&self, mut buffer: Vec<u8>) {
+		if buffer.capacity() == 0 || buffer.capacity() > self.max_capacity {
+			return;
+		}
+		let mut buffers = self.lock();
+		if buffers.len() < self.max_buffers {
+			buffer.clear();
+			buffers.push(buffer);
+		}
+	}
+
+	/// Give the buffer of a message that was handled back to the pool.
+	pub fn recycle(&self, message: OwnedMessage) {
+		let buffer = match message {
+			OwnedMessage::Text(text) => text.into_bytes(),
+			OwnedMessage::Binary(data) |
+			OwnedMessage::Ping(data) |
+			OwnedMessage::Pong(data) => data,
+			OwnedMessage::Close(Some(data)) => data.reason.into_bytes(),
+			OwnedMessage::Close(None) => return,
+		};
+		self.put(buffer);
+	}
+
+	/// The number of buffers in the pool.
+	pub fn len(&self) -> usize {
+		self.lock().len()
+	}
+
+	/// Whether the pool has no buffers to spare.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	fn lock(&self) -> MutexGuard<Vec<Vec<u8>>> {
+		match self.buffers.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+}
+
+impl Default for BufferPool {
+	/// A pool of up to 256 buffers of at most 64 KiB.
+	fn default() -> Self {
+		BufferPool::new(256, 64 * 1024)
+	}
+}
+
+impl fmt::Debug for BufferPool {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("BufferPool")
+		 .field("max_buffers", &self.max_buffers)
+		 .field("max_capacity", &self.max_capacity)
+		 .field("len", &self.len())
+		 .finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keeps_buffers_within_limits() {
+		let pool = BufferPool::new(2, 100);
+
+		pool.put(Vec::with_capacity(1000));
+		assert!(pool.is_empty());
+
+		pool.put(vec![1, 2, 3]);
+		pool.put(Vec::with_capacity(50));
+		pool.put(Vec::with_capacity(50));
+		assert_eq!(pool.len(), 2);
+
+		let buffer = pool.get(10);
+		assert!(buffer.is_empty());
+		assert!(buffer.capacity() >= 10);
+		assert_eq!(pool.len(), 1);
+	}
+}
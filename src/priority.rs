@@ -0,0 +1,456 @@
+//! Keeping pings and pongs from waiting behind large messages.
+//!
+//! A message handed to an asynchronous client is encoded into its write
+//! buffer in one piece, so a ping sent right after a multi-megabyte message
+//! only goes out once all of that message did. On a slow link this can take
+//! long enough for the peer to give up on the connection.
+//!
+//! `Prioritized` sends text and binary messages in fragments and lets pings
+//! and pongs go out in between them, as RFC 6455 allows. Close messages stay
+//! in order behind the messages sent before them, so nothing sent before a
+//! close is cut off.
+//!
+//! `Lanes` goes further and takes text and binary messages tagged with a
+//! `Priority`, sending realtime messages before the bulk ones that wait, so
+//! a large snapshot sent as many messages doesn't hold up time-critical
+//! updates. The fragments of a message can't be interleaved with another
+//! message, so a realtime message still waits for the bulk message that is
+//! being sent, but no longer than that.
+//!
+//! ```rust,no_run
+//! # extern crate tokio;
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::ClientBuilder;
+//! use websocket::OwnedMessage;
+//! use websocket::futures::{Future, Sink};
+//! use websocket::priority::Prioritized;
+//!
+//! let connect = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .async_connect_insecure()
+//!     .and_then(|(client, _)| {
+//!         let client = Prioritized::from_client(client);
+//!         client.send(OwnedMessage::Binary(vec![0; 8 * 1024 * 1024]))
+//!     })
+//!     .and_then(|client| client.send(OwnedMessage::Ping(Vec::new())));
+//! # tokio::run(connect.map(|_| ()).map_err(|_| ()));
+//! # }
+//! ```
+use std::collections::VecDeque;
+
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::Framed;
+
+use client::async::Client;
+use codec::ws::MessageCodec;
+use dataframe::{DataFrame, Opcode};
+use message::OwnedMessage;
+use result::WebSocketError;
+use ws::dataframe::DataFrame as DataFrameable;
+
+/// The size of the fragments text and binary messages are sent in, unless
+/// another size is given.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 16 * 1024;
+
+/// How many realtime messages `Lanes` sends in a row while bulk messages
+/// wait, unless another number is given.
+pub const DEFAULT_BURST: usize = 8;
+
+/// A client that lets pings and pongs overtake the text and binary messages
+/// that are waiting to be sent, see the module docs.
+///
+/// It wraps a sink of frames, like a client using a
+/// `MessageCodec<DataFrame>`, and takes messages. At most one text or binary
+/// message is queued at a time, pings and pongs are always taken right away.
+pub struct Prioritized<C> {
+	inner: C,
+	fragment_size: usize,
+	/// the pings and pongs waiting to be sent
+	control: VecDeque<DataFrame>,
+	/// the fragments of the message waiting to be sent, and closes
+	data: VecDeque<DataFrame>,
+}
+
+impl<S> Prioritized<Framed<S, MessageCodec<DataFrame>>>
+where
+	S: AsyncRead + AsyncWrite,
+{
+	/// Prioritize the messages sent to `client`, keeping the settings of its
+	/// codec and whatever it buffered.
+	pub fn from_client(client: Client<S>) -> Self {
+		let (parts, codec) = client.into_parts_and_codec();
+		Prioritized::new(Framed::from_parts(parts, codec.into_message_type()))
+	}
+}
+
+impl<C> Prioritized<C> {
+	/// Send messages to `inner` in fragments of `DEFAULT_FRAGMENT_SIZE`.
+	pub fn new(inner: C) -> Self {
+		Prioritized::with_fragment_size(inner, DEFAULT_FRAGMENT_SIZE)
+	}
+
+	/// Send messages to `inner` in fragments of `fragment_size` bytes.
+	/// Smaller fragments let pings through sooner at the cost of more
+	/// frame headers.
+	///
+	/// # Panics
+	///
+	/// If `fragment_size` is 0.
+	pub fn with_fragment_size(inner: C, fragment_size: usize) -> Self {
+		assert!(fragment_size > 0, "fragments must not be empty");
+		Prioritized {
+			inner: inner,
+			fragment_size: fragment_size,
+			control: VecDeque::new(),
+			data: VecDeque::new(),
+		}
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Give the wrapped client back, dropping whatever was not sent yet.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+
+	/// Split a text or binary message into its fragments.
+	fn fragment(&mut self, opcode: Opcode, payload: Vec<u8>) {
+		let frames = DataFrame::new(true, opcode, payload).fragment(self.fragment_size);
+		self.data.extend(frames);
+	}
+}
+
+impl<C> Sink for Prioritized<C>
+where
+	C: Sink<SinkItem = DataFrame, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		if item.is_ping() || item.is_pong() {
+			let opcode = if item.is_ping() { Opcode::Ping } else { Opcode::Pong };
+			self.control.push_back(DataFrame::new(true, opcode, item.take_payload()));
+			return Ok(AsyncSink::Ready);
+		}
+		if !self.data.is_empty() {
+			self.poll_complete()?;
+			if !self.data.is_empty() {
+				return Ok(AsyncSink::NotReady(item));
+			}
+		}
+		match item {
+			OwnedMessage::Text(text) => self.fragment(Opcode::Text, text.into_bytes()),
+			OwnedMessage::Binary(data) => self.fragment(Opcode::Binary, data),
+			close => self.data.push_back(DataFrame::new(true, Opcode::Close, close.take_payload())),
+		}
+		Ok(AsyncSink::Ready)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		loop {
+			let control = !self.control.is_empty();
+			let frame = if control {
+				self.control.pop_front()
+			} else {
+				self.data.pop_front()
+			};
+			let frame = match frame {
+				Some(frame) => frame,
+				None => break,
+			};
+			if let AsyncSink::NotReady(frame) = self.inner.start_send(frame)? {
+				if control {
+					self.control.push_front(frame);
+				} else {
+					self.data.push_front(frame);
+				}
+				self.inner.poll_complete()?;
+				return Ok(Async::NotReady);
+			}
+		}
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		if let Async::NotReady = self.poll_complete()? {
+			return Ok(Async::NotReady);
+		}
+		self.inner.close()
+	}
+}
+
+impl<C> Stream for Prioritized<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		self.inner.poll()
+	}
+}
+
+/// How urgent a text or binary message is, see `Lanes`. Pings and pongs
+/// always go first, they don't need one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Priority {
+	/// Time-critical updates, sent before the bulk messages that wait.
+	Realtime,
+	/// Transfers that can wait, like snapshots.
+	Bulk,
+}
+
+/// A client that sends the text and binary messages waiting in its lanes
+/// by their `Priority`, see the module docs.
+///
+/// It takes messages tagged with their priority and sends them through a
+/// `Prioritized` client, one at a time, so pings and pongs still overtake
+/// the fragments of the message being sent. Messages are always accepted
+/// right away, wrap a `WriteQueue` if the lanes must not grow without
+/// bounds. A close message goes out once both lanes are empty, nothing is
+/// taken after it until then.
+///
+/// Bulk messages are not starved: after `DEFAULT_BURST` realtime messages
+/// in a row (see `with_burst`), a waiting bulk message goes out.
+pub struct Lanes<C> {
+	inner: Prioritized<C>,
+	realtime: VecDeque<OwnedMessage>,
+	bulk: VecDeque<OwnedMessage>,
+	close: Option<OwnedMessage>,
+	burst: usize,
+	/// the realtime messages sent in a row while bulk messages waited
+	streak: usize,
+}
+
+impl<S> Lanes<Framed<S, MessageCodec<DataFrame>>>
+where
+	S: AsyncRead + AsyncWrite,
+{
+	/// Send the messages sent to `client` by their priority, in fragments
+	/// of `DEFAULT_FRAGMENT_SIZE`.
+	pub fn from_client(client: Client<S>) -> Self {
+		Lanes::new(Prioritized::from_client(client))
+	}
+}
+
+impl<C> Lanes<C> {
+	/// Send messages by their priority through `inner`.
+	pub fn new(inner: Prioritized<C>) -> Self {
+		Lanes {
+			inner: inner,
+			realtime: VecDeque::new(),
+			bulk: VecDeque::new(),
+			close: None,
+			burst: DEFAULT_BURST,
+			streak: 0,
+		}
+	}
+
+	/// Send at most `burst` realtime messages in a row while bulk messages
+	/// wait.
+	///
+	/// # Panics
+	///
+	/// If `burst` is 0.
+	pub fn with_burst(mut self, burst: usize) -> Self {
+		assert!(burst > 0, "realtime messages must get a turn");
+		self.burst = burst;
+		self
+	}
+
+	/// The number of messages waiting in the lane of `priority`.
+	pub fn queued(&self, priority: Priority) -> usize {
+		match priority {
+			Priority::Realtime => self.realtime.len(),
+			Priority::Bulk => self.bulk.len(),
+		}
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &Prioritized<C> {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut Prioritized<C> {
+		&mut self.inner
+	}
+
+	/// Give the wrapped client back, dropping whatever is still waiting in
+	/// the lanes.
+	pub fn into_inner(self) -> Prioritized<C> {
+		self.inner
+	}
+
+	/// Take the message to send next out of the lanes.
+	fn next(&mut self) -> Option<OwnedMessage> {
+		let bulk_turn = !self.bulk.is_empty() && (self.realtime.is_empty() || self.streak >= self.burst);
+		if bulk_turn {
+			self.streak = 0;
+			return self.bulk.pop_front();
+		}
+		match self.realtime.pop_front() {
+			Some(message) => {
+				if !self.bulk.is_empty() {
+					self.streak += 1;
+				}
+				Some(message)
+			}
+			None => self.close.take(),
+		}
+	}
+}
+
+impl<C> Sink for Lanes<C>
+where
+	C: Sink<SinkItem = DataFrame, SinkError = WebSocketError>,
+{
+	type SinkItem = (Priority, OwnedMessage);
+	type SinkError = WebSocketError;
+
+	fn start_send(
+		&mut self,
+		item: (Priority, OwnedMessage),
+	) -> StartSend<(Priority, OwnedMessage), WebSocketError> {
+		let (priority, message) = item;
+		if message.is_ping() || message.is_pong() {
+			return match self.inner.start_send(message)? {
+				AsyncSink::Ready => Ok(AsyncSink::Ready),
+				AsyncSink::NotReady(message) => Ok(AsyncSink::NotReady((priority, message))),
+			};
+		}
+		if self.close.is_some() {
+			self.poll_complete()?;
+			if self.close.is_some() {
+				return Ok(AsyncSink::NotReady((priority, message)));
+			}
+		}
+		match (message.is_close(), priority) {
+			(true, _) => self.close = Some(message),
+			(false, Priority::Realtime) => self.realtime.push_back(message),
+			(false, Priority::Bulk) => self.bulk.push_back(message),
+		}
+		Ok(AsyncSink::Ready)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		loop {
+			// the next message is only picked once the last one is out, so
+			// a realtime message sent meanwhile can still go first
+			if let Async::NotReady = self.inner.poll_complete()? {
+				return Ok(Async::NotReady);
+			}
+			match self.next() {
+				// an idle `Prioritized` takes any message
+				Some(message) => {
+					self.inner.start_send(message)?;
+				}
+				None => return Ok(Async::Ready(())),
+			}
+		}
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		if let Async::NotReady = self.poll_complete()? {
+			return Ok(Async::NotReady);
+		}
+		self.inner.close()
+	}
+}
+
+impl<C> Stream for Lanes<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		self.inner.poll()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use test_util::Slow;
+
+	#[test]
+	fn pings_overtake_fragments() {
+		let slow: Slow<DataFrame> = Slow::new(1);
+		let mut sink = Prioritized::with_fragment_size(slow, 4);
+		sink.start_send(OwnedMessage::Binary(vec![7; 10])).unwrap();
+		assert!(sink.poll_complete().unwrap().is_not_ready());
+		sink.start_send(OwnedMessage::Ping(vec![1])).unwrap();
+		sink.get_mut().room = 10;
+		assert!(sink.poll_complete().unwrap().is_ready());
+
+		let sent: Vec<_> = sink.get_ref()
+			.sent
+			.iter()
+			.map(|f| (f.opcode, f.finished, f.data.len()))
+			.collect();
+		assert_eq!(
+			sent,
+			vec![
+				(Opcode::Binary, false, 4),
+				(Opcode::Ping, true, 1),
+				(Opcode::Continuation, false, 4),
+				(Opcode::Continuation, true, 2),
+			]
+		);
+	}
+
+	#[test]
+	fn realtime_messages_go_first() {
+		let slow: Slow<DataFrame> = Slow::new(1);
+		let mut sink = Lanes::new(Prioritized::with_fragment_size(slow, 4)).with_burst(2);
+		for i in 0..3 {
+			sink.start_send((Priority::Bulk, OwnedMessage::Binary(vec![i; 2]))).unwrap();
+		}
+		assert!(sink.poll_complete().unwrap().is_not_ready());
+		for i in 0..3 {
+			sink.start_send((Priority::Realtime, OwnedMessage::Text(i.to_string()))).unwrap();
+		}
+		sink.start_send((Priority::Bulk, OwnedMessage::Close(None))).unwrap();
+		assert_eq!(sink.queued(Priority::Realtime), 3);
+		assert!(sink.start_send((Priority::Realtime, OwnedMessage::Text("late".to_string())))
+			.unwrap()
+			.is_not_ready());
+		sink.get_mut().get_mut().room = 100;
+		assert!(sink.poll_complete().unwrap().is_ready());
+
+		let sent: Vec<_> = sink.get_ref()
+			.get_ref()
+			.sent
+			.iter()
+			.map(|f| (f.opcode, f.data[..].to_vec()))
+			.collect();
+		assert_eq!(
+			sent,
+			vec![
+				(Opcode::Binary, vec![0, 0]),
+				// taken before the realtime messages came
+				(Opcode::Binary, vec![1, 1]),
+				(Opcode::Text, b"0".to_vec()),
+				(Opcode::Text, b"1".to_vec()),
+				(Opcode::Binary, vec![2, 2]),
+				(Opcode::Text, b"2".to_vec()),
+				(Opcode::Close, vec![]),
+			]
+		);
+	}
+}
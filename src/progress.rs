@@ -0,0 +1,69 @@
+//! Following how far along large messages are.
+//!
+//! A `ProgressCallback` set on a sender, receiver or codec is told about
+//! every frame of a text or binary message that goes out or comes in, which
+//! is enough to show the progress of files sent over a connection. Sending a
+//! message in fragments (see `Sender::set_fragment_size`) reports progress
+//! after each fragment, a message sent in one frame is reported once. The
+//! size of incoming messages is not known until their last frame arrived.
+//!
+//! ```rust
+//! use websocket::progress::ProgressCallback;
+//! use websocket::sender::Sender;
+//! use websocket::Message;
+//!
+//! let mut sender = Sender::new(false);
+//! sender.set_fragment_size(Some(1024));
+//! sender.set_progress(Some(ProgressCallback::new(|progress| {
+//!     println!("sent {} of {:?} bytes", progress.bytes, progress.total);
+//! })));
+//!
+//! let mut out = Vec::new();
+//! websocket::ws::Sender::send_message(&mut sender, &mut out, &Message::binary(vec![0; 4096]))
+//!     .unwrap();
+//! ```
+use std::fmt;
+use std::sync::Arc;
+
+use middleware::Direction;
+
+/// How much of a message was sent or received so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+	/// Whether the message is going out or coming in.
+	pub direction: Direction,
+	/// The bytes of payload sent or received so far.
+	pub bytes: usize,
+	/// The size of the whole payload, if it is known.
+	pub total: Option<usize>,
+	/// Whether this was the last frame of the message.
+	pub done: bool,
+}
+
+/// A function told about the progress of messages, see the module docs.
+///
+/// Cloning is cheap, all clones call the same function.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<Fn(&Progress) + Send + Sync>);
+
+impl ProgressCallback {
+	/// Call `callback` with the progress of each message. It is called right
+	/// in the middle of reading and writing, so it should return quickly.
+	pub fn new<F>(callback: F) -> Self
+	where
+		F: Fn(&Progress) + Send + Sync + 'static,
+	{
+		ProgressCallback(Arc::new(callback))
+	}
+
+	/// Tell the callback about `progress`.
+	pub fn report(&self, progress: Progress) {
+		(self.0)(&progress)
+	}
+}
+
+impl fmt::Debug for ProgressCallback {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("ProgressCallback")
+	}
+}
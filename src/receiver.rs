@@ -2,14 +2,28 @@
 
 use std::io::{BufReader, Read};
 use std::io::Result as IoResult;
+use std::str;
+use std::thread;
+use std::time::Instant;
 
-use dataframe::{DataFrame, Opcode};
+use bytes::BytesMut;
+
+use dataframe::{DataFrame, Opcode, ReservedClaims};
 use result::{WebSocketResult, WebSocketError};
 use ws;
 use ws::receiver::Receiver as ReceiverTrait;
 use ws::receiver::{MessageIterator, DataFrameIterator};
 use message::OwnedMessage;
+use events::{EventReader, MessageEvent};
+use pool::BufferPool;
+use middleware::{Direction, Middlewares};
+use pause::ReadPause;
+use progress::{Progress, ProgressCallback};
+use server::ratelimit::{self, Exceeded, RateLimit, RateLimitState};
+use server::limit::ConnectionPermit;
 use stream::sync::{AsTcpStream, Stream};
+#[cfg(feature = "metrics")]
+use metrics::{self, ConnectionGuard};
 pub use stream::sync::Shutdown;
 
 /// This reader bundles an existing stream with a parsing algorithm.
@@ -51,6 +65,24 @@ where
 	pub fn incoming_messages<'a>(&'a mut self) -> MessageIterator<'a, Receiver, BufReader<R>> {
 		self.receiver.incoming_messages(&mut self.stream)
 	}
+
+	/// Reads a single message, appending its payload to `buf`.
+	/// See `Receiver::recv_message_into_buf`.
+	pub fn recv_message_into_buf(&mut self, buf: &mut BytesMut) -> WebSocketResult<Received> {
+		self.receiver.recv_message_into_buf(&mut self.stream, buf)
+	}
+
+	/// Reads a single text message, appending it to `text`.
+	/// See `Receiver::recv_text_into`.
+	pub fn recv_text_into(&mut self, text: &mut String) -> WebSocketResult<Received> {
+		self.receiver.recv_text_into(&mut self.stream, text)
+	}
+
+	/// Reads the next piece of the messages received.
+	/// See `Receiver::recv_event`.
+	pub fn recv_event(&mut self) -> WebSocketResult<MessageEvent> {
+		self.receiver.recv_event(&mut self.stream)
+	}
 }
 
 impl<S> Reader<S>
@@ -75,6 +107,26 @@ where
 pub struct Receiver {
 	buffer: Vec<DataFrame>,
 	mask: bool,
+	pool: Option<BufferPool>,
+	/// the opcode and length so far of the message `recv_into` is in the
+	/// middle of
+	partial: Option<(Opcode, usize)>,
+	/// the text `recv_text_into` received so far
+	text: Vec<u8>,
+	middleware: Middlewares,
+	rate_limit: Option<RateLimitState>,
+	/// whether the rate limit was exceeded and the peer was not told yet
+	rate_close_pending: bool,
+	claims: ReservedClaims,
+	/// whether the rest of a message with a claimed opcode is being skipped
+	skipping: bool,
+	progress: Option<ProgressCallback>,
+	pause: ReadPause,
+	events: EventReader,
+	/// the slot the connection takes up in a server's `ConnectionLimiter`
+	permit: Option<ConnectionPermit>,
+	#[cfg(feature = "metrics")]
+	_connection: ConnectionGuard,
 }
 
 impl Receiver {
@@ -83,10 +135,261 @@ impl Receiver {
 		Receiver {
 			buffer: Vec::new(),
 			mask: mask,
+			pool: None,
+			partial: None,
+			text: Vec::new(),
+			middleware: Middlewares::default(),
+			rate_limit: None,
+			rate_close_pending: false,
+			claims: ReservedClaims::new(),
+			skipping: false,
+			progress: None,
+			pause: ReadPause::new(),
+			events: EventReader::new(),
+			permit: None,
+			#[cfg(feature = "metrics")]
+			_connection: ConnectionGuard::new(),
+		}
+	}
+
+	/// Take the buffers for incoming frames from `pool` rather than
+	/// allocating a new one for each frame.
+	pub fn set_pool(&mut self, pool: Option<BufferPool>) {
+		self.pool = pool;
+	}
+
+	/// Run every frame received through this middleware.
+	pub fn set_middleware(&mut self, middleware: Middlewares) {
+		self.middleware = middleware;
+	}
+
+	/// Report the progress of the text and binary messages received, see
+	/// `ProgressCallback`.
+	pub fn set_progress(&mut self, progress: Option<ProgressCallback>) {
+		self.progress = progress;
+	}
+
+	/// Let the reserved bits and opcodes an extension claimed through the
+	/// message readers, see `ReservedClaims`.
+	pub fn set_reserved_claims(&mut self, claims: ReservedClaims) {
+		self.claims = claims;
+	}
+
+	/// Limit how fast frames are received, either by sleeping before
+	/// returning a frame that went over the limit or by failing with an
+	/// error, see `RateLimit`.
+	pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+		self.rate_limit = limit.map(RateLimit::start);
+	}
+
+	/// Hold on to the connection's `ConnectionPermit`, which is released
+	/// when the receiver is dropped.
+	pub fn set_permit(&mut self, permit: Option<ConnectionPermit>) {
+		self.permit = permit;
+	}
+
+	/// The handle pausing the reading of frames, while paused the receive
+	/// methods wait for it to be resumed before reading.
+	pub fn read_pause(&self) -> ReadPause {
+		self.pause.clone()
+	}
+
+	/// Whether the rate limit was just exceeded with `Exceeded::Close`, in
+	/// which case the peer should be sent a close message with
+	/// `POLICY_VIOLATION`. Only the first call after that returns `true`.
+	pub fn take_rate_close(&mut self) -> bool {
+		::std::mem::replace(&mut self.rate_close_pending, false)
+	}
+
+	/// Reads a single message, appending its payload to `buf` instead of
+	/// allocating a new message, and tells what kind of message it was.
+	///
+	/// Control frames may arrive in between the fragments of a message, they
+	/// are returned right away as `Received::Control` and what was received of
+	/// the message so far stays in `buf`, so keep the buffer as it is until
+	/// `Received::Text` or `Received::Binary` comes back. Text is not checked
+	/// to be valid UTF-8, use `recv_text_into` for that.
+	///
+	/// Together with a pool set through `set_pool` no allocations are made
+	/// once `buf` grew large enough.
+	pub fn recv_message_into_buf<R>(
+		&mut self,
+		reader: &mut R,
+		buf: &mut BytesMut,
+	) -> WebSocketResult<Received>
+	where
+		R: Read,
+	{
+		self.recv_into(reader, |data| buf.extend_from_slice(data))
+	}
+
+	/// Reads a single text message, appending it to `text`, see
+	/// `recv_message_into_buf`.
+	///
+	/// Receiving a binary message is an error.
+	pub fn recv_text_into<R>(&mut self, reader: &mut R, text: &mut String) -> WebSocketResult<Received>
+	where
+		R: Read,
+	{
+		let mut received = ::std::mem::replace(&mut self.text, Vec::new());
+		let result = self.recv_into(reader, |data| received.extend_from_slice(data));
+		let result = match result {
+			Ok(Received::Text) => {
+				let appended = str::from_utf8(&received).map(|s| text.push_str(s));
+				received.clear();
+				appended.map(|()| Received::Text).map_err(WebSocketError::from)
+			}
+			Ok(Received::Binary) => {
+				received.clear();
+				Err(WebSocketError::ProtocolError("Expected a text message"))
+			}
+			Ok(control) => Ok(control),
+			Err(e) => {
+				received.clear();
+				Err(e)
+			}
+		};
+		self.text = received;
+		result
+	}
+
+	/// Reads the next piece of the messages received, handing out the
+	/// payload of every fragment as soon as it arrives, see the `events`
+	/// module.
+	///
+	/// Don't mix it with the other receive methods in the middle of a
+	/// message, they don't know about the fragments read here.
+	pub fn recv_event<R>(&mut self, reader: &mut R) -> WebSocketResult<MessageEvent>
+	where
+		R: Read,
+	{
+		loop {
+			if let Some(event) = self.events.pop() {
+				return Ok(event);
+			}
+			let frame = self.recv_claimed_dataframe(reader)?;
+			self.events.push(frame)?;
+		}
+	}
+
+	/// Reads frames until a message is complete or a control frame arrives,
+	/// handing the payloads of data frames to `append`.
+	fn recv_into<R, F>(&mut self, reader: &mut R, mut append: F) -> WebSocketResult<Received>
+	where
+		R: Read,
+		F: FnMut(&[u8]),
+	{
+		loop {
+			let frame = match self.recv_claimed_dataframe(reader) {
+				Ok(frame) => frame,
+				Err(e) => {
+					self.partial = None;
+					return Err(e);
+				}
+			};
+
+			if frame.opcode as u8 >= 8 {
+				let message = <OwnedMessage as ws::Message>::from_dataframes(vec![frame])?;
+				#[cfg(feature = "metrics")]
+				metrics::received(&message, self.mask);
+				return Ok(Received::Control(message));
+			}
+
+			let (opcode, len) = match (frame.opcode, self.partial) {
+				(Opcode::Continuation, Some(partial)) => partial,
+				(Opcode::Continuation, None) => {
+					return Err(WebSocketError::ProtocolError(
+						"Unexpected continuation data frame opcode",
+					));
+				}
+				(opcode, None) => (opcode, 0),
+				(_, Some(_)) => {
+					self.partial = None;
+					return Err(WebSocketError::ProtocolError(
+						"Unexpected data frame opcode",
+					));
+				}
+			};
+			if frame.reserved != [false; 3] {
+				self.partial = None;
+				return Err(WebSocketError::ProtocolError(
+					"Unsupported reserved bits received",
+				));
+			}
+
+			append(&frame.data);
+			let len = len + frame.data.len();
+			let finished = frame.finished;
+			self.report(len, finished);
+			if let Some(ref pool) = self.pool {
+				pool.put(frame.data);
+			}
+
+			if !finished {
+				self.partial = Some((opcode, len));
+				continue;
+			}
+			self.partial = None;
+			let received = match opcode {
+				Opcode::Text => Received::Text,
+				Opcode::Binary => Received::Binary,
+				_ => return Err(WebSocketError::ProtocolError("Unsupported opcode received")),
+			};
+			#[cfg(feature = "metrics")]
+			metrics::sink().message_received(len);
+			return Ok(received);
+		}
+	}
+
+	/// Reads the next frame the message readers should look at, skipping
+	/// the ones claimed by an extension.
+	fn recv_claimed_dataframe<R>(&mut self, reader: &mut R) -> WebSocketResult<DataFrame>
+	where
+		R: Read,
+	{
+		loop {
+			let mut frame = self.recv_dataframe(reader)?;
+			if self.claims.admit(&mut frame, &mut self.skipping) {
+				return Ok(frame);
+			}
+		}
+	}
+
+	/// Tell the progress callback `bytes` of a message were received.
+	fn report(&self, bytes: usize, done: bool) {
+		if let Some(ref progress) = self.progress {
+			progress.report(Progress {
+				direction: Direction::Inbound,
+				bytes: bytes,
+				total: None,
+				done: done,
+			});
+		}
+	}
+
+	/// Report the progress of the message being put together in the buffer.
+	fn report_buffered(&self) {
+		if self.progress.is_none() || self.buffer.first().map_or(true, |f| f.opcode as u8 >= 8) {
+			return;
 		}
+		let bytes = self.buffer.iter().map(|frame| frame.data.len()).sum();
+		let done = self.buffer.last().map_or(false, |frame| frame.finished);
+		self.report(bytes, done);
 	}
 }
 
+/// What kind of message `Receiver::recv_message_into_buf` and
+/// `Receiver::recv_text_into` received.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Received {
+	/// A text message was appended to the buffer.
+	Text,
+	/// A binary message was appended to the buffer.
+	Binary,
+	/// A control message arrived, the buffer was left alone.
+	Control(OwnedMessage),
+}
+
 
 impl ws::Receiver for Receiver {
 	type F = DataFrame;
@@ -98,7 +401,25 @@ impl ws::Receiver for Receiver {
 	where
 		R: Read,
 	{
-		DataFrame::read_dataframe(reader, self.mask)
+		if self.rate_limit.as_ref().map_or(false, RateLimitState::is_exceeded) {
+			return Err(ratelimit::exceeded());
+		}
+		self.pause.wait();
+		let mut frame = match self.pool {
+			Some(ref pool) => DataFrame::read_dataframe_pooled(reader, self.mask, pool)?,
+			None => DataFrame::read_dataframe(reader, self.mask)?,
+		};
+		self.middleware.frame(Direction::Inbound, &mut frame)?;
+		if let Some(ref mut limit) = self.rate_limit {
+			if let Some(wait) = limit.record(frame.data.len(), frame.finished, Instant::now()) {
+				if limit.config().exceeded == Exceeded::Close {
+					self.rate_close_pending = true;
+					return Err(ratelimit::exceeded());
+				}
+				thread::sleep(wait);
+			}
+		}
+		Ok(frame)
 	}
 
 	/// Returns the data frames that constitute one message.
@@ -107,7 +428,7 @@ impl ws::Receiver for Receiver {
 		R: Read,
 	{
 		let mut finished = if self.buffer.is_empty() {
-			let first = self.recv_dataframe(reader)?;
+			let first = self.recv_claimed_dataframe(reader)?;
 
 			if first.opcode == Opcode::Continuation {
 				return Err(WebSocketError::ProtocolError(
@@ -117,18 +438,22 @@ impl ws::Receiver for Receiver {
 
 			let finished = first.finished;
 			self.buffer.push(first);
+			self.report_buffered();
 			finished
 		} else {
 			false
 		};
 
 		while !finished {
-			let next = self.recv_dataframe(reader)?;
+			let next = self.recv_claimed_dataframe(reader)?;
 			finished = next.finished;
 
 			match next.opcode as u8 {
 				// Continuation opcode
-				0 => self.buffer.push(next),
+				0 => {
+					self.buffer.push(next);
+					self.report_buffered();
+				}
 				// Control frame
 				8...15 => {
 					return Ok(vec![next]);
@@ -144,4 +469,149 @@ impl ws::Receiver for Receiver {
 
 		Ok(::std::mem::replace(&mut self.buffer, Vec::new()))
 	}
+
+	/// Reads a single message, reporting it to the metrics sink.
+	#[cfg(feature = "metrics")]
+	fn recv_message<'m, R>(&mut self, reader: &mut R) -> WebSocketResult<OwnedMessage>
+	where
+		R: Read,
+	{
+		let dataframes = self.recv_message_dataframes(reader)?;
+		let message = <OwnedMessage as ws::Message>::from_dataframes(dataframes)?;
+		metrics::received(&message, self.mask);
+		Ok(message)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+	use ws::dataframe::DataFrame as DataFrameable;
+
+	fn frames(frames: &[DataFrame]) -> Cursor<Vec<u8>> {
+		let mut bytes = Vec::new();
+		for frame in frames {
+			frame.write_to(&mut bytes, false).unwrap();
+		}
+		Cursor::new(bytes)
+	}
+
+	#[test]
+	fn pings_leave_the_partial_message_alone() {
+		let input = [
+			DataFrame::new(false, Opcode::Text, b"hello ".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"ping".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"world".to_vec()),
+		];
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&input);
+		let mut buf = BytesMut::new();
+		let ping = Received::Control(OwnedMessage::Ping(b"ping".to_vec()));
+		assert_eq!(receiver.recv_message_into_buf(&mut reader, &mut buf).unwrap(), ping);
+		assert_eq!(&buf[..], b"hello ");
+		assert_eq!(receiver.recv_message_into_buf(&mut reader, &mut buf).unwrap(), Received::Text);
+		assert_eq!(&buf[..], b"hello world");
+
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&input);
+		let mut text = String::new();
+		assert_eq!(receiver.recv_text_into(&mut reader, &mut text).unwrap(), ping);
+		assert_eq!(text, "");
+		assert_eq!(receiver.recv_text_into(&mut reader, &mut text).unwrap(), Received::Text);
+		assert_eq!(text, "hello world");
+	}
+
+	#[test]
+	fn text_readers_refuse_binary_messages() {
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&[
+			DataFrame::new(true, Opcode::Binary, vec![1, 2, 3]),
+			DataFrame::new(true, Opcode::Text, b"after".to_vec()),
+		]);
+		let mut text = String::new();
+		match receiver.recv_text_into(&mut reader, &mut text) {
+			Err(WebSocketError::ProtocolError(_)) => {}
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+		assert_eq!(text, "");
+
+		// the binary payload does not end up in the next message
+		assert_eq!(receiver.recv_text_into(&mut reader, &mut text).unwrap(), Received::Text);
+		assert_eq!(text, "after");
+	}
+
+	#[test]
+	fn text_is_checked_across_fragments() {
+		// "é" split in the middle is fine once the message is complete
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&[
+			DataFrame::new(false, Opcode::Text, vec![b'c', b'a', b'f', 0xC3]),
+			DataFrame::new(true, Opcode::Continuation, vec![0xA9]),
+		]);
+		let mut text = String::new();
+		assert_eq!(receiver.recv_text_into(&mut reader, &mut text).unwrap(), Received::Text);
+		assert_eq!(text, "caf\u{e9}");
+
+		// a lead byte followed by one that can't continue it is not
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&[
+			DataFrame::new(false, Opcode::Text, vec![b'c', b'a', b'f', 0xC3]),
+			DataFrame::new(true, Opcode::Continuation, vec![b'!']),
+		]);
+		let mut text = String::new();
+		match receiver.recv_text_into(&mut reader, &mut text) {
+			Err(WebSocketError::Utf8Error(_)) => {}
+			other => panic!("expected a UTF-8 error, got {:?}", other),
+		}
+		assert_eq!(text, "");
+	}
+
+	#[test]
+	fn unexpected_continuations_are_errors() {
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&[DataFrame::new(true, Opcode::Continuation, b"stray".to_vec())]);
+		let mut buf = BytesMut::new();
+		match receiver.recv_message_into_buf(&mut reader, &mut buf) {
+			Err(WebSocketError::ProtocolError(_)) => {}
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+
+		// as is a new message before the last one was finished
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&[
+			DataFrame::new(false, Opcode::Text, b"one".to_vec()),
+			DataFrame::new(true, Opcode::Text, b"two".to_vec()),
+		]);
+		match receiver.recv_message_into_buf(&mut reader, &mut buf) {
+			Err(WebSocketError::ProtocolError(_)) => {}
+			other => panic!("expected a protocol error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn buffers_are_reused() {
+		let mut receiver = Receiver::new(false);
+		let mut reader = frames(&[
+			DataFrame::new(true, Opcode::Binary, vec![7; 100]),
+			DataFrame::new(true, Opcode::Binary, vec![8; 10]),
+			DataFrame::new(true, Opcode::Text, b"more".to_vec()),
+			DataFrame::new(true, Opcode::Text, b"text".to_vec()),
+		]);
+
+		let mut buf = BytesMut::new();
+		assert_eq!(receiver.recv_message_into_buf(&mut reader, &mut buf).unwrap(), Received::Binary);
+		let capacity = buf.capacity();
+		buf.clear();
+		assert_eq!(receiver.recv_message_into_buf(&mut reader, &mut buf).unwrap(), Received::Binary);
+		assert_eq!(&buf[..], &[8; 10][..]);
+		assert_eq!(buf.capacity(), capacity);
+
+		// text is appended to what the caller has already
+		let mut text = String::from("some ");
+		assert_eq!(receiver.recv_text_into(&mut reader, &mut text).unwrap(), Received::Text);
+		assert_eq!(receiver.recv_text_into(&mut reader, &mut text).unwrap(), Received::Text);
+		assert_eq!(text, "some moretext");
+	}
 }
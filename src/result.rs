@@ -9,12 +9,16 @@ use http;
 use httparse;
 use url::ParseError;
 use server::upgrade::HyperIntoWsError;
+use frames::FrameError;
 
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 use native_tls::Error as TlsError;
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 use native_tls::HandshakeError as TlsHandshakeError;
 
+#[cfg(feature = "serde")]
+use serde_json;
+
 use codec;
 
 /// The type used for WebSocket results
@@ -45,6 +49,13 @@ pub enum WebSocketError {
 	DataFrameError(&'static str),
 	/// No data available
 	NoDataAvailable,
+	/// An operation did not complete within its time limit
+	Timeout,
+	/// An operation was abandoned through a cancel token
+	Cancelled,
+	/// A message was sent after the closing handshake started, see
+	/// `Client::set_auto_close`
+	ConnectionClosed,
 	/// An input/output error
 	IoError(io::Error),
 	/// An HTTP parsing error
@@ -64,6 +75,15 @@ pub enum WebSocketError {
 	TlsHandshakeInterruption,
 	/// A UTF-8 error
 	Utf8Error(Utf8Error),
+	/// A message could not be serialized to or deserialized from JSON, the
+	/// error is a `serde_json::Error`
+	///
+	/// The variant is there without the `serde` feature as well, so turning
+	/// the feature on doesn't change this enum.
+	JsonError(Box<Error + Send + Sync>),
+	/// A message could not be serialized to or deserialized from a format
+	/// other than JSON, see `serializer::MessageSerializer`
+	EncodingError(Box<Error + Send + Sync>),
 }
 
 impl fmt::Display for WebSocketError {
@@ -82,6 +102,9 @@ impl Error for WebSocketError {
 			WebSocketError::ResponseError(_) => "WebSocket response error",
 			WebSocketError::DataFrameError(_) => "WebSocket data frame error",
 			WebSocketError::NoDataAvailable => "No data available",
+			WebSocketError::Timeout => "Timed out",
+			WebSocketError::Cancelled => "Cancelled",
+			WebSocketError::ConnectionClosed => "Connection closed",
 			WebSocketError::IoError(_) => "I/O failure",
 			WebSocketError::HttpError(_) => "HTTP failure",
 			WebSocketError::UrlError(_) => "URL failure",
@@ -93,6 +116,8 @@ impl Error for WebSocketError {
 			WebSocketError::TlsHandshakeInterruption => "TLS Handshake interrupted",
 			WebSocketError::Utf8Error(_) => "UTF-8 failure",
 			WebSocketError::WebSocketUrlError(_) => "WebSocket URL failure",
+			WebSocketError::JsonError(_) => "JSON failure",
+			WebSocketError::EncodingError(_) => "Message encoding failure",
 		}
 	}
 
@@ -104,6 +129,8 @@ impl Error for WebSocketError {
 			WebSocketError::TlsError(ref error) => Some(error),
 			WebSocketError::Utf8Error(ref error) => Some(error),
 			WebSocketError::WebSocketUrlError(ref error) => Some(error),
+			WebSocketError::JsonError(ref error) => Some(&**error),
+			WebSocketError::EncodingError(ref error) => Some(&**error),
 			_ => None,
 		}
 	}
@@ -145,12 +172,27 @@ impl<T> From<TlsHandshakeError<T>> for WebSocketError {
 	}
 }
 
+impl From<FrameError> for WebSocketError {
+	fn from(err: FrameError) -> WebSocketError {
+		match err {
+			FrameError::FragmentedControlFrame => WebSocketError::ProtocolError(err.description()),
+			_ => WebSocketError::DataFrameError(err.description()),
+		}
+	}
+}
+
 impl From<Utf8Error> for WebSocketError {
 	fn from(err: Utf8Error) -> WebSocketError {
 		WebSocketError::Utf8Error(err)
 	}
 }
 
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for WebSocketError {
+	fn from(err: serde_json::Error) -> WebSocketError {
+		WebSocketError::JsonError(Box::new(err))
+	}
+}
 
 impl From<WSUrlErrorKind> for WebSocketError {
 	fn from(err: WSUrlErrorKind) -> WebSocketError {
@@ -173,12 +215,16 @@ impl From<HyperIntoWsError> for WebSocketError {
 			Http(err) => HttpError(err),
 			MethodNotGet => ProtocolError("Request method must be GET"),
 			UnsupportedHttpVersion => ProtocolError("Unsupported request HTTP version"),
-			UnsupportedWebsocketVersion => ProtocolError("Unsupported WebSocket version"),
+			UnsupportedWebsocketVersion(_) => ProtocolError("Unsupported WebSocket version"),
 			NoSecWsKeyHeader => ProtocolError("Missing Sec-WebSocket-Key header"),
 			NoWsUpgradeHeader => ProtocolError("Invalid Upgrade WebSocket header"),
 			NoUpgradeHeader => ProtocolError("Missing Upgrade WebSocket header"),
 			NoWsConnectionHeader => ProtocolError("Invalid Connection WebSocket header"),
 			NoConnectionHeader => ProtocolError("Missing Connection WebSocket header"),
+			OriginNotAllowed => RequestError("Origin not allowed by server policy"),
+			HandshakeTimeout => RequestError("Timed out waiting for the handshake"),
+			TooManyConnections => RequestError("Too many connections"),
+			Filtered => RequestError("Connection refused by the server's accept filter"),
 		}
 	}
 }
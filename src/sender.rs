@@ -1,12 +1,21 @@
 //! The default implementation of a WebSocket Sender.
 
-use std::io::Write;
+use std::cmp;
+use std::io::{self, Write};
 use std::io::Result as IoResult;
-use result::WebSocketResult;
+use std::time::{Duration, Instant};
+use result::{WebSocketError, WebSocketResult};
 use ws::dataframe::DataFrame;
 use stream::sync::AsTcpStream;
 use ws;
 use ws::sender::Sender as SenderTrait;
+use middleware::{Direction, Middlewares};
+use progress::{Progress, ProgressCallback};
+use ws::util::mask::{self, KeySource};
+use ws::util::header::DataFrameFlags;
+use dataframe::{self, Opcode};
+#[cfg(feature = "metrics")]
+use metrics;
 pub use stream::sync::Shutdown;
 
 /// A writer that bundles a stream with a serializer to send the messages.
@@ -40,12 +49,44 @@ where
 	{
 		self.sender.send_message(&mut self.stream, message)
 	}
+
+	/// Sends several messages to the remote endpoint at once.
+	pub fn send_messages<'m, M, I>(&mut self, messages: I) -> WebSocketResult<()>
+	where
+		M: ws::Message + 'm,
+		I: IntoIterator<Item = &'m M>,
+	{
+		self.sender.send_messages(&mut self.stream, messages)
+	}
+
+	/// Sends a frame exactly as given, see `Sender::send_raw_dataframe`.
+	pub fn send_raw_dataframe(
+		&mut self,
+		flags: DataFrameFlags,
+		opcode: Opcode,
+		payload: &[u8],
+	) -> WebSocketResult<()> {
+		self.sender.send_raw_dataframe(&mut self.stream, flags, opcode, payload)
+	}
 }
 
 impl<S> Writer<S>
 where
 	S: AsTcpStream + Write,
 {
+	/// Sends a single message, failing with `WebSocketError::Timeout` if
+	/// writing it takes longer than `timeout`. See
+	/// `Client::send_message_timeout`.
+	pub fn send_message_timeout<M>(&mut self, message: &M, timeout: Duration) -> Result<usize, PartialSend>
+	where
+		M: ws::Message,
+	{
+		let sender = &mut self.sender;
+		send_before(&mut self.stream, Instant::now() + timeout, |writer| {
+			sender.send_message(writer, message)
+		})
+	}
+
 	/// Closes the sender side of the connection, will cause all pending and future IO to
 	/// return immediately with an appropriate value.
 	pub fn shutdown(&self) -> IoResult<()> {
@@ -59,16 +100,204 @@ where
 	}
 }
 
+/// A send that failed or didn't make its deadline, see
+/// `Client::send_message_timeout`.
+#[derive(Debug)]
+pub struct PartialSend {
+	/// The bytes of the message written to the stream before it failed. If
+	/// this is not 0, the peer got part of a frame and the connection can't
+	/// be used anymore.
+	pub written: usize,
+	/// What went wrong, `WebSocketError::Timeout` if the deadline passed.
+	pub error: WebSocketError,
+}
+
+impl From<PartialSend> for WebSocketError {
+	fn from(partial: PartialSend) -> WebSocketError {
+		partial.error
+	}
+}
+
+/// Writes to a TCP stream until a deadline, counting what made it out.
+#[doc(hidden)]
+pub struct DeadlineWriter<'a, W: 'a> {
+	stream: &'a mut W,
+	deadline: Instant,
+	written: usize,
+}
+
+impl<'a, W> Write for DeadlineWriter<'a, W>
+where
+	W: AsTcpStream + Write,
+{
+	fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+		self.set_timeout()?;
+		let written = self.stream.write(buf)?;
+		self.written += written;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		self.set_timeout()?;
+		self.stream.flush()
+	}
+}
+
+impl<'a, W> DeadlineWriter<'a, W>
+where
+	W: AsTcpStream + Write,
+{
+	/// Let the next write block for whatever is left until the deadline.
+	fn set_timeout(&mut self) -> IoResult<()> {
+		let now = Instant::now();
+		if now >= self.deadline {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "send deadline passed"));
+		}
+		// a zero timeout is refused by the OS
+		let left = cmp::max(self.deadline - now, Duration::from_millis(1));
+		self.stream.as_tcp().set_write_timeout(Some(left))
+	}
+}
+
+/// Run `send` with the writes to `stream` timing out at `deadline`, telling
+/// how many bytes were written. The write timeout of the stream is put back
+/// afterwards.
+#[doc(hidden)]
+pub fn send_before<W, F>(stream: &mut W, deadline: Instant, send: F) -> Result<usize, PartialSend>
+where
+	W: AsTcpStream + Write,
+	F: FnOnce(&mut DeadlineWriter<W>) -> WebSocketResult<()>,
+{
+	let previous = match stream.as_tcp().write_timeout() {
+		Ok(previous) => previous,
+		Err(e) => {
+			return Err(PartialSend {
+				written: 0,
+				error: e.into(),
+			})
+		}
+	};
+	let (result, written) = {
+		let mut writer = DeadlineWriter {
+			stream: stream,
+			deadline: deadline,
+			written: 0,
+		};
+		let result = send(&mut writer);
+		(result, writer.written)
+	};
+	let restored = stream.as_tcp().set_write_timeout(previous);
+	let error = match (result, restored) {
+		(Ok(()), Ok(())) => return Ok(written),
+		(Err(WebSocketError::IoError(ref e)), _)
+			if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {
+			WebSocketError::Timeout
+		}
+		(Err(e), _) => e,
+		(Ok(()), Err(e)) => e.into(),
+	};
+	Err(PartialSend {
+		written: written,
+		error: error,
+	})
+}
+
 /// A Sender that wraps a Writer and provides a default implementation using
 /// DataFrames and Messages.
 pub struct Sender {
 	mask: bool,
+	middleware: Middlewares,
+	keys: Option<KeySource>,
+	fragment_size: Option<usize>,
+	progress: Option<ProgressCallback>,
 }
 
 impl Sender {
 	/// Create a new WebSocketSender using the specified Writer.
 	pub fn new(mask: bool) -> Sender {
-		Sender { mask: mask }
+		Sender {
+			mask: mask,
+			middleware: Middlewares::default(),
+			keys: None,
+			fragment_size: None,
+			progress: None,
+		}
+	}
+
+	/// Run every frame sent through this middleware.
+	pub fn set_middleware(&mut self, middleware: Middlewares) {
+		self.middleware = middleware;
+	}
+
+	/// Draw the masking keys of outgoing frames from `keys` instead of the
+	/// thread's random number generator, e.g. to make them reproducible in
+	/// tests.
+	pub fn set_key_source(&mut self, keys: Option<KeySource>) {
+		self.keys = keys;
+	}
+
+	/// Send text and binary messages larger than `size` bytes in fragments
+	/// of that size, e.g. for peers that limit the size of frames. `None`
+	/// sends every message in one frame.
+	///
+	/// # Panics
+	///
+	/// If `size` is `Some(0)`.
+	pub fn set_fragment_size(&mut self, size: Option<usize>) {
+		assert!(size != Some(0), "fragments must not be empty");
+		self.fragment_size = size;
+	}
+
+	/// Report the progress of the text and binary messages sent, see
+	/// `ProgressCallback`.
+	pub fn set_progress(&mut self, progress: Option<ProgressCallback>) {
+		self.progress = progress;
+	}
+
+	/// Mask outgoing frames or not, overriding what the sender was created
+	/// with.
+	///
+	/// Sending unmasked frames as a client or masked frames as a server
+	/// breaks the protocol and conforming peers reject them, this is only
+	/// meant for trusted peers that accept it, test harnesses and measuring
+	/// what masking costs.
+	pub fn dangerous_set_masking(&mut self, mask: bool) {
+		self.mask = mask;
+	}
+
+	fn next_mask(&self) -> Option<[u8; 4]> {
+		mask::next_mask(self.mask, self.keys.as_ref())
+	}
+
+	/// Sends a frame with exactly these flags, opcode and payload, masked as
+	/// this sender masks its frames.
+	///
+	/// The frame skips the middleware and is not checked against the
+	/// protocol, it's up to the caller to e.g. not interleave it with the
+	/// frames of a message.
+	///
+	/// ```rust
+	/// use websocket::dataframe::Opcode;
+	/// use websocket::sender::Sender;
+	/// use websocket::ws::util::header::{FIN, RSV1};
+	///
+	/// let mut sender = Sender::new(false);
+	/// let mut bytes = Vec::new();
+	/// sender.send_raw_dataframe(&mut bytes, FIN | RSV1, Opcode::Binary, b"\x01\x02").unwrap();
+	/// assert_eq!(bytes, vec![0xc2, 0x02, 0x01, 0x02]);
+	/// ```
+	pub fn send_raw_dataframe<W>(
+		&mut self,
+		writer: &mut W,
+		flags: DataFrameFlags,
+		opcode: Opcode,
+		payload: &[u8],
+	) -> WebSocketResult<()>
+	where
+		W: Write,
+	{
+		let key = self.next_mask();
+		dataframe::write_raw_dataframe(writer, flags, opcode, payload, key)
 	}
 }
 
@@ -76,4 +305,90 @@ impl ws::Sender for Sender {
 	fn is_masked(&self) -> bool {
 		self.mask
 	}
+
+	/// Sends a single data frame, running it through the middleware.
+	fn send_dataframe<D, W>(&mut self, writer: &mut W, dataframe: &D) -> WebSocketResult<()>
+	where
+		D: DataFrame,
+		W: Write,
+	{
+		let key = self.next_mask();
+		if self.middleware.is_empty() {
+			return dataframe.write_with_key(writer, key);
+		}
+		self.middleware.outbound_dataframe(dataframe)?.write_with_key(writer, key)
+	}
+
+	/// Sends a single message, running it through the middleware and
+	/// reporting it to the metrics sink.
+	fn send_message<M, W>(&mut self, writer: &mut W, message: &M) -> WebSocketResult<()>
+	where
+		M: ws::Message,
+		W: Write,
+	{
+		let fragment_size = self.fragment_size.filter(|&size| message.message_size(false) > size);
+		if fragment_size.is_none() && self.progress.is_none() && self.middleware.is_empty() {
+			message.serialize_with_key(writer, self.next_mask())?;
+		} else {
+			let frame = dataframe::DataFrame::from_message(message)?;
+			let total = frame.data.len();
+			let is_data = (frame.opcode as u8) < 8;
+			let frames = match fragment_size {
+				Some(size) => frame.fragment(size),
+				None => vec![frame],
+			};
+			let mut sent = 0;
+			for mut frame in frames {
+				sent += frame.data.len();
+				let done = frame.finished;
+				self.middleware.frame(Direction::Outbound, &mut frame)?;
+				frame.write_with_key(writer, self.next_mask())?;
+				match self.progress {
+					Some(ref progress) if is_data => {
+						progress.report(Progress {
+							direction: Direction::Outbound,
+							bytes: sent,
+							total: Some(total),
+							done: done,
+						})
+					}
+					_ => {}
+				}
+			}
+		}
+		#[cfg(feature = "metrics")]
+		metrics::sent(message, self.mask);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::{TcpListener, TcpStream};
+	use message::Message;
+
+	#[test]
+	fn sends_give_up_at_the_deadline() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		// the peer never reads
+		let (_peer, _) = listener.accept().unwrap();
+		let mut writer = Writer {
+			stream: stream,
+			sender: Sender::new(false),
+		};
+
+		let written = writer.send_message_timeout(&Message::text("hello"), Duration::from_secs(5));
+		assert_eq!(written.unwrap(), 7);
+
+		let huge = Message::binary(vec![0; 64 * 1024 * 1024]);
+		let partial = writer.send_message_timeout(&huge, Duration::from_millis(100)).unwrap_err();
+		match partial.error {
+			WebSocketError::Timeout => {}
+			ref e => panic!("expected a timeout, got {:?}", e),
+		}
+		assert!(partial.written < 64 * 1024 * 1024);
+		assert_eq!(writer.stream.write_timeout().unwrap(), None);
+	}
 }
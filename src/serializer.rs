@@ -0,0 +1,165 @@
+//! Formats to send and receive values in.
+//!
+//! A `MessageSerializer` turns values into messages and back. `Json` is
+//! always available with the `serde` feature, `MsgPack` and `Cbor` need the
+//! `msgpack` and `cbor` features and send binary messages. Other formats can
+//! be used by implementing the trait.
+//!
+//! ```rust,no_run
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::ClientBuilder;
+//! use websocket::serializer::Json;
+//!
+//! let mut client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .connect_insecure()
+//!     .unwrap();
+//!
+//! // any other `MessageSerializer`, like `MsgPack`, works the same way
+//! client.send_as::<Json, _>(&("price", 100)).unwrap();
+//! let (name, price): (String, u64) = client.recv_as::<Json, _>().unwrap();
+//! # }
+//! ```
+use std::error::Error;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use json;
+use message::OwnedMessage;
+use result::{WebSocketError, WebSocketResult};
+
+/// A format to send values in.
+pub trait MessageSerializer {
+	/// Serialize `value` into a message.
+	fn serialize<T>(value: &T) -> WebSocketResult<OwnedMessage>
+	where
+		T: Serialize + ?Sized;
+
+	/// Deserialize the payload of a text or binary message, gives `None` for
+	/// control messages.
+	fn deserialize<T>(message: &OwnedMessage) -> Option<WebSocketResult<T>>
+	where
+		T: DeserializeOwned;
+}
+
+/// JSON, sent as text messages.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl MessageSerializer for Json {
+	fn serialize<T>(value: &T) -> WebSocketResult<OwnedMessage>
+	where
+		T: Serialize + ?Sized,
+	{
+		json::encode(value)
+	}
+
+	fn deserialize<T>(message: &OwnedMessage) -> Option<WebSocketResult<T>>
+	where
+		T: DeserializeOwned,
+	{
+		json::decode(message)
+	}
+}
+
+/// MessagePack, sent as binary messages. Structs are written as maps, so
+/// their field names go over the wire.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl MessageSerializer for MsgPack {
+	fn serialize<T>(value: &T) -> WebSocketResult<OwnedMessage>
+	where
+		T: Serialize + ?Sized,
+	{
+		let data = ::rmp_serde::to_vec_named(value).map_err(encoding_error)?;
+		Ok(OwnedMessage::Binary(data))
+	}
+
+	fn deserialize<T>(message: &OwnedMessage) -> Option<WebSocketResult<T>>
+	where
+		T: DeserializeOwned,
+	{
+		payload(message).map(|data| ::rmp_serde::from_slice(data).map_err(encoding_error))
+	}
+}
+
+/// CBOR, sent as binary messages.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl MessageSerializer for Cbor {
+	fn serialize<T>(value: &T) -> WebSocketResult<OwnedMessage>
+	where
+		T: Serialize + ?Sized,
+	{
+		let mut data = Vec::new();
+		::ciborium::ser::into_writer(value, &mut data).map_err(encoding_error)?;
+		Ok(OwnedMessage::Binary(data))
+	}
+
+	fn deserialize<T>(message: &OwnedMessage) -> Option<WebSocketResult<T>>
+	where
+		T: DeserializeOwned,
+	{
+		payload(message).map(|data| ::ciborium::de::from_reader(data).map_err(encoding_error))
+	}
+}
+
+/// The payload of a text or binary message, for formats that don't care
+/// which of the two they came in.
+pub fn payload(message: &OwnedMessage) -> Option<&[u8]> {
+	match *message {
+		OwnedMessage::Text(ref text) => Some(text.as_bytes()),
+		OwnedMessage::Binary(ref data) => Some(data),
+		_ => None,
+	}
+}
+
+/// Wrap the error of a format into a `WebSocketError::EncodingError`.
+pub fn encoding_error<E>(error: E) -> WebSocketError
+where
+	E: Into<Box<Error + Send + Sync>>,
+{
+	WebSocketError::EncodingError(error.into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip<F: MessageSerializer>() {
+		let value = (String::from("price"), 100u64, vec![1u8, 2]);
+		let message = F::serialize(&value).unwrap();
+		let back: (String, u64, Vec<u8>) = F::deserialize(&message).unwrap().unwrap();
+		assert_eq!(back, value);
+		assert!(F::deserialize::<u64>(&OwnedMessage::Pong(Vec::new())).is_none());
+	}
+
+	#[test]
+	fn json_round_trip() {
+		round_trip::<Json>();
+	}
+
+	#[cfg(feature = "msgpack")]
+	#[test]
+	fn msgpack_round_trip() {
+		round_trip::<MsgPack>();
+		match MsgPack::deserialize::<u64>(&OwnedMessage::Binary(vec![0xc1])) {
+			Some(Err(WebSocketError::EncodingError(_))) => {}
+			_ => panic!("expected an encoding error"),
+		}
+	}
+
+	#[cfg(feature = "cbor")]
+	#[test]
+	fn cbor_round_trip() {
+		round_trip::<Cbor>();
+	}
+}
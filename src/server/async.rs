@@ -2,28 +2,55 @@
 use std::io;
 use std::net::ToSocketAddrs;
 use std::net::SocketAddr;
-use server::{WsServer, NoTlsAcceptor};
+use std::time::{Duration, Instant};
+use server::{WsServer, NoTlsAcceptor, ProxyHeader, Admission, Peer};
+use server::proxy;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::timer::Deadline;
+use tokio_io::io::write_all;
 use futures::{Stream, Future};
-use server::upgrade::async::{IntoWs, Upgrade};
+use futures::future;
+use server::upgrade::HyperIntoWsError;
+use server::upgrade::async::{Upgrade, into_ws_with_limits};
 use server::InvalidConnection;
+use server::report_failure;
+use stream::async::Stream as AsyncStream;
 use bytes::BytesMut;
 pub use tokio::reactor::Handle;
 
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tokio_uds::{UnixListener, UnixStream};
+#[cfg(unix)]
+use server::upgrade::PeerCredentials;
+
 #[cfg(any(feature = "async-ssl"))]
 use native_tls::TlsAcceptor;
 #[cfg(any(feature = "async-ssl"))]
 use tokio_tls::{TlsAcceptorExt, TlsStream};
+#[cfg(any(feature = "async-ssl"))]
+use server::ReloadableTlsAcceptor;
 
 /// The asynchronous specialization of a websocket server.
 /// Use this struct to create asynchronous servers.
 pub type Server<S: Send> = WsServer<S, TcpListener>;
 
+/// An asynchronous websocket server listening on a Unix socket, see
+/// `UnixServer::bind_unix`.
+#[cfg(unix)]
+pub type UnixServer = WsServer<NoTlsAcceptor, UnixListener>;
+
 /// A stream of websocket connections and addresses the server generates.
 ///
-/// Each item of the stream is the address of the incoming connection and an `Upgrade`
-/// struct which lets the user decide whether to turn the connection into a websocket
-/// connection or reject it.
+/// Each item of the stream is an `Upgrade` struct which lets the user decide whether
+/// to turn the connection into a websocket connection or reject it, and the address
+/// of the client. Behind a proxy speaking the PROXY protocol this is the address the
+/// proxy passed on.
+///
+/// An error is the failure of a single connection, the stream carries on with the
+/// next one after it. Combinators like `for_each` stop at the first error though,
+/// `incoming_filtered` gives a stream without them.
 pub type Incoming<S: Send> = Box<
 	Stream<
 		Item = (Upgrade<S>, SocketAddr),
@@ -32,6 +59,21 @@ pub type Incoming<S: Send> = Box<
 		+ Send,
 >;
 
+/// A stream of websocket connections coming in on a Unix socket, see
+/// `UnixServer::bind_unix`.
+///
+/// Each item is an `Upgrade` carrying the credentials of the peer process in
+/// its `credentials`. Errors are the failures of single connections, like
+/// for `Incoming`.
+#[cfg(unix)]
+pub type UnixIncoming = Box<
+	Stream<
+		Item = Upgrade<UnixStream>,
+		Error = InvalidConnection<UnixStream, BytesMut>,
+	>
+		+ Send,
+>;
+
 /// Asynchronous methods for creating an async server and accepting incoming connections.
 impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// Bind a websocket server to an address.
@@ -40,22 +82,30 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 	pub fn bind<A: ToSocketAddrs>(addr: A, handle: &Handle) -> io::Result<Self> {
 		let tcp = ::std::net::TcpListener::bind(addr)?;
 		let address = tcp.local_addr()?;
-		Ok(Server {
-			listener: TcpListener::from_std(tcp, handle)?,
-			ssl_acceptor: NoTlsAcceptor,
-		})
+		Ok(WsServer::from_parts(TcpListener::from_std(tcp, handle)?, NoTlsAcceptor))
+	}
+
+	/// Create a server listening on a socket that was set up elsewhere,
+	/// e.g. one inherited through systemd socket activation or configured
+	/// with options this crate does not offer.
+	///
+	/// A standard library listener can be turned into one with
+	/// `TcpListener::from_std`.
+	pub fn from_listener(listener: TcpListener) -> Self {
+		WsServer::from_parts(listener, NoTlsAcceptor)
 	}
 
 	/// Turns the server into a stream of connection objects.
 	///
-	/// Each item of the stream is the address of the incoming connection and an `Upgrade`
-	/// struct which lets the user decide whether to turn the connection into a websocket
-	/// connection or reject it.
+	/// Each item of the stream is an `Upgrade` struct which lets the user decide whether
+	/// to turn the connection into a websocket connection or reject it, and the address
+	/// of the client. Connections that fail are errors of the stream, see `Incoming`.
 	///
 	/// See the [`examples/async-server.rs`]
 	/// (https://github.com/cyderize/rust-websocket/blob/master/examples/async-server.rs)
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TcpStream> {
+		let admission = self.admission();
 		let future = self.listener
 		                 .incoming()
 		                 .map_err(|e| {
@@ -66,21 +116,24 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 				error: e.into(),
 			}
 		})
-		                 .and_then(|stream| {
-			let a = stream.local_addr().unwrap();
-			stream.into_ws()
-			      .map_err(|(stream, req, buf, err)| {
-				InvalidConnection {
-					stream: Some(stream),
-					parsed: req,
-					buffer: Some(buf),
-					error: err,
-				}
+		                 .and_then(move |stream| {
+			let upgrade_admission = admission.clone();
+			prepare(stream, admission.clone()).and_then(move |(stream, peer)| {
+				upgrade_stream(stream, peer, upgrade_admission)
 			})
-			      .map(move |u| (u, a))
+		});
+		let future = future.then(|result| {
+			report_failure(&result);
+			result
 		});
 		Box::new(future)
 	}
+
+	/// Turns the server into a stream of the connections whose handshake
+	/// succeeded, failed connections are logged and skipped.
+	pub fn incoming_filtered(self) -> Incoming<TcpStream> {
+		skip_failures(self.incoming())
+	}
 }
 
 /// Asynchronous methods for creating an async SSL server and accepting incoming connections.
@@ -99,26 +152,146 @@ impl WsServer<TlsAcceptor, TcpListener> {
 	) -> io::Result<Self> {
 		let tcp = ::std::net::TcpListener::bind(addr)?;
 		let address = tcp.local_addr()?;
-		Ok(Server {
-			listener: TcpListener::from_std(tcp, handle)?,
-			ssl_acceptor: acceptor,
-		})
+		Ok(WsServer::from_parts(TcpListener::from_std(tcp, handle)?, acceptor))
+	}
+
+	/// Create an SSL server listening on a socket that was set up elsewhere,
+	/// see `Server::from_listener`.
+	pub fn from_listener_secure(listener: TcpListener, acceptor: TlsAcceptor) -> Self {
+		WsServer::from_parts(listener, acceptor)
 	}
 
 	/// Turns the server into a stream of connection objects.
 	///
-	/// Each item of the stream is the address of the incoming connection and an `Upgrade`
-	/// struct which lets the user decide whether to turn the connection into a websocket
-	/// connection or reject it.
+	/// Each item of the stream is an `Upgrade` struct which lets the user decide whether
+	/// to turn the connection into a websocket connection or reject it, and the address
+	/// of the client. Connections that fail are errors of the stream, see `Incoming`.
 	///
 	/// See the [`examples/async-server.rs`]
 	/// (https://github.com/cyderize/rust-websocket/blob/master/examples/async-server.rs)
 	/// example for a good echo server example.
 	pub fn incoming(self) -> Incoming<TlsStream<TcpStream>> {
+		let admission = self.admission();
+		let acceptor = self.ssl_acceptor;
+		incoming_secure(self.listener, move || acceptor.clone(), admission)
+	}
+
+	/// Turns the server into a stream of the connections whose handshake
+	/// succeeded, failed connections are logged and skipped.
+	pub fn incoming_filtered(self) -> Incoming<TlsStream<TcpStream>> {
+		skip_failures(self.incoming())
+	}
+}
+
+/// Asynchronous methods for an SSL server whose certificate can be replaced
+/// without restarting the listener.
+#[cfg(any(feature = "async-ssl"))]
+impl WsServer<ReloadableTlsAcceptor, TcpListener> {
+	/// Bind an SSL websocket server to an address using a `ReloadableTlsAcceptor`.
+	///
+	/// Keep a clone of the acceptor around and call `reload` on it whenever
+	/// the certificate is renewed, new connections will pick up the change.
+	pub fn bind_secure_reloadable<A: ToSocketAddrs>(
+		addr: A,
+		acceptor: ReloadableTlsAcceptor,
+		handle: &Handle,
+	) -> io::Result<Self> {
+		let tcp = ::std::net::TcpListener::bind(addr)?;
+		Ok(WsServer::from_parts(TcpListener::from_std(tcp, handle)?, acceptor))
+	}
+
+	/// Turns the server into a stream of connection objects.
+	///
+	/// Each TLS handshake uses whatever acceptor was current at the time the
+	/// TCP connection came in.
+	pub fn incoming(self) -> Incoming<TlsStream<TcpStream>> {
+		let admission = self.admission();
 		let acceptor = self.ssl_acceptor;
+		incoming_secure(self.listener, move || acceptor.current(), admission)
+	}
+
+	/// Turns the server into a stream of the connections whose handshake
+	/// succeeded, failed connections are logged and skipped.
+	pub fn incoming_filtered(self) -> Incoming<TlsStream<TcpStream>> {
+		skip_failures(self.incoming())
+	}
+}
+
+/// Asynchronous methods for a server listening on a Unix socket, for local
+/// daemons exposing a websocket control plane.
+#[cfg(unix)]
+impl WsServer<NoTlsAcceptor, UnixListener> {
+	/// Bind a websocket server to a Unix socket created at `path`, which
+	/// must not exist yet.
+	///
+	/// Clients on a Unix socket have no address, the accept filter and the
+	/// connection limiter see all of them as `127.0.0.1`. The TCP options
+	/// and the PROXY protocol don't apply.
+	pub fn bind_unix<P: AsRef<Path>>(path: P, handle: &Handle) -> io::Result<Self> {
+		let listener = ::std::os::unix::net::UnixListener::bind(path)?;
+		Ok(WsServer::from_parts(UnixListener::from_std(listener, handle)?, NoTlsAcceptor))
+	}
+
+	/// Turns the server into a stream of connection objects, see
+	/// `UnixIncoming`. The upgrades tell who connected in `credentials`,
+	/// e.g. to only let the user running the daemon in.
+	pub fn incoming(self) -> UnixIncoming {
+		let admission = self.admission();
 		let future = self.listener
-		                 .incoming()
-		                 .map_err(|e| {
+			.incoming()
+			.map_err(|e| {
+				InvalidConnection {
+					stream: None,
+					parsed: None,
+					buffer: None,
+					error: e.into(),
+				}
+			})
+			.and_then(move |stream| {
+				let credentials = stream.peer_cred().ok().map(|cred| {
+					PeerCredentials {
+						uid: cred.uid,
+						gid: cred.gid,
+					}
+				});
+				debug!("accepted connection on a unix socket from {:?}", credentials);
+				let local = SocketAddr::from(([127, 0, 0, 1], 0));
+				let peer = admission.peer(local, None).map_err(|e| {
+					InvalidConnection {
+						stream: None,
+						parsed: None,
+						buffer: None,
+						error: e,
+					}
+				});
+				let upgrade_admission = admission.clone();
+				future::result(peer)
+					.and_then(move |peer| upgrade_stream(stream, peer, upgrade_admission))
+					.map(move |(mut upgrade, _)| {
+						upgrade.credentials = credentials;
+						upgrade
+					})
+			});
+		let future = future.then(|result| {
+			report_failure(&result);
+			result
+		});
+		Box::new(future)
+	}
+}
+
+#[cfg(any(feature = "async-ssl"))]
+fn incoming_secure<F>(
+	listener: TcpListener,
+	acceptor: F,
+	admission: Admission,
+) -> Incoming<TlsStream<TcpStream>>
+where
+	F: Fn() -> TlsAcceptor + Send + 'static,
+{
+	let future = listener
+		.incoming()
+		.map_err(|e| {
 			InvalidConnection {
 				stream: None,
 				parsed: None,
@@ -126,32 +299,197 @@ impl WsServer<TlsAcceptor, TcpListener> {
 				error: e.into(),
 			}
 		})
-		                 .and_then(move |stream| {
-			let a = stream.local_addr().unwrap();
-			acceptor.accept_async(stream)
-			        .map_err(|e| {
-				InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					// TODO: better error types
-					error: io::Error::new(io::ErrorKind::Other, e).into(),
+		.and_then(move |stream| {
+			let acceptor = acceptor();
+			let tls = prepare(stream, admission.clone()).and_then(move |(stream, peer)| {
+				acceptor
+					.accept_async(stream)
+					.map_err(|e| {
+						InvalidConnection {
+							stream: None,
+							parsed: None,
+							buffer: None,
+							// TODO: better error types
+							error: io::Error::new(io::ErrorKind::Other, e).into(),
+						}
+					})
+					.map(move |s| (s, peer))
+			});
+
+			// the TLS handshake counts towards the handshake timeout as well
+			let upgrade_admission = admission.clone();
+			with_deadline(tls, admission.handshake_limits.timeout)
+				.and_then(move |(stream, peer)| upgrade_stream(stream, peer, upgrade_admission))
+		});
+	let future = future.then(|result| {
+		report_failure(&result);
+		result
+	});
+	Box::new(future)
+}
+
+/// Log and drop the connections of `incoming` that failed.
+fn skip_failures<S>(incoming: Incoming<S>) -> Incoming<S>
+where
+	S: ::stream::Stream + Send + 'static,
+{
+	let upgrades = incoming
+		.then(|result| -> Result<_, InvalidConnection<S, BytesMut>> {
+			Ok(match result {
+				Ok(upgrade) => Some(upgrade),
+				Err(e) => {
+					warn!("dropping connection whose handshake failed: {}", e.error);
+					None
 				}
 			})
-			        .map(move |s| (s, a))
 		})
-		                 .and_then(|(stream, a)| {
-			stream.into_ws()
-			      .map_err(|(stream, req, buf, err)| {
-				InvalidConnection {
+		.filter_map(|upgrade| upgrade);
+	Box::new(upgrades)
+}
+
+/// Get a freshly accepted connection ready for its handshake, this reads the
+/// PROXY protocol header if there is one and counts the connection against
+/// the connection limits.
+fn prepare<S>(
+	stream: TcpStream,
+	admission: Admission,
+) -> Box<Future<Item = (TcpStream, Peer), Error = InvalidConnection<S, BytesMut>> + Send>
+where
+	S: ::stream::Stream + Send + 'static,
+{
+	let addr = match stream.peer_addr().and_then(|addr| {
+		admission.tcp_options.apply_async(&stream).map(|()| addr)
+	}) {
+		Ok(addr) => addr,
+		Err(e) => {
+			return Box::new(future::err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: e.into(),
+			}))
+		}
+	};
+
+	debug!("accepted connection from {}", addr);
+	let header: Box<Future<Item = (TcpStream, Option<ProxyHeader>), Error = io::Error> + Send> =
+		if admission.proxy_protocol {
+			Box::new(proxy::read_header_async(stream).map(|(s, h)| (s, Some(h))))
+		} else {
+			Box::new(future::ok((stream, None)))
+		};
+
+	let timeout = admission.handshake_limits.timeout;
+	let future = header
+		.map_err(HyperIntoWsError::from)
+		.and_then(move |(stream, proxy)| {
+			admission.peer(addr, proxy).map(|peer| (stream, peer))
+		})
+		.map_err(|e| {
+			InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: e,
+			}
+		});
+	with_deadline(future, timeout)
+}
+
+/// Read the handshake off of a freshly accepted stream and apply the server's
+/// policies to it, rejecting it on the spot if need be.
+fn upgrade_stream<S>(
+	stream: S,
+	peer: Peer,
+	admission: Admission,
+) -> Box<Future<Item = (Upgrade<S>, SocketAddr), Error = InvalidConnection<S, BytesMut>> + Send>
+where
+	S: AsyncStream + Send + 'static,
+{
+	let limits = admission.handshake_limits;
+	let handshake = into_ws_with_limits(stream, &limits).or_else(|(stream, req, buf, err)| {
+		let response = match admission.error_response(req.as_ref(), &err) {
+			Some(response) => response,
+			None => {
+				return future::Either::A(future::err(InvalidConnection {
 					stream: Some(stream),
 					parsed: req,
 					buffer: Some(buf),
 					error: err,
-				}
+				}))
+			}
+		};
+
+		// the client is on its way out, it's fine if it doesn't get the message
+		let rejection = write_all(stream, response).then(move |res| {
+			Err(InvalidConnection {
+				stream: res.ok().map(|(stream, _)| stream),
+				parsed: req,
+				buffer: Some(buf),
+				error: err,
 			})
-			      .map(move |u| (u, a))
 		});
-		Box::new(future)
-	}
+		future::Either::B(rejection)
+	});
+
+	let future = with_deadline(handshake, limits.timeout).and_then(move |mut upgrade| {
+		let peer_addr = peer.addr;
+		peer.attach(&mut upgrade);
+
+		let (status, error) = match admission.refusal(&mut upgrade, peer_addr) {
+			Some(refusal) => refusal,
+			None => return future::Either::A(future::ok((upgrade, peer_addr))),
+		};
+		let status = match status {
+			Some(status) => status,
+			None => {
+				return future::Either::A(future::err(InvalidConnection {
+					stream: None,
+					parsed: None,
+					buffer: None,
+					error: error,
+				}))
+			}
+		};
+
+		let rejection = upgrade.reject_with_status(status, None).then(move |res| {
+			Err(InvalidConnection {
+				stream: res.ok().map(|framed| framed.into_parts().inner),
+				parsed: None,
+				buffer: None,
+				error: error,
+			})
+		});
+		future::Either::B(rejection)
+	});
+	Box::new(future)
+}
+
+/// Drop the connection if `future` does not complete within `timeout`.
+fn with_deadline<F, S>(
+	future: F,
+	timeout: Option<Duration>,
+) -> Box<Future<Item = F::Item, Error = InvalidConnection<S, BytesMut>> + Send>
+where
+	F: Future<Error = InvalidConnection<S, BytesMut>> + Send + 'static,
+	S: ::stream::Stream + Send + 'static,
+{
+	let timeout = match timeout {
+		Some(timeout) => timeout,
+		None => return Box::new(future),
+	};
+	let deadline = Deadline::new(future, Instant::now() + timeout);
+	Box::new(deadline.map_err(|e| match e.into_inner() {
+		Some(e) => e,
+		None => {
+			// the stream is owned by the dropped future, so the
+			// client is simply disconnected
+			InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: HyperIntoWsError::HandshakeTimeout,
+			}
+		}
+	}))
 }
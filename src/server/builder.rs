@@ -0,0 +1,450 @@
+//! Configuring a server in one go.
+use std::cmp;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::time::Duration;
+#[cfg(feature = "sync")]
+use std::sync::Arc;
+#[cfg(feature = "sync")]
+use std::thread::{self, JoinHandle};
+
+#[cfg(unix)]
+use net2::TcpBuilder;
+#[cfg(unix)]
+use net2::unix::UnixTcpBuilderExt;
+
+use server::{Admission, AcceptFilter, ConnectionLimiter, HandshakeLimits, HttpFallback};
+use server::{NoTlsAcceptor, RateLimit};
+use server::{OptionalTlsAcceptor, OriginPolicy, Watchdog, WriteQueueLimit, WsServer};
+use stream::TcpOptions;
+use middleware::Middleware;
+
+#[cfg(feature = "async")]
+use server::async;
+#[cfg(feature = "async")]
+use tokio::reactor::Handle;
+#[cfg(feature = "async")]
+use tokio::net::TcpListener as AsyncTcpListener;
+#[cfg(feature = "sync")]
+use server::sync;
+
+/// Build a synchronous or asynchronous websocket server, much like
+/// `ClientBuilder` builds clients.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use websocket::server::{OriginPolicy, ServerBuilder};
+///
+/// let server = ServerBuilder::new()
+///     .bind("127.0.0.1:2794")
+///     .max_connections(1000)
+///     .handshake_timeout(Duration::from_secs(5))
+///     .protocols(vec!["chat"])
+///     .origin_policy(OriginPolicy::allow(vec!["https://example.com"]))
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Use `tls` to make a secure server and `build_async` for an asynchronous one.
+///
+/// To accept connections on several threads, ask for several `workers` and
+/// use `run_workers`, or `build_all` to get a server for each of them.
+pub struct ServerBuilder<S = NoTlsAcceptor>
+where
+	S: OptionalTlsAcceptor + Send,
+{
+	listener: Listener,
+	tls: S,
+	admission: Admission,
+	workers: usize,
+}
+
+/// What a `ServerBuilder` listens on.
+enum Listener {
+	Bind(io::Result<Vec<SocketAddr>>),
+	Existing(TcpListener),
+}
+
+impl Listener {
+	fn listen(self) -> io::Result<TcpListener> {
+		match self {
+			Listener::Bind(addrs) => TcpListener::bind(&addrs?[..]),
+			Listener::Existing(listener) => Ok(listener),
+		}
+	}
+
+	/// Get a listener for each of `workers` workers.
+	///
+	/// Where `SO_REUSEPORT` is available every worker gets a socket of its
+	/// own and the kernel spreads connections among them, otherwise they
+	/// share a single socket.
+	fn listen_all(self, workers: usize) -> io::Result<Vec<TcpListener>> {
+		let mut listeners = Vec::with_capacity(workers);
+		match self {
+			Listener::Bind(addrs) if workers > 1 && cfg!(unix) => {
+				let mut last_error = None;
+				for addr in addrs? {
+					match bind_reuse_port(&addr) {
+						Ok(listener) => {
+							listeners.push(listener);
+							break;
+						}
+						Err(e) => last_error = Some(e),
+					}
+				}
+				// the others go on the same port, even if the system picked it
+				let addr = match listeners.first() {
+					Some(first) => first.local_addr()?,
+					None => {
+						return Err(last_error.unwrap_or_else(|| {
+							io::Error::new(io::ErrorKind::InvalidInput, "no address to bind to")
+						}))
+					}
+				};
+				for _ in 1..workers {
+					listeners.push(bind_reuse_port(&addr)?);
+				}
+			}
+			listener => {
+				let first = listener.listen()?;
+				for _ in 1..workers {
+					listeners.push(first.try_clone()?);
+				}
+				listeners.insert(0, first);
+			}
+		}
+		Ok(listeners)
+	}
+}
+
+#[cfg(unix)]
+fn bind_reuse_port(addr: &SocketAddr) -> io::Result<TcpListener> {
+	let socket = if addr.is_ipv4() {
+		TcpBuilder::new_v4()?
+	} else {
+		TcpBuilder::new_v6()?
+	};
+	socket.reuse_address(true)?;
+	socket.reuse_port(true)?;
+	socket.bind(addr)?;
+	// the same backlog the standard library uses
+	socket.listen(128)
+}
+
+#[cfg(not(unix))]
+fn bind_reuse_port(_addr: &SocketAddr) -> io::Result<TcpListener> {
+	unreachable!("SO_REUSEPORT is only used on unix")
+}
+
+impl ServerBuilder<NoTlsAcceptor> {
+	/// Start building an insecure server with the default settings.
+	pub fn new() -> Self {
+		ServerBuilder {
+			listener: Listener::Bind(Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"no address to bind the server to",
+			))),
+			tls: NoTlsAcceptor,
+			admission: Admission::default(),
+			workers: 1,
+		}
+	}
+}
+
+impl Default for ServerBuilder<NoTlsAcceptor> {
+	fn default() -> Self {
+		ServerBuilder::new()
+	}
+}
+
+impl<S> ServerBuilder<S>
+where
+	S: OptionalTlsAcceptor + Send,
+{
+	/// The address to listen on, if it resolves to several addresses the
+	/// first one that can be bound to is used.
+	pub fn bind<A: ToSocketAddrs>(mut self, addr: A) -> Self {
+		self.listener = Listener::Bind(addr.to_socket_addrs().map(Iterator::collect));
+		self
+	}
+
+	/// Listen on a socket that was set up elsewhere instead of binding one,
+	/// e.g. one inherited through systemd socket activation.
+	pub fn listener(mut self, listener: TcpListener) -> Self {
+		self.listener = Listener::Existing(listener);
+		self
+	}
+
+	/// Secure connections with TLS, given either a `TlsAcceptor` or a
+	/// `ReloadableTlsAcceptor`.
+	pub fn tls<T>(self, acceptor: T) -> ServerBuilder<T>
+	where
+		T: OptionalTlsAcceptor + Send,
+	{
+		ServerBuilder {
+			listener: self.listener,
+			tls: acceptor,
+			admission: self.admission,
+			workers: self.workers,
+		}
+	}
+
+	/// Cap the number of connections open at once, an accepted client takes
+	/// up its slot until it is dropped.
+	pub fn max_connections(self, max: usize) -> Self {
+		self.connection_limiter(ConnectionLimiter::new(Some(max), None))
+	}
+
+	/// Cap the number of connections open at once in total and per client
+	/// address, see `ConnectionLimiter`.
+	pub fn connection_limiter(mut self, limiter: ConnectionLimiter) -> Self {
+		self.admission.connection_limiter = Some(limiter);
+		self
+	}
+
+	/// How long a client has to send its entire handshake.
+	pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+		self.admission.handshake_limits.timeout = Some(timeout);
+		self
+	}
+
+	/// How large and how slow a client's handshake may be.
+	pub fn handshake_limits(mut self, limits: HandshakeLimits) -> Self {
+		self.admission.handshake_limits = limits;
+		self
+	}
+
+	/// The subprotocols the server speaks, see `WsServer::protocols`.
+	pub fn protocols<I, P>(mut self, protocols: I) -> Self
+	where
+		I: IntoIterator<Item = P>,
+		P: Into<String>,
+	{
+		self.admission.protocols = protocols.into_iter().map(Into::into).collect();
+		self
+	}
+
+	/// Only allow connections from origins accepted by this policy.
+	pub fn origin_policy(mut self, policy: OriginPolicy) -> Self {
+		self.admission.origin_policy = policy;
+		self
+	}
+
+	/// Expect every connection to start with a PROXY protocol header.
+	pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+		self.admission.proxy_protocol = enabled;
+		self
+	}
+
+	/// Decide whether to go through with each connection before the
+	/// application gets to see it.
+	pub fn accept_filter(mut self, filter: AcceptFilter) -> Self {
+		self.admission.accept_filter = Some(filter);
+		self
+	}
+
+	/// Ping idle connections and close dead ones.
+	pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+		self.admission.watchdog = Some(watchdog);
+		self
+	}
+
+	/// Answer plain HTTP requests, e.g. health checks, instead of dropping them.
+	pub fn http_fallback(mut self, fallback: HttpFallback) -> Self {
+		self.admission.http_fallback = Some(fallback);
+		self
+	}
+
+	/// Run this middleware on every connection, after the ones added so far.
+	pub fn middleware<M>(mut self, middleware: M) -> Self
+	where
+		M: Middleware + 'static,
+	{
+		self.admission.middleware.push(middleware);
+		self
+	}
+
+	/// Limit the bytes waiting to be written to each asynchronous client
+	/// accepted with `accept_queued`.
+	pub fn write_queue(mut self, limit: WriteQueueLimit) -> Self {
+		self.admission.write_queue = Some(limit);
+		self
+	}
+
+	/// Limit how fast clients may send messages, see `RateLimit`.
+	pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+		self.admission.rate_limit = Some(limit);
+		self
+	}
+
+	/// Set these options on the socket of every connection.
+	pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+		self.admission.tcp_options = options;
+		self
+	}
+
+	/// Accept connections with this many workers, each with a listening
+	/// socket of its own bound with `SO_REUSEPORT` where that is supported.
+	/// See `build_all` and `run_workers`.
+	pub fn workers(mut self, workers: usize) -> Self {
+		self.workers = cmp::max(workers, 1);
+		self
+	}
+
+	/// Bind a synchronous server.
+	#[cfg(feature = "sync")]
+	pub fn build(self) -> io::Result<sync::Server<S>> {
+		let listener = self.listener.listen()?;
+		Ok(WsServer::from_parts(listener, self.tls).with_admission(self.admission))
+	}
+
+	/// Bind an asynchronous server.
+	#[cfg(feature = "async")]
+	pub fn build_async(self, handle: &Handle) -> io::Result<async::Server<S>> {
+		let listener = AsyncTcpListener::from_std(self.listener.listen()?, handle)?;
+		Ok(WsServer::from_parts(listener, self.tls).with_admission(self.admission))
+	}
+
+	/// Bind a synchronous server for each worker.
+	#[cfg(feature = "sync")]
+	pub fn build_all(self) -> io::Result<Vec<sync::Server<S>>>
+	where
+		S: Clone,
+	{
+		let mut servers = Vec::with_capacity(self.workers);
+		for listener in self.listener.listen_all(self.workers)? {
+			let server = WsServer::from_parts(listener, self.tls.clone());
+			servers.push(server.with_admission(self.admission.clone()));
+		}
+		Ok(servers)
+	}
+
+	/// Bind an asynchronous server for each worker, e.g. to run each of
+	/// them on a reactor of its own.
+	#[cfg(feature = "async")]
+	pub fn build_all_async(self, handle: &Handle) -> io::Result<Vec<async::Server<S>>>
+	where
+		S: Clone,
+	{
+		let mut servers = Vec::with_capacity(self.workers);
+		for listener in self.listener.listen_all(self.workers)? {
+			let listener = AsyncTcpListener::from_std(listener, handle)?;
+			let server = WsServer::from_parts(listener, self.tls.clone());
+			servers.push(server.with_admission(self.admission.clone()));
+		}
+		Ok(servers)
+	}
+
+	/// Bind a synchronous server for each worker and run `worker` with each
+	/// of them on a thread of its own.
+	///
+	/// ```rust,no_run
+	/// use websocket::server::ServerBuilder;
+	///
+	/// let workers = ServerBuilder::new()
+	///     .bind("0.0.0.0:2794")
+	///     .workers(4)
+	///     .run_workers(|server| {
+	///         for upgrade in server.filter_map(Result::ok) {
+	///             // handle the connection ...
+	///             let _ = upgrade.accept();
+	///         }
+	///     })
+	///     .unwrap();
+	///
+	/// for worker in workers {
+	///     worker.join().unwrap();
+	/// }
+	/// ```
+	#[cfg(feature = "sync")]
+	pub fn run_workers<F>(self, worker: F) -> io::Result<Vec<JoinHandle<()>>>
+	where
+		S: Clone + 'static,
+		F: Fn(sync::Server<S>) + Send + Sync + 'static,
+	{
+		let worker = Arc::new(worker);
+		let mut handles = Vec::new();
+		for (i, server) in self.build_all()?.into_iter().enumerate() {
+			let worker = worker.clone();
+			let name = format!("websocket-worker-{}", i);
+			handles.push(thread::Builder::new().name(name).spawn(move || (*worker)(server))?);
+		}
+		Ok(handles)
+	}
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+	use super::*;
+	use std::io::Write;
+	use std::net::TcpStream;
+	use std::sync::Mutex;
+	use server::upgrade::HyperIntoWsError;
+	use server::InvalidConnection;
+
+	const HANDSHAKE: &'static [u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+	                                   Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+	                                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+	#[test]
+	fn max_connections_counts_open_connections() {
+		let mut server = ServerBuilder::new().bind("127.0.0.1:0").max_connections(1).build().unwrap();
+		let addr = server.local_addr().unwrap();
+
+		let mut first = TcpStream::connect(addr).unwrap();
+		first.write_all(HANDSHAKE).unwrap();
+		let client = server.accept().ok().unwrap().accept().ok().unwrap();
+
+		let _second = TcpStream::connect(addr).unwrap();
+		match server.accept() {
+			Err(InvalidConnection {
+				error: HyperIntoWsError::TooManyConnections,
+				..
+			}) => {}
+			_ => panic!("expected the second connection to be refused"),
+		}
+
+		drop(client);
+		let mut third = TcpStream::connect(addr).unwrap();
+		third.write_all(HANDSHAKE).unwrap();
+		assert!(server.accept().is_ok());
+	}
+
+	#[test]
+	fn workers_listen_on_the_same_port() {
+		let servers = ServerBuilder::new().bind("127.0.0.1:0").workers(3).build_all().unwrap();
+		assert_eq!(servers.len(), 3);
+		let addr = servers[0].local_addr().unwrap();
+		assert!(servers.iter().all(|s| s.local_addr().unwrap() == addr));
+
+		let existing = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = existing.local_addr().unwrap();
+		let servers = ServerBuilder::new().listener(existing).workers(2).build_all().unwrap();
+		assert_eq!(servers.len(), 2);
+		assert!(servers.iter().all(|s| s.local_addr().unwrap() == addr));
+
+		let servers = ServerBuilder::new().bind("127.0.0.1:0").workers(0).build_all().unwrap();
+		assert_eq!(servers.len(), 1);
+	}
+
+	#[test]
+	fn run_workers_runs_each_worker_on_its_own_thread() {
+		let names = Arc::new(Mutex::new(Vec::new()));
+		let seen = names.clone();
+		let handles = ServerBuilder::new()
+			.bind("127.0.0.1:0")
+			.workers(2)
+			.run_workers(move |_server| {
+				let name = thread::current().name().map(String::from);
+				seen.lock().unwrap().push(name.unwrap());
+			})
+			.unwrap();
+		for handle in handles {
+			handle.join().unwrap();
+		}
+
+		let mut names = names.lock().unwrap().clone();
+		names.sort();
+		assert_eq!(names, vec!["websocket-worker-0", "websocket-worker-1"]);
+	}
+}
@@ -0,0 +1,452 @@
+//! Shutting an asynchronous server down without cutting connections off.
+//!
+//! For a deploy behind a load balancer the old server should stop taking
+//! connections and give the open ones a chance to move over on their own. A
+//! `Drain` keeps track of the connections registered with it, and once
+//! `drain` is called:
+//!
+//! * the stream of incoming connections wrapped with `until_drained` ends,
+//!   which drops the listener,
+//! * every connection is sent a close message with `CloseCode::GoingAway`
+//!   and ends once the peer answered with its own close,
+//! * connections still open at the deadline end right away, they should be
+//!   dropped.
+//!
+//! ```rust,no_run
+//! # extern crate tokio;
+//! # extern crate websocket;
+//! # fn main() {
+//! use std::time::{Duration, Instant};
+//! use websocket::async::Server;
+//! use websocket::futures::{Future, Sink, Stream};
+//! use websocket::server::Drain;
+//!
+//! let server = Server::bind("127.0.0.1:1234", &tokio::reactor::Handle::default()).unwrap();
+//! let drain = Drain::new();
+//!
+//! let connections = drain.clone();
+//! let accept = drain
+//!     .until_drained(server.incoming_filtered())
+//!     .map_err(|e| e.error)
+//!     .for_each(move |(upgrade, _)| {
+//!         let connections = connections.clone();
+//!         let echo = upgrade
+//!             .accept()
+//!             .and_then(move |(client, _)| {
+//!                 let (sink, stream) = connections.register(client).split();
+//!                 stream.filter(|m| m.is_data()).forward(sink)
+//!             })
+//!             .map(|_| ())
+//!             .map_err(|_| ());
+//!         tokio::spawn(echo);
+//!         Ok(())
+//!     });
+//!
+//! // e.g. when a signal comes in
+//! let shutdown = drain
+//!     .drain(Instant::now() + Duration::from_secs(30))
+//!     .map(|stragglers| println!("{} connections were cut off", stragglers));
+//! # tokio::run(accept.map_err(|_| ()).join(shutdown.map_err(|_| ())).map(|_| ()));
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::task::{self, Task};
+use tokio::timer::Delay;
+
+use close::{CloseCode, CloseFrame};
+use message::OwnedMessage;
+use result::WebSocketError;
+
+/// Keeps track of connections to close them when the server shuts down, see
+/// the module docs.
+///
+/// Cloning is cheap, all clones share the same connections.
+#[derive(Clone, Default)]
+pub struct Drain {
+	state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+	draining: bool,
+	/// whether the deadline passed
+	forced: bool,
+	next_id: usize,
+	/// the connections and their tasks, by id
+	connections: HashMap<usize, Option<Task>>,
+	/// the tasks of the streams of incoming connections
+	listeners: HashMap<usize, Task>,
+	/// the task waiting for the connections to close
+	waiter: Option<Task>,
+}
+
+impl State {
+	fn next_id(&mut self) -> usize {
+		self.next_id += 1;
+		self.next_id
+	}
+
+	fn notify_all(&mut self) {
+		for task in self.connections.values().filter_map(Option::as_ref) {
+			task.notify();
+		}
+		for (_, task) in self.listeners.drain() {
+			task.notify();
+		}
+	}
+}
+
+impl Drain {
+	/// A drain without connections.
+	pub fn new() -> Self {
+		Drain::default()
+	}
+
+	/// Start closing the connection `client` once the server drains.
+	pub fn register<C>(&self, client: C) -> Draining<C> {
+		let mut state = lock(&self.state);
+		let id = state.next_id();
+		state.connections.insert(id, None);
+		Draining {
+			inner: client,
+			drain: self.clone(),
+			id: id,
+			close: Close::Idle,
+		}
+	}
+
+	/// End `incoming` once the server drains, so that it stops taking new
+	/// connections.
+	pub fn until_drained<S>(&self, incoming: S) -> UntilDrained<S> {
+		let id = lock(&self.state).next_id();
+		UntilDrained {
+			inner: Some(incoming),
+			drain: self.clone(),
+			id: id,
+		}
+	}
+
+	/// Whether `drain` was called.
+	pub fn is_draining(&self) -> bool {
+		lock(&self.state).draining
+	}
+
+	/// The number of connections still open.
+	pub fn active(&self) -> usize {
+		lock(&self.state).connections.len()
+	}
+
+	/// Close all connections, waiting for them until `deadline`.
+	///
+	/// The future gives the number of connections that were still open at
+	/// the deadline and were told to end at once.
+	pub fn drain(&self, deadline: Instant) -> DrainFuture {
+		let mut state = lock(&self.state);
+		state.draining = true;
+		state.notify_all();
+		DrainFuture {
+			drain: self.clone(),
+			deadline: Delay::new(deadline),
+		}
+	}
+
+	fn deregister(&self, id: usize) {
+		let mut state = lock(&self.state);
+		state.connections.remove(&id);
+		if state.connections.is_empty() {
+			if let Some(waiter) = state.waiter.take() {
+				waiter.notify();
+			}
+		}
+	}
+}
+
+impl fmt::Debug for Drain {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let state = lock(&self.state);
+		f.debug_struct("Drain")
+		 .field("draining", &state.draining)
+		 .field("connections", &state.connections.len())
+		 .finish()
+	}
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<State> {
+	// the state stays consistent even if a holder of the lock panicked
+	match state.lock() {
+		Ok(guard) => guard,
+		Err(poisoned) => poisoned.into_inner(),
+	}
+}
+
+/// A future that resolves once all connections of a `Drain` are closed or
+/// its deadline passed, see `Drain::drain`.
+pub struct DrainFuture {
+	drain: Drain,
+	deadline: Delay,
+}
+
+impl Future for DrainFuture {
+	type Item = usize;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<usize, WebSocketError> {
+		{
+			let mut state = lock(&self.drain.state);
+			if state.connections.is_empty() {
+				return Ok(Async::Ready(0));
+			}
+			state.waiter = Some(task::current());
+		}
+		let elapsed = self.deadline
+		                  .poll()
+		                  .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+		if elapsed.is_not_ready() {
+			return Ok(Async::NotReady);
+		}
+		let mut state = lock(&self.drain.state);
+		state.forced = true;
+		state.notify_all();
+		Ok(Async::Ready(state.connections.len()))
+	}
+}
+
+/// A stream of incoming connections that ends once the server drains, see
+/// `Drain::until_drained`.
+pub struct UntilDrained<S> {
+	inner: Option<S>,
+	drain: Drain,
+	id: usize,
+}
+
+impl<S> Stream for UntilDrained<S>
+where
+	S: Stream,
+{
+	type Item = S::Item;
+	type Error = S::Error;
+
+	fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+		{
+			let mut state = lock(&self.drain.state);
+			if state.draining {
+				// dropping the stream drops the listener
+				self.inner = None;
+			} else {
+				state.listeners.insert(self.id, task::current());
+			}
+		}
+		match self.inner {
+			Some(ref mut inner) => inner.poll(),
+			None => Ok(Async::Ready(None)),
+		}
+	}
+}
+
+impl<S> Drop for UntilDrained<S> {
+	fn drop(&mut self) {
+		lock(&self.drain.state).listeners.remove(&self.id);
+	}
+}
+
+enum Close {
+	Idle,
+	/// the server drains, the close was not sent yet
+	Pending,
+	/// waiting for the peer to answer the close
+	Sent,
+	Done,
+}
+
+/// A connection that is closed when the server drains, see
+/// `Drain::register`.
+///
+/// Once the server drains, the stream ends after the close message that
+/// answered ours, or at the deadline. Messages can still be sent while the
+/// peer finishes up.
+pub struct Draining<C> {
+	inner: C,
+	drain: Drain,
+	id: usize,
+	close: Close,
+}
+
+impl<C> Draining<C> {
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	fn finish(&mut self) {
+		self.close = Close::Done;
+		self.drain.deregister(self.id);
+	}
+}
+
+impl<C> Draining<C>
+where
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	fn send_close(&mut self) -> Result<(), WebSocketError> {
+		let close = CloseFrame::new(CloseCode::GoingAway, "").into();
+		if let AsyncSink::Ready = self.inner.start_send(close)? {
+			self.close = Close::Sent;
+			self.inner.poll_complete()?;
+		}
+		Ok(())
+	}
+}
+
+impl<C> Stream for Draining<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		if let Close::Done = self.close {
+			return Ok(Async::Ready(None));
+		}
+		{
+			let mut state = lock(&self.drain.state);
+			if state.forced {
+				drop(state);
+				self.finish();
+				return Ok(Async::Ready(None));
+			}
+			if let Close::Idle = self.close {
+				if state.draining {
+					self.close = Close::Pending;
+				}
+			}
+			state.connections.insert(self.id, Some(task::current()));
+		}
+		if let Close::Pending = self.close {
+			self.send_close()?;
+		}
+		match self.inner.poll()? {
+			Async::Ready(Some(message)) => {
+				if message.is_close() {
+					if let Close::Sent = self.close {
+						self.finish();
+					}
+				}
+				Ok(Async::Ready(Some(message)))
+			}
+			Async::Ready(None) => {
+				self.finish();
+				Ok(Async::Ready(None))
+			}
+			Async::NotReady => Ok(Async::NotReady),
+		}
+	}
+}
+
+impl<C> Sink for Draining<C>
+where
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+impl<C> Drop for Draining<C> {
+	fn drop(&mut self) {
+		self.drain.deregister(self.id);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::future;
+	use futures::stream;
+	use std::collections::VecDeque;
+	use std::time::Duration;
+
+	/// A client whose peer answers a close with a close.
+	#[derive(Default)]
+	struct Peer {
+		sent: Vec<OwnedMessage>,
+		incoming: VecDeque<OwnedMessage>,
+	}
+
+	impl Stream for Peer {
+		type Item = OwnedMessage;
+		type Error = WebSocketError;
+
+		fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+			match self.incoming.pop_front() {
+				Some(message) => Ok(Async::Ready(Some(message))),
+				None => Ok(Async::NotReady),
+			}
+		}
+	}
+
+	impl Sink for Peer {
+		type SinkItem = OwnedMessage;
+		type SinkError = WebSocketError;
+
+		fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+			if item.is_close() {
+				self.incoming.push_back(OwnedMessage::Close(None));
+			}
+			self.sent.push(item);
+			Ok(AsyncSink::Ready)
+		}
+
+		fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+			Ok(Async::Ready(()))
+		}
+	}
+
+	#[test]
+	fn draining_closes_connections() {
+		future::lazy(|| {
+			let drain = Drain::new();
+			let pending = stream::poll_fn(|| -> Poll<Option<()>, ()> { Ok(Async::NotReady) });
+			let mut incoming = drain.until_drained(pending);
+			let mut client = drain.register(Peer::default());
+			assert!(incoming.poll().unwrap().is_not_ready());
+			assert!(client.poll().unwrap().is_not_ready());
+			assert_eq!(drain.active(), 1);
+
+			let mut done = drain.drain(Instant::now() + Duration::from_secs(60));
+			assert!(incoming.poll().unwrap().is_ready());
+			assert_eq!(client.poll().unwrap(), Async::Ready(Some(OwnedMessage::Close(None))));
+			assert_eq!(client.poll().unwrap(), Async::Ready(None));
+			assert_eq!(
+				client.get_ref().sent,
+				vec![CloseFrame::new(CloseCode::GoingAway, "").into()]
+			);
+			assert_eq!(done.poll().unwrap(), Async::Ready(0));
+			Ok::<(), ()>(())
+		}).wait()
+		  .unwrap();
+	}
+}
@@ -0,0 +1,144 @@
+//! Answering plain HTTP requests made to a websocket server.
+//!
+//! Load balancers checking whether a server is up and people pointing their
+//! browser at it send regular HTTP requests, which a websocket server would
+//! otherwise just hang up on.
+use std::fmt;
+use std::sync::Arc;
+
+use http::{Method, StatusCode};
+use http::header::{HeaderMap, HeaderValue, CONTENT_TYPE, SEC_WEBSOCKET_VERSION, UPGRADE};
+
+use codec::http::RequestHead;
+use header::wire;
+
+/// A response to a plain HTTP request, the connection is closed once it
+/// was sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+	/// The status of the response.
+	pub status: StatusCode,
+	/// Headers to send along, `Content-Length` and `Connection` are added.
+	pub headers: HeaderMap,
+	/// The body of the response.
+	pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+	/// An empty response with the given status.
+	pub fn new(status: StatusCode) -> Self {
+		HttpResponse {
+			status: status,
+			headers: HeaderMap::new(),
+			body: Vec::new(),
+		}
+	}
+
+	/// A plain text response.
+	pub fn text(status: StatusCode, body: &str) -> Self {
+		let mut response = HttpResponse::new(status);
+		response.headers.insert(
+			CONTENT_TYPE,
+			HeaderValue::from_static("text/plain; charset=utf-8"),
+		);
+		response.body = body.as_bytes().to_vec();
+		response
+	}
+
+	/// `426 Upgrade Required`, telling the client to speak websocket.
+	pub fn upgrade_required() -> Self {
+		let mut response = HttpResponse::text(StatusCode::UPGRADE_REQUIRED, "Upgrade Required");
+		response.headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+		response.headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+		response
+	}
+
+	/// The response as it goes over the wire.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = format!("HTTP/1.1 {}\r\n", self.status).into_bytes();
+		wire::write_headers(&self.headers, &mut bytes);
+		bytes.extend_from_slice(
+			format!("Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len()).as_bytes(),
+		);
+		bytes.extend_from_slice(&self.body);
+		bytes
+	}
+}
+
+/// Decides how to answer requests which are not websocket upgrades.
+///
+/// ```rust
+/// # extern crate http;
+/// # extern crate websocket;
+/// # fn main() {
+/// use http::StatusCode;
+/// use websocket::server::{HttpFallback, HttpResponse, ServerBuilder};
+///
+/// let fallback = HttpFallback::new(|request| {
+///     if request.subject.1.path() == "/healthz" {
+///         HttpResponse::text(StatusCode::OK, "OK")
+///     } else {
+///         HttpResponse::new(StatusCode::NOT_FOUND)
+///     }
+/// });
+/// let builder = ServerBuilder::new().http_fallback(fallback);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HttpFallback(Arc<Fn(&RequestHead) -> HttpResponse + Send + Sync>);
+
+impl HttpFallback {
+	/// Answer requests using a closure.
+	pub fn new<F>(respond: F) -> Self
+	where
+		F: Fn(&RequestHead) -> HttpResponse + Send + Sync + 'static,
+	{
+		HttpFallback(Arc::new(respond))
+	}
+
+	/// Answer `GET` requests for `path` with `200 OK`, e.g. for a load
+	/// balancer's health checks, and anything else with
+	/// `426 Upgrade Required`.
+	pub fn health_check(path: &str) -> Self {
+		let path = path.to_string();
+		HttpFallback::new(move |request| if request.subject.0 == Method::GET &&
+		                                    request.subject.1.path() == path {
+			HttpResponse::text(StatusCode::OK, "OK")
+		} else {
+			HttpResponse::upgrade_required()
+		})
+	}
+
+	/// How to answer `request`.
+	pub fn respond(&self, request: &RequestHead) -> HttpResponse {
+		(self.0)(request)
+	}
+}
+
+impl Default for HttpFallback {
+	/// Answers every request with `426 Upgrade Required`.
+	fn default() -> Self {
+		HttpFallback::new(|_| HttpResponse::upgrade_required())
+	}
+}
+
+impl fmt::Debug for HttpFallback {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("HttpFallback")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn serializes_responses() {
+		let response = HttpResponse::upgrade_required().to_bytes();
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+		assert!(response.contains("\r\nUpgrade: websocket\r\n"));
+		assert!(response.contains("\r\nSec-WebSocket-Version: 13\r\n"));
+		assert!(response.ends_with("Content-Length: 16\r\nConnection: close\r\n\r\nUpgrade Required"));
+	}
+}
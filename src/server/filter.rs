@@ -0,0 +1,66 @@
+//! Deciding whether to go through with a connection before answering its handshake.
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use http::StatusCode;
+
+use codec::http::RequestHead;
+
+/// What a server should do with a connection whose handshake was just read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+	/// Hand the connection to the application.
+	Accept,
+	/// Answer with this status and close the connection.
+	Reject(StatusCode),
+	/// Close the connection without answering.
+	Drop,
+}
+
+/// A callback deciding whether to go through with a connection, given the
+/// address of the client and its handshake request.
+///
+/// It runs inside the accept loop, so it should be cheap to evaluate.
+///
+/// ```rust
+/// # extern crate http;
+/// # extern crate websocket;
+/// # fn main() {
+/// use http::StatusCode;
+/// use websocket::server::{AcceptFilter, Decision};
+///
+/// let filter = AcceptFilter::new(|addr, request| {
+///     if addr.ip().is_loopback() {
+///         Decision::Accept
+///     } else if request.subject.1.path() == "/public" {
+///         Decision::Accept
+///     } else {
+///         Decision::Reject(StatusCode::NOT_FOUND)
+///     }
+/// });
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AcceptFilter(Arc<Fn(SocketAddr, &RequestHead) -> Decision + Send + Sync>);
+
+impl AcceptFilter {
+	/// Make a filter out of a closure.
+	pub fn new<F>(f: F) -> Self
+	where
+		F: Fn(SocketAddr, &RequestHead) -> Decision + Send + Sync + 'static,
+	{
+		AcceptFilter(Arc::new(f))
+	}
+
+	/// Decide what to do with a connection.
+	pub fn decide(&self, addr: SocketAddr, request: &RequestHead) -> Decision {
+		(self.0)(addr, request)
+	}
+}
+
+impl fmt::Debug for AcceptFilter {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("AcceptFilter(<closure>)")
+	}
+}
@@ -0,0 +1,263 @@
+//! Recovering the address of a client connecting through reverse proxies.
+//!
+//! Proxies record the addresses a request passed through in the standard
+//! `Forwarded` header (RFC 7239) or in the older `X-Forwarded-For` and
+//! `X-Real-IP` headers. Anyone can send these headers though, so only the
+//! entries added by proxies one trusts should be believed, see `TrustProxy`.
+use std::net::{IpAddr, SocketAddr};
+use std::str;
+
+use http::header::HeaderMap;
+
+/// A single entry of a forwarding chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForwardedAddr {
+	/// An IP address.
+	Ip(IpAddr),
+	/// An IP address along with the port the request came from.
+	Socket(SocketAddr),
+	/// The proxy did not know the address (`unknown`), chose to hide it
+	/// behind an obfuscated identifier (e.g. `_hidden`) or left it out of a
+	/// `Forwarded` element (an empty identifier).
+	Unknown(String),
+}
+
+impl ForwardedAddr {
+	/// The IP address of this entry, if it is known.
+	pub fn ip(&self) -> Option<IpAddr> {
+		match *self {
+			ForwardedAddr::Ip(ip) => Some(ip),
+			ForwardedAddr::Socket(addr) => Some(addr.ip()),
+			ForwardedAddr::Unknown(_) => None,
+		}
+	}
+
+	fn parse(s: &str) -> Self {
+		let s = s.trim().trim_matches('"');
+		if let Ok(addr) = s.parse() {
+			return ForwardedAddr::Socket(addr);
+		}
+		if let Ok(ip) = s.trim_start_matches('[').trim_end_matches(']').parse() {
+			return ForwardedAddr::Ip(ip);
+		}
+		ForwardedAddr::Unknown(s.to_string())
+	}
+}
+
+/// Which proxies are trusted to report the address of the client.
+///
+/// The chain is looked at from the end closest to the server, the address of
+/// the peer that connected to the server being the very last entry.
+///
+/// ```rust
+/// use std::net::IpAddr;
+/// use websocket::server::TrustProxy;
+/// use websocket::server::forwarded::ForwardedAddr;
+///
+/// let peer: IpAddr = "10.0.0.1".parse().unwrap();
+/// let chain = vec![
+///     ForwardedAddr::Ip("192.0.2.1".parse().unwrap()),
+///     ForwardedAddr::Ip("198.51.100.7".parse().unwrap()),
+/// ];
+///
+/// assert_eq!(TrustProxy::Nobody.client_ip(&chain, peer), Some(peer));
+/// assert_eq!(TrustProxy::Hops(1).client_ip(&chain, peer), Some("198.51.100.7".parse().unwrap()));
+/// assert_eq!(TrustProxy::Everyone.client_ip(&chain, peer), Some("192.0.2.1".parse().unwrap()));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrustProxy {
+	/// Ignore the forwarding headers, the peer is the client. This is the default.
+	Nobody,
+	/// Believe every entry, the first address in the chain is the client.
+	Everyone,
+	/// Trust this many proxies in front of the server, e.g. `Hops(1)` when the
+	/// server is only reachable through a single load balancer.
+	Hops(usize),
+	/// Trust the proxies with these addresses.
+	Ips(Vec<IpAddr>),
+}
+
+impl Default for TrustProxy {
+	fn default() -> Self {
+		TrustProxy::Nobody
+	}
+}
+
+impl TrustProxy {
+	/// Find the address of the client given the forwarding chain of a request
+	/// and the address of the peer that connected to the server.
+	///
+	/// Gives `None` when the trusted proxy closest to the client did not know
+	/// the client's address.
+	pub fn client_ip(&self, chain: &[ForwardedAddr], peer: IpAddr) -> Option<IpAddr> {
+		match *self {
+			TrustProxy::Nobody => Some(peer),
+			TrustProxy::Everyone => chain.first().map(|a| a.ip()).unwrap_or(Some(peer)),
+			TrustProxy::Hops(0) => Some(peer),
+			TrustProxy::Hops(hops) => {
+				let index = chain.len().saturating_sub(hops);
+				chain[index..].first().map(|a| a.ip()).unwrap_or(Some(peer))
+			}
+			TrustProxy::Ips(ref trusted) => {
+				if !trusted.contains(&peer) {
+					return Some(peer);
+				}
+				for addr in chain.iter().rev() {
+					match addr.ip() {
+						Some(ip) if trusted.contains(&ip) => continue,
+						ip => return ip,
+					}
+				}
+				chain.first().map(|a| a.ip()).unwrap_or(Some(peer))
+			}
+		}
+	}
+}
+
+/// Read the forwarding chain of a request from its headers, the address
+/// closest to the client comes first.
+///
+/// The `Forwarded` header is preferred, then `X-Forwarded-For` and finally
+/// `X-Real-IP` are looked at. Gives an empty chain if none of them are present.
+pub fn forwarded_chain(headers: &HeaderMap) -> Vec<ForwardedAddr> {
+	let forwarded = header_values(headers, "Forwarded");
+	if !forwarded.is_empty() {
+		return forwarded
+			.iter()
+			.flat_map(|v| split_unquoted(v, ','))
+			.filter(|element| !element.trim().is_empty())
+			// an element without `for` is still a hop, leaving it out would
+			// make `TrustProxy::Hops` count from the wrong end
+			.map(|element| {
+				split_unquoted(element, ';').into_iter().filter_map(|pair| {
+					let mut kv = pair.splitn(2, '=');
+					match (kv.next(), kv.next()) {
+						(Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("for") => {
+							Some(ForwardedAddr::parse(v))
+						}
+						_ => None,
+					}
+				}).next().unwrap_or_else(|| ForwardedAddr::Unknown(String::new()))
+			})
+			.collect();
+	}
+
+	let forwarded_for = header_values(headers, "X-Forwarded-For");
+	if !forwarded_for.is_empty() {
+		return forwarded_for
+			.iter()
+			.flat_map(|v| v.split(','))
+			.filter(|v| !v.trim().is_empty())
+			.map(ForwardedAddr::parse)
+			.collect();
+	}
+
+	header_values(headers, "X-Real-IP").into_iter().take(1).map(ForwardedAddr::parse).collect()
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+	headers
+		.get_all(name)
+		.iter()
+		.filter_map(|v| str::from_utf8(v.as_ref()).ok())
+		.collect()
+}
+
+/// Split on `sep`, except when it appears in a quoted string.
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+	let mut parts = Vec::new();
+	let mut quoted = false;
+	let mut escaped = false;
+	let mut start = 0;
+	for (i, c) in s.char_indices() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if quoted => escaped = true,
+			'"' => quoted = !quoted,
+			c if c == sep && !quoted => {
+				parts.push(&s[start..i]);
+				start = i + c.len_utf8();
+			}
+			_ => {}
+		}
+	}
+	parts.push(&s[start..]);
+	parts
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use http::header::HeaderValue;
+
+	#[test]
+	fn parses_forwarded_header() {
+		let mut headers = HeaderMap::new();
+		headers.append(
+			"Forwarded",
+			HeaderValue::from_static("for=192.0.2.60;proto=http, For=\"[2001:db8:cafe::17]:4711\""),
+		);
+		headers.append("Forwarded", HeaderValue::from_static("for=unknown;by=\"a,b\""));
+		headers.insert("X-Forwarded-For", HeaderValue::from_static("203.0.113.1"));
+
+		assert_eq!(
+			forwarded_chain(&headers),
+			vec![
+				ForwardedAddr::Ip("192.0.2.60".parse().unwrap()),
+				ForwardedAddr::Socket("[2001:db8:cafe::17]:4711".parse().unwrap()),
+				ForwardedAddr::Unknown("unknown".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn elements_without_for_are_hops() {
+		let mut headers = HeaderMap::new();
+		headers.insert(
+			"Forwarded",
+			HeaderValue::from_static("for=203.0.113.1, for=198.51.100.2, by=10.0.0.3;proto=https, "),
+		);
+		let chain = forwarded_chain(&headers);
+		assert_eq!(chain.len(), 3);
+		assert_eq!(chain[2], ForwardedAddr::Unknown(String::new()));
+
+		// the last proxy didn't say who it got the request from
+		let peer = "10.0.0.4".parse().unwrap();
+		assert_eq!(TrustProxy::Hops(1).client_ip(&chain, peer), None);
+		assert_eq!(TrustProxy::Hops(2).client_ip(&chain, peer), Some("198.51.100.2".parse().unwrap()));
+	}
+
+	#[test]
+	fn parses_legacy_headers() {
+		let mut headers = HeaderMap::new();
+		headers.insert("X-Real-IP", HeaderValue::from_static("203.0.113.9"));
+		assert_eq!(
+			forwarded_chain(&headers),
+			vec![ForwardedAddr::Ip("203.0.113.9".parse().unwrap())]
+		);
+
+		headers.insert("X-Forwarded-For", HeaderValue::from_static("203.0.113.1, 10.0.0.2"));
+		assert_eq!(
+			forwarded_chain(&headers),
+			vec![
+				ForwardedAddr::Ip("203.0.113.1".parse().unwrap()),
+				ForwardedAddr::Ip("10.0.0.2".parse().unwrap()),
+			]
+		);
+	}
+
+	#[test]
+	fn trusts_only_known_proxies() {
+		let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+		let chain = vec![
+			ForwardedAddr::Ip(ip("203.0.113.1")),
+			ForwardedAddr::Ip(ip("198.51.100.2")),
+			ForwardedAddr::Ip(ip("10.0.0.2")),
+		];
+		let trust = TrustProxy::Ips(vec![ip("10.0.0.1"), ip("10.0.0.2")]);
+
+		assert_eq!(trust.client_ip(&chain, ip("10.0.0.1")), Some(ip("198.51.100.2")));
+		assert_eq!(trust.client_ip(&chain, ip("192.0.2.5")), Some(ip("192.0.2.5")));
+		assert_eq!(TrustProxy::Hops(5).client_ip(&chain, ip("10.0.0.1")), Some(ip("203.0.113.1")));
+	}
+}
@@ -0,0 +1,134 @@
+//! Capping the number of connections a server handles at once.
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Counts the connections a server has open, in total and per client address,
+/// and refuses new ones above the configured maximums.
+///
+/// Every connection that is let through holds a `ConnectionPermit`, the
+/// connection is counted until its permit is dropped. Clones of a limiter share
+/// their counts, so a limiter can be shared between several servers.
+///
+/// ```rust
+/// use websocket::server::ConnectionLimiter;
+///
+/// let limiter = ConnectionLimiter::new(Some(100), Some(1));
+/// let ip = "192.0.2.1".parse().unwrap();
+///
+/// let permit = limiter.try_acquire(ip).unwrap();
+/// assert!(limiter.try_acquire(ip).is_none());
+///
+/// drop(permit);
+/// assert!(limiter.try_acquire(ip).is_some());
+/// ```
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+	max_total: Option<usize>,
+	max_per_ip: Option<usize>,
+	state: Arc<Mutex<Counts>>,
+}
+
+#[derive(Default)]
+struct Counts {
+	total: usize,
+	per_ip: HashMap<IpAddr, usize>,
+}
+
+impl ConnectionLimiter {
+	/// Create a limiter allowing at most `max_total` connections at once and
+	/// at most `max_per_ip` from a single address, `None` meaning no limit.
+	pub fn new(max_total: Option<usize>, max_per_ip: Option<usize>) -> Self {
+		ConnectionLimiter {
+			max_total: max_total,
+			max_per_ip: max_per_ip,
+			state: Arc::new(Mutex::new(Counts::default())),
+		}
+	}
+
+	/// Count a new connection from `ip`, unless that would exceed one of the
+	/// limits in which case `None` is returned.
+	pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnectionPermit> {
+		let mut counts = lock(&self.state);
+		let from_ip = counts.per_ip.get(&ip).cloned().unwrap_or(0);
+
+		if self.max_total.map(|max| counts.total >= max).unwrap_or(false) ||
+		   self.max_per_ip.map(|max| from_ip >= max).unwrap_or(false) {
+			return None;
+		}
+
+		counts.total += 1;
+		counts.per_ip.insert(ip, from_ip + 1);
+		Some(ConnectionPermit {
+			ip: ip,
+			state: self.state.clone(),
+		})
+	}
+
+	/// The number of connections currently counted.
+	pub fn active(&self) -> usize {
+		lock(&self.state).total
+	}
+
+	/// The number of connections currently counted from `ip`.
+	pub fn active_from(&self, ip: IpAddr) -> usize {
+		lock(&self.state).per_ip.get(&ip).cloned().unwrap_or(0)
+	}
+}
+
+impl fmt::Debug for ConnectionLimiter {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ConnectionLimiter")
+		 .field("max_total", &self.max_total)
+		 .field("max_per_ip", &self.max_per_ip)
+		 .field("active", &self.active())
+		 .finish()
+	}
+}
+
+/// Proof that a connection was let through by a `ConnectionLimiter`.
+///
+/// Keep this around for as long as the connection is open, dropping it
+/// frees up the connection's slot.
+pub struct ConnectionPermit {
+	ip: IpAddr,
+	state: Arc<Mutex<Counts>>,
+}
+
+impl ConnectionPermit {
+	/// The address the connection was counted under.
+	pub fn ip(&self) -> IpAddr {
+		self.ip
+	}
+}
+
+impl fmt::Debug for ConnectionPermit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ConnectionPermit").field("ip", &self.ip).finish()
+	}
+}
+
+impl Drop for ConnectionPermit {
+	fn drop(&mut self) {
+		let mut counts = lock(&self.state);
+		counts.total -= 1;
+		let remove = match counts.per_ip.get_mut(&self.ip) {
+			Some(n) => {
+				*n -= 1;
+				*n == 0
+			}
+			None => false,
+		};
+		if remove {
+			counts.per_ip.remove(&self.ip);
+		}
+	}
+}
+
+fn lock(state: &Mutex<Counts>) -> MutexGuard<Counts> {
+	match state.lock() {
+		Ok(guard) => guard,
+		Err(poisoned) => poisoned.into_inner(),
+	}
+}
@@ -1,12 +1,50 @@
 //! Provides an implementation of a WebSocket server
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+use std::sync::{Arc, RwLock};
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 use native_tls::TlsAcceptor;
 
+use std::net::SocketAddr;
+
+use http::StatusCode;
+use http::header::{HeaderValue, SEC_WEBSOCKET_PROTOCOL};
+
 use codec::http::RequestHead;
-use stream::Stream;
-use self::upgrade::HyperIntoWsError;
+pub use codec::http::HandshakeLimits;
+use stream::{Stream, TcpOptions};
+use middleware::{Middleware, Middlewares};
+use self::upgrade::{HyperIntoWsError, WsUpgrade, bare_response};
 
 pub mod upgrade;
+pub mod origin;
+pub mod proxy;
+pub mod forwarded;
+pub mod limit;
+pub mod filter;
+pub mod watchdog;
+pub mod fallback;
+pub mod queue;
+pub mod ratelimit;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub mod builder;
+#[cfg(feature = "async")]
+pub mod drain;
+#[cfg(feature = "async")]
+pub mod service;
+
+pub use self::origin::OriginPolicy;
+pub use self::forwarded::TrustProxy;
+pub use self::proxy::ProxyHeader;
+pub use self::limit::{ConnectionLimiter, ConnectionPermit};
+pub use self::filter::{AcceptFilter, Decision};
+pub use self::watchdog::Watchdog;
+pub use self::fallback::{HttpFallback, HttpResponse};
+pub use self::queue::{Overflow, WriteQueueLimit};
+pub use self::ratelimit::RateLimit;
+#[cfg(any(feature = "sync", feature = "async"))]
+pub use self::builder::ServerBuilder;
+#[cfg(feature = "async")]
+pub use self::drain::Drain;
 
 #[cfg(feature = "async")]
 pub mod async;
@@ -25,6 +63,56 @@ pub trait OptionalTlsAcceptor {}
 impl OptionalTlsAcceptor for NoTlsAcceptor {}
 #[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
 impl OptionalTlsAcceptor for TlsAcceptor {}
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+impl OptionalTlsAcceptor for ReloadableTlsAcceptor {}
+
+/// A `TlsAcceptor` that can be swapped out while the server is running.
+///
+/// Clones of this struct share the same acceptor, so one can keep a clone
+/// around and call `reload` when a certificate is renewed (e.g. by Let's Encrypt).
+/// Every connection accepted after the reload will use the new acceptor,
+/// connections that were already established are left untouched.
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+#[derive(Clone)]
+pub struct ReloadableTlsAcceptor {
+	inner: Arc<RwLock<TlsAcceptor>>,
+}
+
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+impl ReloadableTlsAcceptor {
+	/// Create a reloadable acceptor starting out with `acceptor`.
+	pub fn new(acceptor: TlsAcceptor) -> Self {
+		ReloadableTlsAcceptor::from_shared(Arc::new(RwLock::new(acceptor)))
+	}
+
+	/// Use an acceptor that is already shared with other parts of the program.
+	/// Writing a new acceptor into the lock has the same effect as calling `reload`.
+	pub fn from_shared(inner: Arc<RwLock<TlsAcceptor>>) -> Self {
+		ReloadableTlsAcceptor { inner: inner }
+	}
+
+	/// Replace the acceptor used for all future connections.
+	pub fn reload(&self, acceptor: TlsAcceptor) {
+		let mut current = match self.inner.write() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		};
+		*current = acceptor;
+	}
+
+	/// Get the acceptor that will be used for the next connection.
+	pub fn current(&self) -> TlsAcceptor {
+		match self.inner.read() {
+			Ok(guard) => guard.clone(),
+			Err(poisoned) => poisoned.into_inner().clone(),
+		}
+	}
+
+	/// Get the shared lock around the acceptor.
+	pub fn shared(&self) -> Arc<RwLock<TlsAcceptor>> {
+		self.inner.clone()
+	}
+}
 
 /// When a sever tries to accept a connection many things can go wrong.
 ///
@@ -50,6 +138,19 @@ where
 	pub error: HyperIntoWsError,
 }
 
+/// Report a connection that did not make it through the handshake.
+fn report_failure<T, S, B>(result: &Result<T, InvalidConnection<S, B>>)
+where
+	S: Stream + Send,
+	B: Send,
+{
+	if let Err(ref e) = *result {
+		debug!("handshake failed: {}", e.error);
+		#[cfg(feature = "metrics")]
+		::metrics::sink().handshake_failed(::std::error::Error::description(&e.error));
+	}
+}
+
 /// Represents a WebSocket server which can work with either normal
 /// (non-secure) connections, or secure WebSocket connections.
 ///
@@ -96,4 +197,330 @@ where
 	listener: L,
 	/// The SSL acceptor given to the server
 	pub ssl_acceptor: S,
+	/// Which origins are allowed to connect, connections from other origins
+	/// are answered with `403 Forbidden`. Defaults to `OriginPolicy::Any`.
+	pub origin_policy: OriginPolicy,
+	/// How large and how slow a client's handshake may be before the client
+	/// is disconnected.
+	pub handshake_limits: HandshakeLimits,
+	/// Whether connections start with a PROXY protocol header (version 1 or 2),
+	/// as sent by load balancers like HAProxy. Defaults to `false`.
+	///
+	/// Only enable this behind a proxy which always sends the header, connections
+	/// without one are dropped.
+	pub proxy_protocol: bool,
+	/// Caps the number of connections open at once, connections over the limit
+	/// are closed right after being accepted. Defaults to no limit.
+	///
+	/// Connections let through carry a `ConnectionPermit` in `WsUpgrade::permit`,
+	/// which the accepted client holds until it is dropped.
+	pub connection_limiter: Option<ConnectionLimiter>,
+	/// Called with every handshake request before it is handed to the
+	/// application, to reject or drop unwanted connections early.
+	pub accept_filter: Option<AcceptFilter>,
+	/// Pings idle connections and closes dead ones, see `Watchdog`.
+	/// Defaults to no watchdog.
+	pub watchdog: Option<Watchdog>,
+	/// Options set on the socket of every connection before its handshake.
+	/// Defaults to the system's defaults.
+	pub tcp_options: TcpOptions,
+	/// The subprotocols the server speaks, the first of a client's requested
+	/// protocols found in here is selected in the handshake response. Defaults
+	/// to none, leaving the choice to the application.
+	pub protocols: Vec<String>,
+	/// Answers plain HTTP requests, e.g. from load balancers checking on the
+	/// server. Defaults to none, dropping such clients without an answer.
+	///
+	/// The connection is still closed and reported as an `InvalidConnection`
+	/// after the response was sent.
+	pub http_fallback: Option<HttpFallback>,
+	/// Sees the handshake and the frames of every connection, see
+	/// `middleware::Middleware`. Defaults to none.
+	pub middleware: Middlewares,
+	/// How many bytes may wait to be written to an asynchronous client
+	/// accepted with `accept_queued`, see `WriteQueue`. Defaults to none, in
+	/// which case `accept_queued` uses the default limit.
+	pub write_queue: Option<WriteQueueLimit>,
+	/// How fast clients may send messages, see `RateLimit`. Applies to
+	/// synchronous clients right away and to asynchronous ones accepted with
+	/// `accept_limited`. Defaults to no limit.
+	pub rate_limit: Option<RateLimit>,
+}
+
+/// The settings deciding how a server treats new connections, kept together
+/// so they can be moved into the accept loop as a whole.
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Clone, Default)]
+struct Admission {
+	origin_policy: OriginPolicy,
+	handshake_limits: HandshakeLimits,
+	proxy_protocol: bool,
+	connection_limiter: Option<ConnectionLimiter>,
+	accept_filter: Option<AcceptFilter>,
+	watchdog: Option<Watchdog>,
+	tcp_options: TcpOptions,
+	protocols: Vec<String>,
+	http_fallback: Option<HttpFallback>,
+	middleware: Middlewares,
+	write_queue: Option<WriteQueueLimit>,
+	rate_limit: Option<RateLimit>,
+}
+
+/// What is known about a connection before its handshake is read, and what
+/// is to be handed over to its upgrade afterwards.
+#[cfg(any(feature = "sync", feature = "async"))]
+struct Peer {
+	addr: SocketAddr,
+	proxy: Option<ProxyHeader>,
+	permit: Option<ConnectionPermit>,
+	watchdog: Option<Watchdog>,
+	protocols: Vec<String>,
+	middleware: Middlewares,
+	write_queue: Option<WriteQueueLimit>,
+	rate_limit: Option<RateLimit>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl Admission {
+	/// Count a new connection from `addr` (or from the client the PROXY protocol
+	/// header names) against the connection limits.
+	fn peer(&self, addr: SocketAddr, proxy: Option<ProxyHeader>) -> Result<Peer, HyperIntoWsError> {
+		let addr = proxy.and_then(|p| p.source()).unwrap_or(addr);
+		let permit = match self.connection_limiter {
+			Some(ref limiter) => {
+				match limiter.try_acquire(addr.ip()) {
+					Some(permit) => Some(permit),
+					None => return Err(HyperIntoWsError::TooManyConnections),
+				}
+			}
+			None => None,
+		};
+		Ok(Peer {
+			addr: addr,
+			proxy: proxy,
+			permit: permit,
+			watchdog: self.watchdog,
+			protocols: self.protocols.clone(),
+			middleware: self.middleware.clone(),
+			write_queue: self.write_queue,
+			rate_limit: self.rate_limit,
+		})
+	}
+
+	/// Check a handshake against the origin policy, the accept filter and the
+	/// middleware.
+	///
+	/// Gives the reason to refuse the connection if it should be refused, along
+	/// with the status to answer with unless it should simply be dropped.
+	fn refusal<S, B>(
+		&self,
+		upgrade: &mut WsUpgrade<S, B>,
+		addr: SocketAddr,
+	) -> Option<(Option<StatusCode>, HyperIntoWsError)>
+	where
+		S: Stream + Send,
+		B: Send,
+	{
+		if !self.origin_policy.allows(upgrade.origin()) {
+			return Some((Some(StatusCode::FORBIDDEN), HyperIntoWsError::OriginNotAllowed));
+		}
+		let decision = match self.accept_filter.as_ref().map(|f| f.decide(addr, &upgrade.request)) {
+			Some(Decision::Accept) | None => {
+				self.middleware.handshake(&upgrade.request, &mut upgrade.headers)
+			}
+			Some(refusal) => refusal,
+		};
+		match decision {
+			Decision::Reject(status) => Some((Some(status), HyperIntoWsError::Filtered)),
+			Decision::Drop => Some((None, HyperIntoWsError::Filtered)),
+			Decision::Accept => None,
+		}
+	}
+
+	/// What to answer a client with whose handshake failed with `error`, if
+	/// anything.
+	fn error_response(
+		&self,
+		request: Option<&RequestHead>,
+		error: &HyperIntoWsError,
+	) -> Option<Vec<u8>> {
+		if let Some(status) = error.response_status() {
+			return Some(bare_response(status));
+		}
+		match (self.http_fallback.as_ref(), request) {
+			(Some(fallback), Some(request)) if error.is_plain_http() => {
+				Some(fallback.respond(request).to_bytes())
+			}
+			_ => None,
+		}
+	}
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl Peer {
+	/// Hand what is known about the peer over to its upgrade.
+	fn attach<S, B>(self, upgrade: &mut WsUpgrade<S, B>)
+	where
+		S: Stream + Send,
+		B: Send,
+	{
+		upgrade.proxy = self.proxy;
+		upgrade.permit = self.permit;
+		upgrade.watchdog = self.watchdog;
+		upgrade.middleware = self.middleware;
+		upgrade.write_queue = self.write_queue;
+		upgrade.rate_limit = self.rate_limit;
+
+		let protocol = upgrade
+			.protocols()
+			.into_iter()
+			.find(|requested| self.protocols.iter().any(|p| p == *requested))
+			.and_then(|protocol| HeaderValue::from_str(protocol).ok());
+		if let Some(protocol) = protocol {
+			upgrade.headers.insert(SEC_WEBSOCKET_PROTOCOL, protocol);
+		}
+	}
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl<S, L> WsServer<S, L>
+where
+	S: OptionalTlsAcceptor + Send,
+	L: Send,
+{
+	fn from_parts(listener: L, ssl_acceptor: S) -> Self {
+		WsServer {
+			listener: listener,
+			ssl_acceptor: ssl_acceptor,
+			origin_policy: OriginPolicy::default(),
+			handshake_limits: HandshakeLimits::default(),
+			proxy_protocol: false,
+			connection_limiter: None,
+			accept_filter: None,
+			watchdog: None,
+			tcp_options: TcpOptions::default(),
+			protocols: Vec::new(),
+			http_fallback: None,
+			middleware: Middlewares::default(),
+			write_queue: None,
+			rate_limit: None,
+		}
+	}
+
+	fn admission(&self) -> Admission {
+		Admission {
+			origin_policy: self.origin_policy.clone(),
+			handshake_limits: self.handshake_limits,
+			proxy_protocol: self.proxy_protocol,
+			connection_limiter: self.connection_limiter.clone(),
+			accept_filter: self.accept_filter.clone(),
+			watchdog: self.watchdog,
+			tcp_options: self.tcp_options,
+			protocols: self.protocols.clone(),
+			http_fallback: self.http_fallback.clone(),
+			middleware: self.middleware.clone(),
+			write_queue: self.write_queue,
+			rate_limit: self.rate_limit,
+		}
+	}
+
+	fn with_admission(mut self, admission: Admission) -> Self {
+		self.origin_policy = admission.origin_policy;
+		self.handshake_limits = admission.handshake_limits;
+		self.proxy_protocol = admission.proxy_protocol;
+		self.connection_limiter = admission.connection_limiter;
+		self.accept_filter = admission.accept_filter;
+		self.watchdog = admission.watchdog;
+		self.tcp_options = admission.tcp_options;
+		self.protocols = admission.protocols;
+		self.http_fallback = admission.http_fallback;
+		self.middleware = admission.middleware;
+		self.write_queue = admission.write_queue;
+		self.rate_limit = admission.rate_limit;
+		self
+	}
+
+	/// Only allow connections from origins accepted by this policy.
+	pub fn with_origin_policy(mut self, policy: OriginPolicy) -> Self {
+		self.origin_policy = policy;
+		self
+	}
+
+	/// Disconnect clients whose handshake is larger or slower than these limits allow.
+	pub fn with_handshake_limits(mut self, limits: HandshakeLimits) -> Self {
+		self.handshake_limits = limits;
+		self
+	}
+
+	/// Expect every connection to start with a PROXY protocol header, the client
+	/// address it carries is available through `WsUpgrade::proxied_addr`.
+	pub fn with_proxy_protocol(mut self, enabled: bool) -> Self {
+		self.proxy_protocol = enabled;
+		self
+	}
+
+	/// Cap the number of connections open at once, see `connection_limiter`.
+	pub fn with_connection_limiter(mut self, limiter: ConnectionLimiter) -> Self {
+		self.connection_limiter = Some(limiter);
+		self
+	}
+
+	/// Decide whether to go through with each connection before the
+	/// application gets to see it.
+	pub fn with_accept_filter(mut self, filter: AcceptFilter) -> Self {
+		self.accept_filter = Some(filter);
+		self
+	}
+
+	/// Ping idle connections and close dead ones, see `Watchdog`.
+	pub fn with_watchdog(mut self, watchdog: Watchdog) -> Self {
+		self.watchdog = Some(watchdog);
+		self
+	}
+
+	/// Set these options on the socket of every connection, e.g. to disable
+	/// Nagle's algorithm.
+	pub fn with_tcp_options(mut self, options: TcpOptions) -> Self {
+		self.tcp_options = options;
+		self
+	}
+
+	/// Select one of these subprotocols for connections requesting it, see
+	/// `protocols`.
+	pub fn with_protocols<I, P>(mut self, protocols: I) -> Self
+	where
+		I: IntoIterator<Item = P>,
+		P: Into<String>,
+	{
+		self.protocols = protocols.into_iter().map(Into::into).collect();
+		self
+	}
+
+	/// Answer plain HTTP requests instead of dropping them, see `http_fallback`.
+	pub fn with_http_fallback(mut self, fallback: HttpFallback) -> Self {
+		self.http_fallback = Some(fallback);
+		self
+	}
+
+	/// Run this middleware after the ones added so far, see `middleware`.
+	pub fn with_middleware<M>(mut self, middleware: M) -> Self
+	where
+		M: Middleware + 'static,
+	{
+		self.middleware.push(middleware);
+		self
+	}
+
+	/// Limit the bytes waiting to be written to each asynchronous client, see
+	/// `write_queue`.
+	pub fn with_write_queue(mut self, limit: WriteQueueLimit) -> Self {
+		self.write_queue = Some(limit);
+		self
+	}
+
+	/// Limit how fast clients may send messages, see `rate_limit`.
+	pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+		self.rate_limit = Some(limit);
+		self
+	}
 }
@@ -0,0 +1,141 @@
+//! Policies deciding which `Origin`s may open a websocket connection.
+//!
+//! Browsers always send an `Origin` header when opening a websocket, and unlike
+//! regular HTTP requests websocket upgrades are not subject to the same-origin
+//! policy. A server talking to browsers should therefore check the origin to
+//! avoid cross-site websocket hijacking.
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides whether an upgrade request's `Origin` header is acceptable.
+///
+/// When a server rejects a connection because of its origin, a
+/// `403 Forbidden` response is sent and the connection fails with
+/// `HyperIntoWsError::OriginNotAllowed`.
+///
+/// ```rust
+/// use websocket::server::OriginPolicy;
+///
+/// let policy = OriginPolicy::allow(vec!["https://example.com", "https://*.example.com"]);
+///
+/// assert!(policy.allows(Some("https://example.com")));
+/// assert!(policy.allows(Some("https://chat.example.com")));
+/// assert!(!policy.allows(Some("https://example.com.evil.org")));
+/// assert!(!policy.allows(None));
+/// ```
+#[derive(Clone)]
+pub enum OriginPolicy {
+	/// Accept any origin, or no origin at all. This is the default.
+	Any,
+	/// Only accept origins matching one of these patterns.
+	///
+	/// A pattern is either an exact origin (`https://example.com`), a single `*`
+	/// matching any origin, or contains one `*` which matches any run of
+	/// characters that does not contain a `/` (`https://*.example.com`).
+	/// Requests without an `Origin` header are rejected.
+	AllowList(Vec<String>),
+	/// Decide using a closure, which is given the value of the `Origin` header
+	/// if there is one.
+	Custom(Arc<Fn(Option<&str>) -> bool + Send + Sync>),
+}
+
+impl OriginPolicy {
+	/// Only accept origins matching one of the patterns, see `AllowList`.
+	pub fn allow<I, S>(origins: I) -> Self
+	where
+		I: IntoIterator<Item = S>,
+		S: Into<String>,
+	{
+		OriginPolicy::AllowList(origins.into_iter().map(Into::into).collect())
+	}
+
+	/// Decide whether to accept an origin using a closure.
+	pub fn custom<F>(f: F) -> Self
+	where
+		F: Fn(Option<&str>) -> bool + Send + Sync + 'static,
+	{
+		OriginPolicy::Custom(Arc::new(f))
+	}
+
+	/// Check an origin (the value of the `Origin` header) against this policy.
+	pub fn allows(&self, origin: Option<&str>) -> bool {
+		match *self {
+			OriginPolicy::Any => true,
+			OriginPolicy::AllowList(ref patterns) => {
+				match origin {
+					Some(origin) => patterns.iter().any(|p| matches_pattern(p, origin)),
+					None => false,
+				}
+			}
+			OriginPolicy::Custom(ref f) => f(origin),
+		}
+	}
+}
+
+impl Default for OriginPolicy {
+	fn default() -> Self {
+		OriginPolicy::Any
+	}
+}
+
+impl fmt::Debug for OriginPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			OriginPolicy::Any => write!(f, "Any"),
+			OriginPolicy::AllowList(ref patterns) => write!(f, "AllowList({:?})", patterns),
+			OriginPolicy::Custom(_) => write!(f, "Custom(..)"),
+		}
+	}
+}
+
+fn matches_pattern(pattern: &str, origin: &str) -> bool {
+	let pattern = pattern.to_lowercase();
+	let origin = origin.trim().to_lowercase();
+
+	if pattern == "*" {
+		return true;
+	}
+
+	match pattern.find('*') {
+		None => pattern == origin,
+		Some(idx) => {
+			let (prefix, suffix) = (&pattern[..idx], &pattern[idx + 1..]);
+			if origin.len() < prefix.len() + suffix.len() {
+				return false;
+			}
+			if !origin.starts_with(prefix) || !origin.ends_with(suffix) {
+				return false;
+			}
+			let wildcard = &origin[prefix.len()..origin.len() - suffix.len()];
+			!wildcard.is_empty() && !wildcard.contains('/')
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn allow_list_wildcards() {
+		let policy = OriginPolicy::allow(vec!["https://*.example.com", "http://localhost:8080"]);
+
+		assert!(policy.allows(Some("https://a.example.com")));
+		assert!(policy.allows(Some("https://a.b.example.com")));
+		assert!(policy.allows(Some("HTTP://LOCALHOST:8080")));
+		assert!(!policy.allows(Some("https://example.com")));
+		assert!(!policy.allows(Some("http://a.example.com")));
+		assert!(!policy.allows(Some("https://evil.org/.example.com")));
+		assert!(!policy.allows(Some("null")));
+		assert!(!policy.allows(None));
+	}
+
+	#[test]
+	fn any_and_custom() {
+		assert!(OriginPolicy::Any.allows(None));
+		let policy = OriginPolicy::custom(|o| o.map(|o| o.ends_with(".internal")).unwrap_or(true));
+		assert!(policy.allows(None));
+		assert!(policy.allows(Some("http://db.internal")));
+		assert!(!policy.allows(Some("http://db.external")));
+	}
+}
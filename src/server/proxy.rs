@@ -0,0 +1,336 @@
+//! Support for the HAProxy PROXY protocol (versions 1 and 2).
+//!
+//! Load balancers such as HAProxy or AWS ELB can prepend a small preamble to
+//! every connection they forward, telling the backend who the real client is.
+//! Without it a server only ever sees the address of the load balancer.
+//!
+//! The preamble is read byte for byte, so nothing but the preamble itself is
+//! taken off of the stream. This allows reading it before a TLS handshake.
+//!
+//! See http://www.haproxy.org/download/1.8/doc/proxy-protocol.txt
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::str;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async")]
+use futures::Future;
+#[cfg(feature = "async")]
+use futures::future::{self, Loop};
+#[cfg(feature = "async")]
+use tokio_io::AsyncRead;
+#[cfg(feature = "async")]
+use tokio_io::io::read_exact;
+
+/// The longest a version 1 header can be, including the trailing CRLF.
+pub const V1_MAX_LENGTH: usize = 107;
+
+/// The signature every version 2 header starts with.
+pub const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The length of the prefix of a PROXY header that is read to find out which
+/// version of the protocol is used (and for version 2, how long the header is).
+const PREFIX_LENGTH: usize = 16;
+
+/// The information a load balancer sent about the connection it is forwarding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyHeader {
+	/// The connection was proxied from `source` to `destination`.
+	Proxied {
+		/// The address of the real client.
+		source: SocketAddr,
+		/// The address the client connected to.
+		destination: SocketAddr,
+	},
+	/// The proxy did not know the addresses or the connection was not proxied at
+	/// all, e.g. a health check (`PROXY UNKNOWN` or a version 2 `LOCAL` command).
+	Unknown,
+}
+
+impl ProxyHeader {
+	/// The address of the real client, if the proxy knew it.
+	pub fn source(&self) -> Option<SocketAddr> {
+		match *self {
+			ProxyHeader::Proxied { source, .. } => Some(source),
+			ProxyHeader::Unknown => None,
+		}
+	}
+
+	/// The address the real client connected to, if the proxy knew it.
+	pub fn destination(&self) -> Option<SocketAddr> {
+		match *self {
+			ProxyHeader::Proxied { destination, .. } => Some(destination),
+			ProxyHeader::Unknown => None,
+		}
+	}
+}
+
+/// Read a PROXY protocol header of either version off of a stream.
+///
+/// The stream is left positioned right after the header.
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<ProxyHeader> {
+	let mut prefix = [0u8; PREFIX_LENGTH];
+	reader.read_exact(&mut prefix[..6])?;
+
+	if &prefix[..6] == b"PROXY " {
+		let mut line = prefix[..6].to_vec();
+		let mut byte = [0u8; 1];
+		while !line.ends_with(b"\r\n") {
+			if line.len() >= V1_MAX_LENGTH {
+				return Err(invalid("PROXY protocol header is too long"));
+			}
+			reader.read_exact(&mut byte)?;
+			line.push(byte[0]);
+		}
+		return parse_v1(&line);
+	}
+
+	if prefix[..6] != V2_SIGNATURE[..6] {
+		return Err(invalid("missing PROXY protocol header"));
+	}
+	reader.read_exact(&mut prefix[6..])?;
+	let mut addresses = vec![0u8; v2_addresses_length(&prefix)?];
+	reader.read_exact(&mut addresses)?;
+	parse_v2(&prefix, &addresses)
+}
+
+/// Read a PROXY protocol header off of a TCP stream like `read_header` does,
+/// but give up with a `TimedOut` error once `timeout` has passed, no matter
+/// how slowly the header trickles in.
+///
+/// This changes the read timeout of the stream.
+pub fn read_header_within(stream: &mut TcpStream, timeout: Duration) -> io::Result<ProxyHeader> {
+	read_header(&mut Deadline {
+		stream: stream,
+		deadline: Instant::now() + timeout,
+	})
+}
+
+/// Reads off of a TCP stream until a deadline, after which every read fails.
+struct Deadline<'a> {
+	stream: &'a mut TcpStream,
+	deadline: Instant,
+}
+
+impl<'a> Read for Deadline<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let now = Instant::now();
+		if now >= self.deadline {
+			return Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				"timed out reading the PROXY protocol header",
+			));
+		}
+		self.stream.set_read_timeout(Some(self.deadline - now))?;
+		self.stream.read(buf)
+	}
+}
+
+/// Asynchronously read a PROXY protocol header of either version off of a stream.
+///
+/// The stream is given back positioned right after the header.
+#[cfg(feature = "async")]
+pub fn read_header_async<S>(
+	stream: S,
+) -> Box<Future<Item = (S, ProxyHeader), Error = io::Error> + Send>
+where
+	S: AsyncRead + Send + 'static,
+{
+	let future = read_exact(stream, vec![0u8; 6]).and_then(|(stream, prefix)| {
+		if prefix == b"PROXY " {
+			let line = future::loop_fn((stream, prefix), |(stream, mut line)| {
+				if line.ends_with(b"\r\n") {
+					return future::Either::A(future::ok(Loop::Break((stream, line))));
+				}
+				if line.len() >= V1_MAX_LENGTH {
+					let err = invalid("PROXY protocol header is too long");
+					return future::Either::A(future::err(err));
+				}
+				future::Either::B(read_exact(stream, [0u8; 1]).map(move |(stream, byte)| {
+					line.push(byte[0]);
+					Loop::Continue((stream, line))
+				}))
+			});
+			let header = line.and_then(|(stream, line)| parse_v1(&line).map(|h| (stream, h)));
+			return future::Either::A(header);
+		}
+
+		if prefix[..] != V2_SIGNATURE[..6] {
+			let err = invalid("missing PROXY protocol header");
+			return future::Either::B(future::Either::A(future::err(err)));
+		}
+		let mut full = [0u8; PREFIX_LENGTH];
+		full[..6].copy_from_slice(&prefix);
+		let header = read_exact(stream, [0u8; PREFIX_LENGTH - 6])
+			.and_then(move |(stream, rest)| {
+				full[6..].copy_from_slice(&rest);
+				let len = v2_addresses_length(&full)?;
+				Ok((stream, full, len))
+			})
+			.and_then(|(stream, full, len)| {
+				read_exact(stream, vec![0u8; len]).and_then(move |(stream, addresses)| {
+					parse_v2(&full, &addresses).map(|h| (stream, h))
+				})
+			});
+		future::Either::B(future::Either::B(header))
+	});
+	Box::new(future)
+}
+
+/// Parse a version 1 header, e.g. `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`.
+pub fn parse_v1(line: &[u8]) -> io::Result<ProxyHeader> {
+	if line.len() > V1_MAX_LENGTH || !line.ends_with(b"\r\n") {
+		return Err(invalid("malformed PROXY protocol header"));
+	}
+	let line = str::from_utf8(&line[..line.len() - 2])
+		.map_err(|_| invalid("malformed PROXY protocol header"))?;
+	let parts: Vec<&str> = line.split(' ').collect();
+
+	match parts.get(1).cloned() {
+		Some("UNKNOWN") if parts[0] == "PROXY" => return Ok(ProxyHeader::Unknown),
+		Some("TCP4") | Some("TCP6") if parts[0] == "PROXY" && parts.len() == 6 => {}
+		_ => return Err(invalid("malformed PROXY protocol header")),
+	}
+
+	let source_ip = parse_ip(parts[2], parts[1])?;
+	let destination_ip = parse_ip(parts[3], parts[1])?;
+	let source_port = parts[4].parse().map_err(|_| invalid("invalid PROXY protocol port"))?;
+	let destination_port = parts[5].parse().map_err(|_| invalid("invalid PROXY protocol port"))?;
+
+	Ok(ProxyHeader::Proxied {
+		source: SocketAddr::new(source_ip, source_port),
+		destination: SocketAddr::new(destination_ip, destination_port),
+	})
+}
+
+/// Parse a version 2 header given its 16 byte prefix and the address block
+/// that follows it.
+pub fn parse_v2(prefix: &[u8], addresses: &[u8]) -> io::Result<ProxyHeader> {
+	if addresses.len() != v2_addresses_length(prefix)? {
+		return Err(invalid("malformed PROXY protocol header"));
+	}
+
+	match prefix[12] {
+		// LOCAL, the proxy is talking to us on its own behalf
+		0x20 => return Ok(ProxyHeader::Unknown),
+		// PROXY
+		0x21 => {}
+		_ => return Err(invalid("unsupported PROXY protocol version or command")),
+	}
+
+	match prefix[13] {
+		// TCP over IPv4
+		0x11 if addresses.len() >= 12 => {
+			let ip = |b: &[u8]| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]));
+			Ok(ProxyHeader::Proxied {
+				source: SocketAddr::new(ip(&addresses[0..4]), be_u16(&addresses[8..10])),
+				destination: SocketAddr::new(ip(&addresses[4..8]), be_u16(&addresses[10..12])),
+			})
+		}
+		// TCP over IPv6
+		0x21 if addresses.len() >= 36 => {
+			let ip = |b: &[u8]| {
+				let mut octets = [0u8; 16];
+				octets.copy_from_slice(b);
+				IpAddr::V6(Ipv6Addr::from(octets))
+			};
+			Ok(ProxyHeader::Proxied {
+				source: SocketAddr::new(ip(&addresses[0..16]), be_u16(&addresses[32..34])),
+				destination: SocketAddr::new(ip(&addresses[16..32]), be_u16(&addresses[34..36])),
+			})
+		}
+		0x11 | 0x21 => Err(invalid("malformed PROXY protocol header")),
+		// UDP, unix sockets and unspecified protocols carry no useful address
+		_ => Ok(ProxyHeader::Unknown),
+	}
+}
+
+fn v2_addresses_length(prefix: &[u8]) -> io::Result<usize> {
+	if prefix.len() < PREFIX_LENGTH || prefix[..12] != V2_SIGNATURE {
+		return Err(invalid("missing PROXY protocol header"));
+	}
+	Ok(be_u16(&prefix[14..16]) as usize)
+}
+
+fn be_u16(b: &[u8]) -> u16 {
+	(b[0] as u16) << 8 | b[1] as u16
+}
+
+fn parse_ip(ip: &str, protocol: &str) -> io::Result<IpAddr> {
+	let ip: IpAddr = ip.parse().map_err(|_| invalid("invalid PROXY protocol address"))?;
+	match (protocol, ip) {
+		("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => Ok(ip),
+		_ => Err(invalid("invalid PROXY protocol address")),
+	}
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Cursor, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	#[test]
+	fn reads_v1_header() {
+		let mut stream = Cursor::new(&b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET /"[..]);
+		let header = read_header(&mut stream).unwrap();
+		assert_eq!(header.source(), Some("192.0.2.1:56324".parse().unwrap()));
+		assert_eq!(header.destination(), Some("192.0.2.2:443".parse().unwrap()));
+		assert_eq!(stream.position(), 42);
+
+		let mut stream = Cursor::new(&b"PROXY UNKNOWN\r\n"[..]);
+		assert_eq!(read_header(&mut stream).unwrap(), ProxyHeader::Unknown);
+
+		let mut stream = Cursor::new(&b"PROXY TCP4 ::1 192.0.2.2 56324 443\r\n"[..]);
+		assert!(read_header(&mut stream).is_err());
+	}
+
+	#[test]
+	fn reads_v2_header() {
+		let mut bytes = V2_SIGNATURE.to_vec();
+		bytes.extend_from_slice(&[0x21, 0x11, 0, 12]);
+		bytes.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb]);
+		bytes.extend_from_slice(b"GET /");
+
+		let mut stream = Cursor::new(&bytes[..]);
+		let header = read_header(&mut stream).unwrap();
+		assert_eq!(header.source(), Some("192.0.2.1:56324".parse().unwrap()));
+		assert_eq!(header.destination(), Some("192.0.2.2:443".parse().unwrap()));
+		assert_eq!(stream.position(), 28);
+
+		let mut stream = Cursor::new(&b"GET / HTTP/1.1\r\n"[..]);
+		assert!(read_header(&mut stream).is_err());
+	}
+
+	#[test]
+	fn slow_headers_run_out_of_time() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let client = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			// every byte comes in well within the timeout, the header as a whole doesn't
+			for byte in b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n".iter() {
+				if stream.write_all(&[*byte]).is_err() {
+					break;
+				}
+				thread::sleep(Duration::from_millis(30));
+			}
+		});
+
+		let (mut stream, _) = listener.accept().unwrap();
+		let start = Instant::now();
+		match read_header_within(&mut stream, Duration::from_millis(200)) {
+			Err(ref e) if e.kind() == io::ErrorKind::TimedOut ||
+			              e.kind() == io::ErrorKind::WouldBlock => {}
+			other => panic!("expected the header to time out, got {:?}", other),
+		}
+		assert!(start.elapsed() < Duration::from_millis(1000));
+		drop(stream);
+		client.join().unwrap();
+	}
+}
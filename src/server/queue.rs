@@ -0,0 +1,281 @@
+//! Keeping slow clients from piling up messages on the server.
+//!
+//! A client that reads slower than the server writes makes the messages meant
+//! for it queue up in memory. A `WriteQueue` keeps count of the bytes waiting
+//! to be written to an asynchronous client and, once they go over the limit,
+//! either drops messages or closes the connection.
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use message::{CloseData, OwnedMessage};
+use result::WebSocketError;
+use ws::Message;
+
+#[cfg(feature = "async")]
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+
+/// Close code for a client that was evicted for breaking the server's policy.
+pub const POLICY_VIOLATION: u16 = 1008;
+/// Close code asking a client to come back later.
+pub const TRY_AGAIN_LATER: u16 = 1013;
+
+/// What to do with a connection whose write queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+	/// Drop text and binary messages until the queue drained, control messages
+	/// are always queued.
+	DropMessages,
+	/// Throw away what is queued and close the connection with this code,
+	/// usually `POLICY_VIOLATION` or `TRY_AGAIN_LATER`.
+	Close(u16),
+}
+
+/// How many bytes may wait to be written to a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteQueueLimit {
+	/// The most bytes of messages queued at once, a single message larger
+	/// than this is let through as long as nothing else is queued.
+	pub max_bytes: usize,
+	/// What to do once the limit is reached.
+	pub overflow: Overflow,
+}
+
+impl Default for WriteQueueLimit {
+	fn default() -> Self {
+		WriteQueueLimit {
+			max_bytes: 1024 * 1024,
+			overflow: Overflow::Close(TRY_AGAIN_LATER),
+		}
+	}
+}
+
+/// The number of bytes queued for a connection, shared with its `WriteQueue`
+/// so it can be looked at after the client was split.
+#[derive(Clone, Debug, Default)]
+pub struct QueueDepth(Arc<AtomicUsize>);
+
+impl QueueDepth {
+	/// The number of bytes waiting to be written.
+	pub fn bytes(&self) -> usize {
+		self.0.load(Ordering::Relaxed)
+	}
+
+	fn set(&self, bytes: usize) {
+		self.0.store(bytes, Ordering::Relaxed)
+	}
+}
+
+/// The error a connection fails with once it was closed for being too slow.
+pub fn evicted() -> WebSocketError {
+	debug!("closed connection, the peer could not keep up");
+	WebSocketError::IoError(io::Error::new(
+		io::ErrorKind::ConnectionAborted,
+		"Peer could not keep up with the messages sent to it",
+	))
+}
+
+/// An asynchronous client whose outgoing messages are queued up to a limit.
+///
+/// This is a drop-in replacement for the client it wraps. Messages are always
+/// accepted right away, and written out whenever the client is flushed. Once
+/// it closed the connection because of `Overflow::Close`, flushing fails with
+/// a `ConnectionAborted` error.
+pub struct WriteQueue<C> {
+	inner: C,
+	limit: WriteQueueLimit,
+	queue: VecDeque<OwnedMessage>,
+	depth: QueueDepth,
+	queued: usize,
+	evicted: bool,
+}
+
+impl<C> WriteQueue<C> {
+	/// Start queueing the messages sent to a client.
+	pub fn new(inner: C, limit: WriteQueueLimit) -> Self {
+		WriteQueue {
+			inner: inner,
+			limit: limit,
+			queue: VecDeque::new(),
+			depth: QueueDepth::default(),
+			queued: 0,
+			evicted: false,
+		}
+	}
+
+	/// The number of bytes waiting to be written.
+	pub fn queued_bytes(&self) -> usize {
+		self.queued
+	}
+
+	/// The number of messages waiting to be written.
+	pub fn queued_messages(&self) -> usize {
+		self.queue.len()
+	}
+
+	/// A handle on the number of bytes waiting to be written, which stays up
+	/// to date after the client was split.
+	pub fn depth(&self) -> QueueDepth {
+		self.depth.clone()
+	}
+
+	/// Get a reference to the wrapped client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Give the wrapped client back, dropping whatever is still queued.
+	pub fn into_inner(self) -> C {
+		self.depth.set(0);
+		self.inner
+	}
+
+	/// Queue a message, unless the queue is full.
+	fn push(&mut self, message: OwnedMessage) {
+		if self.evicted {
+			return;
+		}
+		let size = message.message_size(false);
+		if !self.queue.is_empty() && self.queued + size > self.limit.max_bytes {
+			match self.limit.overflow {
+				Overflow::DropMessages if message.is_data() => {
+					trace!("dropped a message of {} bytes, the write queue is full", size);
+					return;
+				}
+				Overflow::DropMessages => {}
+				Overflow::Close(code) => {
+					debug!("write queue full, closing the connection with {}", code);
+					let close = OwnedMessage::Close(Some(CloseData::new(code, String::new())));
+					self.queue.clear();
+					self.queued = 0;
+					self.evicted = true;
+					return self.enqueue(close);
+				}
+			}
+		}
+		self.enqueue(message)
+	}
+
+	fn enqueue(&mut self, message: OwnedMessage) {
+		self.queued += message.message_size(false);
+		self.queue.push_back(message);
+		self.depth.set(self.queued);
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Sink for WriteQueue<C>
+where
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.push(item);
+		Ok(AsyncSink::Ready)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		while let Some(message) = self.queue.pop_front() {
+			let size = message.message_size(false);
+			if let AsyncSink::NotReady(message) = self.inner.start_send(message)? {
+				self.queue.push_front(message);
+				self.inner.poll_complete()?;
+				return Ok(Async::NotReady);
+			}
+			self.queued -= size;
+			self.depth.set(self.queued);
+		}
+		if let Async::NotReady = self.inner.poll_complete()? {
+			return Ok(Async::NotReady);
+		}
+		if self.evicted {
+			return Err(evicted());
+		}
+		Ok(Async::Ready(()))
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		match self.poll_complete() {
+			Ok(Async::Ready(())) => {}
+			Ok(Async::NotReady) => return Ok(Async::NotReady),
+			// the close frame went out, nothing left to do but close
+			Err(_) if self.evicted => {}
+			Err(e) => return Err(e),
+		}
+		self.inner.close()
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Stream for WriteQueue<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		self.inner.poll()
+	}
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+	use super::*;
+
+	use test_util::Slow;
+
+	fn text(len: usize) -> OwnedMessage {
+		OwnedMessage::Text(::std::iter::repeat('a').take(len).collect())
+	}
+
+	#[test]
+	fn drops_data_messages_when_full() {
+		let limit = WriteQueueLimit {
+			max_bytes: 100,
+			overflow: Overflow::DropMessages,
+		};
+		let mut queue = WriteQueue::new(Slow::<OwnedMessage>::new(0), limit);
+		let depth = queue.depth();
+
+		queue.start_send(text(60)).unwrap();
+		queue.start_send(text(60)).unwrap();
+		queue.start_send(OwnedMessage::Ping(Vec::new())).unwrap();
+		assert_eq!(queue.queued_messages(), 2);
+		assert_eq!(depth.bytes(), 62 + 2);
+
+		queue.get_mut().room = 10;
+		assert_eq!(queue.poll_complete().unwrap(), Async::Ready(()));
+		assert_eq!(depth.bytes(), 0);
+		assert_eq!(queue.get_ref().sent, vec![text(60), OwnedMessage::Ping(Vec::new())]);
+	}
+
+	#[test]
+	fn closes_when_full() {
+		let limit = WriteQueueLimit {
+			max_bytes: 100,
+			overflow: Overflow::Close(POLICY_VIOLATION),
+		};
+		let mut queue = WriteQueue::new(Slow::<OwnedMessage>::new(0), limit);
+
+		queue.start_send(text(60)).unwrap();
+		queue.start_send(text(60)).unwrap();
+		queue.start_send(text(1)).unwrap();
+		assert_eq!(queue.queued_messages(), 1);
+
+		queue.get_mut().room = 10;
+		assert!(queue.poll_complete().is_err());
+		assert_eq!(
+			queue.get_ref().sent,
+			vec![OwnedMessage::Close(Some(CloseData::new(POLICY_VIOLATION, String::new())))]
+		);
+	}
+}
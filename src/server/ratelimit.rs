@@ -0,0 +1,332 @@
+//! Limiting how fast clients may send messages.
+//!
+//! A `RateLimit` gives each connection token buckets for the messages and
+//! bytes it receives. A client sending faster than the buckets refill either
+//! gets slowed down or has its connection closed with `1008` (policy
+//! violation).
+use std::cmp;
+use std::io;
+use std::time::{Duration, Instant};
+
+use result::WebSocketError;
+pub use server::queue::POLICY_VIOLATION;
+
+#[cfg(feature = "async")]
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+#[cfg(feature = "async")]
+use tokio::timer::Delay;
+#[cfg(feature = "async")]
+use message::{CloseData, OwnedMessage};
+#[cfg(feature = "async")]
+use ws::Message;
+
+/// What to do with a connection sending faster than its `RateLimit` allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exceeded {
+	/// Stop reading from the connection until the client is back within its
+	/// limit, synchronous clients sleep and asynchronous ones stop polling
+	/// the socket, so TCP flow control slows the client down.
+	Delay,
+	/// Close the connection with `POLICY_VIOLATION`.
+	Close,
+}
+
+/// How fast a connection may send, limits left at `None` don't apply.
+///
+/// ```rust
+/// use websocket::server::ratelimit::{Exceeded, RateLimit};
+///
+/// let limit = RateLimit {
+///     messages_per_sec: Some(50),
+///     bytes_per_sec: Some(64 * 1024),
+///     exceeded: Exceeded::Close,
+///     ..RateLimit::default()
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+	/// How many messages a connection may send per second.
+	pub messages_per_sec: Option<u32>,
+	/// How many bytes a connection may send per second.
+	pub bytes_per_sec: Option<u64>,
+	/// For how long a connection may save up its allowance to send in a
+	/// burst. Defaults to one second.
+	pub burst: Duration,
+	/// What to do once a connection goes over the limit. Defaults to `Delay`.
+	pub exceeded: Exceeded,
+}
+
+impl Default for RateLimit {
+	fn default() -> Self {
+		RateLimit {
+			messages_per_sec: None,
+			bytes_per_sec: None,
+			burst: Duration::from_secs(1),
+			exceeded: Exceeded::Delay,
+		}
+	}
+}
+
+impl RateLimit {
+	/// Start limiting a connection that was just opened.
+	pub fn start(self) -> RateLimitState {
+		let burst = secs(self.burst);
+		let bucket = |rate: f64| {
+			let rate = rate.max(1.0);
+			Bucket {
+				rate: rate,
+				capacity: rate * burst,
+				tokens: rate * burst,
+			}
+		};
+		RateLimitState {
+			config: self,
+			messages: self.messages_per_sec.map(|rate| bucket(rate as f64)),
+			bytes: self.bytes_per_sec.map(|rate| bucket(rate as f64)),
+			last: Instant::now(),
+			exceeded: false,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+struct Bucket {
+	rate: f64,
+	capacity: f64,
+	tokens: f64,
+}
+
+impl Bucket {
+	/// Take `n` tokens, going into debt if need be, and tell how long it
+	/// takes to pay the debt back in seconds.
+	fn take(&mut self, elapsed: f64, n: f64) -> f64 {
+		self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity) - n;
+		if self.tokens >= 0.0 {
+			0.0
+		} else {
+			-self.tokens / self.rate
+		}
+	}
+}
+
+/// Keeps track of a single connection on behalf of a `RateLimit`.
+#[derive(Clone, Debug)]
+pub struct RateLimitState {
+	config: RateLimit,
+	messages: Option<Bucket>,
+	bytes: Option<Bucket>,
+	last: Instant,
+	exceeded: bool,
+}
+
+impl RateLimitState {
+	/// The settings this connection is limited with.
+	pub fn config(&self) -> &RateLimit {
+		&self.config
+	}
+
+	/// Record `bytes` received at `now`, `finished` tells whether they
+	/// completed a message.
+	///
+	/// Gives how long to hold off reading if the connection went over its
+	/// limit. With `Exceeded::Close` the connection stays over its limit for
+	/// good afterwards.
+	pub fn record(&mut self, bytes: usize, finished: bool, now: Instant) -> Option<Duration> {
+		let elapsed = secs(now.duration_since(cmp::min(self.last, now)));
+		self.last = cmp::max(self.last, now);
+		let mut wait = 0.0f64;
+		if let Some(ref mut bucket) = self.bytes {
+			wait = wait.max(bucket.take(elapsed, bytes as f64));
+		}
+		if let Some(ref mut bucket) = self.messages {
+			let n = if finished { 1.0 } else { 0.0 };
+			wait = wait.max(bucket.take(elapsed, n));
+		}
+		if wait <= 0.0 {
+			return None;
+		}
+		if self.config.exceeded == Exceeded::Close {
+			self.exceeded = true;
+		}
+		Some(Duration::new(wait as u64, (wait.fract() * 1e9) as u32))
+	}
+
+	/// Whether the connection went over its limit and has to be closed.
+	pub fn is_exceeded(&self) -> bool {
+		self.exceeded
+	}
+}
+
+fn secs(duration: Duration) -> f64 {
+	duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+/// The error a connection fails with once it went over its rate limit.
+pub fn exceeded() -> WebSocketError {
+	debug!("closing connection, the peer went over its rate limit");
+	WebSocketError::IoError(io::Error::new(
+		io::ErrorKind::Other,
+		"Peer went over its rate limit",
+	))
+}
+
+/// An asynchronous client whose incoming messages are rate limited.
+///
+/// This is a drop-in replacement for the client it wraps. Once a message puts
+/// the client over its limit, the next one is only read after the client is
+/// back within it, or the stream of messages fails after sending a close
+/// message with `POLICY_VIOLATION`.
+#[cfg(feature = "async")]
+pub struct RateLimited<C> {
+	inner: C,
+	state: RateLimitState,
+	delay: Option<Delay>,
+	close_pending: bool,
+}
+
+#[cfg(feature = "async")]
+impl<C> RateLimited<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	/// Start limiting a client.
+	pub fn new(inner: C, limit: RateLimit) -> Self {
+		RateLimited {
+			inner: inner,
+			state: limit.start(),
+			delay: None,
+			close_pending: false,
+		}
+	}
+
+	/// Get a reference to the limited client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the limited client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Stop limiting the client and give it back.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+
+	fn send_close(&mut self) -> Result<(), WebSocketError> {
+		if !self.close_pending {
+			return Ok(());
+		}
+		let close = OwnedMessage::Close(Some(CloseData::new(POLICY_VIOLATION, String::new())));
+		if let AsyncSink::Ready = self.inner.start_send(close)? {
+			self.close_pending = false;
+			self.inner.poll_complete()?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Stream for RateLimited<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		if self.state.is_exceeded() {
+			self.send_close()?;
+			return Err(exceeded());
+		}
+
+		if let Some(ref mut delay) = self.delay {
+			match delay.poll() {
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Ok(Async::Ready(())) => {}
+				Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e).into()),
+			}
+		}
+		self.delay = None;
+
+		let message = match self.inner.poll()? {
+			Async::Ready(Some(message)) => message,
+			other => return Ok(other),
+		};
+		let now = Instant::now();
+		if let Some(wait) = self.state.record(message.message_size(false), true, now) {
+			match self.state.config().exceeded {
+				Exceeded::Delay => self.delay = Some(Delay::new(now + wait)),
+				Exceeded::Close => {
+					self.close_pending = true;
+					self.send_close()?;
+					return Err(exceeded());
+				}
+			}
+		}
+		Ok(Async::Ready(Some(message)))
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Sink for RateLimited<C>
+where
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn delays_bursts() {
+		let limit = RateLimit {
+			messages_per_sec: Some(2),
+			..RateLimit::default()
+		};
+		let mut state = limit.start();
+		let start = state.last;
+
+		assert_eq!(state.record(10, true, start), None);
+		assert_eq!(state.record(10, false, start), None);
+		assert_eq!(state.record(10, true, start), None);
+		assert_eq!(state.record(10, true, start), Some(Duration::from_millis(500)));
+		let later = start + Duration::from_secs(1);
+		assert_eq!(state.record(10, true, later), None);
+		assert_eq!(state.record(10, true, later), Some(Duration::from_millis(500)));
+		assert!(!state.is_exceeded());
+	}
+
+	#[test]
+	fn closes_on_too_many_bytes() {
+		let limit = RateLimit {
+			bytes_per_sec: Some(100),
+			exceeded: Exceeded::Close,
+			..RateLimit::default()
+		};
+		let mut state = limit.start();
+		let start = state.last;
+
+		assert_eq!(state.record(100, true, start), None);
+		assert!(state.record(1, true, start).is_some());
+		assert!(state.is_exceeded());
+	}
+}
@@ -0,0 +1,158 @@
+//! Handing the connections of an asynchronous server to a service.
+//!
+//! A `ConnectionService` gets every connection the server accepts and gives
+//! back a future handling it, which is spawned onto the default executor.
+//! Like a `tower::Service`, a service says when it is ready for the next
+//! connection, and no connection is taken from the listener until it is, so
+//! a busy service makes new clients wait in the listen backlog instead of
+//! piling up. Services can wrap other services to add timeouts, metrics or
+//! load shedding around the handling of connections.
+//!
+//! Any `FnMut(WsConnection<S>) -> impl IntoFuture<Item = ()>` is a service
+//! that is always ready.
+//!
+//! ```rust,no_run
+//! # extern crate tokio;
+//! # extern crate websocket;
+//! # fn main() {
+//! use websocket::async::Server;
+//! use websocket::futures::{Future, Sink, Stream};
+//! use websocket::server::service::{serve, WsConnection};
+//!
+//! let server = Server::bind("127.0.0.1:1234", &tokio::reactor::Handle::default()).unwrap();
+//!
+//! let echo = serve(server.incoming(), |connection: WsConnection<_>| {
+//!     println!("connection from {}", connection.addr);
+//!     connection.upgrade.accept().and_then(|(client, _)| {
+//!         let (sink, stream) = client.split();
+//!         stream.filter(|m| m.is_data()).forward(sink).map(|_| ())
+//!     })
+//! });
+//! # tokio::run(echo.map_err(|_: websocket::WebSocketError| ()));
+//! # }
+//! ```
+use std::fmt;
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use futures::{Async, Future, IntoFuture, Poll, Stream};
+
+use server::InvalidConnection;
+use server::upgrade::async::Upgrade;
+use stream::async::Stream as AsyncStream;
+
+/// A connection that came in, waiting to be accepted or rejected.
+pub struct WsConnection<S>
+where
+	S: AsyncStream + Send,
+{
+	/// The handshake of the connection.
+	pub upgrade: Upgrade<S>,
+	/// The address of the client, see `async::server::Incoming`.
+	pub addr: SocketAddr,
+}
+
+/// Handles the connections of a server, see the module docs.
+pub trait ConnectionService<S>
+where
+	S: AsyncStream + Send,
+{
+	/// The error of handling a connection, or of the service itself.
+	type Error;
+	/// The future handling a connection.
+	type Future: Future<Item = (), Error = Self::Error>;
+
+	/// Whether the service is ready to take another connection. An error
+	/// stops the server.
+	fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+		Ok(Async::Ready(()))
+	}
+
+	/// Handle `connection`, this is only called after `poll_ready` said the
+	/// service is ready.
+	fn call(&mut self, connection: WsConnection<S>) -> Self::Future;
+}
+
+impl<S, F, R> ConnectionService<S> for F
+where
+	S: AsyncStream + Send,
+	F: FnMut(WsConnection<S>) -> R,
+	R: IntoFuture<Item = ()>,
+{
+	type Error = R::Error;
+	type Future = R::Future;
+
+	fn call(&mut self, connection: WsConnection<S>) -> R::Future {
+		self(connection).into_future()
+	}
+}
+
+/// Hand the connections of `incoming` to `service`.
+///
+/// The future runs until `incoming` ends or the service fails to get ready.
+/// Connections that failed their handshake are skipped, connections whose
+/// handling failed are logged. It has to run on a tokio runtime, the
+/// handling of connections is spawned onto it.
+pub fn serve<S, I, T>(incoming: I, service: T) -> Serve<I, T>
+where
+	S: AsyncStream + Send,
+	I: Stream<Item = (Upgrade<S>, SocketAddr), Error = InvalidConnection<S, BytesMut>>,
+	T: ConnectionService<S>,
+{
+	Serve {
+		incoming: incoming,
+		service: service,
+	}
+}
+
+/// A future handing connections to a service, see `serve`.
+pub struct Serve<I, T> {
+	incoming: I,
+	service: T,
+}
+
+impl<I, T> Serve<I, T> {
+	/// Get a reference to the service.
+	pub fn service(&self) -> &T {
+		&self.service
+	}
+
+	/// Get a mutable reference to the service.
+	pub fn service_mut(&mut self) -> &mut T {
+		&mut self.service
+	}
+}
+
+impl<S, I, T> Future for Serve<I, T>
+where
+	S: AsyncStream + Send,
+	I: Stream<Item = (Upgrade<S>, SocketAddr), Error = InvalidConnection<S, BytesMut>>,
+	T: ConnectionService<S>,
+	T::Future: Send + 'static,
+	T::Error: fmt::Display,
+{
+	type Item = ();
+	type Error = T::Error;
+
+	fn poll(&mut self) -> Poll<(), T::Error> {
+		loop {
+			if let Async::NotReady = self.service.poll_ready()? {
+				return Ok(Async::NotReady);
+			}
+			let (upgrade, addr) = match self.incoming.poll() {
+				Ok(Async::Ready(Some(connection))) => connection,
+				Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				// the server reported it already
+				Err(_) => continue,
+			};
+			let handling = self.service.call(WsConnection {
+				upgrade: upgrade,
+				addr: addr,
+			});
+			::tokio::spawn(handling.map_err(move |e| {
+				warn!("handling the connection from {} failed: {}", addr, e);
+			}));
+		}
+	}
+}
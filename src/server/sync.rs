@@ -1,14 +1,22 @@
 //! Provides an implementation of a WebSocket server
 use std::net::{SocketAddr, ToSocketAddrs, TcpListener, TcpStream};
-use std::io;
+use std::io::{self, Write};
 use std::convert::Into;
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "sync-ssl")]
 use native_tls::{TlsStream, TlsAcceptor};
+#[cfg(feature = "sync-ssl")]
+use server::ReloadableTlsAcceptor;
 
 use codec::http::RequestHead;
-use server::{WsServer, OptionalTlsAcceptor, NoTlsAcceptor, InvalidConnection};
-use server::upgrade::sync::{Upgrade, IntoWs, Buffer};
+use server::{WsServer, OptionalTlsAcceptor, NoTlsAcceptor, InvalidConnection, Admission, Peer};
+use server::report_failure;
+use server::proxy;
+use stream::sync::{Stream, AsTcpStream};
+use server::upgrade::sync::{Upgrade, Buffer, into_ws_with_limits};
 pub use server::upgrade::HyperIntoWsError;
 
 #[cfg(feature = "async")]
@@ -18,6 +26,9 @@ use tokio::net::TcpListener as AsyncTcpListener;
 #[cfg(feature = "async")]
 use server::async;
 
+/// How often `accept_timeout` checks for a connection.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 /// Either the stream was established and it sent a websocket handshake
 /// which represents the `Ok` variant, or there was an error (this is the
 /// `Err` variant).
@@ -46,7 +57,8 @@ where
 	/// of this. It provides high level APIs for creating asynchronous servers.
 	///
 	/// If it is in nonblocking mode, accept() will return an error instead of
-	/// blocking when there are no incoming connections.
+	/// blocking when there are no incoming connections. To block for a while
+	/// only, use `accept_timeout`.
 	///
 	///```no_run
 	/// # extern crate websocket;
@@ -89,11 +101,9 @@ where
 	/// at the start of your server.
 	#[cfg(feature = "async")]
 	pub fn into_async(self, handle: &Handle) -> io::Result<async::Server<S>> {
-		let addr = self.listener.local_addr()?;
-		Ok(WsServer {
-			listener: AsyncTcpListener::from_std(self.listener, handle)?,
-			ssl_acceptor: self.ssl_acceptor,
-		})
+		let admission = self.admission();
+		let listener = AsyncTcpListener::from_std(self.listener, handle)?;
+		Ok(WsServer::from_parts(listener, self.ssl_acceptor).with_admission(admission))
 	}
 }
 
@@ -143,49 +153,29 @@ impl WsServer<TlsAcceptor, TcpListener> {
 	where
 		A: ToSocketAddrs,
 	{
-		Ok(Server {
-			listener: TcpListener::bind(&addr)?,
-			ssl_acceptor: acceptor,
-		})
+		Ok(WsServer::from_parts(TcpListener::bind(&addr)?, acceptor))
+	}
+
+	/// Create an SSL server listening on a socket that was set up elsewhere,
+	/// see `Server::from_listener`.
+	pub fn from_listener_secure(listener: TcpListener, acceptor: TlsAcceptor) -> Self {
+		WsServer::from_parts(listener, acceptor)
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TlsStream<TcpStream>> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
-			Err(e) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: e.into(),
-				})
-			}
-		};
-
-		let stream = match self.ssl_acceptor.accept(stream) {
-			Ok(s) => s,
-			Err(err) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: io::Error::new(io::ErrorKind::Other, err).into(),
-				})
-			}
-		};
+		let result = accept_secure(&self.listener, &self.ssl_acceptor, &self.admission());
+		report_failure(&result);
+		result
+	}
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
-			Err((s, r, b, e)) => {
-				Err(InvalidConnection {
-					stream: Some(s),
-					parsed: r,
-					buffer: b,
-					error: e.into(),
-				})
-			}
-		}
+	/// Accept an incoming WebSocket connection, waiting at most `timeout`
+	/// for one to come in, see `Server::accept_timeout`.
+	pub fn accept_timeout(&mut self, timeout: Duration) -> AcceptResult<TlsStream<TcpStream>> {
+		let (stream, addr) = accept_within(&self.listener, timeout).map_err(accept_failed)?;
+		let result = handshake_secure(stream, addr, &self.ssl_acceptor, &self.admission());
+		report_failure(&result);
+		result
 	}
 }
 
@@ -198,6 +188,263 @@ impl Iterator for WsServer<TlsAcceptor, TcpListener> {
 	}
 }
 
+/// Synchronous methods for an SSL server whose certificate can be replaced
+/// without restarting the listener.
+#[cfg(feature = "sync-ssl")]
+impl WsServer<ReloadableTlsAcceptor, TcpListener> {
+	/// Bind this Server to this socket, utilising a `ReloadableTlsAcceptor`.
+	///
+	/// Keep a clone of the acceptor around and call `reload` on it whenever
+	/// the certificate is renewed, new connections will pick up the change.
+	///
+	/// ```no_run
+	/// extern crate websocket;
+	/// extern crate native_tls;
+	/// # fn main() {
+	/// # use native_tls::{Pkcs12, TlsAcceptor};
+	/// use websocket::sync::Server;
+	/// use websocket::server::ReloadableTlsAcceptor;
+	/// # fn load() -> TlsAcceptor {
+	/// #     let pkcs12 = Pkcs12::from_der(&[], "").unwrap();
+	/// #     TlsAcceptor::builder(pkcs12).unwrap().build().unwrap()
+	/// # }
+	///
+	/// let acceptor = ReloadableTlsAcceptor::new(load());
+	/// let server = Server::bind_secure_reloadable("127.0.0.1:1234", acceptor.clone()).unwrap();
+	///
+	/// // later, from another thread
+	/// acceptor.reload(load());
+	/// # }
+	/// ```
+	pub fn bind_secure_reloadable<A>(addr: A, acceptor: ReloadableTlsAcceptor) -> io::Result<Self>
+	where
+		A: ToSocketAddrs,
+	{
+		Ok(WsServer::from_parts(TcpListener::bind(&addr)?, acceptor))
+	}
+
+	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
+	///
+	/// The acceptor is looked up once a connection came in, so a reload while
+	/// this waits applies to that connection already.
+	pub fn accept(&mut self) -> AcceptResult<TlsStream<TcpStream>> {
+		let (stream, addr) = self.listener.accept().map_err(accept_failed)?;
+		let acceptor = self.ssl_acceptor.current();
+		let result = handshake_secure(stream, addr, &acceptor, &self.admission());
+		report_failure(&result);
+		result
+	}
+
+	/// Accept an incoming WebSocket connection, waiting at most `timeout`
+	/// for one to come in, see `Server::accept_timeout`.
+	pub fn accept_timeout(&mut self, timeout: Duration) -> AcceptResult<TlsStream<TcpStream>> {
+		let (stream, addr) = accept_within(&self.listener, timeout).map_err(accept_failed)?;
+		let acceptor = self.ssl_acceptor.current();
+		let result = handshake_secure(stream, addr, &acceptor, &self.admission());
+		report_failure(&result);
+		result
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+impl Iterator for WsServer<ReloadableTlsAcceptor, TcpListener> {
+	type Item = AcceptResult<TlsStream<TcpStream>>;
+
+	fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+		Some(self.accept())
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+fn accept_secure(
+	listener: &TcpListener,
+	acceptor: &TlsAcceptor,
+	admission: &Admission,
+) -> AcceptResult<TlsStream<TcpStream>> {
+	match listener.accept() {
+		Ok((stream, addr)) => handshake_secure(stream, addr, acceptor, admission),
+		Err(e) => Err(accept_failed(e)),
+	}
+}
+
+#[cfg(feature = "sync-ssl")]
+fn handshake_secure(
+	mut stream: TcpStream,
+	addr: SocketAddr,
+	acceptor: &TlsAcceptor,
+	admission: &Admission,
+) -> AcceptResult<TlsStream<TcpStream>> {
+	// the TLS handshake counts towards the handshake timeout as well
+	let peer = match prepare(&mut stream, addr, admission) {
+		Ok(p) => p,
+		Err(e) => {
+			return Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: e,
+			})
+		}
+	};
+
+	let stream = match acceptor.accept(stream) {
+		Ok(s) => s,
+		Err(err) => {
+			return Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: io::Error::new(io::ErrorKind::Other, err).into(),
+			})
+		}
+	};
+
+	upgrade(stream, peer, admission)
+}
+
+fn accept_plain(listener: &TcpListener, admission: &Admission) -> AcceptResult<TcpStream> {
+	match listener.accept() {
+		Ok((stream, addr)) => handshake_plain(stream, addr, admission),
+		Err(e) => Err(accept_failed(e)),
+	}
+}
+
+fn handshake_plain(mut stream: TcpStream, addr: SocketAddr, admission: &Admission) -> AcceptResult<TcpStream> {
+	let peer = match prepare(&mut stream, addr, admission) {
+		Ok(p) => p,
+		Err(e) => {
+			return Err(InvalidConnection {
+				stream: None,
+				parsed: None,
+				buffer: None,
+				error: e,
+			})
+		}
+	};
+
+	upgrade(stream, peer, admission)
+}
+
+/// Accept a connection, waiting at most `timeout` for one to come in.
+///
+/// The listener is switched to nonblocking mode for the wait and back to
+/// blocking mode afterwards.
+fn accept_within(listener: &TcpListener, timeout: Duration) -> io::Result<(TcpStream, SocketAddr)> {
+	let deadline = Instant::now() + timeout;
+	listener.set_nonblocking(true)?;
+	let accepted = loop {
+		match listener.accept() {
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+			result => break result,
+		}
+		let now = Instant::now();
+		if now >= deadline {
+			break Err(io::Error::new(io::ErrorKind::TimedOut, "no connection came in"));
+		}
+		thread::sleep(cmp::min(deadline - now, ACCEPT_POLL_INTERVAL));
+	};
+	listener.set_nonblocking(false)?;
+	let (stream, addr) = accepted?;
+	// some platforms hand out sockets that inherited the nonblocking mode
+	stream.set_nonblocking(false)?;
+	Ok((stream, addr))
+}
+
+fn accept_failed<S>(e: io::Error) -> InvalidConnection<S, Buffer>
+where
+	S: Stream + Send,
+{
+	InvalidConnection {
+		stream: None,
+		parsed: None,
+		buffer: None,
+		error: e.into(),
+	}
+}
+
+/// Get a freshly accepted connection ready for its handshake, this sets the
+/// handshake timeout, reads the PROXY protocol header if there is one and
+/// counts the connection against the connection limits.
+fn prepare(
+	stream: &mut TcpStream,
+	addr: SocketAddr,
+	admission: &Admission,
+) -> Result<Peer, HyperIntoWsError> {
+	debug!("accepted connection from {}", addr);
+	admission.tcp_options.apply(stream)?;
+	let timeout = admission.handshake_limits.timeout;
+	let proxy = if admission.proxy_protocol {
+		let header = match timeout {
+			Some(timeout) => proxy::read_header_within(stream, timeout),
+			None => proxy::read_header(stream),
+		};
+		match header {
+			Ok(header) => Some(header),
+			Err(ref e) if timeout.is_some() &&
+			              (e.kind() == io::ErrorKind::WouldBlock ||
+			               e.kind() == io::ErrorKind::TimedOut) => {
+				return Err(HyperIntoWsError::HandshakeTimeout);
+			}
+			Err(e) => return Err(e.into()),
+		}
+	} else {
+		None
+	};
+	// the handshake gets a timeout of its own, like with the async server
+	stream.set_read_timeout(timeout)?;
+	admission.peer(addr, proxy)
+}
+
+/// Read the handshake off of a freshly accepted stream and apply the server's
+/// policies to it, rejecting it on the spot if need be.
+fn upgrade<S>(stream: S, peer: Peer, admission: &Admission) -> AcceptResult<S>
+where
+	S: Stream + AsTcpStream + Send,
+{
+	let mut upgrade = match into_ws_with_limits(stream, &admission.handshake_limits) {
+		Ok(u) => u,
+		Err((mut s, r, b, e)) => {
+			if let Some(response) = admission.error_response(r.as_ref(), &e) {
+				// the client is on its way out, it's fine if it doesn't get the message
+				let _ = s.write_all(&response);
+			}
+			return Err(InvalidConnection {
+				stream: Some(s),
+				parsed: r,
+				buffer: b,
+				error: e,
+			});
+		}
+	};
+
+	if let Err(e) = upgrade.stream.as_tcp().set_read_timeout(None) {
+		return Err(InvalidConnection {
+			stream: Some(upgrade.stream),
+			parsed: Some(upgrade.request),
+			buffer: upgrade.buffer,
+			error: e.into(),
+		});
+	}
+
+	let addr = peer.addr;
+	peer.attach(&mut upgrade);
+
+	let (status, error) = match admission.refusal(&mut upgrade, addr) {
+		Some(refusal) => refusal,
+		None => return Ok(upgrade),
+	};
+	let stream = status.map(|status| match upgrade.reject_with_status(status, None) {
+		Ok(s) => s,
+		Err((s, _)) => s,
+	});
+	Err(InvalidConnection {
+		stream: stream,
+		parsed: None,
+		buffer: None,
+		error: error,
+	})
+}
+
 impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// Bind this Server to this socket
 	///
@@ -226,46 +473,70 @@ impl WsServer<NoTlsAcceptor, TcpListener> {
 	/// # }
 	/// ```
 	pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
-		Ok(Server {
-			listener: TcpListener::bind(&addr)?,
-			ssl_acceptor: NoTlsAcceptor,
-		})
+		Ok(WsServer::from_parts(TcpListener::bind(&addr)?, NoTlsAcceptor))
+	}
+
+	/// Create a server listening on a socket that was set up elsewhere,
+	/// e.g. one inherited through systemd socket activation or configured
+	/// with options this crate does not offer.
+	///
+	/// ```rust
+	/// use std::net::TcpListener;
+	/// use websocket::sync::Server;
+	///
+	/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	/// let addr = listener.local_addr().unwrap();
+	///
+	/// let server = Server::from_listener(listener);
+	/// assert_eq!(server.local_addr().unwrap(), addr);
+	/// ```
+	pub fn from_listener(listener: TcpListener) -> Self {
+		WsServer::from_parts(listener, NoTlsAcceptor)
 	}
 
 	/// Wait for and accept an incoming WebSocket connection, returning a WebSocketRequest
 	pub fn accept(&mut self) -> AcceptResult<TcpStream> {
-		let stream = match self.listener.accept() {
-			Ok(s) => s.0,
-			Err(e) => {
-				return Err(InvalidConnection {
-					stream: None,
-					parsed: None,
-					buffer: None,
-					error: e.into(),
-				})
-			}
-		};
+		let result = accept_plain(&self.listener, &self.admission());
+		report_failure(&result);
+		result
+	}
 
-		match stream.into_ws() {
-			Ok(u) => Ok(u),
-			Err((s, r, b, e)) => {
-				Err(InvalidConnection {
-					stream: Some(s),
-					parsed: r,
-					buffer: b,
-					error: e.into(),
-				})
-			}
-		}
+	/// Accept an incoming WebSocket connection like `accept` does, but give
+	/// up with an error of kind `TimedOut` if none came in within `timeout`.
+	///
+	/// This lets an accept loop check a shutdown flag once in a while. The
+	/// server is in blocking mode afterwards, and the timeout covers only
+	/// the wait for a connection, not its handshake.
+	///
+	/// ```no_run
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use std::sync::Arc;
+	/// use std::sync::atomic::{AtomicBool, Ordering};
+	/// use std::time::Duration;
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// let running = Arc::new(AtomicBool::new(true));
+	///
+	/// while running.load(Ordering::SeqCst) {
+	///     if let Ok(upgrade) = server.accept_timeout(Duration::from_secs(1)) {
+	///         // handle the connection ...
+	///     }
+	/// }
+	/// # }
+	/// ```
+	pub fn accept_timeout(&mut self, timeout: Duration) -> AcceptResult<TcpStream> {
+		let (stream, addr) = accept_within(&self.listener, timeout).map_err(accept_failed)?;
+		let result = handshake_plain(stream, addr, &self.admission());
+		report_failure(&result);
+		result
 	}
 
 	/// Create a new independently owned handle to the underlying socket.
 	pub fn try_clone(&self) -> io::Result<Self> {
 		let inner = self.listener.try_clone()?;
-		Ok(Server {
-			listener: inner,
-			ssl_acceptor: self.ssl_acceptor.clone(),
-		})
+		Ok(WsServer::from_parts(inner, self.ssl_acceptor.clone()).with_admission(self.admission()))
 	}
 }
 
@@ -278,6 +549,10 @@ impl Iterator for WsServer<NoTlsAcceptor, TcpListener> {
 }
 
 mod tests {
+	const HANDSHAKE: &'static [u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+	                                   Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+	                                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
 	#[test]
 	// test the set_nonblocking() method for Server<NoSslAcceptor>.
 	// Some of this is copied from
@@ -308,4 +583,204 @@ mod tests {
 		}
 
 	}
+
+	#[test]
+	fn accept_timeout_gives_up() {
+		use super::*;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		match server.accept_timeout(Duration::from_millis(30)) {
+			Err(InvalidConnection {
+				error: HyperIntoWsError::Io(ref e),
+				..
+			}) if e.kind() == io::ErrorKind::TimedOut => {}
+			_ => panic!("expected a timeout"),
+		}
+
+		// a connection that is waiting is taken right away
+		let client = TcpStream::connect(addr).unwrap();
+		let (stream, _) = accept_within(&server.listener, Duration::from_secs(5)).unwrap();
+		assert_eq!(stream.peer_addr().unwrap(), client.local_addr().unwrap());
+	}
+
+	#[test]
+	fn accept_with_custom_headers() {
+		use super::*;
+		use std::io::Read;
+		use std::thread;
+		use http::header::{HeaderMap, HeaderValue, CONNECTION, SET_COOKIE};
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let addr = server.local_addr().unwrap();
+		let client = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream
+				.write_all(
+					b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+					  Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+					  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+				)
+				.unwrap();
+			let mut response = Vec::new();
+			let mut buf = [0; 512];
+			while !response.ends_with(b"\r\n\r\n") {
+				let n = stream.read(&mut buf).unwrap();
+				assert!(n > 0, "connection closed before the end of the response");
+				response.extend_from_slice(&buf[..n]);
+			}
+			String::from_utf8(response).unwrap()
+		});
+
+		let upgrade = server.accept().ok().unwrap();
+		let mut headers = HeaderMap::new();
+		headers.append(SET_COOKIE, HeaderValue::from_static("a=1"));
+		headers.append(SET_COOKIE, HeaderValue::from_static("b=2"));
+		headers.insert(CONNECTION, HeaderValue::from_static("close"));
+		let _client = upgrade.accept_with(headers).ok().unwrap();
+
+		let response = client.join().unwrap();
+		assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+		assert!(response.contains("\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n"));
+		assert!(response.contains("\r\nConnection: Upgrade\r\n"));
+		assert!(!response.contains("close"));
+		assert!(response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+	}
+
+	#[test]
+	fn open_connections_count_against_the_limit() {
+		use super::*;
+		use server::ConnectionLimiter;
+
+		let limiter = ConnectionLimiter::new(Some(1), None);
+		let mut server = Server::bind("127.0.0.1:0").unwrap().with_connection_limiter(limiter.clone());
+		let addr = server.local_addr().unwrap();
+
+		let mut first = TcpStream::connect(addr).unwrap();
+		first.write_all(HANDSHAKE).unwrap();
+		let client = server.accept().ok().unwrap().accept().ok().unwrap();
+		assert_eq!(limiter.active(), 1);
+
+		// the first client is done with its handshake but still connected
+		let _second = TcpStream::connect(addr).unwrap();
+		match server.accept() {
+			Err(InvalidConnection {
+				error: HyperIntoWsError::TooManyConnections,
+				..
+			}) => {}
+			_ => panic!("expected the second connection to be refused"),
+		}
+
+		drop(client);
+		assert_eq!(limiter.active(), 0);
+		let mut third = TcpStream::connect(addr).unwrap();
+		third.write_all(HANDSHAKE).unwrap();
+		assert!(server.accept().is_ok());
+	}
+
+	#[test]
+	fn plain_http_requests_get_the_fallback_response() {
+		use super::*;
+		use std::io::Read;
+		use server::HttpFallback;
+
+		fn respond(server: &mut Server<NoTlsAcceptor>, request: &[u8]) -> String {
+			let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+			client.write_all(request).unwrap();
+			assert!(server.accept().is_err());
+			let mut response = String::new();
+			client.read_to_string(&mut response).unwrap();
+			response
+		}
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let response = respond(&mut server, b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert_eq!(response, "");
+
+		let mut server = server.with_http_fallback(HttpFallback::health_check("/healthz"));
+		let response = respond(&mut server, b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert!(response.ends_with("\r\n\r\nOK"));
+
+		let response = respond(&mut server, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+		assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+		assert!(response.contains("\r\nUpgrade: websocket\r\n"));
+	}
+
+	#[test]
+	fn requests_without_a_key_are_not_accepted() {
+		use super::*;
+		use std::io::Read;
+		use http::header::SEC_WEBSOCKET_KEY;
+
+		let mut server = Server::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+		client.write_all(HANDSHAKE).unwrap();
+
+		let mut upgrade = server.accept().ok().unwrap();
+		upgrade.request.headers.remove(SEC_WEBSOCKET_KEY);
+		match upgrade.accept() {
+			Err((_, ref e)) if e.kind() == io::ErrorKind::InvalidData => {}
+			_ => panic!("expected the upgrade to fail"),
+		}
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		assert!(response.is_empty());
+	}
+
+	#[test]
+	fn accepted_connections_get_the_tcp_options() {
+		use super::*;
+		use net2::TcpStreamExt;
+		use stream::TcpOptions;
+
+		let options = TcpOptions {
+			nodelay: Some(true),
+			keepalive: Some(Duration::from_secs(60)),
+			..TcpOptions::default()
+		};
+		let mut server = Server::bind("127.0.0.1:0").unwrap().with_tcp_options(options);
+		let addr = server.local_addr().unwrap();
+
+		let mut client = TcpStream::connect(addr).unwrap();
+		client.write_all(HANDSHAKE).unwrap();
+		let upgrade = server.accept().ok().unwrap();
+		assert!(upgrade.tcp_stream().nodelay().unwrap());
+		assert_eq!(
+			TcpStreamExt::keepalive(upgrade.tcp_stream()).unwrap(),
+			Some(Duration::from_secs(60))
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "sync-ssl")]
+	fn reloads_apply_to_a_pending_accept() {
+		use super::*;
+		use native_tls::{Certificate, Pkcs12, TlsConnector};
+
+		fn acceptor(pkcs12: &[u8]) -> TlsAcceptor {
+			let identity = Pkcs12::from_der(pkcs12, "websocket").unwrap();
+			TlsAcceptor::builder(identity).unwrap().build().unwrap()
+		}
+
+		let reloadable = ReloadableTlsAcceptor::new(acceptor(include_bytes!("testdata/old.p12")));
+		let mut server = Server::bind_secure_reloadable("127.0.0.1:0", reloadable.clone()).unwrap();
+		let addr = server.local_addr().unwrap();
+		let accepting = thread::spawn(move || server.accept().is_ok());
+
+		// give the server time to start waiting for a connection
+		thread::sleep(Duration::from_millis(100));
+		reloadable.reload(acceptor(include_bytes!("testdata/new.p12")));
+
+		// the client only trusts the new certificate
+		let mut builder = TlsConnector::builder().unwrap();
+		let new = Certificate::from_der(include_bytes!("testdata/new.der")).unwrap();
+		builder.add_root_certificate(new).unwrap();
+		let connector = builder.build().unwrap();
+		let stream = TcpStream::connect(addr).unwrap();
+		let mut stream = connector.connect("localhost", stream).ok().unwrap();
+		stream.write_all(HANDSHAKE).unwrap();
+
+		assert!(accepting.join().unwrap());
+	}
 }
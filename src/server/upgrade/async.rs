@@ -11,13 +11,20 @@ use tokio_io::codec::{Framed, FramedParts};
 use http::header::HeaderMap;
 use http::StatusCode;
 use stream::async::Stream;
-use futures::{Sink, Future};
+use futures::{Sink, Future, IntoFuture};
+use futures::future;
 use futures::Stream as StreamTrait;
 use futures::sink::Send;
-use codec::http::{RequestHead, HttpServerCodec};
+use codec::http::{RequestHead, HttpServerCodec, LimitedHttpServerCodec, HandshakeLimits};
 use codec::ws::{MessageCodec, Context};
 use bytes::BytesMut;
-use client::async::ClientNew;
+use client::async::{Client, ClientNew};
+use server::filter::Decision;
+use server::watchdog::Watched;
+use server::queue::WriteQueue;
+use server::ratelimit::RateLimited;
+use middleware::Middlewares;
+use result::WebSocketError;
 
 use codec::http::MessageHead;
 
@@ -80,14 +87,122 @@ where
 
 	/// Asynchronously accept the websocket handshake, then create a client.
 	/// This will asynchronously send a response accepting the connection
-	/// with custom headers in the response and create a websocket client.
+	/// with custom headers in the response, e.g. `Set-Cookie`, and create a
+	/// websocket client. The headers the handshake needs can't be overwritten
+	/// this way.
 	pub fn accept_with(self, custom_headers: HeaderMap) -> ClientNew<S> {
 		self.internal_accept(Some(custom_headers))
 	}
 
+	/// Asynchronously accept the websocket handshake like `accept`, then watch
+	/// the client with the server's `Watchdog` (or the default one if the
+	/// server has none).
+	pub fn accept_watched(
+		self,
+	) -> Box<
+		Future<Item = (Watched<Client<S>>, HeaderMap), Error = WebSocketError> + ::std::marker::Send,
+	> {
+		let watchdog = self.watchdog.unwrap_or_default();
+		let future = self.internal_accept(None)
+		                 .map(move |(client, headers)| (Watched::new(client, watchdog), headers));
+		Box::new(future)
+	}
+
+	/// Asynchronously accept the websocket handshake like `accept`, then
+	/// queue the messages sent to the client up to the server's limit (or
+	/// the default one if the server has none), see `WriteQueue`.
+	pub fn accept_queued(
+		self,
+	) -> Box<
+		Future<Item = (WriteQueue<Client<S>>, HeaderMap), Error = WebSocketError>
+			+ ::std::marker::Send,
+	> {
+		let limit = self.write_queue.unwrap_or_default();
+		let future = self.internal_accept(None)
+		                 .map(move |(client, headers)| (WriteQueue::new(client, limit), headers));
+		Box::new(future)
+	}
+
+	/// Asynchronously accept the websocket handshake like `accept`, then
+	/// limit how fast the client may send messages to the server's rate limit,
+	/// see `RateLimited`. Without a rate limit on the server the client is not
+	/// limited at all.
+	pub fn accept_limited(
+		self,
+	) -> Box<
+		Future<Item = (RateLimited<Client<S>>, HeaderMap), Error = WebSocketError>
+			+ ::std::marker::Send,
+	> {
+		let limit = self.rate_limit.unwrap_or_default();
+		let future = self.internal_accept(None)
+		                 .map(move |(client, headers)| (RateLimited::new(client, limit), headers));
+		Box::new(future)
+	}
+
+	/// Accept, reject or drop the connection once `decision` resolves, e.g.
+	/// after looking the client's credentials up in a database, without
+	/// blocking the reactor in the meantime.
+	///
+	/// The future resolves to `None` if the connection was rejected or dropped.
+	///
+	/// ```rust,no_run
+	/// # extern crate http;
+	/// # extern crate websocket;
+	/// # use websocket::async::futures::{future, Future};
+	/// # use websocket::server::upgrade::async::Upgrade;
+	/// # use websocket::async::TcpStream;
+	/// use http::StatusCode;
+	/// use websocket::server::Decision;
+	///
+	/// # fn main() {}
+	/// fn is_authorized(token: &str) -> Box<Future<Item = bool, Error = ()> + Send> {
+	///     // ask the database ...
+	///     # Box::new(future::ok(!token.is_empty()))
+	/// }
+	///
+	/// fn handle(upgrade: Upgrade<TcpStream>) {
+	///     let token = upgrade.request.subject.1.query().unwrap_or("").to_string();
+	///     let decision = is_authorized(&token)
+	///         .map(|authorized| if authorized {
+	///             Decision::Accept
+	///         } else {
+	///             Decision::Reject(StatusCode::UNAUTHORIZED)
+	///         })
+	///         .map_err(|()| websocket::WebSocketError::RequestError("authorization failed"));
+	///     let client = upgrade.accept_after(decision);
+	///     // run `client` on the reactor ...
+	/// }
+	/// ```
+	pub fn accept_after<F>(
+		self,
+		decision: F,
+	) -> Box<Future<Item = Option<(Client<S>, HeaderMap)>, Error = WebSocketError> + ::std::marker::Send>
+	where
+		F: IntoFuture<Item = Decision>,
+		F::Future: ::std::marker::Send + 'static,
+		F::Error: Into<WebSocketError>,
+	{
+		let future = decision.into_future()
+		                     .map_err(Into::<WebSocketError>::into)
+		                     .and_then(move |decision| match decision {
+			Decision::Accept => future::Either::A(self.accept().map(Some)),
+			Decision::Reject(status) => {
+				let rejection = self.reject_with_status(status, None)
+				                    .map(|_| None)
+				                    .map_err(WebSocketError::from);
+				future::Either::B(future::Either::A(rejection))
+			}
+			Decision::Drop => future::Either::B(future::Either::B(future::ok(None))),
+		});
+		Box::new(future)
+	}
+
 	fn internal_accept(mut self, custom_headers: Option<HeaderMap>) -> ClientNew<S> {
-		let status = self.prepare_headers(custom_headers);
-		let WsUpgrade { headers, stream, request, buffer } = self;
+		let status = match self.prepare_headers(custom_headers) {
+			Ok(status) => status,
+			Err(e) => return Box::new(future::err(WebSocketError::from(e))),
+		};
+		let WsUpgrade { headers, stream, request, buffer, middleware, permit, .. } = self;
 
 		let duplex = Framed::from_parts(
 			FramedParts {
@@ -104,7 +219,11 @@ where
 			headers: headers.clone(),
 		})
 		                   .map(move |s| {
-			let codec = MessageCodec::default(Context::Server);
+			#[cfg(feature = "metrics")]
+			::metrics::sink().handshake_succeeded();
+			let mut codec = MessageCodec::default(Context::Server);
+			codec.set_middleware(middleware);
+			codec.set_permit(permit);
 			let client = Framed::from_parts(s.into_parts(), codec);
 			(client, headers)
 		})
@@ -116,7 +235,7 @@ where
 	/// into it's original stream. The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject(self) -> Send<Framed<S, HttpServerCodec>> {
-		self.internal_reject(None)
+		self.internal_reject(StatusCode::BAD_REQUEST, None)
 	}
 
 	/// Asynchronously send a rejection message with custom headers and
@@ -124,10 +243,25 @@ where
 	///  The stream being returned is framed with the
 	/// `HttpServerCodec` since that was used to send the rejection message.
 	pub fn reject_with(self, headers: HeaderMap) -> Send<Framed<S, HttpServerCodec>> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(StatusCode::BAD_REQUEST, Some(headers))
 	}
 
-	fn internal_reject(mut self, headers: Option<HeaderMap>) -> Send<Framed<S, HttpServerCodec>> {
+	/// Asynchronously send a rejection message using a status other than
+	/// `400 Bad Request`, e.g. `403 Forbidden`, and deconstruct `self` into
+	/// it's original stream.
+	pub fn reject_with_status(
+		self,
+		status: StatusCode,
+		headers: Option<HeaderMap>,
+	) -> Send<Framed<S, HttpServerCodec>> {
+		self.internal_reject(status, headers)
+	}
+
+	fn internal_reject(
+		mut self,
+		status: StatusCode,
+		headers: Option<HeaderMap>,
+	) -> Send<Framed<S, HttpServerCodec>> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.into_iter());
 		}
@@ -141,7 +275,7 @@ where
 		);
 		duplex.send(MessageHead {
 			version: self.request.version,
-			subject: StatusCode::BAD_REQUEST,
+			subject: status,
 			headers: self.headers,
 		})
 	}
@@ -223,13 +357,33 @@ where
 	fn into_ws(
 		self,
 	) -> Box<Future<Item = Upgrade<Self::Stream>, Error = Self::Error> + ::std::marker::Send> {
-		let future = self.framed(HttpServerCodec)
-		                 .into_future()
-		                 .map_err(|(e, s)| {
+		into_ws_with_limits(self, &HandshakeLimits::default())
+	}
+}
+
+/// Read a websocket handshake off of a stream like `IntoWs::into_ws` does,
+/// but give up on clients whose handshake exceeds the given limits.
+///
+/// The `timeout` of the limits is not enforced here, wrap the returned future
+/// in a timer to do that. Servers take care of this themselves.
+pub fn into_ws_with_limits<S>(
+	stream: S,
+	limits: &HandshakeLimits,
+) -> Box<
+	Future<Item = Upgrade<S>, Error = (S, Option<RequestHead>, BytesMut, HyperIntoWsError)>
+		+ ::std::marker::Send,
+>
+where
+	S: Stream + ::std::marker::Send + 'static,
+{
+	let future = stream
+		.framed(LimitedHttpServerCodec::with_limits(*limits))
+		.into_future()
+		.map_err(|(e, s)| {
 			let FramedParts { inner, readbuf, .. } = s.into_parts();
 			(inner, None, readbuf, e.into())
 		})
-		                 .and_then(|(m, s)| {
+		.and_then(|(m, s)| {
 			let FramedParts { inner, readbuf, .. } = s.into_parts();
 			if let Some(msg) = m {
 				match validate(&msg.subject.0, &msg.version, &msg.headers) {
@@ -244,14 +398,120 @@ where
 				Err((inner, None, readbuf, err))
 			}
 		})
-		                 .map(|(m, stream, buffer)| {
+		.map(|(m, stream, buffer)| {
 			WsUpgrade {
 				headers: HeaderMap::new(),
 				stream: stream,
 				request: m,
 				buffer: buffer,
+				proxy: None,
+				permit: None,
+				watchdog: None,
+				middleware: Middlewares::default(),
+				write_queue: None,
+				rate_limit: None,
+				credentials: None,
 			}
 		});
-		Box::new(future)
+	Box::new(future)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Cursor, Write};
+	use std::sync::{Arc, Mutex};
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use futures::Poll;
+	use tokio_io::AsyncWrite;
+	use stream::ReadWritePair;
+
+	const HANDSHAKE: &'static [u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+	                                   Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+	                                   Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+
+	/// Collects what is written to it, so it can be looked at while the
+	/// upgrade owns the stream.
+	#[derive(Clone, Default)]
+	struct Output(Arc<Mutex<Vec<u8>>>);
+
+	impl Output {
+		fn contents(&self) -> Vec<u8> {
+			self.0.lock().unwrap().clone()
+		}
+	}
+
+	impl Write for Output {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl AsyncWrite for Output {
+		fn shutdown(&mut self) -> Poll<(), io::Error> {
+			Ok(().into())
+		}
+	}
+
+	fn upgrade(output: &Output) -> Upgrade<ReadWritePair<Cursor<&'static [u8]>, Output>> {
+		let stream = ReadWritePair(Cursor::new(HANDSHAKE), output.clone());
+		into_ws_with_limits(stream, &HandshakeLimits::default()).wait().ok().unwrap()
+	}
+
+	#[test]
+	fn accept_after_decides_before_answering() {
+		let output = Output::default();
+		let decided = Arc::new(AtomicBool::new(false));
+		let decision = {
+			let output = output.clone();
+			let decided = decided.clone();
+			future::lazy(move || {
+				assert!(output.contents().is_empty(), "answered before the decision");
+				decided.store(true, Ordering::SeqCst);
+				Ok::<_, WebSocketError>(Decision::Accept)
+			})
+		};
+
+		let accepted = upgrade(&output).accept_after(decision).wait().unwrap();
+		assert!(accepted.is_some());
+		assert!(decided.load(Ordering::SeqCst));
+		assert!(output.contents().starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+	}
+
+	#[test]
+	fn accept_after_rejects_or_drops() {
+		let output = Output::default();
+		let decision = Ok::<_, WebSocketError>(Decision::Reject(StatusCode::FORBIDDEN));
+		assert!(upgrade(&output).accept_after(decision).wait().unwrap().is_none());
+		assert!(output.contents().starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+
+		let output = Output::default();
+		let decision = Ok::<_, WebSocketError>(Decision::Drop);
+		assert!(upgrade(&output).accept_after(decision).wait().unwrap().is_none());
+		assert!(output.contents().is_empty());
+
+		let output = Output::default();
+		let decision = Err::<Decision, _>(WebSocketError::RequestError("not authorized"));
+		assert!(upgrade(&output).accept_after(decision).wait().is_err());
+		assert!(output.contents().is_empty());
+	}
+
+	#[test]
+	fn requests_without_a_key_are_not_accepted() {
+		use http::header::SEC_WEBSOCKET_KEY;
+
+		let output = Output::default();
+		let mut upgrade = upgrade(&output);
+		upgrade.request.headers.remove(SEC_WEBSOCKET_KEY);
+		match upgrade.accept().wait() {
+			Err(WebSocketError::IoError(ref e)) if e.kind() == ErrorKind::InvalidData => {}
+			_ => panic!("expected the upgrade to fail"),
+		}
+		assert!(output.contents().is_empty());
 	}
 }
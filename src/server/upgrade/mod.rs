@@ -5,11 +5,12 @@ use std::io;
 use std::iter::Iterator;
 use std::fmt::{self, Formatter, Display};
 use std::str::{self, FromStr};
+use std::net::{IpAddr, SocketAddr};
 use stream::Stream;
 
 use unicase::Ascii;
-use http::header::{HeaderMap, HeaderName, HeaderValue};
-use http::header::{CONNECTION, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_EXTENSIONS,
+use http::header::{HeaderMap, HeaderValue};
+use http::header::{CONNECTION, ORIGIN, SEC_WEBSOCKET_ACCEPT,
                    SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE};
 use http::{Method, StatusCode, Uri};
 
@@ -18,11 +19,21 @@ use http::{self, Version};
 use httparse;
 
 use codec;
-use codec::http::RequestHead;
-use header::{WebSocketAccept, WebSocketKey, WebSocketVersion};
+use codec::http::{RequestHead, HttpCodecError};
+use header::{HeaderMapExt, TypedHeader, WebSocketAccept, WebSocketExtensions, WebSocketKey,
+             WebSocketProtocol, WebSocketVersion};
 use header::connection::{Connection, ConnectionOption};
 use header::upgrade::{Protocol, ProtocolName, Upgrade};
 use header::sec_websocket_extensions::Extension;
+#[cfg(feature = "sync")]
+use header::wire;
+use server::proxy::ProxyHeader;
+use server::limit::ConnectionPermit;
+use server::watchdog::Watchdog;
+use middleware::Middlewares;
+use server::queue::WriteQueueLimit;
+use server::ratelimit::RateLimit;
+use server::forwarded::{self, ForwardedAddr, TrustProxy};
 
 #[cfg(feature = "async")]
 pub mod async;
@@ -53,6 +64,39 @@ where
 	pub request: RequestHead,
 	/// Some buffered data from the stream, if it exists.
 	pub buffer: B,
+	/// The PROXY protocol header the connection started with, if the server
+	/// was configured to expect one.
+	pub proxy: Option<ProxyHeader>,
+	/// The slot this connection takes up in the server's `ConnectionLimiter`.
+	/// It is handed over to the client on `accept`, which releases it when
+	/// the connection is dropped.
+	pub permit: Option<ConnectionPermit>,
+	/// The watchdog the server wants this connection watched with, if any.
+	/// Synchronous clients get it set on `accept`, asynchronous ones can use
+	/// `accept_watched`.
+	pub watchdog: Option<Watchdog>,
+	/// The middleware the server runs on its connections, it gets set on the
+	/// client on `accept`.
+	pub middleware: Middlewares,
+	/// The limit on bytes waiting to be written the server wants this
+	/// connection held to, asynchronous clients get it with `accept_queued`.
+	pub write_queue: Option<WriteQueueLimit>,
+	/// How fast the server lets this connection send, synchronous clients get
+	/// it set on `accept`, asynchronous ones can use `accept_limited`.
+	pub rate_limit: Option<RateLimit>,
+	/// Who is on the other end of a connection over a Unix socket, `None`
+	/// for other connections.
+	pub credentials: Option<PeerCredentials>,
+}
+
+/// The user and group of the process on the other end of a Unix socket, as
+/// the system tells them, see `UnixServer::bind_unix`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PeerCredentials {
+	/// The user id of the peer process.
+	pub uid: u32,
+	/// The group id of the peer process.
+	pub gid: u32,
 }
 
 impl<S, B> WsUpgrade<S, B>
@@ -62,10 +106,8 @@ where
 {
 	/// Select a protocol to use in the handshake response.
 	pub fn use_protocols(mut self, protocols: Vec<&str>) -> Self {
-		self.headers.insert(
-			"Sec-WebSocket-Protocol",
-			HeaderValue::from_str(&protocols.join(", ")).unwrap(),
-		);
+		let protocols = protocols.into_iter().map(String::from).collect();
+		self.headers.typed_insert(WebSocketProtocol(protocols));
 		self
 	}
 
@@ -74,12 +116,12 @@ where
 	where
 		I: IntoIterator<Item = Extension>,
 	{
-		//let mut extensions: Vec<Extension> = extensions.into_iter().collect().join(", ");
-		self.headers.insert("Sec-WebSocket-Extensions", HeaderValue::from_static(""));
-		/*upsert_header!(self.headers; WebSocketExtensions; {
-			Some(protos) => protos.0.append(&mut extensions),
-			None => WebSocketExtensions(extensions)
-		});*/
+		let mut extensions: Vec<Extension> = extensions.into_iter().collect();
+		if let Some(WebSocketExtensions(mut used)) = self.headers.typed_get() {
+			used.append(&mut extensions);
+			extensions = used;
+		}
+		self.headers.typed_insert(WebSocketExtensions(extensions));
 		self
 	}
 
@@ -93,39 +135,21 @@ where
 		self.request
 		    .headers
 		    .get(SEC_WEBSOCKET_PROTOCOL)
+		    .and_then(|e| str::from_utf8(e.as_bytes()).ok())
 		    .map(|e| {
-			str::from_utf8(e.as_bytes())
-				.unwrap()
-				.split(',')
-				.filter_map(|x| match x.trim() {
-					"" => None,
-					y => Some(y),
-				})
-				.collect::<Vec<&str>>()
+			e.split(',')
+			 .filter_map(|x| match x.trim() {
+				"" => None,
+				y => Some(y),
+			})
+			 .collect::<Vec<&str>>()
 		})
 		    .unwrap_or(vec![])
 	}
 
 	/// A list of extensions requested from the client.
 	pub fn extensions(&self) -> Vec<Extension> {
-		self.request
-		    .headers
-		    .get(SEC_WEBSOCKET_EXTENSIONS)
-		    .map(|e| {
-			str::from_utf8(e.as_bytes())
-				.unwrap()
-				.split(',')
-				.filter_map(|x| match x.trim() {
-					"" => None,
-					y => Some(y),
-				})
-				.filter_map(|x| match Extension::from_str(x) {
-					Ok(ext) => Some(ext),
-					_ => None,
-				})
-				.collect::<Vec<Extension>>()
-		})
-		    .unwrap_or(vec![])
+		self.typed_header::<WebSocketExtensions>().map(|e| e.0).unwrap_or(vec![])
 	}
 
 	/// The client's websocket accept key.
@@ -143,43 +167,71 @@ where
 	/// The client's websocket version.
 	pub fn version(&self) -> Option<WebSocketVersion> {
 		match self.request.headers.get("Sec-WebSocket-Version") {
-			Some(value) => Some(WebSocketVersion::from_str(value.to_str().unwrap()).unwrap()),
+			Some(value) => Some(parse_version(value)),
 			_ => None,
 		}
 	}
 
-	/// Origin of the client
+	/// A header of the request, `None` if it is missing or can't be parsed.
+	pub fn typed_header<H: TypedHeader>(&self) -> Option<H> {
+		self.request.headers.typed_get()
+	}
+
+	/// Origin of the client, `None` if it is not valid UTF-8.
 	pub fn origin(&self) -> Option<&str> {
-		self.request.headers.get("Origin").map(|o| str::from_utf8(o.as_ref()).unwrap())
+		self.request.headers.get("Origin").and_then(|o| str::from_utf8(o.as_ref()).ok())
+	}
+
+	/// The address of the real client as reported by a load balancer using
+	/// the PROXY protocol, if the server was configured to expect it.
+	pub fn proxied_addr(&self) -> Option<SocketAddr> {
+		self.proxy.and_then(|p| p.source())
+	}
+
+	/// The addresses this request was forwarded through according to its
+	/// `Forwarded`, `X-Forwarded-For` or `X-Real-IP` header, client first.
+	///
+	/// These headers can be sent by anyone, use `client_ip` to only believe
+	/// the proxies you trust.
+	pub fn forwarded_chain(&self) -> Vec<ForwardedAddr> {
+		forwarded::forwarded_chain(&self.request.headers)
+	}
+
+	/// The IP address of the client, taking the forwarding headers added by
+	/// trusted proxies into account. `peer` is the address of whoever connected
+	/// to the server (or `proxied_addr` when using the PROXY protocol).
+	pub fn client_ip(&self, peer: IpAddr, trust: &TrustProxy) -> Option<IpAddr> {
+		trust.client_ip(&self.forwarded_chain(), peer)
 	}
 
 	#[cfg(feature = "sync")]
 	fn send(&mut self, status: StatusCode) -> io::Result<()> {
-		write!(
-			&mut self.stream,
-			"{:?} {}\r\n",
-			self.request.version,
-			status
-		)?;
-		write!(&mut self.stream, "{:?}\r\n", self.headers)?;
-		Ok(())
+		let mut response = format!("{:?} {}\r\n", self.request.version, status).into_bytes();
+		wire::write_headers(&self.headers, &mut response);
+		response.extend_from_slice(b"\r\n");
+		self.stream.write_all(&response)
 	}
 
 	#[doc(hidden)]
-	pub fn prepare_headers(&mut self, custom: Option<HeaderMap>) -> StatusCode {
+	pub fn prepare_headers(&mut self, custom: Option<HeaderMap>) -> io::Result<StatusCode> {
 		if let Some(headers) = custom {
 			self.headers.extend(headers.into_iter());
 		}
-		// NOTE: we know there is a key because this is a valid request
-		// i.e. to construct this you must go through the validate function
-		let key = self.request.headers.get(SEC_WEBSOCKET_KEY).unwrap();
-		let key = WebSocketKey::from_str(key.to_str().unwrap()).unwrap();
-		self.headers.append(
-			HeaderName::from_bytes("Sec-WebSocket-Accept".as_bytes()).unwrap(),
-			WebSocketAccept::new(key).into(),
-		);
-		self.headers.append(
-			HeaderName::from_bytes("Connection".as_bytes()).unwrap(),
+		// requests are validated before they get here, but the request is
+		// public and could have been changed since
+		let key = match parse_key(&self.request.headers) {
+			Some(key) => key,
+			None => {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					"upgrade request without a valid key",
+				))
+			}
+		};
+		// the handshake's own headers win over custom ones of the same name
+		self.headers.insert(SEC_WEBSOCKET_ACCEPT, WebSocketAccept::new(key).into());
+		self.headers.insert(
+			CONNECTION,
 			Connection(vec![
 				ConnectionOption::ConnectionHeader(
 					Ascii::new("Upgrade".to_string())
@@ -187,12 +239,12 @@ where
 			])
 			.into(),
 		);
-		self.headers.append(
-			HeaderName::from_bytes("Upgrade".as_bytes()).unwrap(),
+		self.headers.insert(
+			UPGRADE,
 			Upgrade(vec![Protocol::new(ProtocolName::WebSocket, None)]).into(),
 		);
 
-		StatusCode::SWITCHING_PROTOCOLS
+		Ok(StatusCode::SWITCHING_PROTOCOLS)
 	}
 }
 
@@ -204,9 +256,10 @@ pub enum HyperIntoWsError {
 	MethodNotGet,
 	/// Currently HTTP 2 is not supported
 	UnsupportedHttpVersion,
-	/// Currently only WebSocket13 is supported (RFC6455)
-	UnsupportedWebsocketVersion,
-	/// A websocket upgrade request must contain a key
+	/// Currently only WebSocket13 is supported (RFC6455), holds the version
+	/// the client asked for
+	UnsupportedWebsocketVersion(WebSocketVersion),
+	/// A websocket upgrade request must contain a valid key
 	NoSecWsKeyHeader,
 	/// A websocket upgrade request must ask to upgrade to a `websocket`
 	NoWsUpgradeHeader,
@@ -216,6 +269,14 @@ pub enum HyperIntoWsError {
 	NoWsConnectionHeader,
 	/// A websocket upgrade request must contain a `Connection` header
 	NoConnectionHeader,
+	/// The request's `Origin` was refused by the server's `OriginPolicy`
+	OriginNotAllowed,
+	/// The client did not finish sending its handshake in time
+	HandshakeTimeout,
+	/// The server's `ConnectionLimiter` did not let the connection through
+	TooManyConnections,
+	/// The server's `AcceptFilter` rejected or dropped the connection
+	Filtered,
 	/// IO error from reading the underlying socket
 	Io(io::Error),
 	///
@@ -224,7 +285,12 @@ pub enum HyperIntoWsError {
 
 impl Display for HyperIntoWsError {
 	fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
-		fmt.write_str(self.description())
+		match *self {
+			HyperIntoWsError::UnsupportedWebsocketVersion(ref version) => {
+				write!(fmt, "{}: {}", self.description(), version)
+			}
+			_ => fmt.write_str(self.description()),
+		}
 	}
 }
 
@@ -234,12 +300,16 @@ impl Error for HyperIntoWsError {
 		match *self {
 			MethodNotGet => "Request method must be GET",
 			UnsupportedHttpVersion => "Unsupported request HTTP version",
-			UnsupportedWebsocketVersion => "Unsupported WebSocket version",
+			UnsupportedWebsocketVersion(_) => "Unsupported WebSocket version",
 			NoSecWsKeyHeader => "Missing Sec-WebSocket-Key header",
 			NoWsUpgradeHeader => "Invalid Upgrade WebSocket header",
 			NoUpgradeHeader => "Missing Upgrade WebSocket header",
 			NoWsConnectionHeader => "Invalid Connection WebSocket header",
 			NoConnectionHeader => "Missing Connection WebSocket header",
+			OriginNotAllowed => "Origin not allowed by server policy",
+			TooManyConnections => "Too many connections",
+			Filtered => "Connection refused by the server's accept filter",
+			HandshakeTimeout => "Timed out waiting for the handshake",
 			Io(ref e) => e.description(),
 			Http(ref e) => e.description(),
 		}
@@ -254,6 +324,55 @@ impl Error for HyperIntoWsError {
 	}
 }
 
+impl HyperIntoWsError {
+	/// The status a server should answer with before dropping a client which
+	/// caused this error, if the client should be answered at all.
+	///
+	/// A `426 Upgrade Required` has to carry a `Sec-WebSocket-Version` header
+	/// with the supported version, see `bare_response`.
+	pub fn response_status(&self) -> Option<StatusCode> {
+		match *self {
+			HyperIntoWsError::UnsupportedWebsocketVersion(_) => Some(StatusCode::UPGRADE_REQUIRED),
+			HyperIntoWsError::HandshakeTimeout => Some(StatusCode::REQUEST_TIMEOUT),
+			HyperIntoWsError::Http(HttpCodecError::TooLarge) => {
+				Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+			}
+			HyperIntoWsError::Http(HttpCodecError::RequestLineTooLong) => {
+				Some(StatusCode::URI_TOO_LONG)
+			}
+			_ => None,
+		}
+	}
+
+	/// Whether the error means the client sent a plain HTTP request rather
+	/// than a websocket handshake, e.g. a browser or a health check.
+	pub fn is_plain_http(&self) -> bool {
+		use self::HyperIntoWsError::*;
+		match *self {
+			MethodNotGet | NoSecWsKeyHeader | NoWsUpgradeHeader | NoUpgradeHeader |
+			NoWsConnectionHeader | NoConnectionHeader => true,
+			_ => false,
+		}
+	}
+}
+
+/// A bare response with the given status which closes the connection,
+/// used to answer clients whose handshake could not be read.
+///
+/// `426 Upgrade Required` tells the client which websocket version to use.
+pub fn bare_response(status: StatusCode) -> Vec<u8> {
+	let version = if status == StatusCode::UPGRADE_REQUIRED {
+		"Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n"
+	} else {
+		""
+	};
+	format!(
+		"HTTP/1.1 {}\r\n{}Connection: close\r\nContent-Length: 0\r\n\r\n",
+		status,
+		version
+	).into_bytes()
+}
+
 impl From<io::Error> for HyperIntoWsError {
 	fn from(err: io::Error) -> Self {
 		HyperIntoWsError::Io(err)
@@ -276,6 +395,25 @@ impl From<::codec::http::HttpCodecError> for HyperIntoWsError {
 	}
 }
 
+#[cfg(any(feature = "sync", feature = "async"))]
+/// Read a `Sec-WebSocket-Version` header, which may hold anything a client
+/// cares to send.
+fn parse_version(value: &HeaderValue) -> WebSocketVersion {
+	match String::from_utf8_lossy(value.as_bytes()).trim() {
+		"13" => WebSocketVersion::WebSocket13,
+		other => WebSocketVersion::Unknown(other.to_string()),
+	}
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+/// The `Sec-WebSocket-Key` of a request, if it has a valid one.
+fn parse_key(headers: &HeaderMap) -> Option<WebSocketKey> {
+	headers
+		.get(SEC_WEBSOCKET_KEY)
+		.and_then(|key| key.to_str().ok())
+		.and_then(|key| WebSocketKey::from_str(key).ok())
+}
+
 #[cfg(any(feature = "sync", feature = "async"))]
 /// Check whether an incoming request is a valid WebSocket upgrade attempt.
 pub fn validate(
@@ -292,20 +430,18 @@ pub fn validate(
 		return Err(HyperIntoWsError::UnsupportedHttpVersion);
 	}
 
-	if let Some(version) = headers.get(SEC_WEBSOCKET_VERSION).map(|v| {
-		v.to_str().unwrap().parse::<WebSocketVersion>().unwrap()
-	})
-	{
+	if let Some(version) = headers.get(SEC_WEBSOCKET_VERSION).map(parse_version) {
 		if version != WebSocketVersion::WebSocket13 {
-			return Err(HyperIntoWsError::UnsupportedWebsocketVersion);
+			return Err(HyperIntoWsError::UnsupportedWebsocketVersion(version));
 		}
 	}
 
-	if headers.get(SEC_WEBSOCKET_KEY).is_none() {
+	if parse_key(headers).is_none() {
 		return Err(HyperIntoWsError::NoSecWsKeyHeader);
 	}
 
-	match headers.get(UPGRADE).map(|v| v.to_str().unwrap().parse().unwrap()) {
+	// values that aren't text are as good as missing
+	match headers.get(UPGRADE).and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()) {
 		Some(Upgrade(ref upgrade)) => {
 			if upgrade.iter().all(|u| u.name != ProtocolName::WebSocket) {
 				return Err(HyperIntoWsError::NoWsUpgradeHeader);
@@ -325,7 +461,7 @@ pub fn validate(
 		false
 	}
 
-	match headers.get(CONNECTION).map(|v| v.to_str().unwrap().parse().unwrap()) {
+	match headers.get(CONNECTION).and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()) {
 		Some(Connection(ref connection)) => {
 			if !check_connection_header(connection) {
 				return Err(HyperIntoWsError::NoWsConnectionHeader);
@@ -336,3 +472,39 @@ pub fn validate(
 
 	Ok(())
 }
+
+#[cfg(all(test, any(feature = "sync", feature = "async")))]
+mod tests {
+	use super::*;
+
+	fn request(extra: &[(&str, &[u8])]) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="));
+		headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+		headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+		for &(name, value) in extra {
+			let name: ::http::header::HeaderName = name.parse().unwrap();
+			headers.insert(name, HeaderValue::from_bytes(value).unwrap());
+		}
+		headers
+	}
+
+	#[test]
+	fn malformed_requests_are_errors() {
+		let check = |headers: &HeaderMap| validate(&Method::GET, &Version::HTTP_11, headers);
+		assert!(check(&request(&[])).is_ok());
+
+		let cases: Vec<(&str, &[u8])> = vec![
+			("sec-websocket-key", b"not base64"),
+			("sec-websocket-key", b"c2hvcnQ="),
+			("sec-websocket-key", b"\xff\xfe"),
+			("upgrade", b"\xffwebsocket"),
+			("upgrade", b"/"),
+			("connection", b"\xe9t\xe9"),
+			("sec-websocket-version", b"\xff"),
+		];
+		for (name, value) in cases {
+			assert!(check(&request(&[(name, value)])).is_err(), "{}: {:?}", name, value);
+		}
+	}
+}
@@ -1,17 +1,20 @@
 //! Allows you to take an existing request or stream of data and convert it into a
 //! WebSocket client.
-use std::io::{self, BufRead};
+use std::io::{self, Read};
 use std::net::TcpStream;
+use std::time::Instant;
 
 use client::sync::Client;
-use codec::http::{RequestHead, RequestLine};
+use codec::http::{RequestHead, LimitedHttpServerCodec, HandshakeLimits};
 use server::upgrade::{WsUpgrade, HyperIntoWsError, validate};
+use middleware::Middlewares;
 use stream::sync::{Stream, AsTcpStream};
 
 use std::io::BufReader;
-use http::{self, StatusCode};
+use http::StatusCode;
 use http::header::HeaderMap;
-use httparse;
+use bytes::BytesMut;
+use tokio_io::codec::Decoder;
 
 /// This crate uses buffered readers to read in the handshake quickly, in order to
 /// interface with other use cases that don't use buffered readers the buffered readers
@@ -55,43 +58,81 @@ where
 	}
 
 	/// Accept the handshake request and send a response while
-	/// adding on a few headers. These headers are added before the required
-	/// headers are, so some might be overwritten.
+	/// adding on a few headers, e.g. `Set-Cookie`. The headers the handshake
+	/// needs (`Upgrade`, `Connection` and `Sec-WebSocket-Accept`) can't be
+	/// overwritten this way.
+	///
+	/// ```rust,no_run
+	/// # extern crate http;
+	/// # extern crate websocket;
+	/// # fn main() {
+	/// use http::header::{HeaderMap, HeaderValue, SET_COOKIE};
+	/// use websocket::sync::Server;
+	///
+	/// let mut server = Server::bind("127.0.0.1:1234").unwrap();
+	/// let upgrade = server.accept().ok().unwrap();
+	///
+	/// let mut headers = HeaderMap::new();
+	/// headers.insert(SET_COOKIE, HeaderValue::from_static("session=abc; HttpOnly"));
+	/// headers.insert("x-request-id", HeaderValue::from_static("42"));
+	/// let client = upgrade.accept_with(headers).unwrap();
+	/// # }
+	/// ```
 	pub fn accept_with(self, custom_headers: HeaderMap) -> Result<Client<S>, (S, io::Error)> {
 		self.internal_accept(Some(custom_headers))
 	}
 
 	fn internal_accept(mut self, headers: Option<HeaderMap>) -> Result<Client<S>, (S, io::Error)> {
-		let status = self.prepare_headers(headers);
+		let status = match self.prepare_headers(headers) {
+			Ok(status) => status,
+			Err(e) => return Err((self.stream, e)),
+		};
 
 		if let Err(e) = self.send(status) {
 			return Err((self.stream, e));
 		}
 
-		Ok(Client::unchecked(
-			BufReader::new(self.stream),
-			self.headers,
-			false,
-			true,
-		))
+		#[cfg(feature = "metrics")]
+		::metrics::sink().handshake_succeeded();
+
+		let mut client = Client::unchecked(BufReader::new(self.stream), self.headers, false, true);
+		client.set_watchdog(self.watchdog);
+		client.set_middleware(self.middleware);
+		client.set_rate_limit(self.rate_limit);
+		client.set_permit(self.permit);
+		Ok(client)
 	}
 
 	/// Reject the client's request to make a websocket connection.
 	pub fn reject(self) -> Result<S, (S, io::Error)> {
-		self.internal_reject(None)
+		self.internal_reject(StatusCode::BAD_REQUEST, None)
 	}
 
 	/// Reject the client's request to make a websocket connection
 	/// and send extra headers.
 	pub fn reject_with(self, headers: HeaderMap) -> Result<S, (S, io::Error)> {
-		self.internal_reject(Some(headers))
+		self.internal_reject(StatusCode::BAD_REQUEST, Some(headers))
 	}
 
-	fn internal_reject(mut self, headers: Option<HeaderMap>) -> Result<S, (S, io::Error)> {
+	/// Reject the client's request to make a websocket connection
+	/// using a status other than `400 Bad Request`, e.g. `403 Forbidden`.
+	pub fn reject_with_status(
+		self,
+		status: StatusCode,
+		headers: Option<HeaderMap>,
+	) -> Result<S, (S, io::Error)> {
+		self.internal_reject(status, headers)
+	}
+
+	fn internal_reject(
+		mut self,
+		status: StatusCode,
+		headers: Option<HeaderMap>,
+	) -> Result<S, (S, io::Error)> {
 		if let Some(custom) = headers {
 			self.headers.extend(custom.into_iter());
 		}
-		match self.send(StatusCode::BAD_REQUEST) {
+		match self.send(status) {
 			Ok(()) => Ok(self.stream),
 			Err(e) => Err((self.stream, e)),
 		}
@@ -164,73 +205,89 @@ where
 	type Error = (S, Option<RequestHead>, Option<Buffer>, HyperIntoWsError);
 
 	fn into_ws(self) -> Result<Upgrade<Self::Stream>, Self::Error> {
+		into_ws_with_limits(self, &HandshakeLimits::default())
+	}
+}
 
-		let mut buf = String::new();
-		let mut reader = BufReader::new(self);
-		reader.read_line(&mut buf);
-
-		// cleanup
-		let buf2 = buf.clone();
-
-		let mut parse = httparse::Request::new(&mut []);
-
-		let stream = reader.into_inner();
-
-		match parse.parse(buf2.as_bytes()) {
-			Ok(o) => {
-				match o {
-					httparse::Status::Complete(_) => {}
-					_ => return Err((stream, None, None, ::httparse::Error::HeaderValue.into())),
-				}
-			}
-			Err(e) => return Err((stream, None, None, e.into())),
+/// Read a websocket handshake off of a stream like `IntoWs::into_ws` does,
+/// but give up on clients whose handshake exceeds the given limits.
+///
+/// The `timeout` of the limits is only checked between reads, to stop a client
+/// from stalling a single read the stream needs a read timeout as well
+/// (e.g. `TcpStream::set_read_timeout`). Servers take care of this themselves.
+pub fn into_ws_with_limits<S>(
+	mut stream: S,
+	limits: &HandshakeLimits,
+) -> Result<Upgrade<S>, (S, Option<RequestHead>, Option<Buffer>, HyperIntoWsError)>
+where
+	S: Stream + Send,
+{
+	let deadline = limits.timeout.map(|t| Instant::now() + t);
+	let mut codec = LimitedHttpServerCodec::with_limits(*limits);
+	let mut buf = BytesMut::with_capacity(1024);
+	let mut chunk = [0u8; 1024];
+
+	let request = loop {
+		match codec.decode(&mut buf) {
+			Ok(Some(request)) => break request,
+			Ok(None) => {}
+			Err(e) => return Err((stream, None, leftover(buf), e.into())),
 		}
 
-		let cap = buf.len();
-		let pos = buf.len();
-		let buffer = Some(Buffer {
-			buf: buf.into_bytes(),
-			cap: cap,
-			pos: pos,
-		});
-
-		let request = RequestHead {
-			version: match parse.version {
-				Some(0) => http::Version::HTTP_10,
-				Some(1) => http::Version::HTTP_11,
-				Some(_) | None => {
-					return Err((stream, None, buffer, httparse::Error::Version.into()))
-				}
-			},
-			subject: RequestLine(
-				match parse.method.unwrap().parse() {
-					Ok(method) => method,
-					Err(e) => {
-						return Err((stream, None, buffer, httparse::Error::HeaderValue.into()))
-					}
-				},
-				match parse.path.unwrap().parse() {
-					Ok(path) => path,
-					Err(e) => {
-						return Err((stream, None, buffer, httparse::Error::HeaderValue.into()))
-					}
-				},
-			),
-			headers: HeaderMap::new(),
-		};
+		if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+			return Err((stream, None, leftover(buf), HyperIntoWsError::HandshakeTimeout));
+		}
 
-		match validate(&request.subject.0, &request.version, &request.headers) {
-			Ok(_) => {
-				Ok(WsUpgrade {
-					headers: HeaderMap::new(),
-					stream: stream,
-					request: request,
-					buffer: buffer,
-				})
+		match stream.read(&mut chunk) {
+			Ok(0) => {
+				let err = io::Error::new(
+					io::ErrorKind::UnexpectedEof,
+					"Connection dropped before handshake could be read",
+				);
+				return Err((stream, None, leftover(buf), err.into()));
 			}
-			Err(e) => Err((stream, Some(request), buffer, e)),
+			Ok(n) => buf.extend_from_slice(&chunk[..n]),
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(ref e) if deadline.is_some() &&
+			              (e.kind() == io::ErrorKind::WouldBlock ||
+			               e.kind() == io::ErrorKind::TimedOut) => {
+				return Err((stream, None, leftover(buf), HyperIntoWsError::HandshakeTimeout));
+			}
+			Err(e) => return Err((stream, None, leftover(buf), e.into())),
+		}
+	};
+
+	let buffer = leftover(buf);
+	match validate(&request.subject.0, &request.version, &request.headers) {
+		Ok(_) => {
+			Ok(WsUpgrade {
+				headers: HeaderMap::new(),
+				stream: stream,
+				request: request,
+				buffer: buffer,
+				proxy: None,
+				permit: None,
+				watchdog: None,
+				middleware: Middlewares::default(),
+				write_queue: None,
+				rate_limit: None,
+				credentials: None,
+			})
 		}
+		Err(e) => Err((stream, Some(request), buffer, e)),
+	}
+}
+
+fn leftover(buf: BytesMut) -> Option<Buffer> {
+	if buf.is_empty() {
+		return None;
 	}
+	let cap = buf.len();
+	Some(Buffer {
+		buf: buf.to_vec(),
+		pos: 0,
+		cap: cap,
+	})
 }
 
 impl<S> IntoWs for RequestStreamPair<S>
@@ -248,6 +305,13 @@ where
 					stream: self.0,
 					request: self.1,
 					buffer: None,
+					proxy: None,
+					permit: None,
+					watchdog: None,
+					middleware: Middlewares::default(),
+					write_queue: None,
+					rate_limit: None,
+					credentials: None,
 				})
 			}
 			Err(e) => Err((self.0, self.1, e)),
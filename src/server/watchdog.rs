@@ -0,0 +1,280 @@
+//! Detecting and getting rid of connections whose other end went away.
+//!
+//! A peer that disappears without closing its TCP connection (pulled cable,
+//! crashed NAT, sleeping laptop) looks exactly like a peer with nothing to say.
+//! A `Watchdog` pings connections that have been quiet for a while and closes
+//! them once too many pings go unanswered or they stay silent for too long.
+use std::cmp;
+use std::io;
+use std::time::{Duration, Instant};
+
+use message::OwnedMessage;
+use result::WebSocketError;
+
+#[cfg(feature = "async")]
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+#[cfg(feature = "async")]
+use tokio::timer::Delay;
+
+/// Settings for watching a connection, see the module documentation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchdog {
+	/// How long a connection may be quiet before it is pinged.
+	pub ping_interval: Duration,
+	/// How many pings in a row may go unanswered before the connection is closed.
+	pub max_missed_pongs: u32,
+	/// Close connections that did not send anything for this long, even if
+	/// they are not done missing pongs.
+	pub idle_timeout: Option<Duration>,
+}
+
+impl Default for Watchdog {
+	fn default() -> Self {
+		Watchdog {
+			ping_interval: Duration::from_secs(30),
+			max_missed_pongs: 2,
+			idle_timeout: None,
+		}
+	}
+}
+
+impl Watchdog {
+	/// Start watching a connection that was just opened.
+	pub fn start(self) -> WatchdogState {
+		let now = Instant::now();
+		WatchdogState {
+			config: self,
+			last_seen: now,
+			last_ping: now,
+			missed: 0,
+		}
+	}
+}
+
+/// What a watchdog wants done with the connection it is watching.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+	/// Nothing to do for now.
+	Wait,
+	/// Send a ping to the peer.
+	Ping,
+	/// The peer is gone, close the connection.
+	Close,
+}
+
+/// Keeps track of a single connection on behalf of a `Watchdog`.
+///
+/// Feed it every message received with `saw` and call `check` whenever
+/// `next_check` comes around.
+#[derive(Clone, Debug)]
+pub struct WatchdogState {
+	config: Watchdog,
+	last_seen: Instant,
+	last_ping: Instant,
+	missed: u32,
+}
+
+impl WatchdogState {
+	/// The settings this connection is watched with.
+	pub fn config(&self) -> &Watchdog {
+		&self.config
+	}
+
+	/// Record a message from the peer, any message at all shows it is still there.
+	pub fn saw(&mut self, _message: &OwnedMessage) {
+		self.last_seen = Instant::now();
+		self.missed = 0;
+	}
+
+	/// The moment `check` has something to decide.
+	pub fn next_check(&self) -> Instant {
+		let ping_at = cmp::max(self.last_seen, self.last_ping) + self.config.ping_interval;
+		match self.config.idle_timeout {
+			Some(timeout) => cmp::min(ping_at, self.last_seen + timeout),
+			None => ping_at,
+		}
+	}
+
+	/// Decide what should happen to the connection at `now`.
+	pub fn check(&mut self, now: Instant) -> Verdict {
+		if let Some(timeout) = self.config.idle_timeout {
+			if now >= self.last_seen + timeout {
+				return Verdict::Close;
+			}
+		}
+		if now < cmp::max(self.last_seen, self.last_ping) + self.config.ping_interval {
+			return Verdict::Wait;
+		}
+		if self.missed >= self.config.max_missed_pongs {
+			return Verdict::Close;
+		}
+		self.missed += 1;
+		self.last_ping = now;
+		Verdict::Ping
+	}
+}
+
+/// The error a connection fails with when its watchdog closes it.
+pub fn timed_out() -> WebSocketError {
+	debug!("closing connection, the peer stopped responding");
+	WebSocketError::IoError(io::Error::new(
+		io::ErrorKind::TimedOut,
+		"Peer stopped responding to pings",
+	))
+}
+
+/// An asynchronous client watched by a `Watchdog`.
+///
+/// This is a drop-in replacement for the client it wraps, the stream of
+/// messages fails with a `TimedOut` error once the watchdog gives up on
+/// the peer.
+#[cfg(feature = "async")]
+pub struct Watched<C> {
+	inner: C,
+	state: WatchdogState,
+	timer: Delay,
+	ping_pending: bool,
+}
+
+#[cfg(feature = "async")]
+impl<C> Watched<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	/// Start watching a client.
+	pub fn new(inner: C, watchdog: Watchdog) -> Self {
+		let state = watchdog.start();
+		let timer = Delay::new(state.next_check());
+		Watched {
+			inner: inner,
+			state: state,
+			timer: timer,
+			ping_pending: false,
+		}
+	}
+
+	/// Get a reference to the watched client.
+	pub fn get_ref(&self) -> &C {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the watched client.
+	pub fn get_mut(&mut self) -> &mut C {
+		&mut self.inner
+	}
+
+	/// Stop watching the client and give it back.
+	pub fn into_inner(self) -> C {
+		self.inner
+	}
+
+	fn send_ping(&mut self) -> Result<(), WebSocketError> {
+		if !self.ping_pending {
+			return Ok(());
+		}
+		if let AsyncSink::Ready = self.inner.start_send(OwnedMessage::Ping(Vec::new()))? {
+			self.ping_pending = false;
+			self.inner.poll_complete()?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Stream for Watched<C>
+where
+	C: Stream<Item = OwnedMessage, Error = WebSocketError>
+		+ Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type Item = OwnedMessage;
+	type Error = WebSocketError;
+
+	fn poll(&mut self) -> Poll<Option<OwnedMessage>, WebSocketError> {
+		self.send_ping()?;
+
+		if let Async::Ready(message) = self.inner.poll()? {
+			if let Some(ref message) = message {
+				self.state.saw(message);
+				self.timer.reset(self.state.next_check());
+			}
+			return Ok(Async::Ready(message));
+		}
+
+		loop {
+			match self.timer.poll() {
+				Ok(Async::NotReady) => return Ok(Async::NotReady),
+				Ok(Async::Ready(())) => {}
+				Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e).into()),
+			}
+			match self.state.check(Instant::now()) {
+				Verdict::Wait => {}
+				Verdict::Ping => {
+					self.ping_pending = true;
+					self.send_ping()?;
+				}
+				Verdict::Close => return Err(timed_out()),
+			}
+			self.timer.reset(self.state.next_check());
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl<C> Sink for Watched<C>
+where
+	C: Sink<SinkItem = OwnedMessage, SinkError = WebSocketError>,
+{
+	type SinkItem = OwnedMessage;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: OwnedMessage) -> StartSend<OwnedMessage, WebSocketError> {
+		self.inner.start_send(item)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.poll_complete()
+	}
+
+	fn close(&mut self) -> Poll<(), WebSocketError> {
+		self.inner.close()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pings_then_gives_up() {
+		let watchdog = Watchdog {
+			ping_interval: Duration::from_secs(10),
+			max_missed_pongs: 1,
+			idle_timeout: None,
+		};
+		let mut state = watchdog.start();
+		let start = state.last_seen;
+
+		assert_eq!(state.check(start + Duration::from_secs(5)), Verdict::Wait);
+		assert_eq!(state.check(start + Duration::from_secs(10)), Verdict::Ping);
+		assert_eq!(state.check(start + Duration::from_secs(15)), Verdict::Wait);
+		assert_eq!(state.next_check(), start + Duration::from_secs(20));
+		assert_eq!(state.check(start + Duration::from_secs(20)), Verdict::Close);
+	}
+
+	#[test]
+	fn closes_idle_connections() {
+		let watchdog = Watchdog {
+			ping_interval: Duration::from_secs(10),
+			max_missed_pongs: 100,
+			idle_timeout: Some(Duration::from_secs(25)),
+		};
+		let mut state = watchdog.start();
+		let start = state.last_seen;
+
+		assert_eq!(state.check(start + Duration::from_secs(10)), Verdict::Ping);
+		assert_eq!(state.check(start + Duration::from_secs(20)), Verdict::Ping);
+		assert_eq!(state.next_check(), start + Duration::from_secs(25));
+		assert_eq!(state.check(start + Duration::from_secs(25)), Verdict::Close);
+	}
+}
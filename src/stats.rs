@@ -0,0 +1,203 @@
+//! Counting what goes over a connection.
+//!
+//! A `ConnectionStats` set on a client or codec counts the messages, bytes
+//! and pings sent and received and remembers when the connection was last
+//! active, so an application can find idle connections or show numbers on a
+//! dashboard without wrapping every send and receive. Only the payload of
+//! text and binary messages counts as bytes, control frames are left out.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use websocket::ClientBuilder;
+//! use websocket::stats::ConnectionStats;
+//!
+//! let stats = ConnectionStats::new();
+//! let _client = ClientBuilder::new("ws://127.0.0.1:1234")
+//!     .unwrap()
+//!     .stats(stats.clone())
+//!     .connect_insecure()
+//!     .unwrap();
+//!
+//! // ... later, from anywhere
+//! if stats.idle_for().map_or(false, |idle| idle > Duration::from_secs(60)) {
+//!     println!("{} messages in, then nothing for a minute", stats.messages_received());
+//! }
+//! ```
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dataframe::{DataFrame, Opcode};
+use middleware::{Direction, Middleware};
+use result::WebSocketResult;
+use server::queue::QueueDepth;
+
+/// The counters of a connection, see the module docs.
+///
+/// Cloning is cheap, all clones share the same counters, so a clone can be
+/// kept to read them while the connection is in use.
+#[derive(Clone, Default)]
+pub struct ConnectionStats {
+	inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+	messages_sent: AtomicUsize,
+	messages_received: AtomicUsize,
+	bytes_sent: AtomicUsize,
+	bytes_received: AtomicUsize,
+	pings_sent: AtomicUsize,
+	pings_received: AtomicUsize,
+	last_activity: Mutex<Option<Instant>>,
+	queue: Mutex<Option<QueueDepth>>,
+}
+
+impl ConnectionStats {
+	/// Counters of a connection that sent and received nothing yet.
+	pub fn new() -> Self {
+		ConnectionStats::default()
+	}
+
+	/// The number of messages sent, a message sent in fragments counts once.
+	pub fn messages_sent(&self) -> usize {
+		self.inner.messages_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of messages received, a message received in fragments
+	/// counts once.
+	pub fn messages_received(&self) -> usize {
+		self.inner.messages_received.load(Ordering::Relaxed)
+	}
+
+	/// The bytes of text and binary payload sent.
+	pub fn bytes_sent(&self) -> usize {
+		self.inner.bytes_sent.load(Ordering::Relaxed)
+	}
+
+	/// The bytes of text and binary payload received.
+	pub fn bytes_received(&self) -> usize {
+		self.inner.bytes_received.load(Ordering::Relaxed)
+	}
+
+	/// The number of pings sent.
+	pub fn pings_sent(&self) -> usize {
+		self.inner.pings_sent.load(Ordering::Relaxed)
+	}
+
+	/// The number of pings received.
+	pub fn pings_received(&self) -> usize {
+		self.inner.pings_received.load(Ordering::Relaxed)
+	}
+
+	/// When the last frame was sent or received, `None` if there was none.
+	pub fn last_activity(&self) -> Option<Instant> {
+		*lock(&self.inner.last_activity)
+	}
+
+	/// How long ago the last frame was sent or received, `None` if there was
+	/// none.
+	pub fn idle_for(&self) -> Option<Duration> {
+		self.last_activity().map(|at| at.elapsed())
+	}
+
+	/// The bytes waiting to be written, as counted by the queue given to
+	/// `track_queue`. Always 0 without one, the synchronous client writes
+	/// right away.
+	pub fn queue_depth(&self) -> usize {
+		lock(&self.inner.queue).as_ref().map_or(0, QueueDepth::bytes)
+	}
+
+	/// Report the depth of a `WriteQueue` as the queue depth of this
+	/// connection, see `WriteQueue::depth`.
+	pub fn track_queue(&self, depth: QueueDepth) {
+		*lock(&self.inner.queue) = Some(depth);
+	}
+
+	fn count(&self, direction: Direction, frame: &DataFrame) {
+		let (messages, bytes, pings) = match direction {
+			Direction::Outbound => {
+				(&self.inner.messages_sent, &self.inner.bytes_sent, &self.inner.pings_sent)
+			}
+			Direction::Inbound => (
+				&self.inner.messages_received,
+				&self.inner.bytes_received,
+				&self.inner.pings_received,
+			),
+		};
+		match frame.opcode {
+			Opcode::Continuation | Opcode::Text | Opcode::Binary => {
+				bytes.fetch_add(frame.data.len(), Ordering::Relaxed);
+				if frame.finished {
+					messages.fetch_add(1, Ordering::Relaxed);
+				}
+			}
+			Opcode::Ping => {
+				pings.fetch_add(1, Ordering::Relaxed);
+			}
+			_ => {}
+		}
+		*lock(&self.inner.last_activity) = Some(Instant::now());
+	}
+}
+
+impl Middleware for ConnectionStats {
+	fn on_frame(&self, direction: Direction, frame: &mut DataFrame) -> WebSocketResult<()> {
+		self.count(direction, frame);
+		Ok(())
+	}
+}
+
+impl fmt::Debug for ConnectionStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ConnectionStats")
+			.field("messages_sent", &self.messages_sent())
+			.field("messages_received", &self.messages_received())
+			.field("bytes_sent", &self.bytes_sent())
+			.field("bytes_received", &self.bytes_received())
+			.field("pings_sent", &self.pings_sent())
+			.field("pings_received", &self.pings_received())
+			.field("last_activity", &self.last_activity())
+			.field("queue_depth", &self.queue_depth())
+			.finish()
+	}
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+	// plain counters can't be left inconsistent by a panic
+	match mutex.lock() {
+		Ok(guard) => guard,
+		Err(poisoned) => poisoned.into_inner(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn counts_messages_once() {
+		let stats = ConnectionStats::new();
+		assert_eq!(stats.idle_for(), None);
+
+		let mut frames = vec![
+			DataFrame::new(false, Opcode::Text, b"hel".to_vec()),
+			DataFrame::new(true, Opcode::Ping, b"?".to_vec()),
+			DataFrame::new(true, Opcode::Continuation, b"lo".to_vec()),
+			DataFrame::new(true, Opcode::Binary, vec![0; 10]),
+		];
+		for frame in &mut frames {
+			stats.on_frame(Direction::Inbound, frame).unwrap();
+		}
+		stats.on_frame(Direction::Outbound, &mut frames[1]).unwrap();
+
+		assert_eq!(stats.messages_received(), 2);
+		assert_eq!(stats.bytes_received(), 15);
+		assert_eq!(stats.pings_received(), 1);
+		assert_eq!(stats.messages_sent(), 0);
+		assert_eq!(stats.pings_sent(), 1);
+		assert!(stats.last_activity().is_some());
+		assert_eq!(stats.queue_depth(), 0);
+	}
+}
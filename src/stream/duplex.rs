@@ -0,0 +1,227 @@
+//! Streams connected to each other in memory, for testing.
+//!
+//! A client and a server can talk to each other over the two ends of a
+//! `duplex` without binding any ports, which makes for quick and
+//! self-contained tests.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "async")]
+use futures::task::{self, Task};
+
+/// Create a pair of streams connected to each other, whatever is written to
+/// one of them can be read from the other.
+///
+/// Reading blocks until the other end writes something, use `async_duplex`
+/// for streams that work with tokio.
+///
+/// ```rust
+/// use std::thread;
+/// use websocket::ClientBuilder;
+/// use websocket::sync::server::IntoWs;
+/// use websocket::stream::duplex;
+///
+/// let (client, server) = duplex();
+///
+/// let server = thread::spawn(move || {
+///     let upgrade = server.into_ws().ok().unwrap();
+///     let mut client = upgrade.accept().ok().unwrap();
+///     let message = client.recv_message().unwrap();
+///     client.send_message(&message).unwrap();
+/// });
+///
+/// let mut client = ClientBuilder::new("ws://localhost").unwrap()
+///     .connect_on(client)
+///     .unwrap();
+/// client.send_message(&websocket::Message::text("echo")).unwrap();
+/// let echo = client.recv_message().unwrap();
+/// assert_eq!(echo, websocket::OwnedMessage::Text("echo".to_string()));
+/// server.join().unwrap();
+/// ```
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+	let a = Arc::new(Pipe::default());
+	let b = Arc::new(Pipe::default());
+	(
+		DuplexStream {
+			incoming: a.clone(),
+			outgoing: b.clone(),
+			blocking: true,
+		},
+		DuplexStream {
+			incoming: b,
+			outgoing: a,
+			blocking: true,
+		},
+	)
+}
+
+/// Create a pair of streams connected to each other like `duplex` does,
+/// except reading does not block but lets the current task wait for the
+/// other end to write something.
+///
+/// These streams must only be used from within tokio tasks.
+#[cfg(feature = "async")]
+pub fn async_duplex() -> (DuplexStream, DuplexStream) {
+	let (mut a, mut b) = duplex();
+	a.blocking = false;
+	b.blocking = false;
+	(a, b)
+}
+
+/// One end of a `duplex`.
+///
+/// Dropping it closes the connection, the other end reads an end of file and
+/// its writes fail.
+pub struct DuplexStream {
+	incoming: Arc<Pipe>,
+	outgoing: Arc<Pipe>,
+	blocking: bool,
+}
+
+/// The bytes going one way through a duplex.
+#[derive(Default)]
+struct Pipe {
+	state: Mutex<PipeState>,
+	readable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+	buffer: VecDeque<u8>,
+	closed: bool,
+	#[cfg(feature = "async")]
+	reader: Option<Task>,
+}
+
+impl Pipe {
+	fn lock(&self) -> MutexGuard<PipeState> {
+		match self.state.lock() {
+			Ok(guard) => guard,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+
+	/// Wake up whoever is waiting to read.
+	#[cfg_attr(not(feature = "async"), allow(unused_variables))]
+	fn notify(&self, state: &mut PipeState) {
+		self.readable.notify_all();
+		#[cfg(feature = "async")]
+		{
+			if let Some(task) = state.reader.take() {
+				task.notify();
+			}
+		}
+	}
+
+	fn close(&self) {
+		let mut state = self.lock();
+		state.closed = true;
+		self.notify(&mut state);
+	}
+}
+
+impl DuplexStream {
+	/// Close the writing half of this end, the other end reads an end of
+	/// file once it read everything written so far.
+	pub fn close_write(&self) {
+		self.outgoing.close();
+	}
+}
+
+impl Read for DuplexStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let mut state = self.incoming.lock();
+		while state.buffer.is_empty() && !state.closed && !buf.is_empty() {
+			if !self.blocking {
+				#[cfg(feature = "async")]
+				{
+					state.reader = Some(task::current());
+				}
+				return Err(io::ErrorKind::WouldBlock.into());
+			}
+			state = match self.incoming.readable.wait(state) {
+				Ok(guard) => guard,
+				Err(poisoned) => poisoned.into_inner(),
+			};
+		}
+
+		let n = ::std::cmp::min(buf.len(), state.buffer.len());
+		for (slot, byte) in buf.iter_mut().zip(state.buffer.drain(..n)) {
+			*slot = byte;
+		}
+		Ok(n)
+	}
+}
+
+impl Write for DuplexStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let mut state = self.outgoing.lock();
+		if state.closed {
+			return Err(io::Error::new(
+				io::ErrorKind::BrokenPipe,
+				"the other end of the duplex is closed",
+			));
+		}
+		state.buffer.extend(buf);
+		self.outgoing.notify(&mut state);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Drop for DuplexStream {
+	fn drop(&mut self) {
+		self.outgoing.close();
+		self.incoming.close();
+	}
+}
+
+#[cfg(feature = "async")]
+mod async {
+	use std::io;
+	use futures::{Async, Poll};
+	use tokio_io::{AsyncRead, AsyncWrite};
+	use super::DuplexStream;
+
+	impl AsyncRead for DuplexStream {}
+
+	impl AsyncWrite for DuplexStream {
+		fn shutdown(&mut self) -> Poll<(), io::Error> {
+			self.close_write();
+			Ok(Async::Ready(()))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn connects_both_ends() {
+		let (mut a, mut b) = duplex();
+
+		let writer = thread::spawn(move || {
+			a.write_all(b"ping").unwrap();
+			let mut buf = [0; 4];
+			a.read_exact(&mut buf).unwrap();
+			assert_eq!(&buf, b"pong");
+		});
+
+		let mut buf = [0; 4];
+		b.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"ping");
+		b.write_all(b"pong").unwrap();
+		writer.join().unwrap();
+
+		let mut rest = Vec::new();
+		b.read_to_end(&mut rest).unwrap();
+		assert!(rest.is_empty());
+		assert_eq!(b.write(b"anyone?").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+	}
+}
@@ -0,0 +1,317 @@
+//! Streams misbehaving the way real networks do, for testing.
+//!
+//! Wrap either end of a connection in a `Faulty` stream to see how an
+//! application copes with slow links, short reads and writes, and
+//! connections dropping in the middle of a frame.
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async")]
+use futures::{Async, Future};
+#[cfg(feature = "async")]
+use tokio::timer::Delay;
+
+/// How a `Faulty` stream misbehaves, nothing goes wrong by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Faults {
+	/// Delay every read and write by this long.
+	pub latency: Option<Duration>,
+	/// Move at most this many bytes per second in each direction.
+	pub bandwidth: Option<u64>,
+	/// Read at most this many bytes at once.
+	pub max_read: Option<usize>,
+	/// Write at most this many bytes at once, making for partial writes.
+	pub max_write: Option<usize>,
+	/// Fail every read once this many bytes were read.
+	pub fail_read_after: Option<u64>,
+	/// Fail every write once this many bytes were written.
+	pub fail_write_after: Option<u64>,
+	/// The kind of error injected reads and writes fail with.
+	pub error: io::ErrorKind,
+}
+
+impl Default for Faults {
+	fn default() -> Self {
+		Faults {
+			latency: None,
+			bandwidth: None,
+			max_read: None,
+			max_write: None,
+			fail_read_after: None,
+			fail_write_after: None,
+			error: io::ErrorKind::ConnectionReset,
+		}
+	}
+}
+
+/// A stream that misbehaves according to its `Faults`.
+///
+/// Streams made with `new` block the current thread to wait, use `new_async`
+/// for streams driven by tokio.
+///
+/// ```rust
+/// use std::io::{Cursor, Write};
+/// use websocket::stream::faulty::{Faults, Faulty};
+///
+/// let faults = Faults {
+///     max_write: Some(2),
+///     fail_write_after: Some(3),
+///     ..Faults::default()
+/// };
+/// let mut stream = Faulty::new(Cursor::new(Vec::new()), faults);
+///
+/// assert_eq!(stream.write(b"hello").unwrap(), 2);
+/// assert_eq!(stream.write(b"llo").unwrap(), 1);
+/// assert!(stream.write(b"lo").is_err());
+/// ```
+pub struct Faulty<S> {
+	inner: S,
+	faults: Faults,
+	read: Pace,
+	written: Pace,
+	#[cfg(feature = "async")]
+	timer: Option<Delay>,
+	blocking: bool,
+}
+
+/// Keeps track of one direction of a faulty stream.
+#[derive(Default)]
+struct Pace {
+	total: u64,
+	/// the moment the next operation may go ahead
+	gate: Option<Instant>,
+	/// how long the last operation takes to transmit
+	transfer: Duration,
+}
+
+impl<S> Faulty<S> {
+	/// Wrap a blocking stream.
+	pub fn new(inner: S, faults: Faults) -> Self {
+		Faulty {
+			inner: inner,
+			faults: faults,
+			read: Pace::default(),
+			written: Pace::default(),
+			#[cfg(feature = "async")]
+			timer: None,
+			blocking: true,
+		}
+	}
+
+	/// Wrap a non-blocking stream, waiting is done with tokio's timer
+	/// instead of putting the thread to sleep.
+	#[cfg(feature = "async")]
+	pub fn new_async(inner: S, faults: Faults) -> Self {
+		Faulty {
+			blocking: false,
+			..Faulty::new(inner, faults)
+		}
+	}
+
+	/// The faults this stream is suffering from.
+	pub fn faults(&self) -> &Faults {
+		&self.faults
+	}
+
+	/// Change the faults this stream is suffering from, e.g. to let the
+	/// connection recover.
+	pub fn set_faults(&mut self, faults: Faults) {
+		self.faults = faults;
+	}
+
+	/// Get a reference to the wrapped stream.
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the wrapped stream.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.inner
+	}
+
+	/// Get back the wrapped stream.
+	pub fn into_inner(self) -> S {
+		self.inner
+	}
+
+	/// Wait until the next operation in one direction may go ahead.
+	fn wait(&mut self, reading: bool) -> io::Result<()> {
+		let latency = self.faults.latency.unwrap_or_default();
+		let gate = {
+			let pace = if reading { &mut self.read } else { &mut self.written };
+			let transfer = pace.transfer;
+			*pace.gate.get_or_insert_with(|| Instant::now() + latency + transfer)
+		};
+
+		let now = Instant::now();
+		if gate > now {
+			if self.blocking {
+				thread::sleep(gate - now);
+			} else {
+				self.wait_async(gate)?;
+			}
+		}
+		Ok(())
+	}
+
+	#[cfg(feature = "async")]
+	fn wait_async(&mut self, gate: Instant) -> io::Result<()> {
+		let mut timer = self.timer.take().unwrap_or_else(|| Delay::new(gate));
+		timer.reset(gate);
+		match timer.poll() {
+			Ok(Async::Ready(())) => Ok(()),
+			Ok(Async::NotReady) => {
+				self.timer = Some(timer);
+				Err(io::ErrorKind::WouldBlock.into())
+			}
+			Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+		}
+	}
+
+	#[cfg(not(feature = "async"))]
+	fn wait_async(&mut self, _gate: Instant) -> io::Result<()> {
+		unreachable!("only async streams wait asynchronously")
+	}
+
+	/// How many bytes the next operation may move at most, failing if the
+	/// stream is supposed to break down.
+	fn allowance(&self, reading: bool, len: usize) -> io::Result<usize> {
+		let (total, max, fail_after) = if reading {
+			(self.read.total, self.faults.max_read, self.faults.fail_read_after)
+		} else {
+			(self.written.total, self.faults.max_write, self.faults.fail_write_after)
+		};
+
+		let mut len = cmp::min(len, max.unwrap_or(len));
+		if let Some(fail_after) = fail_after {
+			let left = fail_after.saturating_sub(total);
+			if left == 0 && len > 0 {
+				return Err(io::Error::new(self.faults.error, "injected fault"));
+			}
+			len = cmp::min(len as u64, left) as usize;
+		}
+		Ok(len)
+	}
+
+	/// Account for `n` bytes having moved in one direction.
+	fn moved(&mut self, reading: bool, n: usize) {
+		let transfer = match self.faults.bandwidth {
+			Some(bandwidth) if bandwidth > 0 => {
+				let nanos = n as u64 * 1_000_000_000 / bandwidth;
+				Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+			}
+			_ => Duration::from_secs(0),
+		};
+		let pace = if reading { &mut self.read } else { &mut self.written };
+		pace.total += n as u64;
+		pace.gate = None;
+		pace.transfer = transfer;
+	}
+}
+
+impl<S> Read for Faulty<S>
+where
+	S: Read,
+{
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let len = self.allowance(true, buf.len())?;
+		self.wait(true)?;
+		let n = self.inner.read(&mut buf[..len])?;
+		self.moved(true, n);
+		Ok(n)
+	}
+}
+
+impl<S> Write for Faulty<S>
+where
+	S: Write,
+{
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let len = self.allowance(false, buf.len())?;
+		self.wait(false)?;
+		let n = self.inner.write(&buf[..len])?;
+		self.moved(false, n);
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(feature = "sync")]
+mod sync {
+	use std::net::TcpStream;
+	use stream::sync::AsTcpStream;
+	use super::Faulty;
+
+	impl<S> AsTcpStream for Faulty<S>
+	where
+		S: AsTcpStream,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.inner.as_tcp()
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+mod async {
+	use std::io;
+	use futures::Poll;
+	use tokio_io::{AsyncRead, AsyncWrite};
+	use super::Faulty;
+
+	impl<S> AsyncRead for Faulty<S>
+	where
+		S: AsyncRead,
+	{
+	}
+
+	impl<S> AsyncWrite for Faulty<S>
+	where
+		S: AsyncWrite,
+	{
+		fn shutdown(&mut self) -> Poll<(), io::Error> {
+			self.inner.shutdown()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	#[test]
+	fn breaks_down_mid_read() {
+		let faults = Faults {
+			max_read: Some(4),
+			fail_read_after: Some(6),
+			error: io::ErrorKind::BrokenPipe,
+			..Faults::default()
+		};
+		let mut stream = Faulty::new(Cursor::new(vec![1u8; 10]), faults);
+		let mut buf = [0; 10];
+
+		assert_eq!(stream.read(&mut buf).unwrap(), 4);
+		assert_eq!(stream.read(&mut buf).unwrap(), 2);
+		assert_eq!(stream.read(&mut buf).unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+	}
+
+	#[test]
+	fn throttles() {
+		let faults = Faults {
+			bandwidth: Some(1000),
+			..Faults::default()
+		};
+		let mut stream = Faulty::new(Cursor::new(Vec::new()), faults);
+
+		let start = Instant::now();
+		stream.write_all(&[0; 50]).unwrap();
+		stream.write_all(&[0; 50]).unwrap();
+		assert!(start.elapsed() >= Duration::from_millis(50));
+	}
+}
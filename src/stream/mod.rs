@@ -0,0 +1,415 @@
+//! Provides the default stream type for WebSocket connections.
+
+use std::io::{self, Read, Write};
+use std::fmt::Arguments;
+
+pub mod record;
+pub mod faulty;
+pub mod duplex;
+pub mod tcp;
+pub mod tls;
+#[cfg(feature = "sync")]
+pub mod shared;
+
+pub use self::record::Recorder;
+pub use self::duplex::{duplex, DuplexStream};
+pub use self::tcp::TcpOptions;
+pub use self::tls::{TlsVersion, TlsVersions};
+#[cfg(feature = "async")]
+pub use self::duplex::async_duplex;
+
+/// Represents a stream that can be read from, and written to.
+/// This is an abstraction around readable and writable things to be able
+/// to speak websockets over ssl, tcp, unix sockets, etc.
+pub trait Stream: Read + Write {}
+impl<S> Stream for S
+where
+	S: Read + Write,
+{
+}
+
+/// If you would like to combine an input stream and an output stream into a single
+/// stream to talk websockets over then this is the struct for you!
+///
+/// This is useful if you want to use different mediums for different directions.
+pub struct ReadWritePair<R, W>(pub R, pub W)
+where
+	R: Read,
+	W: Write;
+
+impl<R, W> Read for ReadWritePair<R, W>
+where
+	R: Read,
+	W: Write,
+{
+	#[inline(always)]
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.0.read(buf)
+	}
+	#[inline(always)]
+	fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+		self.0.read_to_end(buf)
+	}
+	#[inline(always)]
+	fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
+		self.0.read_to_string(buf)
+	}
+	#[inline(always)]
+	fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+		self.0.read_exact(buf)
+	}
+}
+
+impl<R, W> Write for ReadWritePair<R, W>
+where
+	R: Read,
+	W: Write,
+{
+	#[inline(always)]
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.1.write(buf)
+	}
+	#[inline(always)]
+	fn flush(&mut self) -> io::Result<()> {
+		self.1.flush()
+	}
+	#[inline(always)]
+	fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+		self.1.write_all(buf)
+	}
+	#[inline(always)]
+	fn write_fmt(&mut self, fmt: Arguments) -> io::Result<()> {
+		self.1.write_fmt(fmt)
+	}
+}
+
+/// A collection of traits and implementations for async streams.
+#[cfg(feature = "async")]
+pub mod async {
+	use std::any::Any;
+	use std::io::{self, Read, Write};
+	use std::net::SocketAddr;
+	use std::ops::Deref;
+	use futures::Poll;
+	pub use super::ReadWritePair;
+	pub use std::net::Shutdown;
+	pub use tokio::net::TcpStream;
+	pub use tokio_io::{AsyncWrite, AsyncRead};
+	pub use tokio_io::io::{ReadHalf, WriteHalf};
+	#[cfg(feature = "async-ssl")]
+	pub use tokio_tls::TlsStream;
+
+	/// A stream that can be read from and written to asynchronously.
+	/// This let's us abstract over many async streams like tcp, ssl,
+	/// udp, ssh, etc.
+	pub trait Stream: AsyncRead + AsyncWrite {}
+	impl<S> Stream for S
+	where
+		S: AsyncRead + AsyncWrite,
+	{
+	}
+
+	/// An async `Stream` that can also be used as a borrow to a
+	/// `TcpStream`, like `sync::NetworkStream`. The streams of clients made
+	/// with `ClientBuilder::async_connect` are boxed ones.
+	pub trait NetworkStream: Stream + AsTcpStream {
+		/// The address of the peer of the connection.
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			self.as_tcp().peer_addr()
+		}
+
+		/// The local address of the connection.
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			self.as_tcp().local_addr()
+		}
+
+		/// Shut down the reading, writing or both halves of the connection.
+		/// For TLS connections this shuts down the socket underneath without
+		/// a TLS close notify.
+		fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+			self.as_tcp().shutdown(how)
+		}
+
+		#[doc(hidden)]
+		fn as_any_stream(&self) -> &Any;
+	}
+
+	impl<S> NetworkStream for S
+	where
+		S: Stream + AsTcpStream + 'static,
+	{
+		fn as_any_stream(&self) -> &Any {
+			self
+		}
+	}
+
+	impl NetworkStream + Send {
+		/// The stream as `Any`, to get at the type of stream a boxed one
+		/// holds.
+		pub fn as_any(&self) -> &Any {
+			self.as_any_stream()
+		}
+
+		/// The TLS stream, if the connection is a secure one.
+		#[cfg(feature = "async-ssl")]
+		pub fn as_tls(&self) -> Option<&TlsStream<TcpStream>> {
+			self.as_any().downcast_ref()
+		}
+	}
+
+	/// The ability to access a borrow to an underlying async `TcpStream`,
+	/// so one can set options on the stream such as `nodelay`.
+	pub trait AsTcpStream {
+		/// Get a borrow of the TcpStream
+		fn as_tcp(&self) -> &TcpStream;
+	}
+
+	impl AsTcpStream for TcpStream {
+		fn as_tcp(&self) -> &TcpStream {
+			self
+		}
+	}
+
+	#[cfg(feature = "async-ssl")]
+	impl AsTcpStream for TlsStream<TcpStream> {
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref().get_ref()
+		}
+	}
+
+	impl<T> AsTcpStream for Box<T>
+	where
+		T: AsTcpStream + ?Sized,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.deref().as_tcp()
+		}
+	}
+
+	impl<R, W> AsyncRead for ReadWritePair<R, W>
+	where
+		R: AsyncRead,
+		W: Write,
+	{
+	}
+
+	impl<R, W> AsyncWrite for ReadWritePair<R, W>
+	where
+		W: AsyncWrite,
+		R: Read,
+	{
+		fn shutdown(&mut self) -> Poll<(), io::Error> {
+			self.1.shutdown()
+		}
+	}
+}
+
+/// A collection of traits and implementations for synchronous streams.
+#[cfg(feature = "sync")]
+pub mod sync {
+	pub use super::ReadWritePair;
+	use std::io::{self, Read, Write};
+	use std::any::Any;
+	use std::ops::Deref;
+	use std::net::SocketAddr;
+	pub use std::net::TcpStream;
+	pub use std::net::Shutdown;
+	#[cfg(feature = "sync-ssl")]
+	pub use native_tls::TlsStream;
+
+	pub use super::Stream;
+	pub use super::shared::SharedStream;
+
+	/// a `Stream` that can also be used as a borrow to a `TcpStream`
+	/// this is useful when you want to set `TcpStream` options on a
+	/// `Stream` like `nonblocking`.
+	///
+	/// The addresses of the connection and shutting it down are at hand
+	/// without going through `as_tcp`, also for boxed streams:
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	/// use websocket::stream::sync::{NetworkStream, Shutdown};
+	///
+	/// let client = ClientBuilder::new("wss://example.com").unwrap().connect(None).unwrap();
+	/// let stream = client.stream_ref();
+	/// println!("connected to {}", stream.peer_addr().unwrap());
+	/// stream.shutdown(Shutdown::Both).unwrap();
+	/// ```
+	pub trait NetworkStream: Read + Write + AsTcpStream {
+		/// The address of the peer of the connection.
+		fn peer_addr(&self) -> io::Result<SocketAddr> {
+			self.as_tcp().peer_addr()
+		}
+
+		/// The local address of the connection.
+		fn local_addr(&self) -> io::Result<SocketAddr> {
+			self.as_tcp().local_addr()
+		}
+
+		/// Shut down the reading, writing or both halves of the connection.
+		/// For TLS connections this shuts down the socket underneath without
+		/// a TLS close notify, pending and future IO return right away.
+		fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+			self.as_tcp().shutdown(how)
+		}
+
+		#[doc(hidden)]
+		fn as_any_stream(&self) -> &Any;
+	}
+
+	impl<S> NetworkStream for S
+	where
+		S: Read + Write + AsTcpStream + 'static,
+	{
+		fn as_any_stream(&self) -> &Any {
+			self
+		}
+	}
+
+	/// Getting at the stream in the box `ClientBuilder::connect` gives back,
+	/// the `TcpStream` is always there with `as_tcp`:
+	///
+	/// ```rust,no_run
+	/// use websocket::ClientBuilder;
+	/// use websocket::stream::sync::AsTcpStream;
+	///
+	/// let client = ClientBuilder::new("wss://example.com").unwrap().connect(None).unwrap();
+	/// client.stream_ref().as_tcp().set_nodelay(true).unwrap();
+	/// if client.stream_ref().as_tls().is_some() {
+	///     println!("connected securely");
+	/// }
+	/// ```
+	impl NetworkStream + Send {
+		/// The stream as `Any`, to get at the type of stream a boxed one
+		/// holds.
+		pub fn as_any(&self) -> &Any {
+			self.as_any_stream()
+		}
+
+		/// The TLS stream, if the connection is a secure one.
+		#[cfg(feature = "sync-ssl")]
+		pub fn as_tls(&self) -> Option<&TlsStream<TcpStream>> {
+			self.as_any().downcast_ref()
+		}
+	}
+
+	/// some streams can be split up into separate reading and writing components
+	/// `TcpStream` is an example. This trait marks this ability so one can split
+	/// up the client into two parts.
+	///
+	/// Streams that can't be cloned, like SSL streams, are split into two
+	/// handles of a `SharedStream`.
+	pub trait Splittable {
+		/// The reading component of this type
+		type Reader: Read;
+		/// The writing component of this type
+		type Writer: Write;
+
+		/// Split apart this type into a reading and writing component.
+		fn split(self) -> io::Result<(Self::Reader, Self::Writer)>;
+
+		/// Put the components `split` gave back together, handing them back
+		/// if they are not from the same stream. Types that can't tell
+		/// always hand them back.
+		fn reunite(reader: Self::Reader, writer: Self::Writer) -> Result<Self, (Self::Reader, Self::Writer)>
+		where
+			Self: Sized,
+		{
+			Err((reader, writer))
+		}
+	}
+
+	impl<R, W> Splittable for ReadWritePair<R, W>
+	where
+		R: Read,
+		W: Write,
+	{
+		type Reader = R;
+		type Writer = W;
+
+		fn split(self) -> io::Result<(R, W)> {
+			Ok((self.0, self.1))
+		}
+
+		fn reunite(reader: R, writer: W) -> Result<Self, (R, W)> {
+			Ok(ReadWritePair(reader, writer))
+		}
+	}
+
+	impl Splittable for TcpStream {
+		type Reader = TcpStream;
+		type Writer = TcpStream;
+
+		fn split(self) -> io::Result<(TcpStream, TcpStream)> {
+			self.try_clone().map(|s| (s, self))
+		}
+
+		fn reunite(reader: TcpStream, writer: TcpStream) -> Result<Self, (TcpStream, TcpStream)> {
+			let same = |s: &TcpStream, t: &TcpStream| {
+				s.local_addr().ok() == t.local_addr().ok() && s.peer_addr().ok() == t.peer_addr().ok()
+			};
+			if same(&reader, &writer) && reader.peer_addr().is_ok() {
+				Ok(writer)
+			} else {
+				Err((reader, writer))
+			}
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	impl Splittable for TlsStream<TcpStream> {
+		type Reader = SharedStream<TlsStream<TcpStream>>;
+		type Writer = SharedStream<TlsStream<TcpStream>>;
+
+		fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+			SharedStream::new(self).map(|s| (s.clone(), s))
+		}
+
+		fn reunite(reader: Self::Reader, writer: Self::Writer) -> Result<Self, (Self::Reader, Self::Writer)> {
+			SharedStream::reunite(reader, writer)
+		}
+	}
+
+	impl Splittable for Box<NetworkStream + Send> {
+		type Reader = SharedStream<Box<NetworkStream + Send>>;
+		type Writer = SharedStream<Box<NetworkStream + Send>>;
+
+		fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+			SharedStream::new(self).map(|s| (s.clone(), s))
+		}
+
+		fn reunite(reader: Self::Reader, writer: Self::Writer) -> Result<Self, (Self::Reader, Self::Writer)> {
+			SharedStream::reunite(reader, writer)
+		}
+	}
+
+	/// The ability access a borrow to an underlying TcpStream,
+	/// so one can set options on the stream such as `nonblocking`.
+	pub trait AsTcpStream {
+		/// Get a borrow of the TcpStream
+		fn as_tcp(&self) -> &TcpStream;
+	}
+
+	impl AsTcpStream for TcpStream {
+		fn as_tcp(&self) -> &TcpStream {
+			self
+		}
+	}
+
+	#[cfg(feature = "sync-ssl")]
+	impl AsTcpStream for TlsStream<TcpStream> {
+		fn as_tcp(&self) -> &TcpStream {
+			self.get_ref()
+		}
+	}
+
+	impl<T> AsTcpStream for Box<T>
+	where
+		T: AsTcpStream + ?Sized,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.deref().as_tcp()
+		}
+	}
+}
@@ -0,0 +1,252 @@
+//! Recording the bytes going through a stream, for debugging.
+//!
+//! Wrapping a stream in a `Recorder` gives a dump of everything that went over
+//! the wire, after TLS was taken care of, which is usually all one needs to
+//! find out why another websocket implementation won't talk to this one.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which way the bytes seen by a `Recorder` were going.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+	/// The bytes were read from the stream.
+	Read,
+	/// The bytes were written to the stream.
+	Written,
+}
+
+/// Somewhere for a `Recorder` to keep what it saw.
+pub trait Record {
+	/// Keep a chunk of bytes that went through the stream at time `at`.
+	fn record(&mut self, at: SystemTime, direction: Direction, bytes: &[u8]);
+}
+
+/// A chunk of bytes that went through a stream, as sent down a channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+	/// When the bytes went through.
+	pub at: SystemTime,
+	/// Which way they were going.
+	pub direction: Direction,
+	/// The bytes themselves.
+	pub bytes: Vec<u8>,
+}
+
+impl Record for mpsc::Sender<Chunk> {
+	fn record(&mut self, at: SystemTime, direction: Direction, bytes: &[u8]) {
+		// nobody is listening anymore, that's fine
+		let _ = self.send(Chunk {
+			at: at,
+			direction: direction,
+			bytes: bytes.to_vec(),
+		});
+	}
+}
+
+/// Writes a hex dump of the recorded bytes, e.g. to a file.
+///
+/// Every chunk gets a header line with its timestamp (seconds since the unix
+/// epoch), its direction (`<` read, `>` written) and its length, followed by
+/// the bytes themselves 16 to a line.
+pub struct HexDump<W>(pub W);
+
+impl<W> Record for HexDump<W>
+where
+	W: Write,
+{
+	fn record(&mut self, at: SystemTime, direction: Direction, bytes: &[u8]) {
+		// the recording must not get in the way of the connection itself
+		let _ = write!(self.0, "{}", Dump(at, direction, bytes));
+	}
+}
+
+struct Dump<'a>(SystemTime, Direction, &'a [u8]);
+
+impl<'a> fmt::Display for Dump<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let since_epoch = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+		let arrow = match self.1 {
+			Direction::Read => '<',
+			Direction::Written => '>',
+		};
+		writeln!(
+			f,
+			"{}.{:06} {} {} bytes",
+			since_epoch.as_secs(),
+			since_epoch.subsec_nanos() / 1000,
+			arrow,
+			self.2.len()
+		)?;
+
+		for (i, line) in self.2.chunks(16).enumerate() {
+			write!(f, "  {:04x} ", i * 16)?;
+			for byte in line {
+				write!(f, " {:02x}", byte)?;
+			}
+			for _ in line.len()..16 {
+				f.write_str("   ")?;
+			}
+			f.write_str("  |")?;
+			for &byte in line {
+				let c = if byte.is_ascii_graphic() || byte == b' ' {
+					byte as char
+				} else {
+					'.'
+				};
+				write!(f, "{}", c)?;
+			}
+			f.write_str("|\n")?;
+		}
+		Ok(())
+	}
+}
+
+/// A stream that hands a copy of everything read from or written to it to a
+/// `Record`, the stream itself behaves exactly like the one it wraps.
+///
+/// ```rust
+/// use std::io::{Cursor, Read};
+/// use std::sync::mpsc;
+/// use websocket::stream::{ReadWritePair, Recorder};
+/// use websocket::stream::record::Direction;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let inner = ReadWritePair(Cursor::new(b"hello".to_vec()), Vec::new());
+/// let mut stream = Recorder::new(inner, tx);
+///
+/// let mut buf = String::new();
+/// stream.read_to_string(&mut buf).unwrap();
+///
+/// let chunk = rx.try_recv().unwrap();
+/// assert_eq!(chunk.direction, Direction::Read);
+/// assert_eq!(chunk.bytes, b"hello");
+/// ```
+pub struct Recorder<S, R> {
+	inner: S,
+	record: R,
+}
+
+impl<S, R> Recorder<S, R>
+where
+	R: Record,
+{
+	/// Start recording what goes through `inner` into `record`.
+	pub fn new(inner: S, record: R) -> Self {
+		Recorder {
+			inner: inner,
+			record: record,
+		}
+	}
+
+	/// Get a reference to the recorded stream.
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	/// Get a mutable reference to the recorded stream, whatever is read or
+	/// written through it directly is not recorded.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.inner
+	}
+
+	/// Stop recording and get back the stream and the record.
+	pub fn into_parts(self) -> (S, R) {
+		(self.inner, self.record)
+	}
+}
+
+impl<S, R> Read for Recorder<S, R>
+where
+	S: Read,
+	R: Record,
+{
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		if n > 0 {
+			self.record.record(SystemTime::now(), Direction::Read, &buf[..n]);
+		}
+		Ok(n)
+	}
+}
+
+impl<S, R> Write for Recorder<S, R>
+where
+	S: Write,
+	R: Record,
+{
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let n = self.inner.write(buf)?;
+		if n > 0 {
+			self.record.record(SystemTime::now(), Direction::Written, &buf[..n]);
+		}
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(feature = "sync")]
+mod sync {
+	use std::net::TcpStream;
+	use stream::sync::AsTcpStream;
+	use super::{Record, Recorder};
+
+	impl<S, R> AsTcpStream for Recorder<S, R>
+	where
+		S: AsTcpStream,
+		R: Record,
+	{
+		fn as_tcp(&self) -> &TcpStream {
+			self.inner.as_tcp()
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+mod async {
+	use std::io;
+	use futures::Poll;
+	use tokio_io::{AsyncRead, AsyncWrite};
+	use super::{Record, Recorder};
+
+	impl<S, R> AsyncRead for Recorder<S, R>
+	where
+		S: AsyncRead,
+		R: Record,
+	{
+	}
+
+	impl<S, R> AsyncWrite for Recorder<S, R>
+	where
+		S: AsyncWrite,
+		R: Record,
+	{
+		fn shutdown(&mut self) -> Poll<(), io::Error> {
+			self.inner.shutdown()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn dumps_hex() {
+		let at = UNIX_EPOCH + Duration::new(12, 345678000);
+		let mut dump = HexDump(Vec::new());
+		dump.record(at, Direction::Written, b"\x81\x05Hello, websocket");
+
+		assert_eq!(
+			String::from_utf8(dump.0).unwrap(),
+			"12.345678 > 18 bytes\n\
+			 \x20\x200000  81 05 48 65 6c 6c 6f 2c 20 77 65 62 73 6f 63 6b  |..Hello, websock|\n\
+			 \x20\x200010  65 74                                            |et|\n"
+		);
+	}
+}
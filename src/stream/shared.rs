@@ -0,0 +1,200 @@
+//! Sharing one stream between a reading and a writing half.
+//!
+//! TLS streams can't be cloned like a `TcpStream` can, their state has to be
+//! kept in one place. A `SharedStream` puts the stream behind a lock that
+//! each half takes for one read or write at a time. So that a blocked read
+//! does not keep the writer waiting forever, reads wait for data in short
+//! turns (the poll interval), giving the writer its chance in between.
+//!
+//! This is what `Client::split` uses for TLS streams and for the boxed
+//! streams `ClientBuilder::connect` returns.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stream::sync::AsTcpStream;
+
+/// How long a read waits for data before letting the writer have a turn.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+struct Shared<S> {
+	stream: Mutex<S>,
+	/// a handle to the socket underneath, for shutting it down and changing
+	/// its options without waiting for the lock
+	tcp: TcpStream,
+	/// the read timeout the socket had before it was shared
+	read_timeout: Option<Duration>,
+}
+
+/// A handle to a stream shared between halves, see the module docs.
+///
+/// The socket's read timeout is turned into the poll interval while it is
+/// shared, the timeout it had before still applies to reads as a whole.
+/// The stream should stay blocking.
+pub struct SharedStream<S> {
+	shared: Arc<Shared<S>>,
+}
+
+impl<S> SharedStream<S>
+where
+	S: Read + Write + AsTcpStream,
+{
+	/// Share `stream`, reads wait for data `DEFAULT_POLL_INTERVAL` at a time.
+	pub fn new(stream: S) -> io::Result<Self> {
+		SharedStream::with_poll_interval(stream, DEFAULT_POLL_INTERVAL)
+	}
+
+	/// Share `stream`, reads wait for data `interval` at a time. Shorter
+	/// intervals let writes through sooner at the cost of more wakeups.
+	pub fn with_poll_interval(stream: S, interval: Duration) -> io::Result<Self> {
+		let tcp = stream.as_tcp().try_clone()?;
+		let read_timeout = tcp.read_timeout()?;
+		tcp.set_read_timeout(Some(interval))?;
+		Ok(SharedStream {
+			shared: Arc::new(Shared {
+				stream: Mutex::new(stream),
+				tcp: tcp,
+				read_timeout: read_timeout,
+			}),
+		})
+	}
+}
+
+impl<S> SharedStream<S> {
+	/// Whether `other` is a handle to the same stream.
+	pub fn same_stream(&self, other: &SharedStream<S>) -> bool {
+		Arc::ptr_eq(&self.shared, &other.shared)
+	}
+
+	/// Get the stream back from its last two handles, restoring the read
+	/// timeout it had. The handles are given back if they belong to
+	/// different streams or other handles are still around.
+	pub fn reunite(a: SharedStream<S>, b: SharedStream<S>) -> Result<S, (SharedStream<S>, SharedStream<S>)> {
+		if !a.same_stream(&b) || Arc::strong_count(&a.shared) != 2 {
+			return Err((a, b));
+		}
+		drop(b);
+		let shared = match Arc::try_unwrap(a.shared) {
+			Ok(shared) => shared,
+			Err(_) => unreachable!("no other handles are left"),
+		};
+		// the socket stays usable without its old timeout, so this is best effort
+		let _ = shared.tcp.set_read_timeout(shared.read_timeout);
+		Ok(match shared.stream.into_inner() {
+			Ok(stream) => stream,
+			Err(poisoned) => poisoned.into_inner(),
+		})
+	}
+
+	fn lock(&self) -> io::Result<MutexGuard<S>> {
+		self.shared
+			.stream
+			.lock()
+			.map_err(|_| io::Error::new(io::ErrorKind::Other, "shared stream poisoned"))
+	}
+}
+
+impl<S> Clone for SharedStream<S> {
+	fn clone(&self) -> Self {
+		SharedStream { shared: self.shared.clone() }
+	}
+}
+
+impl<S> Read for SharedStream<S>
+where
+	S: Read,
+{
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let start = Instant::now();
+		loop {
+			let result = self.lock()?.read(buf);
+			match result {
+				Err(ref e) if is_timeout(e) => {
+					if let Some(timeout) = self.shared.read_timeout {
+						if start.elapsed() >= timeout {
+							return result;
+						}
+					}
+					// the lock was released, let a waiting writer take it
+					thread::yield_now();
+				}
+				result => return result,
+			}
+		}
+	}
+}
+
+impl<S> Write for SharedStream<S>
+where
+	S: Write,
+{
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.lock()?.write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.lock()?.flush()
+	}
+
+	fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+		self.lock()?.write_all(buf)
+	}
+}
+
+impl<S> AsTcpStream for SharedStream<S> {
+	fn as_tcp(&self) -> &TcpStream {
+		&self.shared.tcp
+	}
+}
+
+impl<S> fmt::Debug for SharedStream<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("SharedStream").field("tcp", &self.shared.tcp).finish()
+	}
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+	e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+	use stream::sync::{NetworkStream, Splittable};
+
+	#[test]
+	fn writes_get_through_while_reading() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let echo = thread::spawn(move || {
+			let (mut socket, _) = listener.accept().unwrap();
+			let mut buf = [0; 4];
+			socket.read_exact(&mut buf).unwrap();
+			socket.write_all(&buf).unwrap();
+		});
+
+		let stream: Box<NetworkStream + Send> = Box::new(TcpStream::connect(addr).unwrap());
+		let (mut reader, mut writer) = stream.split().unwrap();
+		let read = thread::spawn(move || {
+			let mut buf = [0; 4];
+			reader.read_exact(&mut buf).unwrap();
+			(buf, reader)
+		});
+		thread::sleep(Duration::from_millis(50));
+		writer.write_all(b"ping").unwrap();
+
+		let (buf, reader) = read.join().unwrap();
+		assert_eq!(&buf, b"ping");
+		echo.join().unwrap();
+
+		let clone: Box<NetworkStream + Send> = Box::new(writer.as_tcp().try_clone().unwrap());
+		let other = SharedStream::new(clone).unwrap();
+		let (reader, _) = SharedStream::reunite(reader, other).err().unwrap();
+		let stream = <Box<NetworkStream + Send>>::reunite(reader, writer).ok().unwrap();
+		assert_eq!(stream.as_tcp().read_timeout().unwrap(), None);
+	}
+}
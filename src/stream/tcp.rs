@@ -0,0 +1,70 @@
+//! Tuning the TCP sockets of connections.
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use net2::TcpStreamExt;
+
+/// Options applied to the TCP socket of a connection before its handshake,
+/// options left at `None` keep the system's defaults.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use websocket::stream::TcpOptions;
+///
+/// let options = TcpOptions {
+///     nodelay: Some(true),
+///     keepalive: Some(Duration::from_secs(60)),
+///     ..TcpOptions::default()
+/// };
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TcpOptions {
+	/// Whether to disable Nagle's algorithm, sending small messages right
+	/// away instead of waiting to batch them up.
+	pub nodelay: Option<bool>,
+	/// Turn on TCP keepalive, probing the peer after the connection was
+	/// idle for this long.
+	pub keepalive: Option<Duration>,
+	/// The size of the socket's send buffer in bytes.
+	pub send_buffer_size: Option<usize>,
+	/// The size of the socket's receive buffer in bytes.
+	pub recv_buffer_size: Option<usize>,
+}
+
+impl TcpOptions {
+	/// Apply the options to a stream.
+	pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+		if let Some(nodelay) = self.nodelay {
+			stream.set_nodelay(nodelay)?;
+		}
+		if let Some(keepalive) = self.keepalive {
+			TcpStreamExt::set_keepalive(stream, Some(keepalive))?;
+		}
+		if let Some(size) = self.send_buffer_size {
+			TcpStreamExt::set_send_buffer_size(stream, size)?;
+		}
+		if let Some(size) = self.recv_buffer_size {
+			TcpStreamExt::set_recv_buffer_size(stream, size)?;
+		}
+		Ok(())
+	}
+
+	/// Apply the options to an async stream.
+	#[cfg(feature = "async")]
+	pub fn apply_async(&self, stream: &::tokio::net::TcpStream) -> io::Result<()> {
+		if let Some(nodelay) = self.nodelay {
+			stream.set_nodelay(nodelay)?;
+		}
+		if let Some(keepalive) = self.keepalive {
+			stream.set_keepalive(Some(keepalive))?;
+		}
+		if let Some(size) = self.send_buffer_size {
+			stream.set_send_buffer_size(size)?;
+		}
+		if let Some(size) = self.recv_buffer_size {
+			stream.set_recv_buffer_size(size)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,135 @@
+//! Settings for the TLS connections of clients and servers.
+#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+use native_tls::{Protocol, Result as TlsResult, TlsAcceptorBuilder, TlsConnectorBuilder};
+
+/// A version of the TLS protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+	/// TLS 1.0
+	Tls10,
+	/// TLS 1.1
+	Tls11,
+	/// TLS 1.2
+	Tls12,
+}
+
+impl TlsVersion {
+	const ALL: [TlsVersion; 3] = [TlsVersion::Tls10, TlsVersion::Tls11, TlsVersion::Tls12];
+
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	fn protocol(self) -> Protocol {
+		match self {
+			TlsVersion::Tls10 => Protocol::Tlsv10,
+			TlsVersion::Tls11 => Protocol::Tlsv11,
+			TlsVersion::Tls12 => Protocol::Tlsv12,
+		}
+	}
+}
+
+/// The range of TLS versions a connection may use, by default the one of
+/// the TLS library.
+///
+/// `ClientBuilder::min_tls_version` and `max_tls_version` set it for
+/// clients, servers can apply it to the builder of their acceptor:
+///
+/// ```rust,no_run
+/// # extern crate native_tls;
+/// # extern crate websocket;
+/// # fn main() {
+/// use native_tls::{Pkcs12, TlsAcceptor};
+/// use websocket::stream::tls::{TlsVersion, TlsVersions};
+///
+/// # let pkcs12 = Pkcs12::from_der(&[], "").unwrap();
+/// let mut builder = TlsAcceptor::builder(pkcs12).unwrap();
+/// TlsVersions::new().min(TlsVersion::Tls12).apply_to_acceptor(&mut builder).unwrap();
+/// let acceptor = builder.build().unwrap();
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TlsVersions {
+	min: Option<TlsVersion>,
+	max: Option<TlsVersion>,
+}
+
+impl TlsVersions {
+	/// Leave the versions to the TLS library.
+	pub fn new() -> Self {
+		TlsVersions::default()
+	}
+
+	/// Refuse versions older than `version`.
+	pub fn min(mut self, version: TlsVersion) -> Self {
+		self.min = Some(version);
+		self
+	}
+
+	/// Refuse versions newer than `version`.
+	pub fn max(mut self, version: TlsVersion) -> Self {
+		self.max = Some(version);
+		self
+	}
+
+	/// Whether the TLS library's defaults are kept.
+	pub fn is_default(&self) -> bool {
+		self.min.is_none() && self.max.is_none()
+	}
+
+	/// The versions in the range, oldest first. Empty if the minimum is
+	/// above the maximum.
+	pub fn versions(&self) -> Vec<TlsVersion> {
+		TlsVersion::ALL
+			.iter()
+			.cloned()
+			.filter(|v| self.min.map(|min| *v >= min).unwrap_or(true))
+			.filter(|v| self.max.map(|max| *v <= max).unwrap_or(true))
+			.collect()
+	}
+
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	fn protocols(&self) -> Vec<Protocol> {
+		self.versions().into_iter().map(TlsVersion::protocol).collect()
+	}
+
+	/// Limit the versions of the connections `builder` makes, this does
+	/// nothing if the defaults are kept.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	pub fn apply_to_connector(&self, builder: &mut TlsConnectorBuilder) -> TlsResult<()> {
+		if !self.is_default() {
+			builder.supported_protocols(&self.protocols())?;
+		}
+		Ok(())
+	}
+
+	/// Limit the versions of the connections `builder` accepts, this does
+	/// nothing if the defaults are kept.
+	#[cfg(any(feature = "sync-ssl", feature = "async-ssl"))]
+	pub fn apply_to_acceptor(&self, builder: &mut TlsAcceptorBuilder) -> TlsResult<()> {
+		if !self.is_default() {
+			builder.supported_protocols(&self.protocols())?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn versions_in_range() {
+		assert_eq!(TlsVersions::new().versions().len(), 3);
+		assert_eq!(
+			TlsVersions::new().min(TlsVersion::Tls11).versions(),
+			vec![TlsVersion::Tls11, TlsVersion::Tls12]
+		);
+		assert_eq!(
+			TlsVersions::new().max(TlsVersion::Tls10).versions(),
+			vec![TlsVersion::Tls10]
+		);
+		assert!(TlsVersions::new()
+			.min(TlsVersion::Tls12)
+			.max(TlsVersion::Tls11)
+			.versions()
+			.is_empty());
+	}
+}
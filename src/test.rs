@@ -0,0 +1,250 @@
+//! Utilities for testing applications built on this crate.
+//!
+//! `MockServer` stands in for a real websocket service: it accepts a single
+//! connection and goes through a script of messages it expects and replies it
+//! sends, which can include frames no well-behaved server would ever send.
+//!
+//! ```rust
+//! use websocket::{ClientBuilder, OwnedMessage};
+//! use websocket::test::{MockServer, Script};
+//!
+//! let script = Script::new()
+//!     .expect(OwnedMessage::Text("hello".to_string()))
+//!     .send(OwnedMessage::Text("world".to_string()))
+//!     // a text frame with a reserved bit set
+//!     .send_raw(vec![0xc1, 0x01, b'!']);
+//! let server = MockServer::start(script).unwrap();
+//!
+//! let mut client = ClientBuilder::new(&server.url()).unwrap().connect_insecure().unwrap();
+//! client.send_message(&OwnedMessage::Text("hello".to_string())).unwrap();
+//! assert_eq!(client.recv_message().unwrap(), OwnedMessage::Text("world".to_string()));
+//! assert!(client.recv_message().is_err());
+//!
+//! server.finish().unwrap();
+//! ```
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use message::{CloseData, OwnedMessage};
+use result::WebSocketError;
+use server::sync::Server;
+use server::upgrade::HyperIntoWsError;
+use sync::Client;
+
+// the crate's own benchmarks expect the `test` crate here
+#[cfg(all(feature = "nightly", test))]
+pub use bench::*;
+
+/// A single step of a `Script`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+	/// Wait for the client to send this message, anything else fails the script.
+	Expect(OwnedMessage),
+	/// Wait for the client to send a message, whatever it is.
+	ExpectAny,
+	/// Send a message to the client.
+	Send(OwnedMessage),
+	/// Write these bytes to the client as they are, e.g. a malformed frame.
+	SendRaw(Vec<u8>),
+	/// Do nothing for a while.
+	Sleep(Duration),
+	/// Close the connection without a close handshake, ending the script.
+	Drop,
+}
+
+/// What a `MockServer` does once a client connected, step by step.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Script {
+	steps: Vec<Step>,
+	protocol: Option<String>,
+}
+
+impl Script {
+	/// An empty script, the server accepts the connection and closes it.
+	pub fn new() -> Self {
+		Script::default()
+	}
+
+	/// Add a step to the script.
+	pub fn step(mut self, step: Step) -> Self {
+		self.steps.push(step);
+		self
+	}
+
+	/// Expect the client to send `message`.
+	pub fn expect(self, message: OwnedMessage) -> Self {
+		self.step(Step::Expect(message))
+	}
+
+	/// Expect the client to send any message.
+	pub fn expect_any(self) -> Self {
+		self.step(Step::ExpectAny)
+	}
+
+	/// Send `message` to the client.
+	pub fn send(self, message: OwnedMessage) -> Self {
+		self.step(Step::Send(message))
+	}
+
+	/// Send raw bytes to the client.
+	pub fn send_raw(self, bytes: Vec<u8>) -> Self {
+		self.step(Step::SendRaw(bytes))
+	}
+
+	/// Send a close message to the client and expect one back.
+	pub fn close(self, data: Option<CloseData>) -> Self {
+		self.send(OwnedMessage::Close(data)).expect_any()
+	}
+
+	/// Pause the script.
+	pub fn sleep(self, duration: Duration) -> Self {
+		self.step(Step::Sleep(duration))
+	}
+
+	/// Drop the connection.
+	pub fn drop_connection(self) -> Self {
+		self.step(Step::Drop)
+	}
+
+	/// Agree to use this subprotocol during the handshake.
+	pub fn protocol(mut self, protocol: &str) -> Self {
+		self.protocol = Some(protocol.to_string());
+		self
+	}
+}
+
+/// How a script did not go according to plan.
+#[derive(Debug)]
+pub enum MockError {
+	/// The client sent a different message than the one expected.
+	Unexpected {
+		/// The step of the script that failed, counting from zero.
+		step: usize,
+		/// The message the script expected.
+		expected: OwnedMessage,
+		/// The message the client sent.
+		got: OwnedMessage,
+	},
+	/// The handshake failed.
+	Handshake(HyperIntoWsError),
+	/// The connection failed.
+	WebSocket(WebSocketError),
+	/// The server's thread panicked.
+	Panicked,
+}
+
+impl fmt::Display for MockError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MockError::Unexpected { step, ref expected, ref got } => {
+				write!(f, "step {}: expected {:?}, got {:?}", step, expected, got)
+			}
+			MockError::Handshake(ref e) => write!(f, "handshake failed: {}", e),
+			MockError::WebSocket(ref e) => write!(f, "connection failed: {}", e),
+			MockError::Panicked => f.write_str("the mock server panicked"),
+		}
+	}
+}
+
+impl Error for MockError {
+	fn description(&self) -> &str {
+		match *self {
+			MockError::Unexpected { .. } => "unexpected message",
+			MockError::Handshake(_) => "handshake failed",
+			MockError::WebSocket(_) => "connection failed",
+			MockError::Panicked => "the mock server panicked",
+		}
+	}
+}
+
+impl From<WebSocketError> for MockError {
+	fn from(err: WebSocketError) -> Self {
+		MockError::WebSocket(err)
+	}
+}
+
+impl From<io::Error> for MockError {
+	fn from(err: io::Error) -> Self {
+		MockError::WebSocket(err.into())
+	}
+}
+
+/// A websocket server on an ephemeral local port playing a `Script` with the
+/// first client that connects, in a thread of its own.
+pub struct MockServer {
+	addr: SocketAddr,
+	thread: JoinHandle<Result<(), MockError>>,
+}
+
+impl MockServer {
+	/// Bind to an ephemeral port on `127.0.0.1` and wait for a client.
+	pub fn start(script: Script) -> io::Result<Self> {
+		let server = Server::bind("127.0.0.1:0")?;
+		let addr = server.local_addr()?;
+		let thread = thread::spawn(move || play(server, script));
+		Ok(MockServer {
+			addr: addr,
+			thread: thread,
+		})
+	}
+
+	/// The address the server listens on.
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// The url to connect to the server with.
+	pub fn url(&self) -> String {
+		format!("ws://{}", self.addr)
+	}
+
+	/// Wait for the script to be over and find out how it went.
+	///
+	/// This blocks until a client connected and the script ran to its end.
+	pub fn finish(self) -> Result<(), MockError> {
+		match self.thread.join() {
+			Ok(result) => result,
+			Err(_) => Err(MockError::Panicked),
+		}
+	}
+}
+
+fn play(mut server: Server<::server::NoTlsAcceptor>, script: Script) -> Result<(), MockError> {
+	let upgrade = server.accept().map_err(|e| MockError::Handshake(e.error))?;
+	let upgrade = match script.protocol {
+		Some(ref protocol) => upgrade.use_protocols(vec![protocol.as_str()]),
+		None => upgrade,
+	};
+	let mut client: Client<TcpStream> = upgrade.accept().map_err(|(_, e)| e)?;
+
+	for (i, step) in script.steps.into_iter().enumerate() {
+		match step {
+			Step::Expect(expected) => {
+				let got = client.recv_message()?;
+				if got != expected {
+					return Err(MockError::Unexpected {
+						step: i,
+						expected: expected,
+						got: got,
+					});
+				}
+			}
+			Step::ExpectAny => {
+				client.recv_message()?;
+			}
+			Step::Send(message) => client.send_message(&message)?,
+			Step::SendRaw(bytes) => {
+				let writer = client.writer_mut();
+				writer.write_all(&bytes)?;
+				writer.flush()?;
+			}
+			Step::Sleep(duration) => thread::sleep(duration),
+			Step::Drop => return Ok(()),
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,39 @@
+//! Helpers shared by the tests of the asynchronous parts of the crate.
+use futures::{Async, AsyncSink, Poll, Sink, StartSend};
+
+use result::WebSocketError;
+
+/// A sink that takes up to `room` items before it has to be drained, by
+/// giving it more room.
+pub struct Slow<T> {
+	pub sent: Vec<T>,
+	pub room: usize,
+}
+
+impl<T> Slow<T> {
+	/// A sink with room for `room` items that took none yet.
+	pub fn new(room: usize) -> Self {
+		Slow {
+			sent: Vec::new(),
+			room: room,
+		}
+	}
+}
+
+impl<T> Sink for Slow<T> {
+	type SinkItem = T;
+	type SinkError = WebSocketError;
+
+	fn start_send(&mut self, item: T) -> StartSend<T, WebSocketError> {
+		if self.room == 0 {
+			return Ok(AsyncSink::NotReady(item));
+		}
+		self.room -= 1;
+		self.sent.push(item);
+		Ok(AsyncSink::Ready)
+	}
+
+	fn poll_complete(&mut self) -> Poll<(), WebSocketError> {
+		Ok(Async::Ready(()))
+	}
+}
@@ -6,6 +6,7 @@ use std::io::Write;
 use result::WebSocketResult;
 use ws::util::header as dfh;
 use ws::util::mask::Masker;
+use ws::util::coalesce::Coalescer;
 use ws::util::mask;
 
 /// A generic DataFrame. Every dataframe should be able to
@@ -51,6 +52,13 @@ pub trait DataFrame {
 
 	/// Writes a DataFrame to a Writer.
 	fn write_to(&self, writer: &mut Write, mask: bool) -> WebSocketResult<()> {
+		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
+		self.write_with_key(writer, masking_key)
+	}
+
+	/// Writes a DataFrame to a Writer, masked with `masking_key` if there is
+	/// one.
+	fn write_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
 		let mut flags = dfh::DataFrameFlags::empty();
 		if self.is_last() {
 			flags.insert(dfh::FIN);
@@ -68,8 +76,6 @@ pub trait DataFrame {
 			}
 		}
 
-		let masking_key = if mask { Some(mask::gen_mask()) } else { None };
-
 		let header = dfh::DataFrameHeader {
 			flags: flags,
 			opcode: self.opcode() as u8,
@@ -77,14 +83,24 @@ pub trait DataFrame {
 			len: self.size() as u64,
 		};
 
-		dfh::write_header(writer, header)?;
+		trace!("writing frame with opcode {} of {} bytes (fin: {})", header.opcode, header.len, self.is_last());
+		if header.opcode == 8 {
+			debug!("sending close frame");
+		}
+
+		// the header and the payload go out in a single write, a longer
+		// payload in a vectored one rather than copied after the header
+		let mut header_buf = [0u8; dfh::MAX_HEADER_SIZE];
+		let header_len = dfh::encode_header(header, &mut header_buf)?;
+		let mut writer = Coalescer::new(writer);
+		writer.write_all(&header_buf[..header_len])?;
 
 		match masking_key {
 			Some(mask) => {
-				let mut masker = Masker::new(mask, writer);
+				let mut masker = Masker::new(mask, &mut writer);
 				self.write_payload(&mut masker)?
 			}
-			None => self.write_payload(writer)?,
+			None => self.write_payload(&mut writer)?,
 		};
 		writer.flush()?;
 		Ok(())
@@ -2,7 +2,9 @@
 //!
 //! See the `ws` module documentation for more information.
 
+use std::borrow::Cow;
 use std::io::Write;
+use std::sync::Arc;
 use ws::dataframe::DataFrame as DataFrameable;
 use result::WebSocketResult;
 
@@ -11,9 +13,63 @@ pub trait Message: Sized {
 	/// Writes this message to the writer
 	fn serialize(&self, &mut Write, masked: bool) -> WebSocketResult<()>;
 
+	/// Writes this message to the writer, masked with `masking_key` if
+	/// there is one. Messages that can't be masked with a given key are
+	/// masked with a random one.
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		self.serialize(writer, masking_key.is_some())
+	}
+
 	/// Returns how many bytes this message will take up
 	fn message_size(&self, masked: bool) -> usize;
 
 	/// Attempt to form a message from a series of data frames
 	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self>;
 }
+
+/// A shared message, so the same message can be sent to many connections
+/// (e.g. through codecs of `Arc<OwnedMessage>`) without copying it for each
+/// of them.
+impl<M> Message for Arc<M>
+where
+	M: Message,
+{
+	fn serialize(&self, writer: &mut Write, masked: bool) -> WebSocketResult<()> {
+		(**self).serialize(writer, masked)
+	}
+
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		(**self).serialize_with_key(writer, masking_key)
+	}
+
+	fn message_size(&self, masked: bool) -> usize {
+		(**self).message_size(masked)
+	}
+
+	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self> {
+		Ok(Arc::new(M::from_dataframes(frames)?))
+	}
+}
+
+/// A borrowed or owned message, so a message can be sent by reference
+/// (e.g. through codecs of `Cow<OwnedMessage>`) without cloning it.
+impl<'a, M> Message for Cow<'a, M>
+where
+	M: Message + Clone,
+{
+	fn serialize(&self, writer: &mut Write, masked: bool) -> WebSocketResult<()> {
+		(**self).serialize(writer, masked)
+	}
+
+	fn serialize_with_key(&self, writer: &mut Write, masking_key: Option<[u8; 4]>) -> WebSocketResult<()> {
+		(**self).serialize_with_key(writer, masking_key)
+	}
+
+	fn message_size(&self, masked: bool) -> usize {
+		(**self).message_size(masked)
+	}
+
+	fn from_dataframes<D: DataFrameable>(frames: Vec<D>) -> WebSocketResult<Self> {
+		Ok(Cow::Owned(M::from_dataframes(frames)?))
+	}
+}
@@ -33,4 +33,22 @@ pub trait Sender {
 		message.serialize(writer, self.is_masked())?;
 		Ok(())
 	}
+
+	/// Sends several messages using this sender, they are gathered up and
+	/// written out all at once, which saves a lot of system calls when sending
+	/// many small messages.
+	fn send_messages<'m, M, I, W>(&mut self, writer: &mut W, messages: I) -> WebSocketResult<()>
+	where
+		M: Message + 'm,
+		I: IntoIterator<Item = &'m M>,
+		W: Write,
+	{
+		let mut buf = Vec::new();
+		for message in messages {
+			self.send_message(&mut buf, message)?;
+		}
+		writer.write_all(&buf)?;
+		writer.flush()?;
+		Ok(())
+	}
 }
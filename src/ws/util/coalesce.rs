@@ -0,0 +1,147 @@
+//! Gathering small writes into a single one
+use std::io::Result as IoResult;
+use std::io::{ErrorKind, IoSlice, Write};
+
+const CAPACITY: usize = 1024;
+
+/// Struct to pipe data into another writer, gathering small writes
+/// (like a frame header and a short payload) into a single write. A large
+/// write is not copied, it goes out along with what was gathered so far in
+/// a single vectored write.
+///
+/// Call `flush` once done, the buffered data is lost otherwise.
+pub struct Coalescer<'w> {
+	buf: [u8; CAPACITY],
+	len: usize,
+	end: &'w mut Write,
+}
+
+impl<'w> Coalescer<'w> {
+	/// Create a new Coalescer writing to the endpoint.
+	pub fn new(endpoint: &'w mut Write) -> Self {
+		Coalescer {
+			buf: [0; CAPACITY],
+			len: 0,
+			end: endpoint,
+		}
+	}
+
+	fn write_buffered(&mut self) -> IoResult<()> {
+		if self.len > 0 {
+			let len = self.len;
+			self.len = 0;
+			self.end.write_all(&self.buf[..len])?;
+		}
+		Ok(())
+	}
+}
+
+impl<'w> Write for Coalescer<'w> {
+	fn write(&mut self, data: &[u8]) -> IoResult<usize> {
+		if self.len + data.len() <= CAPACITY {
+			self.buf[self.len..self.len + data.len()].copy_from_slice(data);
+			self.len += data.len();
+			return Ok(data.len());
+		}
+
+		while self.len > 0 {
+			let written = {
+				let slices = [IoSlice::new(&self.buf[..self.len]), IoSlice::new(data)];
+				self.end.write_vectored(&slices)?
+			};
+			if written == 0 {
+				return Err(ErrorKind::WriteZero.into());
+			}
+			if written < self.len {
+				self.buf.copy_within(written..self.len, 0);
+				self.len -= written;
+			} else {
+				let from_data = written - self.len;
+				self.len = 0;
+				if from_data > 0 {
+					return Ok(from_data);
+				}
+			}
+		}
+		self.end.write(data)
+	}
+
+	fn flush(&mut self) -> IoResult<()> {
+		self.write_buffered()?;
+		self.end.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct CountWrites(Vec<u8>, usize);
+
+	impl Write for CountWrites {
+		fn write(&mut self, data: &[u8]) -> IoResult<usize> {
+			self.1 += 1;
+			self.0.write(data)
+		}
+
+		fn write_vectored(&mut self, slices: &[IoSlice]) -> IoResult<usize> {
+			self.1 += 1;
+			self.0.write_vectored(slices)
+		}
+
+		fn flush(&mut self) -> IoResult<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn coalesces_small_writes() {
+		let mut end = CountWrites(Vec::new(), 0);
+		{
+			let mut writer = Coalescer::new(&mut end);
+			writer.write_all(&[1, 2]).unwrap();
+			writer.write_all(&[3; 100]).unwrap();
+			writer.flush().unwrap();
+		}
+		assert_eq!(end.0.len(), 102);
+		assert_eq!(end.1, 1);
+
+		let mut end = CountWrites(Vec::new(), 0);
+		{
+			let mut writer = Coalescer::new(&mut end);
+			writer.write_all(&[1, 2]).unwrap();
+			writer.write_all(&[3; 5000]).unwrap();
+			writer.flush().unwrap();
+		}
+		assert_eq!(end.0, [&[1u8, 2][..], &[3; 5000][..]].concat());
+		assert_eq!(end.1, 1);
+	}
+
+	#[test]
+	fn short_vectored_writes_keep_the_order() {
+		// takes at most 3 bytes per call
+		struct Trickle(Vec<u8>);
+
+		impl Write for Trickle {
+			fn write(&mut self, data: &[u8]) -> IoResult<usize> {
+				let len = ::std::cmp::min(data.len(), 3);
+				self.0.extend_from_slice(&data[..len]);
+				Ok(len)
+			}
+
+			fn flush(&mut self) -> IoResult<()> {
+				Ok(())
+			}
+		}
+
+		let payload: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+		let mut end = Trickle(Vec::new());
+		{
+			let mut writer = Coalescer::new(&mut end);
+			writer.write_all(&[1, 2, 3, 4, 5]).unwrap();
+			writer.write_all(&payload).unwrap();
+			writer.flush().unwrap();
+		}
+		assert_eq!(end.0, [&[1u8, 2, 3, 4, 5][..], &payload[..]].concat());
+	}
+}
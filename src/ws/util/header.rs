@@ -2,137 +2,41 @@
 
 use std::io::{Read, Write};
 use result::{WebSocketResult, WebSocketError};
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use frames;
 
-bitflags! {
-	/// Flags relevant to a WebSocket data frame.
-	pub struct DataFrameFlags: u8 {
-		/// Marks this dataframe as the last dataframe
-		const FIN = 0x80;
-		/// First reserved bit
-		const RSV1 = 0x40;
-		/// Second reserved bit
-		const RSV2 = 0x20;
-		/// Third reserved bit
-		const RSV3 = 0x10;
-	}
-}
-
-/// Represents a data frame header.
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct DataFrameHeader {
-	/// The bit flags for the first byte of the header.
-	pub flags: DataFrameFlags,
-	/// The opcode of the header - must be <= 16.
-	pub opcode: u8,
-	/// The masking key, if any.
-	pub mask: Option<[u8; 4]>,
-	/// The length of the payload.
-	pub len: u64,
-}
+pub use frames::{DataFrameFlags, DataFrameHeader, FIN, MAX_HEADER_SIZE, RSV1, RSV2, RSV3};
 
 /// Writes a data frame header.
 pub fn write_header(writer: &mut Write, header: DataFrameHeader) -> WebSocketResult<()> {
-
-	if header.opcode > 0xF {
-		return Err(WebSocketError::DataFrameError("Invalid data frame opcode"));
-	}
-	if header.opcode >= 8 && header.len >= 126 {
-		return Err(WebSocketError::DataFrameError(
-			"Control frame length too long",
-		));
-	}
-
-	// Write 'FIN', 'RSV1', 'RSV2', 'RSV3' and 'opcode'
-	writer.write_u8((header.flags.bits) | header.opcode)?;
-
-	writer.write_u8(
-		// Write the 'MASK'
-		if header.mask.is_some() { 0x80 } else { 0x00 } |
-		// Write the 'Payload len'
-		if header.len <= 125 { header.len as u8 }
-		else if header.len <= 65535 { 126 }
-		else { 127 },
-	)?;
-
-	// Write 'Extended payload length'
-	if header.len >= 126 && header.len <= 65535 {
-		writer.write_u16::<BigEndian>(header.len as u16)?;
-	} else if header.len > 65535 {
-		writer.write_u64::<BigEndian>(header.len)?;
-	}
-
-	// Write 'Masking-key'
-	if let Some(mask) = header.mask {
-		writer.write_all(&mask)?
-	}
-
+	let mut buf = [0u8; MAX_HEADER_SIZE];
+	let len = encode_header(header, &mut buf)?;
+	writer.write_all(&buf[..len])?;
 	Ok(())
 }
 
+/// Encodes a data frame header into a buffer, returning how many bytes
+/// of the buffer it takes up.
+pub fn encode_header(
+	header: DataFrameHeader,
+	buf: &mut [u8; MAX_HEADER_SIZE],
+) -> WebSocketResult<usize> {
+	Ok(frames::encode_header(&header, buf)?)
+}
+
 /// Reads a data frame header.
 pub fn read_header<R>(reader: &mut R) -> WebSocketResult<DataFrameHeader>
 where
 	R: Read,
 {
-
-	let byte0 = reader.read_u8()?;
-	let byte1 = reader.read_u8()?;
-
-	let flags = DataFrameFlags::from_bits_truncate(byte0);
-	let opcode = byte0 & 0x0F;
-
-	let len = match byte1 & 0x7F {
-		0...125 => (byte1 & 0x7F) as u64,
-		126 => {
-			let len = reader.read_u16::<BigEndian>()? as u64;
-			if len <= 125 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
-			}
-			len
-		}
-		127 => {
-			let len = reader.read_u64::<BigEndian>()?;
-			if len <= 65535 {
-				return Err(WebSocketError::DataFrameError("Invalid data frame length"));
-			}
-			len
-		}
-		_ => unreachable!(),
-	};
-
-	if opcode >= 8 {
-		if len >= 126 {
-			return Err(WebSocketError::DataFrameError(
-				"Control frame length too long",
-			));
-		}
-		if !flags.contains(FIN) {
-			return Err(WebSocketError::ProtocolError(
-				"Illegal fragmented control frame",
-			));
-		}
+	let mut buf = [0u8; MAX_HEADER_SIZE];
+	reader.read_exact(&mut buf[..2])?;
+	let size = frames::header_size(buf[1]);
+	reader.read_exact(&mut buf[2..size])?;
+
+	match frames::decode_header(&buf[..size])? {
+		Some((header, _)) => Ok(header),
+		None => Err(WebSocketError::DataFrameError("Incomplete data frame header")),
 	}
-
-	let mask = if byte1 & 0x80 == 0x80 {
-		Some(
-			[
-				reader.read_u8()?,
-				reader.read_u8()?,
-				reader.read_u8()?,
-				reader.read_u8()?,
-			],
-		)
-	} else {
-		None
-	};
-
-	Ok(DataFrameHeader {
-		flags: flags,
-		opcode: opcode,
-		mask: mask,
-		len: len,
-	})
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -191,6 +95,21 @@ mod tests {
 
 		assert_eq!(&obtained[..], &expected[..]);
 	}
+	#[test]
+	fn test_encode_header_largest() {
+		let header = DataFrameHeader {
+			flags: FIN,
+			opcode: 2,
+			mask: Some([1, 2, 3, 4]),
+			len: 1 << 32,
+		};
+		let expected = [0x82, 0xFF, 0, 0, 0, 1, 0, 0, 0, 0, 1, 2, 3, 4];
+		let mut obtained = [0u8; MAX_HEADER_SIZE];
+		let len = encode_header(header, &mut obtained).unwrap();
+
+		assert_eq!(len, MAX_HEADER_SIZE);
+		assert_eq!(&obtained[..], &expected[..]);
+	}
 	#[bench]
 	fn bench_read_header(b: &mut test::Bencher) {
 		let header = vec![0x42u8, 0xFE, 0x02, 0x00, 0x02, 0x04, 0x08, 0x10];
@@ -1,8 +1,11 @@
 //! Utility functions for masking data frame payload data
-use rand;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+use frames;
+use std::fmt;
 use std::io::Write;
 use std::io::Result as IoResult;
 use std::mem;
+use std::sync::{Arc, Mutex};
 
 /// Struct to pipe data into another writer,
 /// while masking the data being written
@@ -26,12 +29,15 @@ impl<'w> Masker<'w> {
 
 impl<'w> Write for Masker<'w> {
 	fn write(&mut self, data: &[u8]) -> IoResult<usize> {
-		let mut buf = Vec::with_capacity(data.len());
-		for &byte in data.iter() {
-			buf.push(byte ^ self.key[self.pos]);
-			self.pos = (self.pos + 1) % self.key.len();
+		// mask into a scratch buffer on the stack instead of allocating
+		let mut buf = [0u8; 1024];
+		let len = ::std::cmp::min(data.len(), buf.len());
+		for (i, &byte) in data[..len].iter().enumerate() {
+			buf[i] = byte ^ self.key[(self.pos + i) % self.key.len()];
 		}
-		self.end.write(&buf)
+		let written = self.end.write(&buf[..len])?;
+		self.pos = (self.pos + written) % self.key.len();
+		Ok(written)
 	}
 
 	fn flush(&mut self) -> IoResult<()> {
@@ -45,6 +51,71 @@ pub fn gen_mask() -> [u8; 4] {
 	unsafe { mem::transmute(rand::random::<u32>()) }
 }
 
+/// Where the random masking keys and handshake keys of a connection come
+/// from, instead of the thread's random number generator.
+///
+/// Seeding one makes handshakes and masked frames the same on every run,
+/// which is handy for golden-file tests and fuzzing. Never use a seeded source
+/// for real connections, predictable masks defeat their purpose.
+///
+/// Cloning is cheap, all clones draw from the same generator.
+///
+/// ```rust
+/// use websocket::ws::util::mask::KeySource;
+///
+/// let a = KeySource::seeded(42);
+/// let b = KeySource::seeded(42);
+/// assert_eq!(a.mask(), b.mask());
+/// ```
+#[derive(Clone)]
+pub struct KeySource(Arc<Mutex<Box<Rng + Send>>>);
+
+impl KeySource {
+	/// Draw keys from `rng`.
+	pub fn new<R>(rng: R) -> Self
+	where
+		R: Rng + Send + 'static,
+	{
+		KeySource(Arc::new(Mutex::new(Box::new(rng))))
+	}
+
+	/// A fast, reproducible generator seeded with `seed`.
+	pub fn seeded(seed: u64) -> Self {
+		// xorshift must not be seeded with zeros only
+		let seed = [seed as u32, (seed >> 32) as u32, 0x9e37_79b9, 0x7f4a_7c15];
+		KeySource::new(XorShiftRng::from_seed(seed))
+	}
+
+	/// The next masking key.
+	pub fn mask(&self) -> [u8; 4] {
+		let mut key = [0u8; 4];
+		self.0.lock().unwrap().fill_bytes(&mut key);
+		key
+	}
+
+	/// The next handshake key.
+	pub fn key(&self) -> [u8; 16] {
+		let mut key = [0u8; 16];
+		self.0.lock().unwrap().fill_bytes(&mut key);
+		key
+	}
+}
+
+impl fmt::Debug for KeySource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("KeySource")
+	}
+}
+
+/// The masking key to send a frame with, if it's masked at all.
+pub fn next_mask(masked: bool, keys: Option<&KeySource>) -> Option<[u8; 4]> {
+	match (masked, keys) {
+		(false, _) => None,
+		(true, Some(keys)) => Some(keys.mask()),
+		(true, None) => Some(gen_mask()),
+	}
+}
+
 /// Masks data to send to a server and writes
 pub fn mask_data(mask: [u8; 4], data: &[u8]) -> Vec<u8> {
 	let mut out = Vec::with_capacity(data.len());
@@ -55,6 +126,11 @@ pub fn mask_data(mask: [u8; 4], data: &[u8]) -> Vec<u8> {
 	out
 }
 
+/// Masks (or unmasks) data without copying it
+pub fn mask_data_in_place(mask: [u8; 4], data: &mut [u8]) {
+	frames::mask_in_place(mask, 0, data)
+}
+
 #[cfg(all(feature = "nightly", test))]
 mod tests {
 	use super::*;
@@ -69,6 +145,10 @@ mod tests {
 
 		assert_eq!(original, reversed);
 		assert_eq!(obtained, expected);
+
+		let mut in_place = original.clone();
+		mask_data_in_place(key, &mut in_place);
+		assert_eq!(in_place, expected);
 	}
 
 	#[bench]
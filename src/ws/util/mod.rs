@@ -1,5 +1,6 @@
 //! Utility functions for various portions of Rust-WebSocket.
 
+pub mod coalesce;
 pub mod header;
 pub mod mask;
 